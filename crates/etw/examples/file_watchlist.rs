@@ -0,0 +1,83 @@
+//! Print file creations whose path is on a watchlist.
+//!
+//! Needs administrator rights. Runs for 60 seconds.
+
+use std::{thread, time::Duration};
+
+use etw::{
+    provider::{ProviderBuilder, TraceLevel},
+    trace::TraceBuilder,
+    trace_session::{EnableProviderTimeout, TraceSessionBuilder},
+    values::{
+        compound::{StringOrStruct, StructOrValue},
+        in_value::InValue,
+        strings::Utf16Matcher,
+    },
+};
+use windows::core::GUID;
+
+/// Microsoft-Windows-Kernel-File
+const KERNEL_FILE_PROVIDER: GUID = GUID::from_u128(0xedd08927_9cc4_4e65_b970_c2560fb5c289);
+const KERNEL_FILE_KEYWORD_FILENAME: u64 = 0x10;
+const CREATE_ID: u16 = 12;
+
+fn main() {
+    env_logger::init();
+
+    // Encoded once, compared against the UTF-16 file names of all events.
+    let suffixes = [".ps1", ".vbs", ".hta"].map(Utf16Matcher::new);
+    let directories = ["\\AppData\\Local\\Temp\\", "\\Startup\\"].map(Utf16Matcher::new);
+
+    let mut session = TraceSessionBuilder::new("etw-rs-file-watchlist")
+        .close_previous()
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&KERNEL_FILE_PROVIDER)
+        .level(TraceLevel::VERBOSE)
+        .any(KERNEL_FILE_KEYWORD_FILENAME)
+        .build();
+    session
+        .enable_provider(&provider, true, EnableProviderTimeout::Infinite, None)
+        .unwrap();
+
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_handler(move |event, schema, record| {
+            if record.EventHeader.EventDescriptor.Id != CREATE_ID {
+                return;
+            }
+            let Some(index) = schema
+                .properties
+                .fields
+                .iter()
+                .position(|field| field.value.name() == "FileName")
+            else {
+                return;
+            };
+            let StringOrStruct::Struct(data) = &event.data else {
+                return;
+            };
+            let StructOrValue::Value(value) = &data[index] else {
+                return;
+            };
+            let InValue::UnicodeString(strings) = &value.value else {
+                return;
+            };
+            for name in strings {
+                let suspicious_type = suffixes.iter().any(|suffix| suffix.is_suffix_of(name));
+                let watched_directory = directories.iter().any(|directory| directory.is_contained_in(name));
+                // Only matching names are decoded.
+                if suspicious_type && watched_directory {
+                    println!("pid {}: {}", record.EventHeader.ProcessId, name);
+                }
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+
+    thread::sleep(Duration::from_secs(60));
+    trace.shutdown(Duration::from_secs(5)).unwrap();
+}
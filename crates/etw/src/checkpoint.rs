@@ -0,0 +1,257 @@
+use windows::Win32::System::Diagnostics::Etw::EVENT_RECORD;
+
+use crate::values::event::EventRecord;
+
+/// Position in a file trace from which processing can be resumed.
+///
+/// A checkpoint counts the buffers that have been completely delivered and the events
+/// delivered from the buffer after them. Take it with [`crate::trace::Trace::checkpoint`],
+/// persist it, and pass it to [`crate::trace::TraceBuilder::resume_from`] to continue
+/// processing the same file later.
+///
+/// # Exactly-once caveats
+///
+/// ETW has no way to seek in a file, so it still reads the skipped part on resume.
+/// Events in the completely delivered buffers are dropped as a whole, before any
+/// locking, hashing or decoding, and only the buffer with the resume point is skipped
+/// event by event. A checkpoint taken while the handler is
+/// running may or may not include the event being handled, so the event at the resume
+/// boundary can be delivered twice if the sink isn't idempotent. Compare
+/// [`Checkpoint::last_event_hash`] with [`event_hash`] of the first delivered event to
+/// drop such a duplicate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Checkpoint {
+    /// Number of buffers completely delivered.
+    pub buffers: u64,
+    /// Number of events delivered from the buffer following the completed ones.
+    pub events_in_buffer: u64,
+    /// [`event_hash`] of the last delivered event, if any.
+    pub last_event_hash: Option<u64>,
+}
+
+/// Track the processing position and skip events before a resume checkpoint.
+#[derive(Debug, Default)]
+pub(crate) struct CheckpointTracker {
+    position: Checkpoint,
+    resume: Option<Checkpoint>,
+}
+
+impl CheckpointTracker {
+    pub(crate) fn new(resume: Option<Checkpoint>) -> Self {
+        Self {
+            position: Checkpoint::default(),
+            resume,
+        }
+    }
+
+    /// Account for a new event. Returns true if it should be passed to the handler.
+    ///
+    /// `hash` is only called for events that aren't skipped.
+    pub(crate) fn on_event<F: FnOnce() -> u64>(&mut self, hash: F) -> bool {
+        let skip = match &self.resume {
+            Some(resume) => {
+                self.position.buffers < resume.buffers
+                    || (self.position.buffers == resume.buffers
+                        && self.position.events_in_buffer < resume.events_in_buffer)
+            }
+            None => false,
+        };
+
+        self.position.events_in_buffer += 1;
+        if skip {
+            return false;
+        }
+
+        self.resume = None;
+        self.position.last_event_hash = Some(hash());
+        true
+    }
+
+    /// Account for a completely delivered buffer.
+    pub(crate) fn on_buffer(&mut self) {
+        self.position.buffers += 1;
+        self.position.events_in_buffer = 0;
+    }
+
+    pub(crate) fn position(&self) -> Checkpoint {
+        match &self.resume {
+            // Still skipping; report the resume point rather than the partial position
+            Some(resume) => *resume,
+            None => self.position,
+        }
+    }
+
+    pub(crate) fn is_resuming(&self) -> bool {
+        self.resume.is_some()
+    }
+
+    /// Whether the next buffer lies wholly before the resume checkpoint, so its events
+    /// can be dropped without calling [`CheckpointTracker::on_event`].
+    pub(crate) fn skips_buffer(&self) -> bool {
+        self.resume.is_some_and(|resume| self.position.buffers < resume.buffers)
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+fn fnv1a(mut hash: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Stable hash of an event record, used to detect duplicates at a resume boundary.
+///
+/// Covers the provider, event descriptor id, timestamp, process and thread ids and the
+/// user data. The hash is stable across processes and crate versions, and works on the
+/// raw record so events can be skipped without decoding them.
+///
+/// It deliberately doesn't use the semantic equality of [`crate::values::semantic`]:
+/// the duplicate at a resume boundary is the same record read again from the same file,
+/// so its bytes, padding included, are identical and a raw hash is exact. A semantic
+/// hash would need a schema for every event, including ones that fail to decode, and
+/// isn't stable across crate versions, while checkpoints are persisted. Use the semantic
+/// equality to compare decoded payloads from different records instead.
+pub fn event_hash(event_record: &EVENT_RECORD) -> u64 {
+    let header = &event_record.EventHeader;
+    let mut hash = FNV_OFFSET_BASIS;
+    hash = fnv1a(hash, &header.ProviderId.to_u128().to_le_bytes());
+    hash = fnv1a(hash, &header.EventDescriptor.Id.to_le_bytes());
+    hash = fnv1a(hash, &header.TimeStamp.to_le_bytes());
+    hash = fnv1a(hash, &header.ProcessId.to_le_bytes());
+    hash = fnv1a(hash, &header.ThreadId.to_le_bytes());
+    fnv1a(hash, EventRecord(event_record).userdata())
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{core::GUID, Win32::System::Diagnostics::Etw::EVENT_RECORD};
+
+    use super::{event_hash, Checkpoint, CheckpointTracker};
+
+    /// Deliver `buffers` with the given number of events, returning the delivered (buffer, index) pairs.
+    ///
+    /// Skipped buffers bypass [`CheckpointTracker::on_event`] like in the trace's handlers.
+    fn run(tracker: &mut CheckpointTracker, buffers: &[u64]) -> Vec<(usize, u64)> {
+        let mut delivered = Vec::new();
+        for (buffer, events) in buffers.iter().enumerate() {
+            if tracker.skips_buffer() {
+                tracker.on_buffer();
+                continue;
+            }
+            for idx in 0..*events {
+                if tracker.on_event(|| (buffer as u64) << 32 | idx) {
+                    delivered.push((buffer, idx));
+                }
+            }
+            tracker.on_buffer();
+        }
+        delivered
+    }
+
+    #[test]
+    fn test_position_counts_buffers_and_events() {
+        let mut tracker = CheckpointTracker::new(None);
+        assert!(tracker.on_event(|| 1));
+        assert!(tracker.on_event(|| 2));
+        tracker.on_buffer();
+        assert!(tracker.on_event(|| 3));
+
+        assert_eq!(
+            tracker.position(),
+            Checkpoint {
+                buffers: 1,
+                events_in_buffer: 1,
+                last_event_hash: Some(3),
+            }
+        );
+    }
+
+    #[test]
+    fn test_resume_skips_processed_events() {
+        let buffers = [3, 4, 2];
+        let all = run(&mut CheckpointTracker::new(None), &buffers);
+
+        let resume = Checkpoint {
+            buffers: 1,
+            events_in_buffer: 2,
+            last_event_hash: Some(1 << 32 | 1),
+        };
+        let mut tracker = CheckpointTracker::new(Some(resume));
+        let resumed = run(&mut tracker, &buffers);
+
+        assert_eq!(all[..5].iter().chain(resumed.iter()).copied().collect::<Vec<_>>(), all);
+        assert!(!tracker.is_resuming());
+        assert_eq!(tracker.position().buffers, 3);
+    }
+
+    #[test]
+    fn test_resume_at_buffer_boundary() {
+        let resume = Checkpoint {
+            buffers: 2,
+            events_in_buffer: 0,
+            last_event_hash: None,
+        };
+        let mut tracker = CheckpointTracker::new(Some(resume));
+        assert_eq!(run(&mut tracker, &[1, 1, 2]), vec![(2, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_skips_whole_buffers_before_resume() {
+        let resume = Checkpoint {
+            buffers: 2,
+            events_in_buffer: 1,
+            last_event_hash: None,
+        };
+        let mut tracker = CheckpointTracker::new(Some(resume));
+        assert!(tracker.skips_buffer());
+        tracker.on_buffer();
+        assert!(tracker.skips_buffer());
+        tracker.on_buffer();
+
+        // The buffer with the resume point is skipped event by event
+        assert!(!tracker.skips_buffer());
+        assert!(!tracker.on_event(|| 0));
+        assert!(tracker.on_event(|| 1));
+        tracker.on_buffer();
+        assert!(!tracker.skips_buffer());
+        assert!(!CheckpointTracker::new(None).skips_buffer());
+    }
+
+    #[test]
+    fn test_position_while_skipping_is_resume_point() {
+        let resume = Checkpoint {
+            buffers: 5,
+            events_in_buffer: 0,
+            last_event_hash: Some(42),
+        };
+        let mut tracker = CheckpointTracker::new(Some(resume));
+        run(&mut tracker, &[1, 1]);
+        assert!(tracker.is_resuming());
+        assert_eq!(tracker.position(), resume);
+    }
+
+    #[test]
+    fn test_event_hash_is_stable() {
+        let mut userdata = [1u8, 2, 3, 4, 0];
+        let mut record = EVENT_RECORD::default();
+        record.EventHeader.ProviderId = GUID::from_u128(0x1c95126e_7eea_49a9_a3fe_a378b03ddb4d);
+        record.EventHeader.EventDescriptor.Id = 3019;
+        record.EventHeader.TimeStamp = 133_274_160_000_000_000;
+        record.EventHeader.ProcessId = 1234;
+        record.EventHeader.ThreadId = 5678;
+        record.UserData = userdata.as_mut_ptr().cast();
+        record.UserDataLength = 4;
+
+        // Persisted in checkpoints, so the value must not change
+        assert_eq!(event_hash(&record), 0x9bdc1f4af7ae9272);
+        // Raw bytes, so trailing padding makes a different record
+        record.UserDataLength = 5;
+        assert_ne!(event_hash(&record), 0x9bdc1f4af7ae9272);
+    }
+}
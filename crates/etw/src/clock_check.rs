@@ -0,0 +1,420 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    timestamp::{duration_to_ticks, system_time_to_ticks, ticks_to_duration, Timestamp, TimestampContext},
+    trace_session::ClockResolution,
+    values::event::EventRecord,
+};
+
+/// A timestamp that went backwards within one session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampRegression {
+    pub session: String,
+    /// Arrival index of the event with the earlier timestamp.
+    pub arrival: u64,
+    pub previous_timestamp: i64,
+    pub timestamp: i64,
+}
+
+impl TimestampRegression {
+    pub fn delta(&self) -> Duration {
+        ticks_to_duration(self.previous_timestamp - self.timestamp)
+    }
+}
+
+/// How the skew between two sessions developed over the checked stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkewTrend {
+    Stable,
+    Growing,
+    Shrinking,
+}
+
+/// Skew beyond the threshold between two sessions.
+///
+/// The skew is measured as how far behind `session` was compared to the latest
+/// timestamp of `reference` at the time an event of `session` arrived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSkew {
+    pub session: String,
+    pub reference: String,
+    pub first: Duration,
+    pub last: Duration,
+    pub max: Duration,
+    pub occurrences: usize,
+}
+
+impl SessionSkew {
+    pub fn trend(&self) -> SkewTrend {
+        let tolerance = self.first.max(self.last) / 10;
+        if self.last > self.first + tolerance {
+            SkewTrend::Growing
+        } else if self.first > self.last + tolerance {
+            SkewTrend::Shrinking
+        } else {
+            SkewTrend::Stable
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionClockReport {
+    pub session: String,
+    /// The clock configured for the session, if known.
+    pub clock: Option<ClockResolution>,
+    pub samples: usize,
+}
+
+/// Result of [`ClockSanityChecker::diagnose`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockDiagnosis {
+    pub sessions: Vec<SessionClockReport>,
+    pub regressions: Vec<TimestampRegression>,
+    pub skews: Vec<SessionSkew>,
+}
+
+impl ClockDiagnosis {
+    pub fn is_healthy(&self) -> bool {
+        self.regressions.is_empty() && self.skews.is_empty()
+    }
+
+    /// True if the sessions are known to be configured with different clocks.
+    pub fn has_mixed_clocks(&self) -> bool {
+        let mut clocks = self.sessions.iter().filter_map(|session| session.clock);
+        match clocks.next() {
+            Some(first) => clocks.any(|clock| clock != first),
+            None => false,
+        }
+    }
+
+    fn clock_of(&self, session: &str) -> Option<ClockResolution> {
+        self.sessions
+            .iter()
+            .find(|report| report.session == session)
+            .and_then(|report| report.clock)
+    }
+}
+
+impl fmt::Display for ClockDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_healthy() {
+            return write!(f, "no clock problems detected");
+        }
+
+        let clock_name = |session: &str| match self.clock_of(session) {
+            Some(clock) => format!("{:?}", clock),
+            None => "an unknown clock".to_string(),
+        };
+
+        let mut first = true;
+        for skew in &self.skews {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            let trend = match skew.trend() {
+                SkewTrend::Stable => "stable",
+                SkewTrend::Growing => "growing",
+                SkewTrend::Shrinking => "shrinking",
+            };
+            write!(
+                f,
+                "session {} uses {}, session {} uses {}; skew ~{}ms and {}",
+                skew.session,
+                clock_name(&skew.session),
+                skew.reference,
+                clock_name(&skew.reference),
+                skew.last.as_millis(),
+                trend,
+            )?;
+        }
+        for regression in &self.regressions {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(
+                f,
+                "session {} ({}) went back in time by {}us at event {}",
+                regression.session,
+                clock_name(&regression.session),
+                regression.delta().as_micros(),
+                regression.arrival,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    clock: Option<ClockResolution>,
+    last_timestamp: Option<i64>,
+    samples: usize,
+}
+
+/// Checks that events merged from several sessions are ordered consistently.
+///
+/// Attach a shared checker to the trace of each session with
+/// [`crate::trace::TraceBuilder::clock_check`], which registers the session's clock and
+/// records every event in the order it was delivered. Without a trace, feed the events
+/// with [`ClockSanityChecker::record`] or [`ClockSanityChecker::record_event`]. Sessions configured
+/// with different [`ClockResolution`]s produce timestamps that drift apart, which shows
+/// up as cross-session skew. Events are buffered per session by ETW, so some skew is
+/// expected; only skew beyond the threshold is reported.
+#[derive(Debug)]
+pub struct ClockSanityChecker {
+    threshold: Duration,
+    sessions: HashMap<String, SessionState>,
+    order: Vec<String>,
+    arrivals: u64,
+    regressions: Vec<TimestampRegression>,
+    skews: HashMap<(String, String), SessionSkew>,
+}
+
+impl ClockSanityChecker {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            sessions: HashMap::new(),
+            order: Vec::new(),
+            arrivals: 0,
+            regressions: Vec::new(),
+            skews: HashMap::new(),
+        }
+    }
+
+    /// Register a session together with its configured clock so it can be named in the report.
+    pub fn register_session(&mut self, session: &str, clock: Option<ClockResolution>) {
+        self.session_state(session).clock = clock;
+    }
+
+    /// Record the timestamp (in 100ns FILETIME ticks) of the next event delivered for `session`.
+    pub fn record(&mut self, session: &str, timestamp: i64) {
+        let arrival = self.arrivals;
+        self.arrivals += 1;
+
+        let state = self.session_state(session);
+        state.samples += 1;
+        let previous = state.last_timestamp.replace(timestamp);
+        if let Some(previous) = previous
+            && timestamp < previous
+        {
+            self.regressions.push(TimestampRegression {
+                session: session.to_string(),
+                arrival,
+                previous_timestamp: previous,
+                timestamp,
+            });
+        }
+
+        let threshold = duration_to_ticks(self.threshold);
+        for (reference, state) in &self.sessions {
+            if reference == session {
+                continue;
+            }
+            let Some(reference_timestamp) = state.last_timestamp else {
+                continue;
+            };
+            let behind = reference_timestamp - timestamp;
+            if behind <= threshold {
+                continue;
+            }
+            let behind = ticks_to_duration(behind);
+            self.skews
+                .entry((session.to_string(), reference.clone()))
+                .and_modify(|skew| {
+                    skew.last = behind;
+                    skew.max = skew.max.max(behind);
+                    skew.occurrences += 1;
+                })
+                .or_insert_with(|| SessionSkew {
+                    session: session.to_string(),
+                    reference: reference.clone(),
+                    first: behind,
+                    last: behind,
+                    max: behind,
+                    occurrences: 1,
+                });
+        }
+    }
+
+    /// Record the timestamp of an event delivered for `session`, interpreted with the
+    /// session trace's `context`, see [`crate::trace::Trace::timestamp_context`].
+    ///
+    /// Events without a usable timestamp (see [`Timestamp::Missing`]) only count as an
+    /// arrival; they can't regress or skew. [`Timestamp::Relative`] timestamps only compare
+    /// well within their session.
+    pub fn record_event(&mut self, session: &str, context: &TimestampContext, event_record: &EventRecord) {
+        match context.classify(&event_record.0.EventHeader) {
+            Timestamp::Absolute(time) => self.record(session, system_time_to_ticks(SystemTime::from(time))),
+            Timestamp::Relative(duration) => self.record(session, duration_to_ticks(duration)),
+            Timestamp::Missing => {
+                self.arrivals += 1;
+                self.session_state(session).samples += 1;
+            }
+        }
+    }
+
+    pub fn diagnose(&self) -> ClockDiagnosis {
+        let sessions = self
+            .order
+            .iter()
+            .map(|name| {
+                let state = &self.sessions[name];
+                SessionClockReport {
+                    session: name.clone(),
+                    clock: state.clock,
+                    samples: state.samples,
+                }
+            })
+            .collect();
+
+        let mut skews: Vec<SessionSkew> = self.skews.values().cloned().collect();
+        skews.sort_by(|a, b| (&a.session, &a.reference).cmp(&(&b.session, &b.reference)));
+
+        ClockDiagnosis {
+            sessions,
+            regressions: self.regressions.clone(),
+            skews,
+        }
+    }
+
+    fn session_state(&mut self, session: &str) -> &mut SessionState {
+        if !self.sessions.contains_key(session) {
+            self.order.push(session.to_string());
+        }
+        self.sessions.entry(session.to_string()).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use windows::Win32::System::Diagnostics::Etw::{EVENT_HEADER, EVENT_RECORD};
+
+    use crate::{timestamp::TimestampContext, trace_session::ClockResolution, values::event::EventRecord};
+
+    use super::{ClockSanityChecker, SkewTrend};
+
+    const MS: i64 = 10_000;
+
+    #[test]
+    fn test_interleaved_sessions_are_healthy() {
+        let mut checker = ClockSanityChecker::new(Duration::from_millis(5));
+        for i in 0..100 {
+            checker.record("kernel", i * MS);
+            checker.record("user", i * MS + MS / 2);
+        }
+
+        let diagnosis = checker.diagnose();
+        assert!(diagnosis.is_healthy());
+        assert_eq!(diagnosis.sessions.len(), 2);
+        assert_eq!(diagnosis.sessions[0].samples, 100);
+    }
+
+    #[test]
+    fn test_regression_within_session() {
+        let mut checker = ClockSanityChecker::new(Duration::from_millis(5));
+        checker.record("user", 10 * MS);
+        checker.record("user", 20 * MS);
+        checker.record("user", 15 * MS);
+        checker.record("user", 30 * MS);
+
+        let diagnosis = checker.diagnose();
+        assert!(diagnosis.skews.is_empty());
+        assert_eq!(diagnosis.regressions.len(), 1);
+        assert_eq!(diagnosis.regressions[0].arrival, 2);
+        assert_eq!(diagnosis.regressions[0].delta(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_growing_skew_between_sessions() {
+        let mut checker = ClockSanityChecker::new(Duration::from_millis(5));
+        checker.register_session("A", Some(ClockResolution::SystemTime));
+        checker.register_session("B", Some(ClockResolution::QueryPerformanceCounter));
+        for i in 0..20 {
+            checker.record("A", i * 10 * MS);
+            checker.record("B", i * 10 * MS - i * MS);
+        }
+
+        let diagnosis = checker.diagnose();
+        assert!(diagnosis.regressions.is_empty());
+        assert!(diagnosis.has_mixed_clocks());
+        assert_eq!(diagnosis.skews.len(), 1);
+        let skew = &diagnosis.skews[0];
+        assert_eq!(skew.session, "B");
+        assert_eq!(skew.reference, "A");
+        assert_eq!(skew.last, Duration::from_millis(19));
+        assert_eq!(skew.trend(), SkewTrend::Growing);
+        assert_eq!(
+            diagnosis.to_string(),
+            "session B uses QueryPerformanceCounter, session A uses SystemTime; skew ~19ms and growing"
+        );
+    }
+
+    #[test]
+    fn test_constant_skew_is_stable() {
+        let mut checker = ClockSanityChecker::new(Duration::from_millis(5));
+        for i in 0..20 {
+            checker.record("A", i * 100 * MS + 12 * MS);
+            checker.record("B", i * 100 * MS);
+        }
+
+        let diagnosis = checker.diagnose();
+        assert!(!diagnosis.has_mixed_clocks());
+        assert_eq!(diagnosis.skews.len(), 1);
+        assert_eq!(diagnosis.skews[0].max, Duration::from_millis(12));
+        assert_eq!(diagnosis.skews[0].trend(), SkewTrend::Stable);
+    }
+
+    #[test]
+    fn test_events_without_timestamp_keep_arrival_order() {
+        let mut checker = ClockSanityChecker::new(Duration::from_millis(5));
+        let header = |timestamp| EVENT_HEADER {
+            TimeStamp: timestamp,
+            ..Default::default()
+        };
+        for timestamp in [20 * MS, 0, 30 * MS] {
+            let event_record = EVENT_RECORD {
+                EventHeader: header(timestamp),
+                ..Default::default()
+            };
+            checker.record_event("user", &TimestampContext::new(), &EventRecord(&event_record));
+        }
+
+        let diagnosis = checker.diagnose();
+        assert!(diagnosis.is_healthy());
+        assert_eq!(diagnosis.sessions[0].samples, 3);
+    }
+
+    #[test]
+    fn test_raw_timestamps_convert_with_session_context() {
+        /// 2023-05-01 12:00:00 UTC.
+        const TICKS_2023: i64 = 133_274_160_000_000_000;
+        // A 10MHz QPC that read 1000 when the session started
+        let qpc =
+            TimestampContext::raw_since_start(ClockResolution::QueryPerformanceCounter, 10_000_000, TICKS_2023, 1_000);
+        let record = |timestamp| EVENT_RECORD {
+            EventHeader: EVENT_HEADER {
+                TimeStamp: timestamp,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut checker = ClockSanityChecker::new(Duration::from_millis(5));
+        for i in 0..20 {
+            let converted = record(TICKS_2023 + i * 10 * MS);
+            checker.record_event("converted", &TimestampContext::new(), &EventRecord(&converted));
+            checker.record_event("raw", &qpc, &EventRecord(&record(1_000 + i * 10 * MS + MS)));
+        }
+
+        assert!(checker.diagnose().is_healthy());
+    }
+}
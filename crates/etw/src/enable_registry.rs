@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    sync::{Arc, Mutex},
+};
+
+use once_cell::sync::Lazy;
+use windows::core::GUID;
+
+use crate::error::TraceError;
+
+/// Reference counts for providers enabled through this crate.
+///
+/// ETW keeps a single enable state per (session, provider) pair. When several
+/// owners inside one process share a session (e.g. via [`crate::trace_session::TraceSession::open_existing`]),
+/// one owner disabling "its" provider also disables it for the others. The registry
+/// counts the enables per (session name, provider) and only lets the last owner issue
+/// the real disable.
+///
+/// Using the registry is opt-in: only [`crate::trace_session::TraceSession::enable_provider_shared`]
+/// and [`crate::trace_session::TraceSession::disable_provider_shared`] go through it.
+#[derive(Debug, Default)]
+pub struct EnableRegistry {
+    /// Owners per (session name, provider). Each count has its own lock, which is held
+    /// while enabling or disabling the provider, so that only calls for the same provider
+    /// in the same session wait for each other.
+    counts: Mutex<HashMap<(OsString, GUID), Arc<Mutex<usize>>>>,
+}
+
+static GLOBAL_ENABLE_REGISTRY: Lazy<EnableRegistry> = Lazy::new(EnableRegistry::new);
+
+impl EnableRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide registry.
+    pub fn global() -> &'static EnableRegistry {
+        &GLOBAL_ENABLE_REGISTRY
+    }
+
+    /// The count of the provider in the session, None if it isn't registered.
+    fn count(&self, session: &OsStr, provider: &GUID) -> Option<Arc<Mutex<usize>>> {
+        let counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        counts.get(&(session.to_os_string(), *provider)).map(Arc::clone)
+    }
+
+    /// The count of the provider in the session, registered with no owners if it isn't yet.
+    fn register(&self, session: &OsStr, provider: &GUID) -> Arc<Mutex<usize>> {
+        let mut counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        Arc::clone(counts.entry((session.to_os_string(), *provider)).or_default())
+    }
+
+    /// Remove `count` for the provider in the session, unless it was replaced already.
+    fn unregister(&self, session: &OsStr, provider: &GUID, count: &Arc<Mutex<usize>>) {
+        let mut counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        let key = (session.to_os_string(), *provider);
+        if counts.get(&key).is_some_and(|registered| Arc::ptr_eq(registered, count)) {
+            counts.remove(&key);
+        }
+    }
+
+    fn counts(&self) -> Vec<((OsString, GUID), Arc<Mutex<usize>>)> {
+        let counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        counts.iter().map(|(key, count)| (key.clone(), Arc::clone(count))).collect()
+    }
+
+    /// Issue `enable` and record one more owner of the provider if it succeeds.
+    ///
+    /// `enable` is called on every acquire so that changed keywords or levels take effect.
+    pub fn acquire<F>(&self, session: &OsStr, provider: &GUID, enable: F) -> Result<usize, TraceError>
+    where
+        F: FnOnce() -> Result<(), TraceError>,
+    {
+        let mut enable = Some(enable);
+        loop {
+            let count = self.register(session, provider);
+            let mut owners = count.lock().unwrap_or_else(|err| err.into_inner());
+            // The last owner may have released the provider while this waited for the lock
+            if !self.count(session, provider).is_some_and(|registered| Arc::ptr_eq(&registered, &count)) {
+                continue;
+            }
+            let enable = enable.take().expect("enable is only called once");
+            if let Err(err) = enable() {
+                if *owners == 0 {
+                    self.unregister(session, provider, &count);
+                }
+                return Err(err);
+            }
+            *owners += 1;
+            return Ok(*owners);
+        }
+    }
+
+    /// Drop one owner of the provider and call `disable` if it was the last one.
+    ///
+    /// Returns true if `disable` was called. Releasing a provider that isn't registered
+    /// calls `disable` as well, so that the registry never keeps a provider enabled, but
+    /// doesn't register it. The last release removes the provider's entry even if
+    /// `disable` fails, as that usually means the session was stopped from outside of
+    /// this process.
+    pub fn release<F>(&self, session: &OsStr, provider: &GUID, disable: F) -> Result<bool, TraceError>
+    where
+        F: FnOnce() -> Result<(), TraceError>,
+    {
+        let Some(count) = self.count(session, provider) else {
+            disable()?;
+            return Ok(true);
+        };
+        let mut owners = count.lock().unwrap_or_else(|err| err.into_inner());
+        if *owners > 1 {
+            *owners -= 1;
+            return Ok(false);
+        }
+        // Still holding the lock, so an acquire waiting for it enables the provider again
+        let result = disable();
+        *owners = 0;
+        self.unregister(session, provider, &count);
+        result.map(|()| true)
+    }
+
+    /// Forget all entries for a session, e.g. after it has been stopped.
+    pub fn forget_session(&self, session: &OsStr) {
+        let mut counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        counts.retain(|(name, _), _| name != session);
+    }
+
+    /// Number of owners currently registered for the provider in the session.
+    pub fn refcount(&self, session: &OsStr, provider: &GUID) -> usize {
+        self.count(session, provider)
+            .map_or(0, |count| *count.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Snapshot of all registered (session, provider, refcount) entries for diagnostics.
+    pub fn dump(&self) -> Vec<(OsString, GUID, usize)> {
+        self.counts()
+            .into_iter()
+            .map(|((session, provider), count)| (session, provider, *count.lock().unwrap_or_else(|err| err.into_inner())))
+            .filter(|(_, _, count)| *count > 0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, ffi::OsStr, sync::mpsc, thread};
+
+    use windows::core::GUID;
+
+    use crate::error::TraceError;
+
+    use super::EnableRegistry;
+
+    const PROVIDER: GUID = GUID::from_u128(0x22fb2cd6_0e7b_422b_a0c7_2fad1fd0e716);
+
+    #[test]
+    fn test_shared_provider_is_disabled_by_last_owner() {
+        let registry = EnableRegistry::new();
+        let session = OsStr::new("shared_session");
+        let enables = Cell::new(0);
+        let disables = Cell::new(0);
+
+        let enable = || {
+            enables.set(enables.get() + 1);
+            Ok(())
+        };
+        let disable = || {
+            disables.set(disables.get() + 1);
+            Ok(())
+        };
+
+        assert_eq!(registry.acquire(session, &PROVIDER, enable).unwrap(), 1);
+        assert_eq!(registry.acquire(session, &PROVIDER, enable).unwrap(), 2);
+        assert_eq!(enables.get(), 2);
+
+        assert!(!registry.release(session, &PROVIDER, disable).unwrap());
+        assert_eq!(disables.get(), 0);
+        assert_eq!(registry.refcount(session, &PROVIDER), 1);
+
+        assert!(registry.release(session, &PROVIDER, disable).unwrap());
+        assert_eq!(disables.get(), 1);
+        assert_eq!(registry.refcount(session, &PROVIDER), 0);
+        assert!(registry.dump().is_empty());
+    }
+
+    #[test]
+    fn test_failed_enable_is_not_counted() {
+        let registry = EnableRegistry::new();
+        let session = OsStr::new("session");
+
+        let result = registry.acquire(session, &PROVIDER, || {
+            Err(TraceError::Configuration("enable failed".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(registry.refcount(session, &PROVIDER), 0);
+        assert!(registry.counts().is_empty());
+    }
+
+    #[test]
+    fn test_release_of_unregistered_provider_disables_it() {
+        let registry = EnableRegistry::new();
+        let disables = Cell::new(0);
+
+        let released = registry.release(OsStr::new("session"), &PROVIDER, || {
+            disables.set(disables.get() + 1);
+            Ok(())
+        });
+        assert!(released.unwrap());
+        assert_eq!(disables.get(), 1);
+        assert!(registry.counts().is_empty());
+    }
+
+    #[test]
+    fn test_failed_disable_removes_stale_entry() {
+        let registry = EnableRegistry::new();
+        let session = OsStr::new("session");
+
+        registry.acquire(session, &PROVIDER, || Ok(())).unwrap();
+        let result = registry.release(session, &PROVIDER, || {
+            Err(TraceError::Configuration("session is gone".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(registry.refcount(session, &PROVIDER), 0);
+        assert!(registry.counts().is_empty());
+        assert_eq!(registry.acquire(session, &PROVIDER, || Ok(())).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_sessions_are_counted_separately() {
+        let registry = EnableRegistry::new();
+
+        registry.acquire(OsStr::new("a"), &PROVIDER, || Ok(())).unwrap();
+        registry.acquire(OsStr::new("b"), &PROVIDER, || Ok(())).unwrap();
+        assert!(registry.release(OsStr::new("a"), &PROVIDER, || Ok(())).unwrap());
+        assert_eq!(registry.refcount(OsStr::new("b"), &PROVIDER), 1);
+
+        registry.forget_session(OsStr::new("b"));
+        assert!(registry.dump().is_empty());
+    }
+
+    #[test]
+    fn test_enable_doesnt_block_other_sessions() {
+        let registry = &EnableRegistry::new();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+
+        thread::scope(|scope| {
+            let slow = scope.spawn(move || {
+                registry.acquire(OsStr::new("slow"), &PROVIDER, || {
+                    entered_tx.send(()).unwrap();
+                    finish_rx.recv().unwrap();
+                    Ok(())
+                })
+            });
+            entered_rx.recv().unwrap();
+
+            // The enable of the other session is still running
+            assert_eq!(registry.acquire(OsStr::new("fast"), &PROVIDER, || Ok(())).unwrap(), 1);
+            assert!(registry.release(OsStr::new("fast"), &PROVIDER, || Ok(())).unwrap());
+
+            finish_tx.send(()).unwrap();
+            assert_eq!(slow.join().unwrap().unwrap(), 1);
+        });
+        assert_eq!(registry.refcount(OsStr::new("slow"), &PROVIDER), 1);
+    }
+}
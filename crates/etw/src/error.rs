@@ -18,6 +18,26 @@ pub enum TraceError {
     Decode(#[from] ParseError),
     #[error("Thread join error")]
     ThreadJoin,
+    #[error("Event record pointer is null")]
+    NullEventRecord,
+    #[error("Trace processing didn't finish within {0:?}")]
+    ShutdownTimeout(std::time::Duration),
+    #[error("Provider {provider:?} didn't process the enable request within {timeout:?}")]
+    EnableTimeout { provider: GUID, timeout: std::time::Duration },
+    #[error("Session lease error: {0}")]
+    Lease(std::io::Error),
+    #[error("Failed to open trace {target}: {source}")]
+    OpenTrace {
+        /// The log file or session name.
+        target: String,
+        #[source]
+        source: windows::core::Error,
+    },
+    #[error("Failed to enable {} providers", .0.len())]
+    EnableProviders(Vec<(GUID, TraceError)>),
+    #[cfg(feature = "json")]
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
 }
 
 impl From<WIN32_ERROR> for TraceError {
@@ -74,6 +94,37 @@ pub enum ParseError {
     NoMapName,
     #[error("Not implemented")]
     NotImplemented,
+    #[error("Invalid version range: {0}")]
+    InvalidVersionRange(String),
+    #[error("Malformed event record: {0}")]
+    MalformedRecord(&'static str),
+    #[error("Unknown type name: {0}")]
+    UnknownTypeName(String),
+    #[error("Unknown socket address family {0}")]
+    UnknownAddressFamily(u16),
+    #[error("Invalid TRACE_EVENT_INFO buffer: {0}")]
+    InvalidTraceEventInfo(String),
+    #[error("Failed to decode property {path} at offset {offset}: {source}")]
+    Property {
+        /// Path of the failing property, e.g. `Outer[2].Inner`.
+        path: String,
+        /// Byte offset of the failing property in the decoded data.
+        offset: usize,
+        source: Box<ParseError>,
+    },
+}
+
+/// Why a decoded value couldn't be converted to a native type.
+#[derive(thiserror::Error, Debug)]
+pub enum ConversionError {
+    #[error("Can't convert a {from} value to {to}")]
+    TypeMismatch { from: InType, to: &'static str },
+    #[error("Expected a single element, got {0}")]
+    NotAScalar(usize),
+    #[error("Value {value} is out of range for {to}")]
+    OutOfRange { value: String, to: &'static str },
+    #[error("Invalid SID: {0}")]
+    Sid(#[source] ParseError),
 }
 
 impl From<Infallible> for ParseError {
@@ -82,6 +133,61 @@ impl From<Infallible> for ParseError {
     }
 }
 
+impl ParseError {
+    /// Record that the error happened in the property `segment` at `offset` of the enclosing data.
+    ///
+    /// Segments are either property names or array indices like `[2]`.
+    pub(crate) fn at_property(self, segment: &str, offset: usize) -> Self {
+        match self {
+            ParseError::Property {
+                path,
+                offset: inner_offset,
+                source,
+            } => {
+                let path = if path.starts_with('[') {
+                    format!("{}{}", segment, path)
+                } else {
+                    format!("{}.{}", segment, path)
+                };
+                ParseError::Property {
+                    path,
+                    offset: offset + inner_offset,
+                    source,
+                }
+            }
+            err => ParseError::Property {
+                path: segment.to_string(),
+                offset,
+                source: Box::new(err),
+            },
+        }
+    }
+
+    /// Path of the property that failed to decode, if known.
+    pub fn property_path(&self) -> Option<&str> {
+        match self {
+            ParseError::Property { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Byte offset of the property that failed to decode, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            ParseError::Property { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// The error without the property location.
+    pub fn root_cause(&self) -> &ParseError {
+        match self {
+            ParseError::Property { source, .. } => source.root_cause(),
+            err => err,
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ParserBuilderError {
     #[error("Invalid index {index}/{count}")]
@@ -99,3 +205,15 @@ pub enum EventPropertyInfoError {
     #[error("UTF16 decode error: {0}")]
     Utf16(#[from] FromUtf16Error),
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum WellKnownEventError {
+    #[error("Unexpected event {id} version {version} of provider {provider:?}")]
+    UnexpectedEvent { provider: GUID, id: u16, version: u8 },
+    #[error("Event payload is not a struct")]
+    NotAStruct,
+    #[error("Missing property {0}")]
+    MissingProperty(&'static str),
+    #[error("Property {0} has an unexpected type")]
+    UnexpectedType(&'static str),
+}
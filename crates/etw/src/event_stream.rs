@@ -0,0 +1,239 @@
+//! Decoded events as an asynchronous stream, instead of a callback on the processing thread.
+//!
+//! Set up with [`crate::trace::TraceBuilder::into_stream`]. The handler copies each
+//! decoded event into a bounded queue that an [`EventStream`] consumes; when the queue
+//! is full, the [`Backpressure`] policy decides what happens to new events.
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use futures_core::Stream;
+
+use crate::values::event::EventOwned;
+
+/// What to do with an event when the queue of the stream is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// Drop the oldest queued event to make room for the new one.
+    DropOldest,
+    /// Drop the new event.
+    #[default]
+    DropNewest,
+    /// Block the processing thread until the consumer makes room.
+    ///
+    /// ETW drops buffers of real-time sessions that aren't consumed in time, so this
+    /// only moves the loss out of this process for slow consumers.
+    Block,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    events: VecDeque<EventOwned>,
+    /// No more events will be pushed.
+    closed: bool,
+    /// The stream was dropped, pushed events are discarded.
+    detached: bool,
+    waker: Option<Waker>,
+}
+
+/// The queue between the processing thread and an [`EventStream`].
+#[derive(Debug)]
+pub(crate) struct EventChannel {
+    state: Mutex<State>,
+    not_full: Condvar,
+    capacity: usize,
+    backpressure: Backpressure,
+    dropped: AtomicU64,
+}
+
+impl EventChannel {
+    pub(crate) fn new(capacity: usize, backpressure: Backpressure) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            not_full: Condvar::new(),
+            capacity,
+            backpressure,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn push(&self, event: EventOwned) {
+        let mut state = self.lock();
+        if self.backpressure == Backpressure::Block {
+            state = self
+                .not_full
+                .wait_while(state, |state| {
+                    state.events.len() >= self.capacity && !state.closed && !state.detached
+                })
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        if state.closed || state.detached {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if state.events.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            match self.backpressure {
+                Backpressure::DropNewest => return,
+                Backpressure::DropOldest | Backpressure::Block => {
+                    state.events.pop_front();
+                }
+            }
+        }
+        state.events.push_back(event);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// End the stream once the queued events are consumed.
+    pub(crate) fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        drop(state);
+        self.not_full.notify_all();
+    }
+
+    pub(crate) fn dropped_events(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Decoded events of a trace, see [`crate::trace::TraceBuilder::into_stream`].
+///
+/// Ends when the trace is closed or its processing ends, e.g. because the session
+/// was stopped or the end of the log file was reached.
+#[derive(Debug)]
+pub struct EventStream {
+    channel: Arc<EventChannel>,
+}
+
+impl EventStream {
+    pub(crate) fn new(channel: Arc<EventChannel>) -> Self {
+        Self { channel }
+    }
+
+    /// Number of events dropped so far, see [`Backpressure`].
+    pub fn dropped_events(&self) -> u64 {
+        self.channel.dropped_events()
+    }
+}
+
+impl Stream for EventStream {
+    type Item = EventOwned;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.channel.lock();
+        if let Some(event) = state.events.pop_front() {
+            drop(state);
+            self.channel.not_full.notify_one();
+            return Poll::Ready(Some(event));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.channel.lock().events.len(), None)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        let mut state = self.channel.lock();
+        state.detached = true;
+        state.events.clear();
+        drop(state);
+        self.channel.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::System::Diagnostics::Etw::EVENT_HEADER;
+
+    use crate::values::{
+        compound::StringOrStructOwned,
+        event::{Header, HeaderOwned},
+    };
+
+    use super::*;
+
+    fn event(id: u16) -> EventOwned {
+        let mut header = EVENT_HEADER::default();
+        header.EventDescriptor.Id = id;
+        EventOwned {
+            header: HeaderOwned::from(&Header::from(&header)),
+            data: StringOrStructOwned::String(String::new()),
+        }
+    }
+
+    fn poll(stream: &mut EventStream) -> Poll<Option<u16>> {
+        let mut cx = Context::from_waker(Waker::noop());
+        Pin::new(stream)
+            .poll_next(&mut cx)
+            .map(|event| event.map(|event| event.header.event_descriptor.id))
+    }
+
+    #[test]
+    fn test_drop_newest_keeps_queued_events() {
+        let channel = Arc::new(EventChannel::new(2, Backpressure::DropNewest));
+        let mut stream = EventStream::new(Arc::clone(&channel));
+        for id in 1..=4 {
+            channel.push(event(id));
+        }
+        channel.close();
+
+        assert_eq!(poll(&mut stream), Poll::Ready(Some(1)));
+        assert_eq!(poll(&mut stream), Poll::Ready(Some(2)));
+        assert_eq!(poll(&mut stream), Poll::Ready(None));
+        assert_eq!(stream.dropped_events(), 2);
+    }
+
+    #[test]
+    fn test_drop_oldest_keeps_latest_events() {
+        let channel = Arc::new(EventChannel::new(2, Backpressure::DropOldest));
+        let mut stream = EventStream::new(Arc::clone(&channel));
+        assert_eq!(poll(&mut stream), Poll::Pending);
+        for id in 1..=4 {
+            channel.push(event(id));
+        }
+
+        assert_eq!(poll(&mut stream), Poll::Ready(Some(3)));
+        assert_eq!(poll(&mut stream), Poll::Ready(Some(4)));
+        assert_eq!(poll(&mut stream), Poll::Pending);
+        assert_eq!(stream.dropped_events(), 2);
+    }
+
+    #[test]
+    fn test_dropped_stream_unblocks_producer() {
+        let channel = Arc::new(EventChannel::new(1, Backpressure::Block));
+        let stream = EventStream::new(Arc::clone(&channel));
+        channel.push(event(1));
+        let producer = {
+            let channel = Arc::clone(&channel);
+            std::thread::spawn(move || channel.push(event(2)))
+        };
+        drop(stream);
+        producer.join().unwrap();
+
+        assert_eq!(channel.dropped_events(), 1);
+    }
+}
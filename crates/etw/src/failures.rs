@@ -0,0 +1,361 @@
+//! Capture of events that failed to decode, for post-mortem analysis.
+//!
+//! The last failures are kept in a bounded [`FailureRing`]. Dumped failures are text
+//! files with one `header_hex userdata_hex` line per event, preceded by a `#` comment
+//! with the schema key and the error, so they can be fed back to the decoder with
+//! [`FailureRecord::load`] and [`FailureRecord::event_record`].
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::Write as _,
+    fs,
+    io::{self, BufWriter, Write},
+    mem::size_of,
+    path::Path,
+    slice,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use windows::{
+    core::GUID,
+    Win32::System::Diagnostics::Etw::{EVENT_HEADER, EVENT_RECORD},
+};
+
+use crate::values::{event::EventRecord, value::hex};
+
+/// A failed event with the raw data needed to reproduce the failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailureRecord {
+    /// Raw bytes of the `EVENT_HEADER`.
+    pub header: Vec<u8>,
+    /// Payload of the event, truncated to the ring's byte cap.
+    pub userdata: Vec<u8>,
+    /// Payload length before truncation.
+    pub userdata_length: usize,
+    /// The error and its sources, separated by `: `.
+    pub error: String,
+}
+
+impl FailureRecord {
+    pub fn new(event_record: &EVENT_RECORD, error: &dyn Error, max_userdata: usize) -> Self {
+        let header = unsafe {
+            slice::from_raw_parts(
+                &event_record.EventHeader as *const _ as *const u8,
+                size_of::<EVENT_HEADER>(),
+            )
+        };
+        let userdata_length = usize::from(event_record.UserDataLength);
+        let userdata = EventRecord(event_record).userdata();
+        let userdata = &userdata[..userdata.len().min(max_userdata)];
+        Self {
+            header: header.to_vec(),
+            userdata: userdata.to_vec(),
+            userdata_length,
+            error: render_error_chain(error),
+        }
+    }
+
+    fn event_header(&self) -> Option<EVENT_HEADER> {
+        if self.header.len() != size_of::<EVENT_HEADER>() {
+            return None;
+        }
+        Some(unsafe { std::ptr::read_unaligned(self.header.as_ptr() as *const EVENT_HEADER) })
+    }
+
+    /// Schema cache key of the event: provider, event id and event version.
+    pub fn schema_key(&self) -> Option<(GUID, u16, u8)> {
+        self.event_header().map(|header| {
+            (
+                header.ProviderId,
+                header.EventDescriptor.Id,
+                header.EventDescriptor.Version,
+            )
+        })
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.userdata.len() < self.userdata_length
+    }
+
+    /// Rebuild the event record for decoding it again.
+    ///
+    /// The record points into the payload of `self`, which must outlive it. Truncated
+    /// payloads are passed with their truncated length.
+    pub fn event_record(&mut self) -> Option<EVENT_RECORD> {
+        let header = self.event_header()?;
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.EventHeader = header;
+        event_record.UserDataLength = self.userdata.len().try_into().ok()?;
+        event_record.UserData = self.userdata.as_mut_ptr() as *mut _;
+        Some(event_record)
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        if let Some((provider, id, version)) = self.schema_key() {
+            write!(writer, "# {:?} {} {}", provider, id, version)?;
+        } else {
+            write!(writer, "#")?;
+        }
+        if self.is_truncated() {
+            write!(writer, " (truncated from {} bytes)", self.userdata_length)?;
+        }
+        writeln!(writer, ": {}", self.error.replace(['\r', '\n'], " "))?;
+        // Drop the `0x` prefix, offline tooling expects bare digits
+        let (header, userdata) = (hex(&self.header), hex(&self.userdata));
+        writeln!(writer, "{} {}", &header[2..], &userdata[2..])
+    }
+
+    /// Read failures written by [`FailureRing::dump_to`].
+    ///
+    /// The error of a record is taken from the comment preceding it; records without
+    /// one get an empty error.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<FailureRecord>> {
+        let mut records = Vec::new();
+        let mut error = String::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.trim();
+            if let Some(comment) = line.strip_prefix('#') {
+                error = comment
+                    .split_once(": ")
+                    .map(|(_, error)| error.to_string())
+                    .unwrap_or_default();
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid failure record: {}", line));
+            let (header, userdata) = line.split_once(' ').unwrap_or((line, ""));
+            let header = decode_hex(header).ok_or_else(invalid)?;
+            if header.len() != size_of::<EVENT_HEADER>() {
+                return Err(invalid());
+            }
+            let userdata = decode_hex(userdata).ok_or_else(invalid)?;
+            records.push(FailureRecord {
+                header,
+                userdata_length: userdata.len(),
+                userdata,
+                error: std::mem::take(&mut error),
+            });
+        }
+        Ok(records)
+    }
+}
+
+fn render_error_chain(error: &dyn Error) -> String {
+    let mut rendered = error.to_string();
+    let mut source = error.source();
+    while let Some(err) = source {
+        // Some errors already include their source in their message
+        let message = err.to_string();
+        if !rendered.contains(&message) {
+            let _ = write!(rendered, ": {}", message);
+        }
+        source = err.source();
+    }
+    rendered
+}
+
+/// Parse hex digits without prefix, as written by [`FailureRing::dump_to`].
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+/// The last events that failed to decode.
+///
+/// Holds at most `capacity` records of at most `max_userdata` payload bytes each, and
+/// evicts the oldest record when full. A capacity of 0 disables capturing.
+#[derive(Debug, Default)]
+pub struct FailureRing {
+    capacity: AtomicUsize,
+    max_userdata: AtomicUsize,
+    records: Mutex<VecDeque<FailureRecord>>,
+    /// Number of failures passed to [`FailureRing::record`], retained or not.
+    failed_events: AtomicU64,
+}
+
+impl FailureRing {
+    pub fn new(capacity: usize, max_userdata: usize) -> Self {
+        Self {
+            capacity: AtomicUsize::new(capacity),
+            max_userdata: AtomicUsize::new(max_userdata),
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            failed_events: AtomicU64::new(0),
+        }
+    }
+
+    /// Change the bounds, evicting the oldest records if there are too many now.
+    ///
+    /// Records captured earlier keep their payload even if it exceeds the new cap.
+    pub fn set_bounds(&self, capacity: usize, max_userdata: usize) {
+        self.max_userdata.store(max_userdata, Ordering::Relaxed);
+        self.capacity.store(capacity, Ordering::Relaxed);
+        let mut records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+        let excess = records.len().saturating_sub(capacity);
+        records.drain(..excess);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity() != 0
+    }
+
+    /// Number of events that failed to decode, including those not retained because
+    /// capturing is disabled or the ring is full.
+    pub fn failed_events(&self) -> u64 {
+        self.failed_events.load(Ordering::Relaxed)
+    }
+
+    pub fn record(&self, event_record: &EVENT_RECORD, error: &dyn Error) {
+        self.failed_events.fetch_add(1, Ordering::Relaxed);
+        if !self.is_enabled() {
+            return;
+        }
+        self.push(FailureRecord::new(event_record, error, self.max_userdata.load(Ordering::Relaxed)));
+    }
+
+    pub fn push(&self, record: FailureRecord) {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return;
+        }
+        let mut records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+        while records.len() >= capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Copies of the retained records, oldest first.
+    pub fn records(&self) -> Vec<FailureRecord> {
+        let records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+        records.iter().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap_or_else(|err| err.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap_or_else(|err| err.into_inner()).clear();
+    }
+
+    /// Write the retained records to `path`, oldest first.
+    pub fn dump_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let records = self.records();
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        for record in &records {
+            record.write_to(&mut writer)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{core::GUID, Win32::System::Diagnostics::Etw::EVENT_RECORD};
+
+    use crate::{
+        error::ParseError,
+        schema::{
+            cache::{EventInfo, PropertyInfo, PropertyStructInfo},
+            in_type::InType,
+            out_type::OutType,
+        },
+    };
+
+    use super::{FailureRecord, FailureRing};
+
+    const PROVIDER: GUID = GUID::from_u128(0x22fb2cd6_0e7b_422b_a0c7_2fad1fd0e716);
+
+    fn event_record(id: u16, userdata: &mut [u8]) -> EVENT_RECORD {
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.EventHeader.ProviderId = PROVIDER;
+        event_record.EventHeader.EventDescriptor.Id = id;
+        event_record.EventHeader.EventDescriptor.Version = 2;
+        event_record.UserDataLength = userdata.len().try_into().unwrap();
+        event_record.UserData = userdata.as_mut_ptr() as *mut _;
+        event_record
+    }
+
+    fn uint64_schema() -> EventInfo {
+        EventInfo::new(
+            PROVIDER,
+            1,
+            2,
+            PropertyStructInfo::new(vec![PropertyInfo::value("Address", InType::UInt64, OutType::HexInt64)]),
+        )
+    }
+
+    #[test]
+    fn test_ring_evicts_oldest_records() {
+        let ring = FailureRing::new(3, 16);
+        for id in 0..5 {
+            let mut userdata = [id as u8; 4];
+            ring.record(&event_record(id, &mut userdata), &ParseError::PrematureEndOfData);
+        }
+        let ids = ring
+            .records()
+            .iter()
+            .map(|record| record.schema_key().unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 3, 4]);
+
+        ring.set_bounds(1, 16);
+        assert_eq!(ring.records()[0].schema_key(), Some((PROVIDER, 4, 2)));
+        ring.set_bounds(0, 16);
+        assert!(ring.is_empty());
+        ring.record(&event_record(5, &mut [0; 4]), &ParseError::PrematureEndOfData);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_payload_is_capped() {
+        let ring = FailureRing::new(1, 4);
+        let mut userdata = [1, 2, 3, 4, 5, 6];
+        ring.record(&event_record(1, &mut userdata), &ParseError::PrematureEndOfData);
+        let record = &ring.records()[0];
+        assert_eq!(record.userdata, vec![1, 2, 3, 4]);
+        assert_eq!(record.userdata_length, 6);
+        assert!(record.is_truncated());
+    }
+
+    #[test]
+    fn test_dump_round_trips_through_decoder() {
+        let schema = uint64_schema();
+        let mut userdata = [0xaa, 0xbb, 0xcc, 0xdd];
+        let event = event_record(1, &mut userdata);
+        let error = schema.decode(&event).unwrap_err();
+
+        let ring = FailureRing::new(4, 64);
+        ring.record(&event, &error);
+        let path = std::env::temp_dir().join(format!("etw-rs-failures-{}.txt", std::process::id()));
+        ring.dump_to(&path).unwrap();
+        let mut loaded = FailureRecord::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, ring.records());
+        assert!(loaded[0].error.contains("Address"), "{}", loaded[0].error);
+        let replayed = loaded[0].event_record().unwrap();
+        assert_eq!(replayed.EventHeader.ProviderId, PROVIDER);
+        let replay_error = schema.decode(&replayed).unwrap_err();
+        assert_eq!(replay_error.to_string(), error.to_string());
+    }
+}
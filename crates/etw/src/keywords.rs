@@ -0,0 +1,248 @@
+//! Names of the keyword bits set on events.
+//!
+//! Provider keywords come from the provider's manifest through TDH. The top 16 bits are
+//! reserved: bits 48 to 55 are the standard keywords of `winmeta.xml`, bits 56 to 63 mark
+//! the event log channels of manifest providers. Bits without a name are reported as a
+//! residual value.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use once_cell::sync::Lazy;
+use windows::core::GUID;
+
+use crate::tdh_wrappers::{EventFieldType, ProviderFieldInformation};
+
+/// Standard keywords defined in `winmeta.xml`.
+pub const STANDARD_KEYWORDS: &[(&str, u64)] = &[
+    ("win:ResponseTime", 0x0001_0000_0000_0000),
+    ("win:WDIContext", 0x0002_0000_0000_0000),
+    ("win:WDIDiag", 0x0004_0000_0000_0000),
+    ("win:SQM", 0x0008_0000_0000_0000),
+    ("win:AuditFailure", 0x0010_0000_0000_0000),
+    ("win:AuditSuccess", 0x0020_0000_0000_0000),
+    ("win:CorrelationHint", 0x0040_0000_0000_0000),
+    ("win:EventlogClassic", 0x0080_0000_0000_0000),
+];
+
+/// The keywords a provider defines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeywordTable {
+    entries: Vec<(String, u64)>,
+}
+
+impl KeywordTable {
+    /// Entries with a value of 0 are ignored.
+    pub fn new(entries: impl IntoIterator<Item = (String, u64)>) -> Self {
+        Self {
+            entries: entries.into_iter().filter(|(_, value)| *value != 0).collect(),
+        }
+    }
+
+    /// The keywords in the manifest of `provider`.
+    ///
+    /// Empty for providers without a manifest or without keywords.
+    pub fn from_provider(provider: &GUID) -> Self {
+        match ProviderFieldInformation::new(provider, &EventFieldType::KeywordInformation) {
+            Ok(field_info) => Self::new(
+                field_info
+                    .iter()
+                    .map(|info| (info.name().to_string_lossy().into_owned(), info.value())),
+            ),
+            Err(err) => {
+                log::debug!("No keywords for provider {:?}: {}", provider, err);
+                Self::default()
+            }
+        }
+    }
+
+    /// Names of all keywords whose bits are all set in `keyword`, provider keywords
+    /// first, followed by the standard keywords.
+    ///
+    /// Keywords of several bits are only reported if all of their bits are set.
+    pub fn names_for(&self, keyword: u64) -> Vec<&str> {
+        self.decompose(keyword).0
+    }
+
+    /// The bits of `keyword` that aren't part of any keyword reported by [`KeywordTable::names_for`].
+    pub fn residual(&self, keyword: u64) -> u64 {
+        self.decompose(keyword).1
+    }
+
+    fn decompose(&self, keyword: u64) -> (Vec<&str>, u64) {
+        let mut names = Vec::new();
+        let mut covered = 0;
+        for (name, value) in &self.entries {
+            if keyword & value == *value {
+                names.push(name.as_str());
+                covered |= value;
+            }
+        }
+        for (name, value) in STANDARD_KEYWORDS {
+            // Manifests may define the standard keywords themselves
+            if keyword & value == *value && covered & value == 0 {
+                names.push(name);
+                covered |= value;
+            }
+        }
+        (names, keyword & !covered)
+    }
+}
+
+/// The keywords of an event, resolved through the keyword table of its provider.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Keywords {
+    pub value: u64,
+    pub names: Vec<String>,
+    /// Bits of `value` without a name.
+    pub residual: u64,
+}
+
+impl Keywords {
+    /// The names, followed by the residual in hex if there is one.
+    pub fn elements(&self) -> Vec<String> {
+        let mut elements = self.names.clone();
+        if self.residual != 0 {
+            elements.push(format!("{:#x}", self.residual));
+        }
+        elements
+    }
+}
+
+/// Formats as `Connection|Errors (0x30)`, with the residual as a last element if there is one.
+impl fmt::Display for Keywords {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let elements = self.elements();
+        if elements.is_empty() {
+            write!(f, "{:#x}", self.value)
+        } else {
+            write!(f, "{} ({:#x})", elements.join("|"), self.value)
+        }
+    }
+}
+
+/// Keyword tables of providers, looked up when a provider's keywords are resolved first.
+#[derive(Debug, Default)]
+pub struct KeywordResolver {
+    tables: RwLock<HashMap<GUID, Arc<KeywordTable>>>,
+}
+
+static GLOBAL_KEYWORD_RESOLVER: Lazy<KeywordResolver> = Lazy::new(KeywordResolver::new);
+
+impl KeywordResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide resolver.
+    pub fn global() -> &'static KeywordResolver {
+        &GLOBAL_KEYWORD_RESOLVER
+    }
+
+    /// Use `table` for `provider` instead of looking it up with TDH.
+    pub fn insert(&self, provider: GUID, table: KeywordTable) {
+        self.tables
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(provider, Arc::new(table));
+    }
+
+    /// The keyword table of `provider`, querying TDH on first use.
+    pub fn table(&self, provider: &GUID) -> Arc<KeywordTable> {
+        if let Some(table) = self.tables.read().unwrap_or_else(|err| err.into_inner()).get(provider) {
+            return Arc::clone(table);
+        }
+        let table = Arc::new(KeywordTable::from_provider(provider));
+        Arc::clone(
+            self.tables
+                .write()
+                .unwrap_or_else(|err| err.into_inner())
+                .entry(*provider)
+                .or_insert(table),
+        )
+    }
+
+    /// Names of the keywords set in `keyword`, see [`KeywordTable::names_for`].
+    pub fn names_for(&self, provider: &GUID, keyword: u64) -> Vec<String> {
+        self.table(provider).names_for(keyword).into_iter().map(str::to_string).collect()
+    }
+
+    pub fn resolve(&self, provider: &GUID, keyword: u64) -> Keywords {
+        let table = self.table(provider);
+        let (names, residual) = table.decompose(keyword);
+        Keywords {
+            value: keyword,
+            names: names.into_iter().map(str::to_string).collect(),
+            residual,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::core::GUID;
+
+    use super::{KeywordResolver, KeywordTable, Keywords};
+
+    const PROVIDER: GUID = GUID::from_u128(0x0b6f3d2e_41c5_4f0e_9a6c_5b1e8d7c2a90);
+
+    fn table() -> KeywordTable {
+        KeywordTable::new([
+            ("Connection".to_string(), 0x10),
+            ("Errors".to_string(), 0x20),
+            ("Transfer".to_string(), 0x0c),
+            ("None".to_string(), 0),
+        ])
+    }
+
+    #[test]
+    fn test_names_for_single_and_multi_bit_keywords() {
+        let table = table();
+        assert_eq!(table.names_for(0x30), vec!["Connection", "Errors"]);
+        assert_eq!(table.names_for(0x0c), vec!["Transfer"]);
+        // Only one of the bits of Transfer
+        assert_eq!(table.names_for(0x04), Vec::<&str>::new());
+        assert_eq!(table.residual(0x04), 0x04);
+        assert_eq!(table.names_for(0), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_standard_and_unknown_bits() {
+        let table = table();
+        let keyword = 0x8000_0000_0000_0000 | 0x0020_0000_0000_0000 | 0x10 | 0x100;
+        assert_eq!(table.names_for(keyword), vec!["Connection", "win:AuditSuccess"]);
+        assert_eq!(table.residual(keyword), 0x8000_0000_0000_0100);
+    }
+
+    #[test]
+    fn test_resolver_uses_inserted_table() {
+        let resolver = KeywordResolver::new();
+        resolver.insert(PROVIDER, table());
+        let keywords = resolver.resolve(&PROVIDER, 0x130);
+        assert_eq!(
+            keywords,
+            Keywords {
+                value: 0x130,
+                names: vec!["Connection".to_string(), "Errors".to_string()],
+                residual: 0x100,
+            }
+        );
+        assert_eq!(keywords.to_string(), "Connection|Errors|0x100 (0x130)");
+        assert_eq!(resolver.resolve(&PROVIDER, 0x30).to_string(), "Connection|Errors (0x30)");
+        assert_eq!(resolver.names_for(&PROVIDER, 0x130), vec!["Connection", "Errors"]);
+    }
+
+    #[test]
+    fn test_provider_without_keyword_metadata() {
+        let resolver = KeywordResolver::new();
+        resolver.insert(PROVIDER, KeywordTable::default());
+        let keywords = resolver.resolve(&PROVIDER, 0x0001_0000_0000_0003);
+        assert_eq!(keywords.names, vec!["win:ResponseTime".to_string()]);
+        assert_eq!(keywords.residual, 3);
+        assert_eq!(resolver.resolve(&PROVIDER, 0).to_string(), "0x0");
+    }
+}
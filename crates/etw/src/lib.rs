@@ -1,10 +1,23 @@
+pub mod checkpoint;
+pub mod clock_check;
+pub mod enable_registry;
 pub mod error;
+pub mod failures;
+pub mod keywords;
 pub mod provider;
+pub mod replay;
 pub mod schema;
 pub mod tdh_wrappers;
+pub mod timestamp;
 pub mod trace;
 pub mod trace_session;
 pub mod values;
+pub mod watchdog;
+pub mod well_known;
 pub mod windows;
+#[cfg(feature = "async")]
+pub mod event_stream;
 #[cfg(feature = "serde")]
 pub mod serde;
+#[cfg(feature = "test-util")]
+pub mod mock;
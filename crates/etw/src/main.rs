@@ -43,6 +43,12 @@ fn cli() -> Command {
                     Arg::new("event_id")
                         .long("id")
                         .value_parser(clap::value_parser!(u16)),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Show the raw TDH property information")
+                        .action(clap::ArgAction::SetTrue),
                 ),
         )
         .subcommand(
@@ -65,7 +71,7 @@ fn list_providers() {
     }
 }
 
-fn list_events(provider_guid: &GUID, event_id: Option<u16>) {
+fn list_events(provider_guid: &GUID, event_id: Option<u16>, raw: bool) {
     let event_descriptors = ProviderEventDescriptors::new(provider_guid).unwrap();
 
     println!("List for provider {:?}", provider_guid);
@@ -80,6 +86,30 @@ fn list_events(provider_guid: &GUID, event_id: Option<u16>) {
         println!("{:?}", event_descriptor);
 
         let trace_event_info = TraceEventInfo::from_provider_guid(provider_guid, event_descriptor.data()).unwrap();
+        let names = [
+            ("Event", trace_event_info.event_name_string()),
+            ("Task", trace_event_info.task_name_string()),
+            ("Level", trace_event_info.level_name_string()),
+            ("Channel", trace_event_info.channel_name_string()),
+        ];
+        for (label, name) in names {
+            if let Some(name) = name {
+                println!("  {}: {}", label, name.trim_end());
+            }
+        }
+        let keywords = trace_event_info.keyword_names();
+        if !keywords.is_empty() {
+            println!("  Keywords: {}", keywords.join(", "));
+        }
+        if let Some(message) = trace_event_info.event_message_string() {
+            println!("  Message: {}", message.trim_end());
+        }
+        if raw {
+            for property in trace_event_info.raw_properties() {
+                println!("    {:?}", property);
+            }
+            continue;
+        }
         let event_info = EventInfo::parse(&trace_event_info, None).unwrap();
         
         for property in event_info.properties.fields {
@@ -106,6 +136,7 @@ fn main() {
             list_events(
                 events_args.get_one::<GUID>("provider").unwrap(),
                 events_args.get_one::<u16>("event_id").copied(),
+                events_args.get_flag("raw"),
             );
         }
         Some(("fieldinfo", fieldinfo_args)) => {
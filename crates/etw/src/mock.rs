@@ -0,0 +1,236 @@
+//! Traces fed from synthetic events instead of ETW, to test handlers without a session.
+//!
+//! Build records with [`EventRecordBuilder`], collect them in a [`MockEventSource`] and
+//! open the trace with [`crate::trace::TraceBuilder::mock`] and
+//! [`crate::trace::TraceBuilder::open_mock`]. The records are delivered on a
+//! background thread through the same callbacks `ProcessTrace` calls, so decoding,
+//! checkpoints, buffer predicates, replay pacing and failure capture behave like for
+//! a real trace.
+
+use std::{
+    ffi::c_void,
+    fmt,
+    sync::{atomic::Ordering, Arc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use windows::{
+    core::GUID,
+    Win32::System::Diagnostics::Etw::{EVENT_HEADER, EVENT_RECORD, EVENT_TRACE_LOGFILEW},
+};
+
+use crate::{
+    error::TraceError,
+    schema::cache::{EventInfo, SchemaCache},
+    timestamp::system_time_to_ticks,
+    trace::{buffer_handler, event_record_handler, HandlerData},
+};
+
+const DEFAULT_EVENTS_PER_BUFFER: usize = 16;
+
+/// A synthetic event record with its payload.
+#[derive(Clone)]
+pub struct MockRecord {
+    header: EVENT_HEADER,
+    userdata: Vec<u8>,
+}
+
+impl fmt::Debug for MockRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockRecord")
+            .field("provider", &self.header.ProviderId)
+            .field("id", &self.header.EventDescriptor.Id)
+            .field("version", &self.header.EventDescriptor.Version)
+            .field("timestamp", &self.header.TimeStamp)
+            .field("userdata_len", &self.userdata.len())
+            .finish()
+    }
+}
+
+impl MockRecord {
+    pub fn header(&self) -> &EVENT_HEADER {
+        &self.header
+    }
+
+    pub fn userdata(&self) -> &[u8] {
+        &self.userdata
+    }
+}
+
+pub struct EventRecordBuilder {
+    record: MockRecord,
+}
+
+impl EventRecordBuilder {
+    pub fn new(provider: GUID, id: u16, version: u8) -> Self {
+        let mut header = EVENT_HEADER::default();
+        header.ProviderId = provider;
+        header.EventDescriptor.Id = id;
+        header.EventDescriptor.Version = version;
+        Self {
+            record: MockRecord {
+                header,
+                userdata: Vec::new(),
+            },
+        }
+    }
+
+    pub fn opcode(mut self, opcode: u8) -> Self {
+        self.record.header.EventDescriptor.Opcode = opcode;
+        self
+    }
+
+    pub fn level(mut self, level: u8) -> Self {
+        self.record.header.EventDescriptor.Level = level;
+        self
+    }
+
+    pub fn keywords(mut self, keywords: u64) -> Self {
+        self.record.header.EventDescriptor.Keyword = keywords;
+        self
+    }
+
+    pub fn process_id(mut self, process_id: u32) -> Self {
+        self.record.header.ProcessId = process_id;
+        self
+    }
+
+    pub fn thread_id(mut self, thread_id: u32) -> Self {
+        self.record.header.ThreadId = thread_id;
+        self
+    }
+
+    pub fn activity_id(mut self, activity_id: GUID) -> Self {
+        self.record.header.ActivityId = activity_id;
+        self
+    }
+
+    /// Timestamp in 100ns ticks since 1601, like the timestamps of file traces.
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.record.header.TimeStamp = timestamp;
+        self
+    }
+
+    pub fn system_time(self, time: SystemTime) -> Self {
+        self.timestamp(system_time_to_ticks(time))
+    }
+
+    pub fn userdata(mut self, userdata: impl Into<Vec<u8>>) -> Self {
+        self.record.userdata = userdata.into();
+        self
+    }
+
+    pub fn build(self) -> MockRecord {
+        self.record
+    }
+}
+
+/// Events and schemas for a mock trace.
+#[derive(Debug, Clone)]
+pub struct MockEventSource {
+    records: Vec<MockRecord>,
+    schemas: Vec<EventInfo>,
+    events_per_buffer: usize,
+    pacing: Option<Duration>,
+}
+
+impl Default for MockEventSource {
+    fn default() -> Self {
+        Self {
+            records: Vec::new(),
+            schemas: Vec::new(),
+            events_per_buffer: DEFAULT_EVENTS_PER_BUFFER,
+            pacing: None,
+        }
+    }
+}
+
+impl MockEventSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(mut self, record: MockRecord) -> Self {
+        self.records.push(record);
+        self
+    }
+
+    pub fn records(mut self, records: impl IntoIterator<Item = MockRecord>) -> Self {
+        self.records.extend(records);
+        self
+    }
+
+    /// Use `schema` to decode events of its provider, id and version instead of
+    /// looking it up with TDH. It's added to the trace's schema cache when the trace is
+    /// opened; mock traces get a cache of their own unless one is set with
+    /// [`crate::trace::TraceBuilder::schema_cache`].
+    pub fn schema(mut self, schema: EventInfo) -> Self {
+        self.schemas.push(schema);
+        self
+    }
+
+    /// Number of events after which the buffer callback is called. Defaults to 16.
+    pub fn events_per_buffer(mut self, events_per_buffer: usize) -> Self {
+        self.events_per_buffer = events_per_buffer.max(1);
+        self
+    }
+
+    /// Wait between events, e.g. to test stopping a trace while it's processing.
+    pub fn pacing(mut self, pacing: Duration) -> Self {
+        self.pacing = Some(pacing);
+        self
+    }
+
+    pub(crate) fn inject_schemas(&mut self, cache: &SchemaCache) {
+        for schema in self.schemas.drain(..) {
+            cache.insert(schema.provider_guid, schema.event_id, schema);
+        }
+    }
+
+    /// Deliver the records like `ProcessTrace` does.
+    pub(crate) fn process(
+        self,
+        handler_data: Arc<HandlerData>,
+        start: Option<SystemTime>,
+        end: Option<SystemTime>,
+    ) -> Result<(), TraceError> {
+        let start = start.map(system_time_to_ticks);
+        let end = end.map(system_time_to_ticks);
+        let context = Arc::as_ptr(&handler_data) as *mut c_void;
+        let mut logfile = EVENT_TRACE_LOGFILEW {
+            Context: context,
+            ..Default::default()
+        };
+
+        for buffer in self.records.chunks(self.events_per_buffer) {
+            for record in buffer {
+                if handler_data.stop_trace.load(Ordering::Acquire) {
+                    return Ok(());
+                }
+                let timestamp = record.header.TimeStamp;
+                if start.is_some_and(|start| timestamp < start) || end.is_some_and(|end| timestamp > end) {
+                    continue;
+                }
+                let mut userdata = record.userdata.clone();
+                let mut event_record = EVENT_RECORD {
+                    EventHeader: record.header,
+                    UserDataLength: userdata.len().try_into().map_err(|_| {
+                        TraceError::Configuration(format!("Mock record {:?} has too much user data", record))
+                    })?,
+                    UserData: userdata.as_mut_ptr() as *mut c_void,
+                    UserContext: context,
+                    ..Default::default()
+                };
+                unsafe { event_record_handler(&mut event_record) };
+                if let Some(pacing) = self.pacing {
+                    thread::sleep(pacing);
+                }
+            }
+            if unsafe { buffer_handler(&mut logfile) } == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
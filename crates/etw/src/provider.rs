@@ -1,8 +1,14 @@
+use std::{ffi::c_void, iter, mem::size_of};
+
 use windows::{
-    core::GUID,
-    Win32::System::Diagnostics::Etw::{
-        TRACE_LEVEL_CRITICAL, TRACE_LEVEL_ERROR, TRACE_LEVEL_INFORMATION, TRACE_LEVEL_NONE,
-        TRACE_LEVEL_VERBOSE, TRACE_LEVEL_WARNING,
+    core::{GUID, HRESULT},
+    Win32::{
+        Foundation::{ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS, ERROR_WMI_GUID_NOT_FOUND},
+        System::Diagnostics::Etw::{
+            EnumerateTraceGuidsEx, TraceGuidQueryInfo, TRACE_ENABLE_INFO, TRACE_GUID_INFO,
+            TRACE_LEVEL_CRITICAL, TRACE_LEVEL_ERROR, TRACE_LEVEL_INFORMATION, TRACE_LEVEL_NONE,
+            TRACE_LEVEL_VERBOSE, TRACE_LEVEL_WARNING, TRACE_PROVIDER_INSTANCE_INFO,
+        },
     },
 };
 
@@ -20,12 +26,110 @@ mod constants {
     pub const PROCESS_GUID:            GUID = GUID::from_u128(0x3d6fa8d0fe0511d09dda00c04fd7ba7c);
     pub const REGISTRY_GUID:           GUID = GUID::from_u128(0xae53722ec86311d2865900c04fa321a1);
     pub const SPLIT_IO_GUID:           GUID = GUID::from_u128(0xd837ca9212b944a5ad6a3a65b3578aa8);
+    pub const SYSTEM_TRACE_CONTROL_GUID: GUID = GUID::from_u128(0x9e814aad320411d29a82006008a86939);
     pub const TCP_IP_GUID:             GUID = GUID::from_u128(0x9a280ac0c8e011d184e200c04fb998a2);
     pub const THREAD_GUID:             GUID = GUID::from_u128(0x3d6fa8d1fe0511d09dda00c04fd7ba7c);
 }
 
 pub use constants::*;
 
+/// Windows 11 system providers, enabled with `EnableTraceEx2` on a session started with
+/// [`crate::trace_session::TraceSessionBuilder::system_logger`].
+#[rustfmt::skip]
+mod system_constants {
+    use windows::core::GUID;
+
+    pub const SYSTEM_ALPC_PROVIDER_GUID:      GUID = GUID::from_u128(0xfcb9baafe529498092e9ced1a6aadfdf);
+    pub const SYSTEM_CONFIG_PROVIDER_GUID:    GUID = GUID::from_u128(0xfef3a8b6318d4b67a96a3b0f6b8f18fe);
+    pub const SYSTEM_CPU_PROVIDER_GUID:       GUID = GUID::from_u128(0xc6c5265feae84650aae49d48603d8510);
+    pub const SYSTEM_HYPERVISOR_PROVIDER_GUID: GUID = GUID::from_u128(0xbafa072a918a4bedb622bc152097098f);
+    pub const SYSTEM_INTERRUPT_PROVIDER_GUID: GUID = GUID::from_u128(0xd4bbee17b5454888858b744169015b25);
+    pub const SYSTEM_IO_PROVIDER_GUID:        GUID = GUID::from_u128(0x3d5c43e30f1c4202b817174c0070dc79);
+    pub const SYSTEM_LOCK_PROVIDER_GUID:      GUID = GUID::from_u128(0x721ddfd3dacc4e1eb26aa2cb31d4705a);
+    pub const SYSTEM_MEMORY_PROVIDER_GUID:    GUID = GUID::from_u128(0x82958ca9b6cd47f8a3a803ae85a4bc24);
+    pub const SYSTEM_OBJECT_PROVIDER_GUID:    GUID = GUID::from_u128(0xfebd74603d1d47ebaf49c9eeb1e146f2);
+    pub const SYSTEM_POWER_PROVIDER_GUID:     GUID = GUID::from_u128(0xc134884a32d5448880e514ed7abb8269);
+    pub const SYSTEM_PROCESS_PROVIDER_GUID:   GUID = GUID::from_u128(0x151f55dc467d471f83b55f889d46ff66);
+    pub const SYSTEM_PROFILE_PROVIDER_GUID:   GUID = GUID::from_u128(0xbfeb03241cee496fa4092ac2b48a6322);
+    pub const SYSTEM_REGISTRY_PROVIDER_GUID:  GUID = GUID::from_u128(0x16156bd9fab44cfaa23289d1099058e3);
+    pub const SYSTEM_SCHEDULER_PROVIDER_GUID: GUID = GUID::from_u128(0x599a2a764d9149109ac77d33f2e97a6c);
+    pub const SYSTEM_SYSCALL_PROVIDER_GUID:   GUID = GUID::from_u128(0x434286f76f1b45bbb37e95f623046c7c);
+    pub const SYSTEM_TIMER_PROVIDER_GUID:     GUID = GUID::from_u128(0x4f061568e215499fab2eeda0ae890a5b);
+
+    pub const SYSTEM_PROVIDERS: &[GUID] = &[
+        SYSTEM_ALPC_PROVIDER_GUID,
+        SYSTEM_CONFIG_PROVIDER_GUID,
+        SYSTEM_CPU_PROVIDER_GUID,
+        SYSTEM_HYPERVISOR_PROVIDER_GUID,
+        SYSTEM_INTERRUPT_PROVIDER_GUID,
+        SYSTEM_IO_PROVIDER_GUID,
+        SYSTEM_LOCK_PROVIDER_GUID,
+        SYSTEM_MEMORY_PROVIDER_GUID,
+        SYSTEM_OBJECT_PROVIDER_GUID,
+        SYSTEM_POWER_PROVIDER_GUID,
+        SYSTEM_PROCESS_PROVIDER_GUID,
+        SYSTEM_PROFILE_PROVIDER_GUID,
+        SYSTEM_REGISTRY_PROVIDER_GUID,
+        SYSTEM_SCHEDULER_PROVIDER_GUID,
+        SYSTEM_SYSCALL_PROVIDER_GUID,
+        SYSTEM_TIMER_PROVIDER_GUID,
+    ];
+
+    pub const SYSTEM_PROCESS_KW_GENERAL:        u64 = 0x0000_0001;
+    pub const SYSTEM_PROCESS_KW_INSWAP:         u64 = 0x0000_0002;
+    pub const SYSTEM_PROCESS_KW_FREEZE:         u64 = 0x0000_0004;
+    pub const SYSTEM_PROCESS_KW_PERF_COUNTER:   u64 = 0x0000_0008;
+    pub const SYSTEM_PROCESS_KW_WAKE_COUNTER:   u64 = 0x0000_0010;
+    pub const SYSTEM_PROCESS_KW_WAKE_DROP:      u64 = 0x0000_0020;
+    pub const SYSTEM_PROCESS_KW_WAKE_EVENT:     u64 = 0x0000_0040;
+    pub const SYSTEM_PROCESS_KW_DEBUG_EVENTS:   u64 = 0x0000_0080;
+    pub const SYSTEM_PROCESS_KW_DBGPRINT:       u64 = 0x0000_0100;
+    pub const SYSTEM_PROCESS_KW_JOB:            u64 = 0x0000_0200;
+    pub const SYSTEM_PROCESS_KW_WORKER_THREAD:  u64 = 0x0000_0400;
+    pub const SYSTEM_PROCESS_KW_THREAD:         u64 = 0x0000_0800;
+    pub const SYSTEM_PROCESS_KW_LOADER:         u64 = 0x0000_1000;
+
+    pub const SYSTEM_IO_KW_DISK:                u64 = 0x0000_0001;
+    pub const SYSTEM_IO_KW_DISK_INIT:           u64 = 0x0000_0002;
+    pub const SYSTEM_IO_KW_FILENAME:            u64 = 0x0000_0004;
+    pub const SYSTEM_IO_KW_SPLIT:               u64 = 0x0000_0008;
+    pub const SYSTEM_IO_KW_FILE:                u64 = 0x0000_0010;
+    pub const SYSTEM_IO_KW_OPTICAL:             u64 = 0x0000_0020;
+    pub const SYSTEM_IO_KW_OPTICAL_INIT:        u64 = 0x0000_0040;
+    pub const SYSTEM_IO_KW_DRIVERS:             u64 = 0x0000_0080;
+    pub const SYSTEM_IO_KW_CC:                  u64 = 0x0000_0100;
+    pub const SYSTEM_IO_KW_NETWORK:             u64 = 0x0000_0200;
+
+    pub const SYSTEM_MEMORY_KW_GENERAL:         u64 = 0x0000_0001;
+    pub const SYSTEM_MEMORY_KW_HARD_FAULTS:     u64 = 0x0000_0002;
+    pub const SYSTEM_MEMORY_KW_ALL_FAULTS:      u64 = 0x0000_0004;
+    pub const SYSTEM_MEMORY_KW_POOL:            u64 = 0x0000_0008;
+    pub const SYSTEM_MEMORY_KW_MEMINFO:         u64 = 0x0000_0010;
+    pub const SYSTEM_MEMORY_KW_PFSECTION:       u64 = 0x0000_0020;
+    pub const SYSTEM_MEMORY_KW_MEMINFO_WS:      u64 = 0x0000_0040;
+    pub const SYSTEM_MEMORY_KW_HEAP:            u64 = 0x0000_0080;
+    pub const SYSTEM_MEMORY_KW_WS:              u64 = 0x0000_0100;
+    pub const SYSTEM_MEMORY_KW_CONTMEM_GEN:     u64 = 0x0000_0200;
+    pub const SYSTEM_MEMORY_KW_VIRTUAL_ALLOC:   u64 = 0x0000_0400;
+    pub const SYSTEM_MEMORY_KW_FOOTPRINT:       u64 = 0x0000_0800;
+    pub const SYSTEM_MEMORY_KW_SESSION:         u64 = 0x0000_1000;
+    pub const SYSTEM_MEMORY_KW_REFSET:          u64 = 0x0000_2000;
+    pub const SYSTEM_MEMORY_KW_VAMAP:           u64 = 0x0000_4000;
+    pub const SYSTEM_MEMORY_KW_NONTRADEABLE:    u64 = 0x0000_8000;
+}
+
+pub use system_constants::*;
+
+/// Whether `guid` is one of the [`SYSTEM_PROVIDERS`].
+pub fn is_system_provider(guid: &GUID) -> bool {
+    SYSTEM_PROVIDERS.contains(guid)
+}
+
+use crate::{error::TraceError, tdh_wrappers::{ProviderMetadata, Providers}};
+
+/// Default limit for the number of providers a [`ProviderSpec::NameGlob`] may match.
+pub const DEFAULT_MAX_GLOB_MATCHES: usize = 16;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct TraceLevel(u8);
@@ -68,6 +172,30 @@ impl ProviderBuilder {
         }
     }
 
+    /// A provider registered under `name`, compared case-insensitively, e.g.
+    /// `Microsoft-Windows-Kernel-Process`.
+    ///
+    /// Enumerates the registered providers, so prefer [`ProviderBuilder::from_guid`]
+    /// when the GUID is known. Fails if no provider has the name.
+    pub fn from_name(name: &str) -> Result<Self, TraceError> {
+        let providers = Providers::new()?;
+        let guid = providers.find_by_name(name).ok_or_else(|| {
+            TraceError::Configuration(format!("No provider named {:?} is registered", name))
+        })?;
+        Ok(Self::from_guid(&guid))
+    }
+
+    /// One of the [`SYSTEM_PROVIDERS`], e.g. [`SYSTEM_PROCESS_PROVIDER_GUID`], with the
+    /// provider specific `keywords` such as [`SYSTEM_PROCESS_KW_GENERAL`].
+    ///
+    /// Fails if `id` isn't a system provider.
+    pub fn system_provider(id: &GUID, keywords: u64) -> Result<Self, TraceError> {
+        if !is_system_provider(id) {
+            return Err(TraceError::Configuration(format!("{:?} is not a system provider", id)));
+        }
+        Ok(Self::from_guid(id).any(keywords))
+    }
+
     pub fn any(mut self, any: u64) -> Self {
         self.any = any;
         self
@@ -117,4 +245,446 @@ impl Provider {
     pub fn all(&self) -> u64 {
         self.all
     }
+
+    /// The keywords, levels, channels, tasks and opcodes in the provider's manifest.
+    pub fn metadata(&self) -> Result<ProviderMetadata, TraceError> {
+        ProviderMetadata::new(&self.id)
+    }
+}
+
+/// How a session has a provider enabled, see [`query_provider_sessions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProviderSessionInfo {
+    /// Id of the session, the low 16 bits of its control handle.
+    pub logger_id: u16,
+    pub level: TraceLevel,
+    pub any: u64,
+    pub all: u64,
+    /// The `EVENT_ENABLE_PROPERTY_*` flags the provider was enabled with.
+    pub enable_property: u32,
+}
+
+impl From<&TRACE_ENABLE_INFO> for ProviderSessionInfo {
+    fn from(info: &TRACE_ENABLE_INFO) -> Self {
+        Self {
+            logger_id: info.LoggerId,
+            level: TraceLevel::from(info.Level),
+            any: info.MatchAnyKeyword,
+            all: info.MatchAllKeyword,
+            enable_property: info.EnableProperty,
+        }
+    }
+}
+
+/// The sessions that currently have `provider` enabled.
+///
+/// Each session is reported once, even if several processes registered the provider.
+/// Returns an empty list for providers that are neither registered nor enabled.
+pub fn query_provider_sessions(provider: &GUID) -> Result<Vec<ProviderSessionInfo>, TraceError> {
+    let mut buffer = Vec::new();
+    loop {
+        let mut size = 0;
+        let status = unsafe {
+            EnumerateTraceGuidsEx(
+                TraceGuidQueryInfo,
+                Some(provider as *const GUID as *const c_void),
+                size_of::<GUID>() as u32,
+                (!buffer.is_empty()).then_some(buffer.as_mut_ptr() as *mut c_void),
+                u32::try_from(buffer.len()).unwrap(),
+                &mut size,
+            )
+        };
+        match status {
+            ERROR_SUCCESS => {
+                buffer.truncate(size as usize);
+                return Ok(parse_provider_sessions(&buffer));
+            }
+            ERROR_INSUFFICIENT_BUFFER => buffer.resize(size as usize, 0),
+            ERROR_WMI_GUID_NOT_FOUND => return Ok(Vec::new()),
+            status => return Err(windows::core::Error::from(HRESULT::from_win32(status.0)).into()),
+        }
+    }
+}
+
+/// Parse the `TRACE_GUID_INFO` returned for `TraceGuidQueryInfo`.
+///
+/// It is followed by one `TRACE_PROVIDER_INSTANCE_INFO` per registration, each of them
+/// followed by one `TRACE_ENABLE_INFO` per session enabling it.
+fn parse_provider_sessions(buffer: &[u8]) -> Vec<ProviderSessionInfo> {
+    let mut sessions: Vec<ProviderSessionInfo> = Vec::new();
+    let Some(guid_info) = read_unaligned::<TRACE_GUID_INFO>(buffer, 0) else {
+        return sessions;
+    };
+    let mut offset = size_of::<TRACE_GUID_INFO>();
+    for _ in 0..guid_info.InstanceCount {
+        let Some(instance) = read_unaligned::<TRACE_PROVIDER_INSTANCE_INFO>(buffer, offset) else {
+            log::warn!("Provider instance info at offset {} is truncated", offset);
+            break;
+        };
+        let enable_infos = offset + size_of::<TRACE_PROVIDER_INSTANCE_INFO>();
+        for idx in 0..instance.EnableCount as usize {
+            let Some(info) = read_unaligned::<TRACE_ENABLE_INFO>(buffer, enable_infos + idx * size_of::<TRACE_ENABLE_INFO>())
+            else {
+                log::warn!("Enable info {} of provider instance at offset {} is truncated", idx, offset);
+                break;
+            };
+            if info.IsEnabled != 0 && !sessions.iter().any(|session| session.logger_id == info.LoggerId) {
+                sessions.push(ProviderSessionInfo::from(&info));
+            }
+        }
+        if instance.NextOffset == 0 {
+            break;
+        }
+        offset += instance.NextOffset as usize;
+    }
+    sessions
+}
+
+fn read_unaligned<T: Copy>(buffer: &[u8], offset: usize) -> Option<T> {
+    let bytes = buffer.get(offset..offset.checked_add(size_of::<T>())?)?;
+    Some(unsafe { (bytes.as_ptr() as *const T).read_unaligned() })
+}
+
+/// Returns true if the provider name `name` is `wanted`, ignoring case.
+pub(crate) fn name_matches(name: &str, wanted: &str) -> bool {
+    name.to_lowercase() == wanted.to_lowercase()
+}
+
+/// Returns true if `name` matches `pattern`, ignoring case.
+///
+/// `*` matches any sequence of characters, including none, and `?` matches exactly
+/// one character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    fn eq(a: char, b: char) -> bool {
+        a == b || a.to_lowercase().eq(b.to_lowercase())
+    }
+
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it's matched up to
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some(&c) if c == '?' || eq(c, name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// A provider to enable, as given in a configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum ProviderSpec {
+    Guid {
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde::guid::serialize", deserialize_with = "crate::serde::guid::deserialize"))]
+        #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+        guid: GUID,
+        #[cfg_attr(feature = "serde", serde(default = "default_level"))]
+        level: u8,
+        #[cfg_attr(feature = "serde", serde(default))]
+        keywords: u64,
+    },
+    /// All registered providers whose name matches a pattern, see [`glob_match`].
+    NameGlob {
+        name_glob: String,
+        #[cfg_attr(feature = "serde", serde(default = "default_level"))]
+        level: u8,
+        #[cfg_attr(feature = "serde", serde(default))]
+        keywords: u64,
+        /// Maximum number of providers the pattern may match, [`DEFAULT_MAX_GLOB_MATCHES`]
+        /// if not set.
+        #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+        max_matches: Option<usize>,
+    },
+}
+
+#[cfg(feature = "serde")]
+fn default_level() -> u8 {
+    TraceLevel::VERBOSE.into()
+}
+
+impl ProviderSpec {
+    /// Resolve the spec against the providers registered on this system.
+    pub fn resolve(&self) -> Result<Vec<Provider>, TraceError> {
+        match self {
+            Self::Guid { .. } => self.expand(iter::empty()),
+            Self::NameGlob { .. } => {
+                let providers = Providers::new()?;
+                self.expand(
+                    providers
+                        .iter()
+                        .map(|provider| (provider.guid(), provider.name().to_string_lossy().into_owned())),
+                )
+            }
+        }
+    }
+
+    /// Resolve the spec against a list of provider GUIDs and names.
+    ///
+    /// A glob matching more providers than allowed is a configuration error listing the
+    /// matches, so an overly broad pattern doesn't enable lots of providers by accident.
+    pub fn expand(&self, known: impl IntoIterator<Item = (GUID, String)>) -> Result<Vec<Provider>, TraceError> {
+        match self {
+            Self::Guid { guid, level, keywords } => Ok(vec![
+                ProviderBuilder::from_guid(guid).any(*keywords).level((*level).into()).build(),
+            ]),
+            Self::NameGlob { name_glob, level, keywords, max_matches } => {
+                let max_matches = max_matches.unwrap_or(DEFAULT_MAX_GLOB_MATCHES);
+                let mut matches = known
+                    .into_iter()
+                    .filter(|(_, name)| glob_match(name_glob, name))
+                    .collect::<Vec<_>>();
+                matches.sort_by(|(_, a), (_, b)| a.cmp(b));
+                if matches.len() > max_matches {
+                    let names = matches.iter().map(|(_, name)| name.as_str()).collect::<Vec<_>>();
+                    return Err(TraceError::Configuration(format!(
+                        "Provider pattern {:?} matches {} providers, more than the maximum of {}: {}",
+                        name_glob,
+                        matches.len(),
+                        max_matches,
+                        names.join(", ")
+                    )));
+                }
+                if matches.is_empty() {
+                    log::warn!("Provider pattern {:?} doesn't match any provider", name_glob);
+                }
+                Ok(matches
+                    .iter()
+                    .map(|(guid, name)| {
+                        log::info!("Provider pattern {:?} matched {} ({:?})", name_glob, name, guid);
+                        ProviderBuilder::from_guid(guid).any(*keywords).level((*level).into()).build()
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use windows::{
+        core::GUID,
+        Win32::System::Diagnostics::Etw::{TRACE_ENABLE_INFO, TRACE_GUID_INFO, TRACE_PROVIDER_INSTANCE_INFO},
+    };
+
+    use crate::{error::TraceError, tdh_wrappers::ProviderMetadata, well_known::KERNEL_PROCESS_PROVIDER};
+
+    use super::{
+        glob_match, name_matches, parse_provider_sessions, ProviderBuilder, ProviderSpec, TraceLevel, DEFAULT_MAX_GLOB_MATCHES,
+        SYSTEM_PROCESS_KW_GENERAL, SYSTEM_PROCESS_PROVIDER_GUID,
+    };
+
+    fn known_providers() -> Vec<(GUID, String)> {
+        [
+            "Microsoft-Windows-DNS-Client",
+            "Microsoft-Windows-DNSServer",
+            "Microsoft-Windows-DNS-Server-Service",
+            "Microsoft-Windows-Kernel-Process",
+            "Microsoft-Windows-WinINet",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| (GUID::from_u128(idx as u128 + 1), name.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("Microsoft-Windows-DNS-*", "Microsoft-Windows-DNS-Client"));
+        assert!(!glob_match("Microsoft-Windows-DNS-*", "Microsoft-Windows-DNSServer"));
+        assert!(glob_match("microsoft-windows-dns-*", "Microsoft-Windows-DNS-Client"));
+        assert!(glob_match("*Kernel*", "Microsoft-Windows-Kernel-Process"));
+        assert!(glob_match("Microsoft-Windows-Win?Net", "Microsoft-Windows-WinINet"));
+        assert!(!glob_match("Microsoft-Windows-Win?Net", "Microsoft-Windows-WinNet"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(!glob_match("", "a"));
+    }
+
+    #[test]
+    fn test_name_matches_ignores_case() {
+        assert!(name_matches("Microsoft-Windows-Kernel-Process", "microsoft-windows-kernel-process"));
+        assert!(name_matches("Ünicode-Provider", "ÜNICODE-PROVIDER"));
+        assert!(!name_matches("Microsoft-Windows-Kernel-Process", "Microsoft-Windows-Kernel-*"));
+        assert!(!name_matches("Microsoft-Windows-DNS-Client", "Microsoft-Windows-DNS"));
+    }
+
+    #[test]
+    fn test_from_name_resolves_registered_provider() {
+        let builder = ProviderBuilder::from_name("microsoft-windows-kernel-process").unwrap();
+        assert_eq!(builder.id, KERNEL_PROCESS_PROVIDER);
+        match ProviderBuilder::from_name("etw-rs-no-such-provider") {
+            Err(TraceError::Configuration(message)) => assert!(message.contains("etw-rs-no-such-provider"), "{message}"),
+            _ => panic!("resolved an unregistered provider"),
+        }
+    }
+
+    fn bytes_of<T>(value: &T) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+    }
+
+    #[test]
+    fn test_parse_provider_sessions_skips_disabled_and_repeated_sessions() {
+        let enable_info = |logger_id: u16, is_enabled: u32| TRACE_ENABLE_INFO {
+            IsEnabled: is_enabled,
+            Level: u8::from(TraceLevel::INFORMATION),
+            LoggerId: logger_id,
+            MatchAnyKeyword: 0x30,
+            MatchAllKeyword: 0x10,
+            ..Default::default()
+        };
+        let instance = |enable_count: u32, next_offset: u32| TRACE_PROVIDER_INSTANCE_INFO {
+            NextOffset: next_offset,
+            EnableCount: enable_count,
+            Pid: 4,
+            Flags: 0,
+        };
+        let instance_size = size_of::<TRACE_PROVIDER_INSTANCE_INFO>() + 2 * size_of::<TRACE_ENABLE_INFO>();
+
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(bytes_of(&TRACE_GUID_INFO { InstanceCount: 2, Reserved: 0 }));
+        buffer.extend_from_slice(bytes_of(&instance(2, instance_size as u32)));
+        buffer.extend_from_slice(bytes_of(&enable_info(3, 1)));
+        buffer.extend_from_slice(bytes_of(&enable_info(5, 0)));
+        buffer.extend_from_slice(bytes_of(&instance(2, 0)));
+        buffer.extend_from_slice(bytes_of(&enable_info(3, 1)));
+        buffer.extend_from_slice(bytes_of(&enable_info(7, 1)));
+
+        let sessions = parse_provider_sessions(&buffer);
+        assert_eq!(sessions.iter().map(|session| session.logger_id).collect::<Vec<_>>(), vec![3, 7]);
+        assert_eq!(sessions[0].level, TraceLevel::INFORMATION);
+        assert_eq!((sessions[0].any, sessions[0].all), (0x30, 0x10));
+
+        // Truncated buffers yield the sessions before the cut
+        let sessions = parse_provider_sessions(&buffer[..buffer.len() - 1]);
+        assert_eq!(sessions.iter().map(|session| session.logger_id).collect::<Vec<_>>(), vec![3]);
+        assert!(parse_provider_sessions(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_metadata_of_manifest_provider() {
+        let metadata = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER).build().metadata().unwrap();
+        // WINEVENT_KEYWORD_PROCESS
+        let (name, _description) = &metadata.keywords[&0x10];
+        assert_eq!(name, "WINEVENT_KEYWORD_PROCESS");
+        assert!(!metadata.tasks.is_empty());
+
+        let unknown = ProviderBuilder::from_guid(&GUID::from_u128(0x5f0e3c1b_8d2a_4b7e_9c6f_1a2b3c4d5e6f)).build();
+        assert_eq!(unknown.metadata().unwrap(), ProviderMetadata::default());
+    }
+
+    #[test]
+    fn test_system_provider_rejects_other_providers() {
+        let provider = ProviderBuilder::system_provider(&SYSTEM_PROCESS_PROVIDER_GUID, SYSTEM_PROCESS_KW_GENERAL)
+            .unwrap()
+            .build();
+        assert_eq!(*provider.id(), SYSTEM_PROCESS_PROVIDER_GUID);
+        assert_eq!(provider.any(), SYSTEM_PROCESS_KW_GENERAL);
+        match ProviderBuilder::system_provider(&KERNEL_PROCESS_PROVIDER, SYSTEM_PROCESS_KW_GENERAL) {
+            Err(TraceError::Configuration(message)) => assert!(message.contains("not a system provider"), "{message}"),
+            _ => panic!("accepted a regular provider as system provider"),
+        }
+    }
+
+    #[test]
+    fn test_glob_match_unicode() {
+        assert!(glob_match("Ünicode-*", "ünicode-Provider"));
+        assert!(!glob_match("Straße-?", "STRASSE-1"));
+        assert!(glob_match("Σ*", "σίγμα"));
+        assert!(glob_match("??-Provider", "日本-Provider"));
+    }
+
+    #[test]
+    fn test_name_glob_expands_to_matches() {
+        let spec = ProviderSpec::NameGlob {
+            name_glob: "Microsoft-Windows-DNS*".to_string(),
+            level: TraceLevel::INFORMATION.into(),
+            keywords: 0x8000,
+            max_matches: None,
+        };
+        let providers = spec.expand(known_providers()).unwrap();
+        assert_eq!(
+            providers.iter().map(|provider| provider.id().to_u128()).collect::<Vec<_>>(),
+            vec![1, 3, 2]
+        );
+        assert!(providers
+            .iter()
+            .all(|provider| provider.any() == 0x8000 && provider.level() == TraceLevel::INFORMATION));
+    }
+
+    #[test]
+    fn test_name_glob_over_limit_is_configuration_error() {
+        let spec = ProviderSpec::NameGlob {
+            name_glob: "Microsoft-*".to_string(),
+            level: TraceLevel::VERBOSE.into(),
+            keywords: 0,
+            max_matches: Some(2),
+        };
+        match spec.expand(known_providers()) {
+            Err(TraceError::Configuration(message)) => {
+                assert!(message.contains("matches 5 providers"), "{message}");
+                assert!(message.contains("Microsoft-Windows-WinINet"), "{message}");
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let many = (0..=DEFAULT_MAX_GLOB_MATCHES as u128)
+            .map(|idx| (GUID::from_u128(idx), format!("Provider-{idx}")))
+            .collect::<Vec<_>>();
+        let spec = ProviderSpec::NameGlob {
+            name_glob: "Provider-*".to_string(),
+            level: TraceLevel::VERBOSE.into(),
+            keywords: 0,
+            max_matches: None,
+        };
+        assert!(spec.expand(many).is_err());
+    }
+
+    #[test]
+    fn test_guid_spec_needs_no_lookup() {
+        let guid = GUID::from_u128(0x42);
+        let spec = ProviderSpec::Guid { guid, level: 4, keywords: 1 };
+        let providers = spec.expand(known_providers()).unwrap();
+        assert_eq!(providers.len(), 1);
+        assert_eq!(*providers[0].id(), guid);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_name_glob_from_config() {
+        let spec: ProviderSpec = serde_json::from_str(
+            r#"{"name_glob": "Microsoft-Windows-DNS-*", "level": 4, "keywords": 32768}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            spec,
+            ProviderSpec::NameGlob {
+                name_glob: "Microsoft-Windows-DNS-*".to_string(),
+                level: 4,
+                keywords: 0x8000,
+                max_matches: None,
+            }
+        );
+    }
 }
@@ -0,0 +1,502 @@
+//! Paced replay of recorded traces.
+//!
+//! A [`ReplayDriver`] delays the delivery of events from a file trace so that the
+//! handler sees them with the same spacing as when they were recorded, optionally sped
+//! up. Attach it with [`crate::trace::TraceBuilder::replay`]; the handler set on the
+//! builder is then driven exactly like for a live trace.
+//!
+//! The recorded spacing is taken from the trace's [`TimestampContext`], so traces opened
+//! with [`crate::trace::TraceBuilder::raw_timestamps`] are paced by their session's clock.
+
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::timestamp::TimestampContext;
+
+const DEFAULT_MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// Monotonic time source used for pacing, so the pacing can be tested without sleeping.
+pub trait ReplayClock {
+    /// Time elapsed since an arbitrary, fixed origin.
+    fn now(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    origin: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl ReplayClock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Deliver events with the spacing they were recorded with.
+    Original,
+    /// Divide the recorded spacing by the factor, e.g. 10.0 replays ten times faster.
+    Factor(f64),
+    /// Deliver events as fast as the handler consumes them.
+    Unpaced,
+}
+
+impl ReplaySpeed {
+    fn scale(&self, recorded: Duration) -> Option<Duration> {
+        match *self {
+            ReplaySpeed::Original => Some(recorded),
+            ReplaySpeed::Factor(factor) if factor > 0.0 && factor.is_finite() => {
+                Some(recorded.div_f64(factor))
+            }
+            ReplaySpeed::Factor(_) | ReplaySpeed::Unpaced => None,
+        }
+    }
+}
+
+/// Progress of a replay and how well delivery kept up with the schedule.
+///
+/// Drift is how late an event was delivered compared to when it was due. It grows if
+/// the handler is slower than the replay speed allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayProgress {
+    pub delivered: u64,
+    /// Events dropped because they were before a seek target.
+    pub skipped: u64,
+    pub last_timestamp: Option<i64>,
+    /// Total time spent sleeping to keep to the schedule.
+    pub slept: Duration,
+    pub last_drift: Duration,
+    pub max_drift: Duration,
+    total_drift: Duration,
+}
+
+impl ReplayProgress {
+    pub fn mean_drift(&self) -> Duration {
+        match u32::try_from(self.delivered) {
+            Ok(0) => Duration::ZERO,
+            Ok(delivered) => self.total_drift / delivered,
+            Err(_) => self.total_drift.div_f64(self.delivered as f64),
+        }
+    }
+
+    fn record_drift(&mut self, drift: Duration) {
+        self.last_drift = drift;
+        self.max_drift = self.max_drift.max(drift);
+        self.total_drift += drift;
+    }
+}
+
+#[derive(Debug, Default)]
+struct ControlState {
+    paused: bool,
+    cancelled: bool,
+    seek: Option<i64>,
+    /// Set when the schedule has to be restarted at the next delivered event.
+    rebase: bool,
+    progress: ReplayProgress,
+}
+
+#[derive(Debug, Default)]
+struct ControlShared {
+    state: Mutex<ControlState>,
+    changed: Condvar,
+}
+
+/// Handle to pause, resume and seek a running replay from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayControl {
+    shared: Arc<ControlShared>,
+}
+
+impl ReplayControl {
+    fn lock(&self) -> MutexGuard<'_, ControlState> {
+        self.shared.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Hold back the next event until [`ReplayControl::resume`] is called.
+    pub fn pause(&self) {
+        self.lock().paused = true;
+    }
+
+    /// Continue a paused replay. The time spent paused doesn't count as drift.
+    pub fn resume(&self) {
+        let mut state = self.lock();
+        if state.paused {
+            state.paused = false;
+            state.rebase = true;
+        }
+        self.shared.changed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    /// Skip ahead to the first event at or after `timestamp`.
+    ///
+    /// Skipped events aren't delivered to the handler, and pacing restarts at the
+    /// first event delivered after the seek. ETW reads files sequentially, so seeking
+    /// backwards isn't possible; a target at or before the last delivered event has
+    /// no effect and false is returned.
+    pub fn seek(&self, timestamp: i64) -> bool {
+        let mut state = self.lock();
+        if state.progress.last_timestamp.is_some_and(|last| timestamp <= last) {
+            return false;
+        }
+        state.seek = Some(timestamp);
+        self.shared.changed.notify_all();
+        true
+    }
+
+    pub fn progress(&self) -> ReplayProgress {
+        self.lock().progress
+    }
+
+    /// Stop pacing for good and release a paused replay, e.g. when the trace is
+    /// torn down. The remaining events are delivered without delay.
+    pub(crate) fn cancel(&self) {
+        let mut state = self.lock();
+        state.cancelled = true;
+        state.paused = false;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Paces the delivery of recorded events.
+pub struct ReplayDriver<C = MonotonicClock> {
+    clock: C,
+    speed: ReplaySpeed,
+    max_sleep: Duration,
+    control: ReplayControl,
+    /// Set by the trace the driver is attached to.
+    timestamps: TimestampContext,
+    /// Timestamp and clock time of the event the schedule is anchored at.
+    base: Option<(i64, Duration)>,
+}
+
+impl<C> fmt::Debug for ReplayDriver<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplayDriver")
+            .field("speed", &self.speed)
+            .field("max_sleep", &self.max_sleep)
+            .field("base", &self.base)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReplayDriver {
+    pub fn new(speed: ReplaySpeed) -> Self {
+        Self::with_clock(speed, MonotonicClock::default())
+    }
+}
+
+impl<C: ReplayClock> ReplayDriver<C> {
+    pub fn with_clock(speed: ReplaySpeed, clock: C) -> Self {
+        Self {
+            clock,
+            speed,
+            max_sleep: DEFAULT_MAX_SLEEP,
+            control: ReplayControl::default(),
+            timestamps: TimestampContext::new(),
+            base: None,
+        }
+    }
+
+    /// Upper bound for a single delay, so long idle gaps in a recording don't stall
+    /// the replay. The schedule restarts after a clamped delay, so the clamped time
+    /// isn't reported as drift.
+    pub fn max_sleep(mut self, max_sleep: Duration) -> Self {
+        self.max_sleep = max_sleep;
+        self
+    }
+
+    pub fn control(&self) -> ReplayControl {
+        self.control.clone()
+    }
+
+    /// Interpret event timestamps with `context`, see [`crate::trace::Trace::timestamp_context`].
+    pub(crate) fn set_timestamp_context(&mut self, context: TimestampContext) {
+        self.timestamps = context;
+    }
+
+    /// Wait until the event with `timestamp` is due.
+    ///
+    /// Returns false if the event is before a seek target and must not be delivered.
+    pub fn pace(&mut self, timestamp: i64) -> bool {
+        let mut state = self.control.lock();
+        while state.paused && !state.cancelled {
+            state = self
+                .control
+                .shared
+                .changed
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+        if let Some(target) = state.seek {
+            if timestamp < target {
+                state.progress.skipped += 1;
+                return false;
+            }
+            state.seek = None;
+            state.rebase = true;
+        }
+        if std::mem::take(&mut state.rebase) {
+            self.base = None;
+        }
+        let cancelled = state.cancelled;
+        drop(state);
+
+        let (slept, drift) = if cancelled {
+            (Duration::ZERO, Duration::ZERO)
+        } else {
+            self.wait_until_due(timestamp)
+        };
+
+        let mut state = self.control.lock();
+        let progress = &mut state.progress;
+        progress.delivered += 1;
+        progress.last_timestamp = Some(timestamp);
+        progress.slept += slept;
+        progress.record_drift(drift);
+        true
+    }
+
+    fn wait_until_due(&mut self, timestamp: i64) -> (Duration, Duration) {
+        let now = self.clock.now();
+        let Some((base_timestamp, base_time)) = self.base else {
+            self.base = Some((timestamp, now));
+            return (Duration::ZERO, Duration::ZERO);
+        };
+        // Out of order timestamps are due right away, as are those of a clock that
+        // can't be converted
+        let Some(recorded) = self.timestamps.elapsed(base_timestamp, timestamp) else {
+            return (Duration::ZERO, Duration::ZERO);
+        };
+        let Some(offset) = self.speed.scale(recorded) else {
+            return (Duration::ZERO, Duration::ZERO);
+        };
+        let due = base_time + offset;
+        if now >= due {
+            return (Duration::ZERO, now - due);
+        }
+
+        let delay = due - now;
+        let sleep = delay.min(self.max_sleep);
+        self.clock.sleep(sleep);
+        let delivered = self.clock.now();
+        if sleep < delay {
+            self.base = Some((timestamp, delivered));
+            (sleep, Duration::ZERO)
+        } else {
+            (sleep, delivered.saturating_sub(due))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    use crate::{timestamp::TimestampContext, trace_session::ClockResolution};
+
+    use super::{ReplayClock, ReplayDriver, ReplaySpeed};
+
+    const TICKS_PER_MILLISECOND: i64 = 10_000;
+
+    /// Clock that only advances by sleeping, plus whatever the handler "costs".
+    #[derive(Clone, Default)]
+    struct MockClock {
+        now: Rc<Cell<Duration>>,
+        sleeps: Rc<Cell<Vec<Duration>>>,
+    }
+
+    impl MockClock {
+        fn advance(&self, duration: Duration) {
+            self.now.set(self.now.get() + duration);
+        }
+
+        fn sleeps(&self) -> Vec<Duration> {
+            let sleeps = self.sleeps.take();
+            self.sleeps.set(sleeps.clone());
+            sleeps
+        }
+    }
+
+    impl ReplayClock for MockClock {
+        fn now(&self) -> Duration {
+            self.now.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            let mut sleeps = self.sleeps.take();
+            sleeps.push(duration);
+            self.sleeps.set(sleeps);
+            self.advance(duration);
+        }
+    }
+
+    /// Event timestamps of a synthetic recording, in milliseconds after the first event.
+    fn recording(offsets_ms: &[i64]) -> Vec<i64> {
+        offsets_ms.iter().map(|ms| 130_000_000_000_000_000 + ms * TICKS_PER_MILLISECOND).collect()
+    }
+
+    #[test]
+    fn test_original_speed_keeps_recorded_spacing() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Original, clock.clone());
+        for timestamp in recording(&[0, 100, 150, 400]) {
+            assert!(driver.pace(timestamp));
+        }
+        assert_eq!(
+            clock.sleeps(),
+            vec![Duration::from_millis(100), Duration::from_millis(50), Duration::from_millis(250)]
+        );
+        let progress = driver.control().progress();
+        assert_eq!(progress.delivered, 4);
+        assert_eq!(progress.slept, Duration::from_millis(400));
+        assert_eq!(progress.max_drift, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_raw_timestamps_are_paced_by_session_clock() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Original, clock.clone());
+        // QPC at 1MHz, so 1000 ticks per millisecond
+        driver.set_timestamp_context(TimestampContext::raw(ClockResolution::QueryPerformanceCounter, 1_000_000));
+        for timestamp in [5_000_000, 5_100_000, 5_150_000] {
+            assert!(driver.pace(timestamp));
+        }
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(100), Duration::from_millis(50)]);
+    }
+
+    #[test]
+    fn test_unknown_clock_frequency_is_unpaced() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Original, clock.clone());
+        driver.set_timestamp_context(TimestampContext::raw(ClockResolution::CpuCycleCounter, 0));
+        for timestamp in [5_000_000, 5_100_000] {
+            assert!(driver.pace(timestamp));
+        }
+        assert!(clock.sleeps().is_empty());
+    }
+
+    #[test]
+    fn test_speed_factor_divides_delays() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Factor(10.0), clock.clone());
+        for timestamp in recording(&[0, 100, 300]) {
+            driver.pace(timestamp);
+        }
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(10), Duration::from_millis(20)]);
+    }
+
+    #[test]
+    fn test_unpaced_never_sleeps() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Unpaced, clock.clone());
+        for timestamp in recording(&[0, 100, 300]) {
+            driver.pace(timestamp);
+        }
+        assert!(clock.sleeps().is_empty());
+        assert_eq!(driver.control().progress().delivered, 3);
+    }
+
+    #[test]
+    fn test_max_sleep_clamps_gaps_without_drift() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Original, clock.clone())
+            .max_sleep(Duration::from_secs(1));
+        for timestamp in recording(&[0, 60_000, 60_100]) {
+            driver.pace(timestamp);
+        }
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(1), Duration::from_millis(100)]);
+        assert_eq!(driver.control().progress().max_drift, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_slow_handler_is_reported_as_drift() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Original, clock.clone());
+        let timestamps = recording(&[0, 10, 20, 30]);
+        for timestamp in timestamps {
+            driver.pace(timestamp);
+            // The handler takes 15ms per event, but events are 10ms apart
+            clock.advance(Duration::from_millis(15));
+        }
+        let progress = driver.control().progress();
+        assert_eq!(progress.last_drift, Duration::from_millis(15));
+        assert_eq!(progress.max_drift, Duration::from_millis(15));
+        assert_eq!(progress.mean_drift(), Duration::from_millis(30) / 4);
+        assert!(clock.sleeps().is_empty());
+    }
+
+    #[test]
+    fn test_seek_skips_and_restarts_schedule() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Original, clock.clone());
+        let timestamps = recording(&[0, 100, 200, 300, 350]);
+        let control = driver.control();
+        assert!(driver.pace(timestamps[0]));
+        assert!(control.seek(timestamps[3]));
+        assert!(!driver.pace(timestamps[1]));
+        assert!(!driver.pace(timestamps[2]));
+        assert!(driver.pace(timestamps[3]));
+        assert!(driver.pace(timestamps[4]));
+        // Nothing is slept for the 300ms skipped over
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(50)]);
+        let progress = control.progress();
+        assert_eq!((progress.delivered, progress.skipped), (3, 2));
+        assert!(!control.seek(timestamps[0]));
+    }
+
+    #[test]
+    fn test_time_spent_paused_is_not_drift() {
+        let clock = MockClock::default();
+        let mut driver = ReplayDriver::with_clock(ReplaySpeed::Original, clock.clone());
+        let control = driver.control();
+        let timestamps = recording(&[0, 10, 20]);
+        driver.pace(timestamps[0]);
+        control.pause();
+        clock.advance(Duration::from_secs(5));
+        control.resume();
+        driver.pace(timestamps[1]);
+        driver.pace(timestamps[2]);
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(10)]);
+        assert_eq!(control.progress().max_drift, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_cancel_releases_paused_replay() {
+        let mut driver = ReplayDriver::new(ReplaySpeed::Original);
+        let control = driver.control();
+        control.pause();
+        let handle = std::thread::spawn({
+            let control = control.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(20));
+                control.cancel();
+            }
+        });
+        assert!(driver.pace(0));
+        handle.join().unwrap();
+        assert!(!control.is_paused());
+    }
+}
@@ -1,87 +1,391 @@
-use std::{collections::{hash_map::Entry, HashMap, HashSet}, slice, sync::{Arc, RwLock}};
+use std::{borrow::Cow, collections::{hash_map::Entry, HashMap, HashSet}, hash::Hash, sync::{Arc, Mutex, RwLock}};
 
+use once_cell::sync::Lazy;
 use windows::{
     core::GUID,
     Win32::System::Diagnostics::Etw::{
-        PropertyHasCustomSchema, PropertyParamCount, PropertyParamFixedCount, PropertyParamLength, PropertyStruct, EVENTMAP_ENTRY_VALUETYPE_STRING, EVENTMAP_ENTRY_VALUETYPE_ULONG, EVENTMAP_INFO_FLAG_MANIFEST_PATTERNMAP, EVENT_PROPERTY_INFO, EVENT_RECORD, TDH_INTYPE_HEXINT32, TDH_INTYPE_UINT16, TDH_INTYPE_UINT32, TDH_INTYPE_UINT8, _TDH_IN_TYPE
+        PropertyHasCustomSchema, PropertyParamCount, PropertyParamFixedCount, PropertyParamLength, PropertyStruct, EVENTMAP_ENTRY_VALUETYPE_STRING, EVENTMAP_ENTRY_VALUETYPE_ULONG, EVENTMAP_INFO_FLAG_MANIFEST_BITMAP, EVENTMAP_INFO_FLAG_MANIFEST_PATTERNMAP, EVENTMAP_INFO_FLAG_WBEM_BITMAP, EVENTMAP_INFO_FLAG_WBEM_FLAG, EVENTMAP_INFO_FLAG_WBEM_VALUEMAP, EVENT_PROPERTY_INFO, EVENT_RECORD, TDH_INTYPE_HEXINT32, TDH_INTYPE_UINT16, TDH_INTYPE_UINT32, TDH_INTYPE_UINT8, _TDH_IN_TYPE
     },
 };
 
 use crate::{
-    error::{ParseError, TraceError}, tdh_wrappers::{EventMapInfo, TraceEventInfo}, values::{compound::{StringOrStruct, Struct, StructArray, StructOrValue}, event::{Event, EventRecord, Header}, in_value::InValue, value::Value}
+    error::{ParseError, TraceError}, tdh_wrappers::{DecodingSource, EventMapInfo, TraceEventInfo}, values::{compound::{StringOrStruct, Struct, StructArray, StructOrValue, Truncation}, event::{Event, EventRecord, Header}, in_value::InValue, value::Value}
 };
 
+#[cfg(feature = "serde")]
+use super::merged::MergedEvent;
 use super::{in_type::InType, out_type::OutType};
 
 pub struct SchemaCache {
     schemas: RwLock<HashMap<(GUID, u16, u8), Arc<EventInfo>>>,
+    /// TraceLogging schemas, keyed by the decode GUID (the provider group if there is one,
+    /// otherwise the provider) and then by the event's TraceLogging metadata.
+    tlg_schemas: RwLock<HashMap<GUID, HashMap<Box<[u8]>, Arc<EventInfo>>>>,
+    /// Schemas of classic (MOF) events, keyed by provider, opcode and version.
+    classic_schemas: RwLock<HashMap<(GUID, u8, u8), Arc<EventInfo>>>,
 }
 
 impl SchemaCache {
     pub fn new() -> Self {
         Self {
             schemas: RwLock::new(HashMap::new()),
+            tlg_schemas: RwLock::new(HashMap::new()),
+            classic_schemas: RwLock::new(HashMap::new()),
         }
     }
 
+    /// The cache used to decode events delivered to trace handlers.
+    pub fn global() -> &'static SchemaCache {
+        static EVENT_SCHEMAS: Lazy<SchemaCache> = Lazy::new(SchemaCache::new);
+        &EVENT_SCHEMAS
+    }
+
+    /// Add a schema for the provider, event id and version of `info`, replacing a cached
+    /// one.
+    ///
+    /// Cached schemas are used instead of looking them up with TDH, e.g. to decode log
+    /// files on machines that don't have the provider's manifest installed.
+    pub fn insert(&self, info: EventInfo) -> Arc<EventInfo> {
+        let schema = Arc::new(info);
+        let key = (schema.provider_guid, schema.event_id, schema.event_version);
+        self.schemas
+            .write()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(key, Arc::clone(&schema));
+        schema
+    }
+
+    /// Drop all cached schemas, so that long-running sessions can shed memory.
+    ///
+    /// Schemas still referenced by decoded events stay alive until those are dropped,
+    /// later lookups parse the schemas again.
+    pub fn clear(&self) {
+        self.schemas.write().unwrap_or_else(|err| err.into_inner()).clear();
+        self.tlg_schemas.write().unwrap_or_else(|err| err.into_inner()).clear();
+        self.classic_schemas.write().unwrap_or_else(|err| err.into_inner()).clear();
+    }
+
+    /// Load the schemas dumped by `etwschema`, an object of provider GUIDs to event ids
+    /// to [`MergedEvent`]s, e.g. from a `serde_json::Deserializer`.
+    ///
+    /// Every version of a merged event is cached with the properties present in it.
+    /// Properties whose definition changed between versions keep their earliest one,
+    /// see [`MergedEvent::conflicts`], and as the dump has no value maps, mapped values
+    /// are decoded without their names.
+    #[cfg(feature = "serde")]
+    pub fn from_serialized<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, TraceError> {
+        let dump: HashMap<String, HashMap<u16, MergedEvent>> = serde::Deserialize::deserialize(deserializer)
+            .map_err(|err| TraceError::Configuration(format!("Invalid schema dump: {err}")))?;
+        let cache = Self::new();
+        for (provider, events) in dump {
+            let provider_guid = crate::serde::guid::parse_guid(&provider)
+                .map_err(|err| TraceError::Configuration(format!("Invalid provider GUID {provider:?}: {err}")))?;
+            for (event_id, merged) in events {
+                for version in merged.versions.iter() {
+                    let fields = merged
+                        .fields_for_version(version)
+                        .map(|property| property.property_info.clone())
+                        .collect();
+                    let schema = EventInfo::new(provider_guid, event_id, version, PropertyStructInfo::new(fields));
+                    cache.insert(schema);
+                }
+            }
+        }
+        Ok(cache)
+    }
+
     pub fn get_from_event_record(&self, event_record: &EVENT_RECORD) -> Result<Arc<EventInfo>, TraceError> {
+        // TraceLogging events carry their schema and usually all have id 0,
+        // so they are told apart by their metadata.
+        let record = EventRecord(event_record);
+        if let Some(tlg_schema) = record.tracelogging_schema() {
+            let decode_guid = record
+                .provider_traits()
+                .and_then(|traits| traits.group_guid)
+                .unwrap_or(event_record.EventHeader.ProviderId);
+            return self.get_or_parse_tlg(decode_guid, tlg_schema, event_record);
+        }
+        // Classic events share an id of 0, the event type is in the opcode.
+        if record.is_classic_event() {
+            let key = (record.provider_guid(), record.opcode(), record.version());
+            return Self::get_or_parse(&self.classic_schemas, key, event_record);
+        }
+
         let key = (
             event_record.EventHeader.ProviderId,
             event_record.EventHeader.EventDescriptor.Id,
             event_record.EventHeader.EventDescriptor.Version,
         );
-        if let Ok(guard) = self.schemas.read() {
-            if let Some(schema) = guard.get(&key) {
-                return Ok(Arc::clone(schema));
-            }
-        }
-        else {
-            todo!("Mutex was poisoned");
-        }
-        if let Ok(mut guard) = self.schemas.write() {
-            // Can't use .or_insert_with because errors cannot exit the closure 
-            match guard.entry(key) {
-                Entry::Occupied(entry) => Ok(Arc::clone(entry.get())),
-                Entry::Vacant(entry) => {
-                    let trace_event_info = TraceEventInfo::from_event(event_record)?; 
-                    let cached_event_info = EventInfo::parse(&trace_event_info, Some(event_record))?;
-                    log::trace!(
-                        "Caching event info for {:?}:{}:{}: {:?}",
-                        event_record.EventHeader.ProviderId,
-                        event_record.EventHeader.EventDescriptor.Id,
-                        event_record.EventHeader.EventDescriptor.Version,
-                        &cached_event_info
-                    );
-                    Ok(Arc::clone(entry.insert(Arc::new(cached_event_info))))
-                }
-            }
+        Self::get_or_parse(&self.schemas, key, event_record)
+    }
+
+    fn get_or_parse<K>(
+        schemas: &RwLock<HashMap<K, Arc<EventInfo>>>,
+        key: K,
+        event_record: &EVENT_RECORD,
+    ) -> Result<Arc<EventInfo>, TraceError>
+    where
+        K: Eq + Hash,
+    {
+        // A poisoned lock means a thread panicked while updating the cache. The map itself
+        // may still be fine, but report it so that decoding doesn't silently continue.
+        if let Some(schema) = schemas
+            .read()
+            .map_err(|_| ParseError::CacheMutexPoisoned)?
+            .get(&key)
+        {
+            return Ok(Arc::clone(schema));
         }
-        else {
-            todo!("Mutex was poisoned");
+        let mut guard = schemas.write().map_err(|_| ParseError::CacheMutexPoisoned)?;
+        // Can't use .or_insert_with because errors cannot exit the closure 
+        match guard.entry(key) {
+            Entry::Occupied(entry) => Ok(Arc::clone(entry.get())),
+            Entry::Vacant(entry) => Ok(Arc::clone(entry.insert(Arc::new(Self::parse_schema(event_record)?)))),
         }
     }
 
-    pub fn get(&self, provider_id: GUID, event_id: u16, event_version: u8) -> Option<Arc<EventInfo>> {
-        if let Ok(guard) = self.schemas.read() {
-            guard.get(&(provider_id, event_id, event_version)).map(|v| Arc::clone(v))
+    /// Like [`SchemaCache::get_or_parse`] for TraceLogging schemas, which are compared by
+    /// their metadata bytes so that events with colliding metadata hashes don't share one.
+    fn get_or_parse_tlg(&self, decode_guid: GUID, metadata: &[u8], event_record: &EVENT_RECORD) -> Result<Arc<EventInfo>, TraceError> {
+        if let Some(schema) = self
+            .tlg_schemas
+            .read()
+            .map_err(|_| ParseError::CacheMutexPoisoned)?
+            .get(&decode_guid)
+            .and_then(|schemas| schemas.get(metadata))
+        {
+            return Ok(Arc::clone(schema));
         }
-        else {
-            log::warn!("mutex was poisoned");
-            None
+        let mut guard = self.tlg_schemas.write().map_err(|_| ParseError::CacheMutexPoisoned)?;
+        let schemas = guard.entry(decode_guid).or_default();
+        if let Some(schema) = schemas.get(metadata) {
+            return Ok(Arc::clone(schema));
         }
+        let schema = Arc::new(Self::parse_schema(event_record)?);
+        schemas.insert(metadata.into(), Arc::clone(&schema));
+        Ok(schema)
+    }
+
+    fn parse_schema(event_record: &EVENT_RECORD) -> Result<EventInfo, TraceError> {
+        let trace_event_info = TraceEventInfo::from_event(event_record)?; 
+        let cached_event_info = EventInfo::parse(&trace_event_info, Some(event_record))?;
+        log::trace!(
+            "Caching event info for {:?}:{}:{}: {:?}",
+            event_record.EventHeader.ProviderId,
+            event_record.EventHeader.EventDescriptor.Id,
+            event_record.EventHeader.EventDescriptor.Version,
+            &cached_event_info
+        );
+        Ok(cached_event_info)
+    }
 
+    /// Look up a cached schema for a provider's event.
+    ///
+    /// Returns `Ok(None)` if the schema isn't cached and an error if the cache lock is poisoned.
+    pub fn get(&self, provider_id: GUID, event_id: u16, event_version: u8) -> Result<Option<Arc<EventInfo>>, TraceError> {
+        let guard = self.schemas.read().map_err(|_| ParseError::CacheMutexPoisoned)?;
+        Ok(guard.get(&(provider_id, event_id, event_version)).map(Arc::clone))
     }
 }
 
-#[derive(Debug)]
+/// Decode an event record received outside of this crate, e.g. in an existing
+/// `ProcessTrace` callback, looking up its schema in `cache`.
+///
+/// # Safety
+///
+/// `record` must be null or point to a valid `EVENT_RECORD` whose `UserData` points to
+/// `UserDataLength` readable bytes and whose `ExtendedData` points to
+/// `ExtendedDataCount` valid items. The record and the memory it points to must stay
+/// valid and unchanged for the lifetime `'a` of the returned event, which borrows from
+/// it. For records passed to an `EventRecordCallback` that is the duration of the
+/// callback.
+pub unsafe fn decode_raw<'a>(
+    record: *const EVENT_RECORD,
+    cache: &SchemaCache,
+) -> Result<(Arc<EventInfo>, Event<'a>), TraceError> {
+    let Some(record) = (unsafe { record.as_ref::<'a>() }) else {
+        return Err(TraceError::NullEventRecord);
+    };
+    let schema = cache.get_from_event_record(record)?;
+    let event = schema.decode(record)?;
+    Ok((schema, event))
+}
+
+#[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub enum StringOrIntegerMap {
     Integer(HashMap<u32, String>),
+    /// A map of flags, whose entries each name one or more bits.
+    Bitmap(HashMap<u32, String>),
     String(HashMap<String, String>),
+    /// A manifest pattern map, whose entries name values that match their input pattern
+    /// once formatted with the printf-style `format`, e.g. `%08x`.
+    Pattern {
+        format: String,
+        /// (input pattern, name) pairs, in the order of the manifest.
+        entries: Vec<(String, String)>,
+    },
+}
+
+/// Providers and names of maps that couldn't be parsed, so each is only warned about once.
+static UNSUPPORTED_MAPS: Lazy<Mutex<HashSet<(GUID, String)>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Format `value` with a printf-style format string containing a single integer
+/// conversion (`%u`, `%d`, `%i`, `%x`, `%X` or `%o`).
+///
+/// Flags `#`, `0` and `-`, a width and length modifiers are supported. Returns None
+/// for formats without exactly one supported conversion.
+fn format_pattern(format: &str, value: u32) -> Option<String> {
+    let mut output = String::with_capacity(format.len() + 10);
+    let mut chars = format.chars().peekable();
+    let mut converted = false;
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            output.push('%');
+            continue;
+        }
+        if converted {
+            return None;
+        }
+        let (mut alternate, mut zero, mut left) = (false, false, false);
+        while let Some(flag) = chars.next_if(|c| matches!(c, '#' | '0' | '-' | '+' | ' ')) {
+            match flag {
+                '#' => alternate = true,
+                '0' => zero = true,
+                '-' => left = true,
+                _ => (),
+            }
+        }
+        let mut width = 0usize;
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width = width.saturating_mul(10).saturating_add(digit as usize - '0' as usize);
+        }
+        // Length modifiers (h, l, ll, I32, I64) don't change a 32 bit value
+        while let Some(modifier) = chars.next_if(|c| matches!(c, 'h' | 'l' | 'I')) {
+            if modifier == 'I' {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+        }
+        let (prefix, digits) = match chars.next()? {
+            'u' => ("", value.to_string()),
+            'd' | 'i' => {
+                let value = value as i32;
+                (if value < 0 { "-" } else { "" }, value.unsigned_abs().to_string())
+            },
+            'x' => (if alternate && value != 0 { "0x" } else { "" }, format!("{value:x}")),
+            'X' => (if alternate && value != 0 { "0X" } else { "" }, format!("{value:X}")),
+            'o' => (if alternate && value != 0 { "0" } else { "" }, format!("{value:o}")),
+            _ => return None,
+        };
+        let padding = width.saturating_sub(prefix.len() + digits.len());
+        if left {
+            output.push_str(prefix);
+            output.push_str(&digits);
+            output.push_str(&" ".repeat(padding));
+        } else if zero {
+            output.push_str(prefix);
+            output.push_str(&"0".repeat(padding));
+            output.push_str(&digits);
+        } else {
+            output.push_str(&" ".repeat(padding));
+            output.push_str(prefix);
+            output.push_str(&digits);
+        }
+        converted = true;
+    }
+    converted.then_some(output)
 }
 
 impl StringOrIntegerMap {
+    /// The name an integer map assigns to `value`.
+    ///
+    /// Returns None for unmapped values and for string maps. Bitmaps only name values
+    /// that exactly match one of their entries, see [`StringOrIntegerMap::resolve`].
+    /// Pattern maps name values whose formatted text matches an entry's input pattern.
+    pub fn name(&self, value: u32) -> Option<&str> {
+        match self {
+            Self::Integer(map) | Self::Bitmap(map) => map.get(&value).map(String::as_str),
+            Self::String(_) => None,
+            Self::Pattern { entries, .. } => {
+                let formatted = self.pattern_text(value)?;
+                entries
+                    .iter()
+                    .find(|(input, _)| input.eq_ignore_ascii_case(&formatted))
+                    .map(|(_, name)| name.as_str())
+            },
+        }
+    }
+
+    /// `value` formatted with the format string of a pattern map, i.e. the text its
+    /// entries' input patterns are matched against.
+    ///
+    /// Returns None for other maps and for format strings that aren't supported.
+    pub fn pattern_text(&self, value: u32) -> Option<String> {
+        match self {
+            Self::Pattern { format, .. } => format_pattern(format, value),
+            Self::Integer(_) | Self::Bitmap(_) | Self::String(_) => None,
+        }
+    }
+
+    /// `value` as text: its name as given by [`StringOrIntegerMap::resolve`], e.g.
+    /// `READ | WRITE` for bitmaps, otherwise the value itself.
+    ///
+    /// Unnamed values are formatted in hex for bitmaps, with the format string for
+    /// pattern maps and in decimal for other maps.
+    pub fn format_value(&self, value: u32) -> String {
+        if let Some(name) = self.resolve(value) {
+            return name.into_owned();
+        }
+        match self {
+            Self::Bitmap(_) => format!("{value:#x}"),
+            Self::Pattern { .. } => self.pattern_text(value).unwrap_or_else(|| value.to_string()),
+            Self::Integer(_) | Self::String(_) => value.to_string(),
+        }
+    }
+
+    /// The name of `value` in an integer map, or the names of the flags set in `value`
+    /// joined with ` | ` in a bitmap.
+    ///
+    /// Flags that are part of a named composite flag set in `value` aren't listed
+    /// again. Bits of a bitmap value without a name are appended in hex. Returns None
+    /// if no name applies at all.
+    pub fn resolve(&self, value: u32) -> Option<Cow<'_, str>> {
+        let Self::Bitmap(map) = self else {
+            return self.name(value).map(Cow::Borrowed);
+        };
+        if let Some(name) = map.get(&value) {
+            return Some(Cow::Borrowed(name));
+        }
+        let mut candidates = map
+            .iter()
+            .filter(|(flag, _)| **flag != 0 && value & **flag == **flag)
+            .collect::<Vec<_>>();
+        // Composite flags first, so the flags they cover aren't named again
+        candidates.sort_by_key(|(flag, _)| (std::cmp::Reverse(flag.count_ones()), **flag));
+        let mut covered = 0;
+        let mut flags = Vec::new();
+        for (flag, name) in candidates {
+            if flag & !covered != 0 {
+                covered |= flag;
+                flags.push((flag, name));
+            }
+        }
+        if flags.is_empty() {
+            return None;
+        }
+        flags.sort_by_key(|(flag, _)| **flag);
+        let mut names = flags.into_iter().map(|(_, name)| name.clone()).collect::<Vec<_>>();
+        if value & !covered != 0 {
+            names.push(format!("{:#x}", value & !covered));
+        }
+        Some(Cow::Owned(names.join(" | ")))
+    }
+
     fn has_map_name(property: &EVENT_PROPERTY_INFO) -> bool {
         unsafe {
             if (property.Flags.0 & PropertyStruct.0) != 0 {
@@ -125,69 +429,94 @@ impl StringOrIntegerMap {
             let event_map_info = EventMapInfo::from(map_name, event_record)?;
             let map_name = String::from_utf16(map_name_without_nul)?;
 
-            if (event_map_info.data().Flag.0 & EVENTMAP_INFO_FLAG_MANIFEST_PATTERNMAP.0)
-                != 0
-            {
-                let format_string = event_map_info.offset_string(
-                    event_map_info
-                        .data()
-                        .Anonymous
-                        .FormatStringOffset
-                        .try_into()?,
-                    false,
-                );
-                let _format_string = format_string.map(|f| String::from_utf16(f)).transpose()?.unwrap_or("".to_string());
+            match Self::from_event_map_info(&event_map_info) {
+                Ok(map) => Ok((map_name, map)),
+                Err(ParseError::NotImplemented) => {
+                    let mut unsupported = UNSUPPORTED_MAPS.lock().unwrap_or_else(|err| err.into_inner());
+                    if unsupported.insert((event_record.EventHeader.ProviderId, map_name.clone())) {
+                        log::warn!("Event provider {:?} id {} version {} - Map '{}' has an unsupported value type {}", event_record.EventHeader.ProviderId, event_record.EventHeader.EventDescriptor.Id, event_record.EventHeader.EventDescriptor.Version, map_name, event_map_info.data().Anonymous.MapEntryValueType.0);
+                    }
+                    Err(ParseError::NotImplemented)
+                },
+                Err(err) => Err(err),
+            }
+        }
+    }
 
-                log::warn!("Event provider {:?} id {} version {} - Manifest pattern map '{}' not implemented", event_record.EventHeader.ProviderId, event_record.EventHeader.EventDescriptor.Id, event_record.EventHeader.EventDescriptor.Version, map_name);
-                Err(ParseError::NotImplemented)
-            } else {
-                match event_map_info.data().Anonymous.MapEntryValueType {
-                    EVENTMAP_ENTRY_VALUETYPE_ULONG => {
-                        let mut map = HashMap::new();
-                        for idx in 0..event_map_info.len() {
-                            if let Some(entry) = event_map_info.get(idx) {
-                                let key = entry.Anonymous.Value;
-                                let value = entry.OutputOffset;
-                                let value = event_map_info
-                                    .offset_string(value.try_into()?, false);
-                                let value = value.map(|v| String::from_utf16(v)).transpose()?.unwrap_or("".to_string());
-                                map.insert(key, value);
-                            }
-                        }
+    /// Parse the entries of a map as returned by `TdhGetEventMapInformation`.
+    ///
+    /// Returns [`ParseError::NotImplemented`] for maps with an unknown entry value type.
+    pub fn from_event_map_info(event_map_info: &EventMapInfo) -> Result<StringOrIntegerMap, ParseError> {
+        let offset_string = |offset: u32| -> Result<String, ParseError> {
+            Ok(event_map_info
+                .offset_string(offset.try_into()?, false)
+                .map(String::from_utf16)
+                .transpose()?
+                .unwrap_or_default())
+        };
 
-                        Ok((map_name, StringOrIntegerMap::Integer(map)))
-                    },
+        unsafe {
+            let flags = event_map_info.data().Flag.0;
+            if (flags & EVENTMAP_INFO_FLAG_MANIFEST_PATTERNMAP.0) != 0 {
+                let format = offset_string(event_map_info.data().Anonymous.FormatStringOffset)?;
+                let mut entries = Vec::with_capacity(event_map_info.len());
+                for idx in 0..event_map_info.len() {
+                    if let Some(entry) = event_map_info.get(idx) {
+                        entries.push((offset_string(entry.Anonymous.InputOffset)?, offset_string(entry.OutputOffset)?));
+                    }
+                }
+                return Ok(StringOrIntegerMap::Pattern { format, entries });
+            }
 
-                    EVENTMAP_ENTRY_VALUETYPE_STRING => {
-                        let mut map = HashMap::new();
-                        for idx in 0..event_map_info.len() {
-                            if let Some(entry) = event_map_info.get(idx) {
-                                let key = entry.Anonymous.InputOffset;
-                                let key = event_map_info
-                                    .offset_string(key.try_into()?, false);
-                                let key = key.map(|k| String::from_utf16(k)).transpose()?.unwrap_or("".to_string());
-
-                                let value = entry.OutputOffset;
-                                let value = event_map_info
-                                    .offset_string(value.try_into()?, false);
-                                let value = value.map(|v| String::from_utf16(v)).transpose()?.unwrap_or("".to_string());
-                                map.insert(key, value);
-                            }
+            match event_map_info.data().Anonymous.MapEntryValueType {
+                EVENTMAP_ENTRY_VALUETYPE_ULONG => {
+                    // WMI bitmaps list bit positions instead of bit values
+                    let bit_positions = (flags & EVENTMAP_INFO_FLAG_WBEM_BITMAP.0) != 0;
+                    let is_bitmap = bit_positions
+                        || (flags & EVENTMAP_INFO_FLAG_MANIFEST_BITMAP.0) != 0
+                        || (flags & (EVENTMAP_INFO_FLAG_WBEM_VALUEMAP.0 | EVENTMAP_INFO_FLAG_WBEM_FLAG.0))
+                            == (EVENTMAP_INFO_FLAG_WBEM_VALUEMAP.0 | EVENTMAP_INFO_FLAG_WBEM_FLAG.0);
+                    let mut map = HashMap::new();
+                    for idx in 0..event_map_info.len() {
+                        if let Some(entry) = event_map_info.get(idx) {
+                            let key = if bit_positions {
+                                match 1u32.checked_shl(entry.Anonymous.Value) {
+                                    Some(key) => key,
+                                    None => continue,
+                                }
+                            } else {
+                                entry.Anonymous.Value
+                            };
+                            map.insert(key, offset_string(entry.OutputOffset)?);
                         }
+                    }
 
-                        Ok((map_name, StringOrIntegerMap::String(map)))
-                    },
+                    if is_bitmap {
+                        Ok(StringOrIntegerMap::Bitmap(map))
+                    } else {
+                        Ok(StringOrIntegerMap::Integer(map))
+                    }
+                },
 
-                    _ => {
-                        unreachable!("Event provider {:?} id {} version {} - In map '{}' unknown MAP_VALUETYPE {}", event_record.EventHeader.ProviderId, event_record.EventHeader.EventDescriptor.Id, event_record.EventHeader.EventDescriptor.Version, map_name, event_map_info.data().Anonymous.MapEntryValueType.0);
+                EVENTMAP_ENTRY_VALUETYPE_STRING => {
+                    let mut map = HashMap::new();
+                    for idx in 0..event_map_info.len() {
+                        if let Some(entry) = event_map_info.get(idx) {
+                            map.insert(offset_string(entry.Anonymous.InputOffset)?, offset_string(entry.OutputOffset)?);
+                        }
                     }
-                }
+
+                    Ok(StringOrIntegerMap::String(map))
+                },
+
+                _ => Err(ParseError::NotImplemented),
             }
         }
     }
 }
          
-#[cfg_attr(feature = "serde", derive(Debug, serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct EventInfo {
     #[cfg_attr(feature = "serde", serde(serialize_with = "super::super::serde::guid::serialize", deserialize_with = "super::super::serde::guid::deserialize"))]
@@ -195,6 +524,12 @@ pub struct EventInfo {
     pub provider_guid: GUID,
     pub event_id: u16,
     pub event_version: u8,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub provider_name: Option<String>,
+    /// Provider group of TraceLogging providers.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none", serialize_with = "super::super::serde::guid_option::serialize", deserialize_with = "super::super::serde::guid_option::deserialize"))]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub provider_group_guid: Option<GUID>,
     #[cfg_attr(feature = "serde", serde(flatten))]
     pub properties: PropertyStructInfo,
     #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "HashMap::is_empty"))]
@@ -202,12 +537,34 @@ pub struct EventInfo {
 }
 
 impl EventInfo {
+    /// Create a schema from its parts, e.g. to decode synthetic payloads in tests.
+    pub fn new(provider_guid: GUID, event_id: u16, event_version: u8, properties: PropertyStructInfo) -> Self {
+        Self {
+            provider_guid,
+            event_id,
+            event_version,
+            provider_name: None,
+            provider_group_guid: None,
+            properties,
+            maps: HashMap::new(),
+        }
+    }
+
     pub fn parse(trace_event_info: &TraceEventInfo, event_record: Option<&EVENT_RECORD>) -> Result<Self, ParseError> {
         let mut length_count_properties = HashSet::new();
         let mut maps = HashMap::new();
         let provider_guid = trace_event_info.provider_guid();
         let event_id = trace_event_info.event_id();
         let event_version = trace_event_info.event_version();
+        let mut provider_name = trace_event_info.provider_name_string();
+        let mut provider_group_guid = None;
+        if let Some(event_record) = event_record
+            && matches!(trace_event_info.decoding_source(), DecodingSource::Tlg)
+            && let Some(traits) = EventRecord(event_record).provider_traits()
+        {
+            provider_name = provider_name.or(traits.name);
+            provider_group_guid = traits.group_guid;
+        }
 
         for idx in 0..trace_event_info.property_count() {
             let property =
@@ -236,6 +593,8 @@ impl EventInfo {
                     Ok((name, map)) => {
                         maps.insert(name, map);
                     },
+                    // Values of properties with unsupported maps are decoded without their names
+                    Err(ParseError::NotImplemented) => (),
                     Err(err) => {
                         log::warn!("Event provider {:?} id {} version {} - Error parsing map: {}", provider_guid, event_id, event_version, err);
                     }
@@ -247,6 +606,8 @@ impl EventInfo {
             provider_guid,
             event_id,
             event_version,
+            provider_name,
+            provider_group_guid,
             maps,
             properties: PropertyStructInfo::parse(
                 &trace_event_info,
@@ -258,29 +619,245 @@ impl EventInfo {
     }
 }
 
+impl TryFrom<&TraceEventInfo> for EventInfo {
+    type Error = ParseError;
+
+    /// Convert the TDH view of an event into its serializable schema.
+    ///
+    /// Maps are only resolved by [`EventInfo::parse`] with an event record.
+    fn try_from(trace_event_info: &TraceEventInfo) -> Result<Self, Self::Error> {
+        EventInfo::parse(trace_event_info, None)
+    }
+}
+
 impl EventInfo {
+    /// Name of the provider as found in the provider's manifest, if it has one.
+    pub fn provider_name(&self) -> Option<&str> {
+        self.provider_name.as_deref()
+    }
+
+    /// Provider group of TraceLogging providers, if the provider belongs to one.
+    pub fn provider_group_guid(&self) -> Option<GUID> {
+        self.provider_group_guid
+    }
+
+    /// Returns true if the schema doesn't describe any properties.
+    ///
+    /// Some providers emit payloads for such events anyway. Those are decoded
+    /// as [`StringOrStruct::Opaque`] carrying the raw user data.
+    pub fn is_opaque(&self) -> bool {
+        self.properties.fields.is_empty()
+    }
+
+    /// Number of bytes taken by the leading properties whose size doesn't depend on the payload.
+    ///
+    /// Events with a shorter payload can't be decoded, so this can be used to reject
+    /// truncated records before attempting a full decode.
+    pub fn fixed_prefix_size(&self) -> usize {
+        self.properties.fixed_prefix_size()
+    }
+
+    /// Lower bound for the payload size of this event.
+    ///
+    /// This is the sum of the sizes of all properties with a fixed size. Properties
+    /// with a variable size are counted as empty.
+    pub fn min_size(&self) -> usize {
+        self.properties.min_size()
+    }
+
     pub fn decode<'b, 'c>(&self, event_record: &'b EVENT_RECORD) -> Result<Event<'c>, ParseError>
     where
         'b: 'c,
     {
-        let _event = EventRecord(event_record);
-        let mut length_count_values = HashMap::new();
-        let userdata = unsafe {
-            slice::from_raw_parts(
-                event_record.UserData as *const u8,
-                event_record.UserDataLength.into(),
-            )
-        };
-        let (struc, remainder) = self.properties.decode(userdata, &mut length_count_values)?;
-        if !remainder.is_empty() {
-            log::warn!("Unused data after parsing event record");
-        }
+        self.decode_record(event_record, EventRecord(event_record).pointer_size(), false)
+    }
+
+    /// Decode `event_record` with pointers of `pointer_size` bytes, tolerating short
+    /// payloads like [`EventInfo::decode_tolerant`] if `tolerant` is set.
+    pub(crate) fn decode_record<'b, 'c>(&self, event_record: &'b EVENT_RECORD, pointer_size: usize, tolerant: bool) -> Result<Event<'c>, ParseError>
+    where
+        'b: 'c,
+    {
+        let userdata = EventRecord(event_record).validated_userdata()?;
+        let mut context = DecodeContext::new(pointer_size);
+        let data = self.decode_userdata_in(userdata, &mut context, tolerant)?;
 
         Ok(Event {
             header: Header::from(&event_record.EventHeader),
-            data: StringOrStruct::Struct(struc),
+            data,
         })
     }
+
+    /// Decode an event payload without the surrounding event record.
+    ///
+    /// Pointers are assumed to have the size of this process's pointers.
+    pub fn decode_userdata<'b>(&self, userdata: &'b [u8]) -> Result<StringOrStruct<'b>, ParseError> {
+        self.decode_userdata_in(userdata, &mut DecodeContext::default(), false)
+    }
+
+    /// Decode the top-level properties of `userdata`. If `tolerant` is set, a payload
+    /// that ends partway through them is returned as [`StringOrStruct::Partial`].
+    fn decode_userdata_in<'b>(&self, userdata: &'b [u8], context: &mut DecodeContext, tolerant: bool) -> Result<StringOrStruct<'b>, ParseError> {
+        if self.is_opaque() && !userdata.is_empty() {
+            return Ok(StringOrStruct::Opaque(userdata));
+        }
+        let mut values = Vec::with_capacity(self.properties.fields.len());
+        let mut remaining = userdata;
+
+        for field in &self.properties.fields {
+            let offset = userdata.len() - remaining.len();
+            match field.decode(remaining, context) {
+                Ok((value, rest)) => {
+                    values.push(value);
+                    remaining = rest;
+                }
+                Err(err) if tolerant && matches!(err.root_cause(), ParseError::PrematureEndOfData) => {
+                    log::debug!(
+                        "Event provider {:?} id {} version {} ended at property {} at offset {}",
+                        self.provider_guid,
+                        self.event_id,
+                        self.event_version,
+                        field.value.name(),
+                        offset
+                    );
+                    let truncation = Truncation {
+                        decoded_fields: values.len(),
+                        declared_fields: self.properties.fields.len(),
+                        offset,
+                    };
+                    let mut struc = Struct { values, names: Some(self.properties.field_names()) };
+                    self.resolve_maps(&self.properties, &mut struc);
+                    return Ok(StringOrStruct::Partial(struc, truncation));
+                }
+                Err(err) => return Err(err.at_property(field.value.name(), offset)),
+            }
+        }
+        if !remaining.is_empty() {
+            log::warn!("Unused data after parsing event record");
+        }
+        let mut struc = Struct { values, names: Some(self.properties.field_names()) };
+        self.resolve_maps(&self.properties, &mut struc);
+
+        Ok(StringOrStruct::Struct(struc))
+    }
+
+    /// Set [`Value::mapped`] of the values whose property has a map in [`EventInfo::maps`].
+    fn resolve_maps(&self, properties: &PropertyStructInfo, data: &mut Struct<'_>) {
+        if self.maps.is_empty() {
+            return;
+        }
+        for (field, value) in properties.fields.iter().zip(data.values.iter_mut()) {
+            match (&field.value, value) {
+                (PropertyNestedInfo::Struct(_, struct_info), StructOrValue::Struct(members)) => {
+                    for member in &mut members.values {
+                        self.resolve_maps(struct_info, member);
+                    }
+                }
+                (PropertyNestedInfo::Value(_, value_info), StructOrValue::Value(value)) => {
+                    if let Some(map) = value_info.map_name.as_ref().and_then(|name| self.maps.get(name)) {
+                        value.mapped = value.resolve_map(map);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Like [`EventInfo::decode`], but see [`EventInfo::decode_userdata_tolerant`].
+    pub fn decode_tolerant<'b, 'c>(&self, event_record: &'b EVENT_RECORD) -> Result<Event<'c>, ParseError>
+    where
+        'b: 'c,
+    {
+        self.decode_record(event_record, EventRecord(event_record).pointer_size(), true)
+    }
+
+    /// Decode an event payload that may carry fewer top-level properties than the schema declares.
+    ///
+    /// If the payload ends partway through the top-level properties, the properties
+    /// decoded so far are returned as [`StringOrStruct::Partial`] instead of an error.
+    /// This recovers events of binaries newer or older than the manifest that was
+    /// used to get the schema. Other errors fail the decode like for
+    /// [`EventInfo::decode_userdata`].
+    pub fn decode_userdata_tolerant<'b>(&self, userdata: &'b [u8]) -> Result<StringOrStruct<'b>, ParseError> {
+        self.decode_userdata_in(userdata, &mut DecodeContext::default(), true)
+    }
+
+    /// Split an event payload into the raw bytes of its top-level properties.
+    ///
+    /// Lengths and counts are resolved like for a full decode, but the bytes aren't
+    /// interpreted, so callers can do their own typing on fast paths.
+    pub fn decode_raw_fields<'a, 'b>(&'a self, userdata: &'b [u8]) -> Result<Vec<RawField<'a, 'b>>, ParseError> {
+        let mut context = DecodeContext::default();
+        let mut fields = Vec::with_capacity(self.properties.fields.len());
+        let mut remaining = userdata;
+
+        for field in &self.properties.fields {
+            let offset = userdata.len() - remaining.len();
+            let (_, rest) = field
+                .decode(remaining, &mut context)
+                .map_err(|err| err.at_property(field.value.name(), offset))?;
+            let (in_type, out_type) = match &field.value {
+                PropertyNestedInfo::Struct(..) => (InType::Null, None),
+                PropertyNestedInfo::Value(_, value_info) => (value_info.in_type, Some(value_info.out_type)),
+                PropertyNestedInfo::CustomSchema(..) => (InType::Binary, None),
+            };
+            fields.push(RawField {
+                name: field.value.name(),
+                data: &remaining[..remaining.len() - rest.len()],
+                in_type,
+                out_type,
+            });
+            remaining = rest;
+        }
+
+        Ok(fields)
+    }
+
+    /// The leaf properties of this schema, in payload order.
+    ///
+    /// Properties of nested structs are named by their dotted path, e.g. `Header.ProcessId`.
+    /// Struct arrays aren't expanded, they are a single entry with the schema of their elements.
+    pub fn properties_flat(&self) -> impl Iterator<Item = FlatProperty<'_>> {
+        let mut properties = Vec::new();
+        self.properties.flatten_into("", &mut properties);
+        properties.into_iter()
+    }
+}
+
+/// State of decoding one event payload, passed down to its properties.
+#[derive(Debug, Clone)]
+pub struct DecodeContext {
+    /// Values of the properties that are the length or count of others, by their handle.
+    pub length_count_values: HashMap<usize, usize>,
+    /// Size of pointers in the payload, see [`EventRecord::pointer_size`].
+    pub pointer_size: usize,
+}
+
+impl DecodeContext {
+    pub fn new(pointer_size: usize) -> Self {
+        Self {
+            length_count_values: HashMap::new(),
+            pointer_size,
+        }
+    }
+}
+
+/// Uses the pointer size of this process.
+impl Default for DecodeContext {
+    fn default() -> Self {
+        Self::new(size_of::<usize>())
+    }
+}
+
+/// Undecoded bytes of a top-level property, see [`EventInfo::decode_raw_fields`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawField<'a, 'b> {
+    pub name: &'a str,
+    pub data: &'b [u8],
+    /// [`InType::Null`] for struct properties.
+    pub in_type: InType,
+    /// None for struct properties.
+    pub out_type: Option<OutType>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -308,21 +885,50 @@ pub struct PropertyInfo {
 }
 
 impl PropertyInfo {
+    /// Returns the size of this property in bytes, or None if it depends on the payload.
+    pub fn fixed_size(&self) -> Option<usize> {
+        let PropertyValue::Constant(count) = self.count else {
+            return None;
+        };
+        match &self.value {
+            PropertyNestedInfo::Struct(_, struct_info) => struct_info.fixed_size().map(|size| size * count),
+            PropertyNestedInfo::Value(_, value_info) => match (value_info.in_type.size(), &self.length) {
+                (Some(size), _) => Some(size * count),
+                (None, PropertyValue::Constant(length)) if value_info.in_type == InType::Binary && *length != 0 => Some(length * count),
+                _ => None,
+            },
+            PropertyNestedInfo::CustomSchema(..) => match &self.length {
+                PropertyValue::Constant(length) if *length != 0 => Some(length * count),
+                _ => None,
+            },
+        }
+    }
+
+    /// Whether the length of the property is given by another property, and is 0.
+    ///
+    /// Binary values and non null-terminated strings without a length extend to the
+    /// end of the data, but a referenced length of 0 makes them empty.
+    fn has_referenced_zero_length(&self, length: usize) -> bool {
+        length == 0 && matches!(self.length, PropertyValue::Reference(_))
+    }
+
     pub fn decode<'b>(
         &self,
         mut userdata: &'b [u8],
-        length_count_values: &mut HashMap<usize, usize>,
+        context: &mut DecodeContext,
     ) -> Result<(StructOrValue<'b>, &'b [u8]), ParseError> {
         let length = match self.length {
             PropertyValue::Constant(size) => size,
-            PropertyValue::Reference(handle) => length_count_values
+            PropertyValue::Reference(handle) => context
+                .length_count_values
                 .get(&handle)
                 .copied()
                 .ok_or_else(|| ParseError::InvalidPropertyReference(handle))?,
         };
         let count = match self.count {
             PropertyValue::Constant(size) => size,
-            PropertyValue::Reference(handle) => length_count_values
+            PropertyValue::Reference(handle) => context
+                .length_count_values
                 .get(&handle)
                 .copied()
                 .ok_or_else(|| ParseError::InvalidPropertyReference(handle))?,
@@ -330,9 +936,17 @@ impl PropertyInfo {
         match self.value {
             PropertyNestedInfo::Struct(ref _name, ref struct_info) => {
                 let mut array_members = Vec::with_capacity(count);
-
-                for _ in 0..count {
-                    let (struc, remaining) = struct_info.decode(userdata, length_count_values)?;
+                let property_len = userdata.len();
+
+                for idx in 0..count {
+                    let offset = property_len - userdata.len();
+                    let (struc, remaining) = struct_info
+                        .decode(userdata, context)
+                        .map_err(|err| if self.is_array {
+                            err.at_property(&format!("[{}]", idx), offset)
+                        } else {
+                            err
+                        })?;
                     userdata = remaining;
                     array_members.push(struc);
                 }
@@ -347,9 +961,17 @@ impl PropertyInfo {
             }
             PropertyNestedInfo::Value(ref _name, ref value_info) => {
                 log::trace!("Decoding value type {:?}, length {:?}, count {:?}, is_array {:?}, {} bytes remaining", value_info.in_type, length, count, self.is_array, userdata.len());
+                let empty_when_unsized = matches!(
+                    value_info.in_type,
+                    InType::Binary | InType::NonNullTerminatedString | InType::NonNullTerminatedAnsiString
+                );
+                if empty_when_unsized && self.has_referenced_zero_length(length) {
+                    let (value, _) = value_info.decode(&userdata[..0], context, length, count, self.is_array)?;
+                    return Ok((StructOrValue::Value(value), userdata));
+                }
                 let (value, remaining) = value_info.decode(
                     userdata,
-                    length_count_values,
+                    context,
                     length,
                     count,
                     self.is_array,
@@ -357,18 +979,100 @@ impl PropertyInfo {
                 userdata = remaining;
                 Ok((StructOrValue::Value(value), userdata))
             }
+            PropertyNestedInfo::CustomSchema(..) => {
+                if self.has_referenced_zero_length(length) {
+                    let (value, _) = Value::parse(&userdata[..0], InType::Binary, length, count, self.is_array)?;
+                    return Ok((StructOrValue::Value(value), userdata));
+                }
+                let (value, remaining) = Value::parse(userdata, InType::Binary, length, count, self.is_array)?;
+                Ok((StructOrValue::Value(value), remaining))
+            }
+        }
+    }
+}
+
+/// Properties of schemas written by hand, e.g. for [`crate::mock::MockEventSource`].
+#[cfg(any(test, feature = "test-util"))]
+impl PropertyInfo {
+    /// A single value, with the length of `in_type` or else sized by the payload.
+    pub fn value(name: &str, in_type: InType, out_type: OutType) -> Self {
+        Self {
+            length: PropertyValue::Constant(in_type.size().unwrap_or(0)),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
         }
     }
+
+    /// The property with a fixed length, e.g. of binary values.
+    pub fn with_length(mut self, length: usize) -> Self {
+        self.length = PropertyValue::Constant(length);
+        self
+    }
+
+    /// An array of `count` of the property's values.
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = PropertyValue::Constant(count);
+        self.is_array = true;
+        self
+    }
 }
 
+/// The properties of an event or of a struct property.
+///
+/// Build it with [`PropertyStructInfo::new`] or from a `Vec<PropertyInfo>`. It can't be
+/// built with a struct literal: the field names that decoded [`Struct`]s share are
+/// collected once, when the struct is built.
 #[derive(Debug, PartialEq, Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "PropertyStructFields"))]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct PropertyStructInfo {
+    /// The properties of the struct. Renaming them requires building the struct again
+    /// with [`PropertyStructInfo::new`], which takes the names decoded structs carry.
     pub fields: Vec<PropertyInfo>,
+    /// Names of `fields`, shared by all [`Struct`]s decoded with this schema.
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    #[cfg_attr(feature = "schemars", schemars(skip))]
+    names: Arc<[String]>,
+}
+
+/// The serialized form of [`PropertyStructInfo`], whose field names are taken again
+/// when deserializing.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+struct PropertyStructFields {
+    fields: Vec<PropertyInfo>,
+}
+
+#[cfg(feature = "serde")]
+impl From<PropertyStructFields> for PropertyStructInfo {
+    fn from(value: PropertyStructFields) -> Self {
+        Self::new(value.fields)
+    }
+}
+
+impl From<Vec<PropertyInfo>> for PropertyStructInfo {
+    fn from(fields: Vec<PropertyInfo>) -> Self {
+        Self::new(fields)
+    }
 }
 
 impl PropertyStructInfo {
+    pub fn new(fields: Vec<PropertyInfo>) -> Self {
+        let names = fields.iter().map(|field| field.value.name().to_string()).collect();
+        Self { fields, names }
+    }
+
     pub fn parse(
         trace_event_info: &TraceEventInfo,
         length_count_properties: &HashSet<usize>,
@@ -421,7 +1125,14 @@ impl PropertyStructInfo {
                         )?,
                     )
                 } else if (property.Flags.0 & PropertyHasCustomSchema.0) != 0 {
-                    todo!()
+                    let schema_offset = property.Anonymous1.customSchemaType.CustomSchemaOffset;
+                    let schema = trace_event_info.custom_schema(schema_offset).ok_or_else(|| {
+                        ParseError::InvalidTraceEventInfo(format!(
+                            "custom schema of property {} at offset {} is truncated",
+                            name, schema_offset
+                        ))
+                    })?;
+                    PropertyNestedInfo::CustomSchema(name, schema.to_vec())
                 } else {
                     let map_name_offset = property.Anonymous1.nonStructType.MapNameOffset;
                     let map_name = trace_event_info.offset_string(map_name_offset, false).map(String::from_utf16).transpose()?;
@@ -446,23 +1157,92 @@ impl PropertyStructInfo {
             }
         }
 
-        Ok(Self { fields })
+        Ok(Self::new(fields))
+    }
+
+    /// Append the leaf properties of this struct to `properties`, with their paths
+    /// starting with `prefix`, see [`EventInfo::properties_flat`].
+    fn flatten_into<'a>(&'a self, prefix: &str, properties: &mut Vec<FlatProperty<'a>>) {
+        for field in &self.fields {
+            let path = format!("{}{}", prefix, field.value.name());
+            let (in_type, out_type, struct_array) = match &field.value {
+                PropertyNestedInfo::Struct(_, struct_info) if !field.is_array => {
+                    struct_info.flatten_into(&format!("{}.", path), properties);
+                    continue;
+                }
+                PropertyNestedInfo::Struct(_, struct_info) => (InType::Null, None, Some(struct_info)),
+                PropertyNestedInfo::Value(_, value_info) => (value_info.in_type, Some(value_info.out_type), None),
+                PropertyNestedInfo::CustomSchema(..) => (InType::Binary, None, None),
+            };
+            properties.push(FlatProperty {
+                path,
+                in_type,
+                out_type,
+                is_array: field.is_array,
+                struct_array,
+            });
+        }
+    }
+
+    /// Returns the size of the struct in bytes, or None if any field has a variable size.
+    pub fn fixed_size(&self) -> Option<usize> {
+        self.fields.iter().map(PropertyInfo::fixed_size).sum()
+    }
+
+    /// Returns the size of the leading fields that have a fixed size.
+    pub fn fixed_prefix_size(&self) -> usize {
+        self.fields.iter().map_while(PropertyInfo::fixed_size).sum()
+    }
+
+    /// Returns the sum of the sizes of all fixed size fields.
+    pub fn min_size(&self) -> usize {
+        self.fields.iter().filter_map(PropertyInfo::fixed_size).sum()
+    }
+
+    /// Names of the fields, in order, as attached to decoded [`Struct`]s.
+    pub fn field_names(&self) -> Arc<[String]> {
+        Arc::clone(&self.names)
     }
 
     pub fn decode<'b>(
         &self,
         mut userdata: &'b [u8],
-        length_count_values: &mut HashMap<usize, usize>,
+        context: &mut DecodeContext,
     ) -> Result<(Struct<'b>, &'b [u8]), ParseError> {
         let mut values = Vec::with_capacity(self.fields.len());
+        let struct_len = userdata.len();
 
         for field in &self.fields {
-            let (value, remaining) = field.decode(userdata, length_count_values)?;
+            let offset = struct_len - userdata.len();
+            let (value, remaining) = field
+                .decode(userdata, context)
+                .map_err(|err| err.at_property(field.value.name(), offset))?;
             userdata = remaining;
             values.push(value);
         }
 
-        Ok((Struct { values }, userdata))
+        Ok((Struct { values, names: Some(self.field_names()) }, userdata))
+    }
+}
+
+/// A leaf property of a schema, see [`EventInfo::properties_flat`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatProperty<'a> {
+    /// Names of the enclosing structs and of the property, separated by dots.
+    pub path: String,
+    /// [`InType::Null`] for struct arrays.
+    pub in_type: InType,
+    /// None for struct arrays and custom schema properties.
+    pub out_type: Option<OutType>,
+    pub is_array: bool,
+    /// Schema of the elements, if this is a struct array.
+    pub struct_array: Option<&'a PropertyStructInfo>,
+}
+
+impl FlatProperty<'_> {
+    /// Returns true if this is an array of structs, whose members are not flattened.
+    pub fn is_struct_array(&self) -> bool {
+        self.struct_array.is_some()
     }
 }
 
@@ -472,6 +1252,10 @@ impl PropertyStructInfo {
 pub enum PropertyNestedInfo {
     Struct(String, PropertyStructInfo),
     Value(String, PropertyValueInfo),
+    /// A property described by a custom schema, e.g. of some TraceLogging providers.
+    /// Holds the schema as TDH returns it: protocol (u16), length (u16) and the schema.
+    /// Decoded as opaque binary of the declared length.
+    CustomSchema(String, Vec<u8>),
 }
 
 impl PropertyNestedInfo {
@@ -479,6 +1263,28 @@ impl PropertyNestedInfo {
         match self {
             Self::Struct(name, _) => name,
             Self::Value(name, _) => name,
+            Self::CustomSchema(name, _) => name,
+        }
+    }
+
+    /// The protocol of a custom schema property, the first u16 of its blob.
+    pub fn protocol(&self) -> Option<u16> {
+        match self {
+            Self::CustomSchema(_, blob) => Some(u16::from_le_bytes(blob.get(..2)?.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// The schema of a custom schema property, without the protocol and length in front.
+    ///
+    /// None if the blob is shorter than its declared length.
+    pub fn custom_schema(&self) -> Option<&[u8]> {
+        match self {
+            Self::CustomSchema(_, blob) => {
+                let length = usize::from(u16::from_le_bytes(blob.get(2..4)?.try_into().ok()?));
+                blob.get(4..4 + length)
+            }
+            _ => None,
         }
     }
 }
@@ -500,12 +1306,14 @@ impl PropertyValueInfo {
     pub fn decode<'b>(
         &self,
         userdata: &'b [u8],
-        length_count_values: &mut HashMap<usize, usize>,
+        context: &mut DecodeContext,
         length: usize,
         count: usize,
         is_array: bool,
     ) -> Result<(Value<'b>, &'b [u8]), ParseError> {
-        let (value, remainder) = Value::parse(userdata, self.in_type, length, count, is_array)?;
+        let (mut value, remainder) =
+            Value::parse_with_pointer_size(userdata, self.in_type, length, count, is_array, context.pointer_size)?;
+        value.out_type = Some(self.out_type);
         if let Some(handle) = self.handle {
             if count != 1 || value.is_array() {
                 return Err(ParseError::PropertySizeNotAScalar);
@@ -517,7 +1325,7 @@ impl PropertyValueInfo {
                 InValue::HexInt32(val) => val.get(0).unwrap().try_into()?,
                 _ => return Err(ParseError::InvalidPropertySizeType(self.in_type)),
             };
-            length_count_values.insert(handle, int_value);
+            context.length_count_values.insert(handle, int_value);
         }
         Ok((value, remainder))
     }
@@ -526,34 +1334,33 @@ impl PropertyValueInfo {
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, mem::size_of, slice, sync::Arc};
+    use std::{collections::HashMap, mem::size_of, sync::Arc};
 
-    use windows::{core::GUID, Win32::System::Diagnostics::Etw::{EVENT_HEADER, EVENT_PROPERTY_INFO, EVENT_RECORD, PropertyStruct}};
+    use windows::{core::GUID, Win32::System::Diagnostics::Etw::{EVENT_HEADER, EVENT_HEADER_EXTENDED_DATA_ITEM, EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL, EVENT_HEADER_EXT_TYPE_PROV_TRAITS, EVENT_HEADER_FLAG_CLASSIC_HEADER, EVENT_MAP_ENTRY, EVENT_MAP_INFO, EVENT_PROPERTY_INFO, EVENT_RECORD, EVENTMAP_INFO_FLAG_MANIFEST_PATTERNMAP, PropertyStruct}};
 
     use crate::{
-        error::ParseError,
+        error::{ParseError, TraceError},
+        failures::decode_hex,
+        provider::PROCESS_GUID,
         schema::{in_type::InType, out_type::OutType},
-        tdh_wrappers::ProviderEventDescriptors,
-        values::{compound::{StringOrStruct, StructOrValue}, in_value::InValue, value::Value},
+        tdh_wrappers::{EventMapInfo, ProviderEventDescriptors},
+        values::{
+            compound::{StringOrStruct, StringOrStructOwned, StructOrValue, StructOrValueOwned, Truncation},
+            event::{EventOwned, EventRecord},
+            in_value::{InValue, InValueOwned},
+            value::Value,
+        },
     };
 
     use super::{
-        EventInfo, PropertyInfo, PropertyNestedInfo, PropertyStructInfo, PropertyValue, PropertyValueInfo, SchemaCache, StringOrIntegerMap,
+        decode_raw, format_pattern, DecodeContext, EventInfo, PropertyInfo, PropertyNestedInfo, PropertyStructInfo, PropertyValue, PropertyValueInfo, SchemaCache, StringOrIntegerMap,
     };
 
-    fn decode_hex(hex: &str) -> Vec<u8> {
-        assert_eq!(hex.len() % 2, 0, "hex input must have an even number of digits");
-        (0..hex.len())
-            .step_by(2)
-            .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap())
-            .collect()
-    }
-
     fn event_record_from_hex(header_hex: &str, userdata_hex: &str) -> (EVENT_RECORD, Vec<u8>) {
-        let header = decode_hex(header_hex);
+        let header = decode_hex(header_hex).unwrap();
         assert_eq!(header.len(), size_of::<EVENT_HEADER>());
 
-        let mut userdata = decode_hex(userdata_hex);
+        let mut userdata = decode_hex(userdata_hex).unwrap();
         let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
 
         unsafe {
@@ -592,16 +1399,10 @@ mod tests {
         assert_eq!(event_record.EventHeader.EventDescriptor.Id, 1);
         assert_eq!(event_record.EventHeader.EventDescriptor.Version, 4);
 
-        let userdata = unsafe {
-            slice::from_raw_parts(
-                event_record.UserData as *const u8,
-                event_record.UserDataLength.into(),
-            )
-        };
-        let mut length_count_values = HashMap::new();
+        let userdata = EventRecord(&event_record).validated_userdata().unwrap();
         let (struc, remainder) = schema
             .properties
-            .decode(userdata, &mut length_count_values)
+            .decode(userdata, &mut DecodeContext::default())
             .unwrap();
         assert!(
             remainder.is_empty(),
@@ -638,12 +1439,12 @@ mod tests {
                 },
             ),
         };
-        let mut length_count_values: HashMap<usize, usize> = std::collections::HashMap::new();
-        let (value, remaining) = decoder.decode(&data, &mut length_count_values).unwrap();
+        let (value, remaining) = decoder.decode(&data, &mut DecodeContext::default()).unwrap();
         assert_eq!(remaining, &[] as &[u8]);
         let StructOrValue::Value(Value {
             raw,
             is_array,
+            out_type,
             value: InValue::UInt8(val),
         }) = value
         else {
@@ -653,6 +1454,7 @@ mod tests {
         assert_eq!(val.len(), 1);
         assert_eq!(raw, &data);
         assert_eq!(is_array, false);
+        assert_eq!(out_type, Some(OutType::Byte));
     }
 
     #[test]
@@ -672,12 +1474,12 @@ mod tests {
                 },
             ),
         };
-        let mut length_count_values: HashMap<usize, usize> = std::collections::HashMap::new();
-        let (value, remaining) = decoder.decode(&data, &mut length_count_values).unwrap();
+        let (value, remaining) = decoder.decode(&data, &mut DecodeContext::default()).unwrap();
         assert_eq!(remaining, &[] as &[u8]);
         let StructOrValue::Value(Value {
             raw,
             is_array,
+            out_type,
             value: InValue::UInt32(val),
         }) = value
         else {
@@ -687,6 +1489,7 @@ mod tests {
         assert_eq!(val.len(), 1);
         assert_eq!(raw, &data);
         assert_eq!(is_array, false);
+        assert_eq!(out_type, Some(OutType::Int));
     }
 
     #[test]
@@ -709,12 +1512,12 @@ mod tests {
                 },
             ),
         };
-        let mut length_count_values: HashMap<usize, usize> = std::collections::HashMap::new();
-        let (value, remaining) = decoder.decode(&data, &mut length_count_values).unwrap();
+        let (value, remaining) = decoder.decode(&data, &mut DecodeContext::default()).unwrap();
         assert_eq!(remaining, &[] as &[u8]);
         let StructOrValue::Value(Value {
             raw,
             is_array,
+            out_type,
             value: InValue::UInt32(val),
         }) = value
         else {
@@ -726,6 +1529,7 @@ mod tests {
         assert_eq!(val.len(), 3);
         assert_eq!(raw, &data);
         assert_eq!(is_array, false);
+        assert_eq!(out_type, Some(OutType::Int));
     }
 
     #[test]
@@ -745,18 +1549,334 @@ mod tests {
                 },
             ),
         };
-        let mut length_count_values: HashMap<usize, usize> = std::collections::HashMap::new();
         let ParseError::UnexpectedSize =
-            decoder.decode(&data, &mut length_count_values).unwrap_err()
+            decoder.decode(&data, &mut DecodeContext::default()).unwrap_err()
         else {
             panic!("Expected ParseError::UnexpectedSize");
         };
     }
 
     #[test]
-    fn test_string_or_integer_map_has_no_map_name_for_zero_offset() {
-        let property = unsafe { std::mem::zeroed::<EVENT_PROPERTY_INFO>() };
-        assert!(!StringOrIntegerMap::has_map_name(&property));
+    fn test_decode_error_reports_property_path_and_offset() {
+        let value = |name: &str, in_type, length| PropertyInfo {
+            length: PropertyValue::Constant(length),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type: OutType::Int,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        };
+        let element = PropertyStructInfo::new(vec![value("Low", InType::UInt16, 2), value("High", InType::UInt32, 4)]);
+        let schema = PropertyStructInfo::new(vec![
+            value("Header", InType::UInt32, 4),
+            PropertyInfo {
+                length: PropertyValue::Constant(0),
+                count: PropertyValue::Constant(2),
+                is_array: true,
+                value: PropertyNestedInfo::Struct("Items".to_string(), element),
+            },
+        ]);
+
+        // Header, one complete item and the first field of the second item
+        let data = [0u8; 4 + 6 + 2];
+        let err = schema.decode(&data, &mut DecodeContext::default()).unwrap_err();
+
+        assert_eq!(err.property_path(), Some("Items[1].High"));
+        assert_eq!(err.offset(), Some(12));
+        let ParseError::PrematureEndOfData = err.root_cause() else {
+            panic!("Expected ParseError::PrematureEndOfData, got {:?}", err);
+        };
+    }
+
+    #[test]
+    fn test_decode_pointers_with_record_pointer_size() {
+        let value = |name: &str, in_type: InType| PropertyInfo {
+            length: PropertyValue::Constant(in_type.size().unwrap()),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type: OutType::HexInt64,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        };
+        let schema = PropertyStructInfo::new(vec![
+            value("Address", InType::Pointer),
+            value("Size", InType::SizeT),
+            value("Flags", InType::UInt32),
+        ]);
+
+        for pointer_size in [4, 8] {
+            let mut data = Vec::new();
+            data.extend_from_slice(&0x1000u64.to_le_bytes()[..pointer_size]);
+            data.extend_from_slice(&0x20u64.to_le_bytes()[..pointer_size]);
+            data.extend_from_slice(&7u32.to_le_bytes());
+
+            let (struc, remaining) = schema.decode(&data, &mut DecodeContext::new(pointer_size)).unwrap();
+
+            assert!(remaining.is_empty());
+            let [
+                StructOrValue::Value(Value { value: InValue::Pointer(address), raw: address_raw, .. }),
+                StructOrValue::Value(Value { value: InValue::SizeT(size), .. }),
+                StructOrValue::Value(Value { value: InValue::UInt32(flags), .. }),
+            ] = &struc.values[..]
+            else {
+                panic!("Unexpected values for {}-byte pointers: {:?}", pointer_size, struc.values);
+            };
+            assert_eq!(address.get(0), Some(0x1000));
+            assert_eq!(address_raw.len(), pointer_size);
+            assert_eq!(size.get(0), Some(0x20));
+            assert_eq!(flags.get(0), Some(7));
+        }
+    }
+
+    #[test]
+    fn test_decode_pointer_rejects_unknown_pointer_size() {
+        let data = [0u8; 8];
+        let err = Value::parse_with_pointer_size(&data, InType::Pointer, 8, 1, false, 2).unwrap_err();
+        let ParseError::UnexpectedSize = err else {
+            panic!("Expected ParseError::UnexpectedSize, got {:?}", err);
+        };
+    }
+
+    fn counted_schema(counted: PropertyNestedInfo) -> PropertyStructInfo {
+        let value = |name: &str, in_type, length, handle| PropertyInfo {
+            length: PropertyValue::Constant(length),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type: OutType::Int,
+                    map_name: None,
+                    handle,
+                },
+            ),
+        };
+        PropertyStructInfo::new(vec![
+            value("Count", InType::UInt16, 2, Some(0)),
+            PropertyInfo {
+                length: PropertyValue::Constant(size_of::<u32>()),
+                count: PropertyValue::Reference(0),
+                is_array: true,
+                value: counted,
+            },
+            value("Trailer", InType::UInt16, 2, None),
+        ])
+    }
+
+    fn assert_trailer(value: &StructOrValue<'_>) {
+        let StructOrValue::Value(Value {
+            value: InValue::UInt16(trailer),
+            ..
+        }) = value
+        else {
+            panic!("Expected the trailer to decode as UInt16, got {:?}", value);
+        };
+        assert_eq!(trailer.get(0), Some(0xbeef));
+    }
+
+    #[test]
+    fn test_decode_zero_count_scalar_array() {
+        let schema = counted_schema(PropertyNestedInfo::Value(
+            "Values".to_string(),
+            PropertyValueInfo {
+                in_type: InType::UInt32,
+                out_type: OutType::Int,
+                map_name: None,
+                handle: None,
+            },
+        ));
+        let data = [0x00, 0x00, 0xef, 0xbe];
+        let (struc, remaining) = schema.decode(&data, &mut DecodeContext::default()).unwrap();
+
+        assert!(remaining.is_empty());
+        let StructOrValue::Value(Value {
+            value: InValue::UInt32(values),
+            raw,
+            is_array,
+            ..
+        }) = &struc.values[1]
+        else {
+            panic!("Expected an empty UInt32 array, got {:?}", struc.values[1]);
+        };
+        assert!(values.is_empty());
+        assert!(raw.is_empty());
+        assert!(*is_array);
+        assert_trailer(&struc.values[2]);
+    }
+
+    #[test]
+    fn test_decode_zero_count_binary_array() {
+        let schema = counted_schema(PropertyNestedInfo::Value(
+            "Blobs".to_string(),
+            PropertyValueInfo {
+                in_type: InType::Binary,
+                out_type: OutType::HexBinary,
+                map_name: None,
+                handle: None,
+            },
+        ));
+        let data = [0x00, 0x00, 0xef, 0xbe];
+        let (struc, remaining) = schema.decode(&data, &mut DecodeContext::default()).unwrap();
+
+        assert!(remaining.is_empty());
+        let StructOrValue::Value(Value {
+            value: InValue::Binary(blobs),
+            is_array,
+            ..
+        }) = &struc.values[1]
+        else {
+            panic!("Expected an empty binary array, got {:?}", struc.values[1]);
+        };
+        assert!(blobs.is_empty());
+        assert!(*is_array);
+        assert_trailer(&struc.values[2]);
+    }
+
+    #[test]
+    fn test_decode_binary_with_referenced_zero_length() {
+        let value = |name: &str, in_type, length, handle| PropertyInfo {
+            length,
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type: OutType::Null,
+                    map_name: None,
+                    handle,
+                },
+            ),
+        };
+        let schema = PropertyStructInfo::new(vec![
+            value("Size", InType::UInt16, PropertyValue::Constant(2), Some(0)),
+            value("Blob", InType::Binary, PropertyValue::Reference(0), None),
+            value("Trailer", InType::UInt16, PropertyValue::Constant(2), None),
+            value("Rest", InType::Binary, PropertyValue::Constant(0), None),
+        ]);
+        let data = [0x00, 0x00, 0xef, 0xbe, 0x01, 0x02];
+        let (struc, remaining) = schema.decode(&data, &mut DecodeContext::default()).unwrap();
+
+        assert!(remaining.is_empty());
+        let StructOrValue::Value(Value { value: InValue::Binary(blob), raw, .. }) = &struc.values[1] else {
+            panic!("Expected an empty blob, got {:?}", struc.values[1]);
+        };
+        assert_eq!(blob, &[&[] as &[u8]]);
+        assert!(raw.is_empty());
+        assert_trailer(&struc.values[2]);
+        let StructOrValue::Value(Value { value: InValue::Binary(rest), .. }) = &struc.values[3] else {
+            panic!("Expected the rest of the data, got {:?}", struc.values[3]);
+        };
+        assert_eq!(rest, &[&[0x01, 0x02][..]]);
+    }
+
+    #[test]
+    fn test_decode_string_with_referenced_zero_length() {
+        let value = |name: &str, in_type, length, handle| PropertyInfo {
+            length,
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type: OutType::Null,
+                    map_name: None,
+                    handle,
+                },
+            ),
+        };
+        let schema = PropertyStructInfo::new(vec![
+            value("Length", InType::UInt16, PropertyValue::Constant(2), Some(0)),
+            value("Name", InType::NonNullTerminatedString, PropertyValue::Reference(0), None),
+            value("Trailer", InType::UInt16, PropertyValue::Constant(2), None),
+        ]);
+        let data = [0x00, 0x00, 0xef, 0xbe];
+        let (struc, remaining) = schema.decode(&data, &mut DecodeContext::default()).unwrap();
+
+        assert!(remaining.is_empty());
+        let StructOrValue::Value(Value { value: InValue::NonNullTerminatedString(name), .. }) = &struc.values[1] else {
+            panic!("Expected an empty string, got {:?}", struc.values[1]);
+        };
+        assert!(name.is_empty());
+        assert_trailer(&struc.values[2]);
+    }
+
+    #[test]
+    fn test_decode_zero_count_struct_array() {
+        let element = PropertyStructInfo::new(vec![PropertyInfo {
+            length: PropertyValue::Constant(size_of::<u32>()),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                "Field".to_string(),
+                PropertyValueInfo {
+                    in_type: InType::UInt32,
+                    out_type: OutType::Int,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        }]);
+        let schema = counted_schema(PropertyNestedInfo::Struct("Items".to_string(), element));
+        let data = [0x00, 0x00, 0xef, 0xbe];
+        let (struc, remaining) = schema.decode(&data, &mut DecodeContext::default()).unwrap();
+
+        assert!(remaining.is_empty());
+        let StructOrValue::Struct(items) = &struc.values[1] else {
+            panic!("Expected an empty struct array, got {:?}", struc.values[1]);
+        };
+        assert!(items.values.is_empty());
+        assert!(items.is_array);
+        assert_trailer(&struc.values[2]);
+    }
+
+    #[test]
+    fn test_fixed_prefix_size_stops_at_variable_field() {
+        let value = |in_type, length: usize, count: PropertyValue| PropertyInfo {
+            length: PropertyValue::Constant(length),
+            count,
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                "test".to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type: OutType::Null,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        };
+        let schema = PropertyStructInfo::new(vec![
+            value(InType::UInt32, 4, PropertyValue::Constant(1)),
+            value(InType::UInt16, 2, PropertyValue::Constant(3)),
+            value(InType::UnicodeString, 0, PropertyValue::Constant(1)),
+            value(InType::UInt64, 8, PropertyValue::Constant(1)),
+            value(InType::UInt8, 1, PropertyValue::Reference(0)),
+        ]);
+
+        assert_eq!(schema.fixed_prefix_size(), 10);
+        assert_eq!(schema.min_size(), 18);
+        assert_eq!(schema.fixed_size(), None);
+    }
+
+    #[test]
+    fn test_string_or_integer_map_has_no_map_name_for_zero_offset() {
+        let property = unsafe { std::mem::zeroed::<EVENT_PROPERTY_INFO>() };
+        assert!(!StringOrIntegerMap::has_map_name(&property));
     }
 
     #[test]
@@ -766,6 +1886,107 @@ mod tests {
         assert!(!StringOrIntegerMap::has_map_name(&property));
     }
 
+    /// Lay out an `EVENT_MAP_INFO` of a pattern map like TDH does: header, entries, then strings.
+    fn synthetic_pattern_map(format: &str, entries: &[(&str, &str)]) -> EventMapInfo {
+        let header_size = std::mem::offset_of!(EVENT_MAP_INFO, MapEntryArray);
+        let mut buffer = vec![0u8; header_size + entries.len() * size_of::<EVENT_MAP_ENTRY>()];
+        let push_string = |buffer: &mut Vec<u8>, text: &str| {
+            let offset = buffer.len() as u32;
+            buffer.extend(text.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+            offset
+        };
+
+        let mut info = EVENT_MAP_INFO {
+            Flag: EVENTMAP_INFO_FLAG_MANIFEST_PATTERNMAP,
+            EntryCount: entries.len() as u32,
+            ..Default::default()
+        };
+        info.NameOffset = push_string(&mut buffer, "PatternMap");
+        info.Anonymous.FormatStringOffset = push_string(&mut buffer, format);
+        // Written first, the header's trailing entry is overwritten by the actual entries
+        unsafe {
+            std::ptr::write_unaligned(buffer.as_mut_ptr().cast(), info);
+        }
+        for (idx, (input, output)) in entries.iter().enumerate() {
+            let mut entry = EVENT_MAP_ENTRY {
+                OutputOffset: push_string(&mut buffer, output),
+                ..Default::default()
+            };
+            entry.Anonymous.InputOffset = push_string(&mut buffer, input);
+            unsafe {
+                std::ptr::write_unaligned(buffer[header_size + idx * size_of::<EVENT_MAP_ENTRY>()..].as_mut_ptr().cast(), entry);
+            }
+        }
+        EventMapInfo { buffer }
+    }
+
+    #[test]
+    fn test_pattern_map_names_matching_values() {
+        let map_info = synthetic_pattern_map("%08x", &[("00000001", "First"), ("0000002a", "Answer")]);
+        let map = StringOrIntegerMap::from_event_map_info(&map_info).unwrap();
+
+        let StringOrIntegerMap::Pattern { format, entries } = &map else {
+            panic!("Expected a pattern map, got {:?}", map);
+        };
+        assert_eq!(format, "%08x");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(map.name(1), Some("First"));
+        assert_eq!(map.name(42), Some("Answer"));
+        assert_eq!(map.resolve(42).as_deref(), Some("Answer"));
+    }
+
+    #[test]
+    fn test_pattern_map_falls_back_for_unmatched_values() {
+        let map_info = synthetic_pattern_map("%u", &[("1", "First")]);
+        let map = StringOrIntegerMap::from_event_map_info(&map_info).unwrap();
+
+        assert_eq!(map.name(2), None);
+        assert_eq!(map.resolve(2), None);
+        assert_eq!(map.format_value(2), "2");
+        assert_eq!(map.pattern_text(2).as_deref(), Some("2"));
+        assert_eq!(StringOrIntegerMap::Integer(HashMap::new()).pattern_text(2), None);
+    }
+
+    #[test]
+    fn test_format_value_of_value_map() {
+        let map = StringOrIntegerMap::Integer(HashMap::from([
+            (1, "READ".to_string()),
+            (2, "WRITE".to_string()),
+        ]));
+        assert_eq!(map.format_value(1), "READ");
+        // Value maps only name exact matches
+        assert_eq!(map.format_value(3), "3");
+    }
+
+    #[test]
+    fn test_format_value_of_bitmap_with_overlapping_bits() {
+        let map = StringOrIntegerMap::Bitmap(HashMap::from([
+            (0x1, "READ".to_string()),
+            (0x2, "WRITE".to_string()),
+            (0x3, "READ_WRITE".to_string()),
+            (0x4, "EXECUTE".to_string()),
+        ]));
+        assert_eq!(map.format_value(0x3), "READ_WRITE");
+        assert_eq!(map.format_value(0x5), "READ | EXECUTE");
+        assert_eq!(map.format_value(0x7), "READ_WRITE | EXECUTE");
+        assert_eq!(map.format_value(0x9), "READ | 0x8");
+        assert_eq!(map.format_value(0x8), "0x8");
+    }
+
+    #[test]
+    fn test_format_pattern() {
+        assert_eq!(format_pattern("%u", 42).as_deref(), Some("42"));
+        assert_eq!(format_pattern("%d", u32::MAX).as_deref(), Some("-1"));
+        assert_eq!(format_pattern("%08x", 0x2a).as_deref(), Some("0000002a"));
+        assert_eq!(format_pattern("%#X", 0x2a).as_deref(), Some("0X2A"));
+        assert_eq!(format_pattern("Code %lu%%", 7).as_deref(), Some("Code 7%"));
+        assert_eq!(format_pattern("%-4u|", 7).as_deref(), Some("7   |"));
+        assert_eq!(format_pattern("%I64x", 255).as_deref(), Some("ff"));
+        assert_eq!(format_pattern("no conversion", 1), None);
+        assert_eq!(format_pattern("%u %u", 1), None);
+        assert_eq!(format_pattern("%s", 1), None);
+    }
+
     #[test]
     fn test_schema_cache_keys_by_event_version() {
         let provider_guid = GUID::try_from("22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716").unwrap();
@@ -774,14 +1995,18 @@ mod tests {
             provider_guid,
             event_id: 1,
             event_version: 1,
-            properties: PropertyStructInfo { fields: Vec::new() },
+            provider_name: None,
+            provider_group_guid: None,
+            properties: PropertyStructInfo::new(Vec::new()),
             maps: HashMap::new(),
         });
         let schema_v4 = Arc::new(EventInfo {
             provider_guid,
             event_id: 1,
             event_version: 4,
-            properties: PropertyStructInfo { fields: Vec::new() },
+            provider_name: None,
+            provider_group_guid: None,
+            properties: PropertyStructInfo::new(Vec::new()),
             maps: HashMap::new(),
         });
 
@@ -796,9 +2021,772 @@ mod tests {
             .unwrap()
             .insert((provider_guid, 1, 4), Arc::clone(&schema_v4));
 
-        assert!(Arc::ptr_eq(&cache.get(provider_guid, 1, 1).unwrap(), &schema_v1));
-        assert!(Arc::ptr_eq(&cache.get(provider_guid, 1, 4).unwrap(), &schema_v4));
-        assert!(cache.get(provider_guid, 1, 0).is_none());
+        assert!(Arc::ptr_eq(&cache.get(provider_guid, 1, 1).unwrap().unwrap(), &schema_v1));
+        assert!(Arc::ptr_eq(&cache.get(provider_guid, 1, 4).unwrap().unwrap(), &schema_v4));
+        assert!(cache.get(provider_guid, 1, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_schema_cache_clear() {
+        let cache = SchemaCache::new();
+        let schema = cache.insert(three_uint32_event_info());
+        cache
+            .classic_schemas
+            .write()
+            .unwrap()
+            .insert((PROCESS_GUID, 1, 0), Arc::clone(&schema));
+        cache
+            .tlg_schemas
+            .write()
+            .unwrap()
+            .entry(PROCESS_GUID)
+            .or_default()
+            .insert(Box::new([7]), Arc::clone(&schema));
+
+        cache.clear();
+
+        assert!(cache.schemas.read().unwrap().is_empty());
+        assert!(cache.tlg_schemas.read().unwrap().is_empty());
+        assert!(cache.classic_schemas.read().unwrap().is_empty());
+        // Schemas handed out before stay usable
+        assert_eq!(schema.properties.fields.len(), 3);
+    }
+
+    #[test]
+    fn test_insert_is_keyed_by_schema() {
+        let cache = SchemaCache::new();
+        let schema = cache.insert(EventInfo::new(PROCESS_GUID, 7, 2, PropertyStructInfo::new(Vec::new())));
+        assert!(Arc::ptr_eq(&cache.get(PROCESS_GUID, 7, 2).unwrap().unwrap(), &schema));
+        assert!(cache.get(PROCESS_GUID, 7, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_classic_events_are_cached_by_opcode() {
+        let cache = SchemaCache::new();
+        let schema = Arc::new(three_uint32_event_info());
+        cache
+            .classic_schemas
+            .write()
+            .unwrap()
+            .insert((PROCESS_GUID, 1, 4), Arc::clone(&schema));
+
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.EventHeader.Flags = EVENT_HEADER_FLAG_CLASSIC_HEADER as u16;
+        event_record.EventHeader.ProviderId = PROCESS_GUID;
+        event_record.EventHeader.EventDescriptor.Opcode = 1;
+        event_record.EventHeader.EventDescriptor.Version = 4;
+        assert!(Arc::ptr_eq(&cache.get_from_event_record(&event_record).unwrap(), &schema));
+        // The manifest schemas are keyed by id and not consulted for classic events
+        assert!(cache.get(PROCESS_GUID, 0, 4).unwrap().is_none());
+    }
+
+    /// Extended data of TraceLogging events of a provider in the
+    /// `4f50731a-89cf-4782-b3e0-dce8c90476ba` group.
+    const TLG_PROVIDER_TRAITS: &[u8] = include_bytes!("../../tests/resources/tracelogging/provider_traits.bin");
+    const TLG_PROCESS_STARTED: &[u8] = include_bytes!("../../tests/resources/tracelogging/process_started.bin");
+    const TLG_PROCESS_STOPPED: &[u8] = include_bytes!("../../tests/resources/tracelogging/process_stopped.bin");
+    const TLG_GROUP: GUID = GUID::from_u128(0x4f50731a_89cf_4782_b3e0_dce8c90476ba);
+
+    fn tlg_extended_data(metadata: &[u8]) -> [EVENT_HEADER_EXTENDED_DATA_ITEM; 2] {
+        let item = |ext_type: u32, data: &[u8]| EVENT_HEADER_EXTENDED_DATA_ITEM {
+            ExtType: ext_type as u16,
+            DataSize: data.len() as u16,
+            DataPtr: data.as_ptr() as u64,
+            ..Default::default()
+        };
+        [
+            item(EVENT_HEADER_EXT_TYPE_PROV_TRAITS, TLG_PROVIDER_TRAITS),
+            item(EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL, metadata),
+        ]
+    }
+
+    fn tlg_event_record(provider: GUID, items: &mut [EVENT_HEADER_EXTENDED_DATA_ITEM]) -> EVENT_RECORD {
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.EventHeader.ProviderId = provider;
+        event_record.ExtendedData = items.as_mut_ptr();
+        event_record.ExtendedDataCount = items.len() as u16;
+        event_record
+    }
+
+    #[test]
+    fn test_tracelogging_provider_traits_fixture() {
+        let mut items = tlg_extended_data(TLG_PROCESS_STARTED);
+        let event_record = tlg_event_record(PROCESS_GUID, &mut items);
+        let record = EventRecord(&event_record);
+
+        let traits = record.provider_traits().unwrap();
+        assert_eq!(traits.name.as_deref(), Some("Contoso.Windows.ProcessMonitor"));
+        assert_eq!(traits.group_guid, Some(TLG_GROUP));
+        assert_eq!(record.tracelogging_schema(), Some(TLG_PROCESS_STARTED));
+    }
+
+    #[test]
+    fn test_tracelogging_schemas_are_cached_by_metadata() {
+        let cache = SchemaCache::new();
+        let started = Arc::new(three_uint32_event_info());
+        let stopped = Arc::new(three_uint32_event_info());
+        {
+            let mut tlg_schemas = cache.tlg_schemas.write().unwrap();
+            let group_schemas = tlg_schemas.entry(TLG_GROUP).or_default();
+            group_schemas.insert(TLG_PROCESS_STARTED.into(), Arc::clone(&started));
+            group_schemas.insert(TLG_PROCESS_STOPPED.into(), Arc::clone(&stopped));
+        }
+
+        let mut items = tlg_extended_data(TLG_PROCESS_STARTED);
+        let event_record = tlg_event_record(PROCESS_GUID, &mut items);
+        assert!(Arc::ptr_eq(&cache.get_from_event_record(&event_record).unwrap(), &started));
+
+        let mut items = tlg_extended_data(TLG_PROCESS_STOPPED);
+        let event_record = tlg_event_record(PROCESS_GUID, &mut items);
+        assert!(Arc::ptr_eq(&cache.get_from_event_record(&event_record).unwrap(), &stopped));
+
+        // Another provider of the group shares the schemas
+        let mut items = tlg_extended_data(TLG_PROCESS_STARTED);
+        let event_record = tlg_event_record(GUID::from_u128(0x6d2b_6f0e_0c1d_4a3e_9f5b_2c7a_8e41_d093), &mut items);
+        assert!(Arc::ptr_eq(&cache.get_from_event_record(&event_record).unwrap(), &started));
+    }
+
+    #[test]
+    fn test_poisoned_schema_cache_returns_error() {
+        let provider_guid = GUID::try_from("22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716").unwrap();
+        let cache = SchemaCache::new();
+        std::thread::scope(|scope| {
+            let result = scope
+                .spawn(|| {
+                    let _guard = cache.schemas.write().unwrap();
+                    panic!("poison the schema cache");
+                })
+                .join();
+            assert!(result.is_err());
+        });
+
+        assert!(matches!(
+            cache.get(provider_guid, 1, 0),
+            Err(TraceError::Decode(ParseError::CacheMutexPoisoned))
+        ));
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.EventHeader.ProviderId = provider_guid;
+        event_record.EventHeader.EventDescriptor.Id = 1;
+        assert!(matches!(
+            cache.get_from_event_record(&event_record),
+            Err(TraceError::Decode(ParseError::CacheMutexPoisoned))
+        ));
+    }
+
+    #[test]
+    fn test_decode_raw_uses_cached_schema() {
+        let provider_guid = GUID::try_from("22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716").unwrap();
+        let cache = SchemaCache::new();
+        let schema = Arc::new(EventInfo::new(
+            provider_guid,
+            7,
+            2,
+            PropertyStructInfo::new(vec![PropertyInfo {
+                length: PropertyValue::Constant(size_of::<u32>()),
+                count: PropertyValue::Constant(1),
+                is_array: false,
+                value: PropertyNestedInfo::Value(
+                    "Status".to_string(),
+                    PropertyValueInfo {
+                        in_type: InType::UInt32,
+                        out_type: OutType::Int,
+                        map_name: None,
+                        handle: None,
+                    },
+                ),
+            }]),
+        ));
+        cache.schemas.write().unwrap().insert((provider_guid, 7, 2), Arc::clone(&schema));
+
+        let mut userdata = 0xc0000022u32.to_le_bytes();
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.EventHeader.ProviderId = provider_guid;
+        event_record.EventHeader.EventDescriptor.Id = 7;
+        event_record.EventHeader.EventDescriptor.Version = 2;
+        event_record.UserDataLength = userdata.len().try_into().unwrap();
+        event_record.UserData = userdata.as_mut_ptr() as *mut _;
+
+        let (decoded_schema, event) = unsafe { decode_raw(&event_record, &cache) }.unwrap();
+        assert!(Arc::ptr_eq(&decoded_schema, &schema));
+        let StringOrStruct::Struct(struc) = event.data else {
+            panic!("Expected a structured payload, got {:?}", event.data);
+        };
+        let StructOrValue::Value(Value {
+            value: InValue::UInt32(status),
+            ..
+        }) = &struc.values[0]
+        else {
+            panic!("Expected UInt32, got {:?}", struc.values[0]);
+        };
+        assert_eq!(status.get(0), Some(0xc0000022));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_decode_with_schemas_from_serialized_dump() {
+        let dump = r#"{
+            "22fb2cd6-0e7b-422b-a0c7-2fad1fd0e716": {
+                "7": {
+                    "versions": "1-2",
+                    "properties": [
+                        {
+                            "min_version": 1,
+                            "versions": "1-2",
+                            "length": { "Constant": 4 },
+                            "count": { "Constant": 1 },
+                            "is_array": false,
+                            "value": { "Value": ["Status", { "in_type": "UInt32", "out_type": "UnsignedInt" }] }
+                        },
+                        {
+                            "min_version": 2,
+                            "versions": "2",
+                            "length": { "Constant": 2 },
+                            "count": { "Constant": 1 },
+                            "is_array": false,
+                            "value": { "Value": ["Flags", { "in_type": "UInt16", "out_type": "UnsignedShort" }] }
+                        }
+                    ]
+                }
+            }
+        }"#;
+        let cache = SchemaCache::from_serialized(&mut serde_json::Deserializer::from_str(dump)).unwrap();
+        let provider_guid = GUID::try_from("22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716").unwrap();
+        assert_eq!(cache.get(provider_guid, 7, 1).unwrap().unwrap().properties.fields.len(), 1);
+
+        // Decoding a cached event doesn't need TdhGetEventInformation
+        let mut userdata = [0x22, 0x00, 0x00, 0xc0, 0x05, 0x00];
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.EventHeader.ProviderId = provider_guid;
+        event_record.EventHeader.EventDescriptor.Id = 7;
+        event_record.EventHeader.EventDescriptor.Version = 2;
+        event_record.UserDataLength = userdata.len().try_into().unwrap();
+        event_record.UserData = userdata.as_mut_ptr() as *mut _;
+
+        let (schema, event) = unsafe { decode_raw(&event_record, &cache) }.unwrap();
+        assert_eq!(schema.event_version, 2);
+        let StringOrStruct::Struct(struc) = event.data else {
+            panic!("Expected a structured payload, got {:?}", event.data);
+        };
+        let StructOrValue::Value(Value {
+            value: InValue::UInt16(flags),
+            ..
+        }) = &struc.values[1]
+        else {
+            panic!("Expected UInt16, got {:?}", struc.values[1]);
+        };
+        assert_eq!(flags.get(0), Some(5));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_from_serialized_rejects_invalid_provider_guid() {
+        let result = SchemaCache::from_serialized(&mut serde_json::Deserializer::from_str(r#"{ "not-a-guid": {} }"#));
+        assert!(matches!(result, Err(TraceError::Configuration(msg)) if msg.contains("not-a-guid")));
+    }
+
+    #[test]
+    fn test_decode_raw_rejects_null_record() {
+        let result = unsafe { decode_raw(std::ptr::null(), &SchemaCache::new()) };
+        assert!(matches!(result, Err(TraceError::NullEventRecord)));
+    }
+
+    #[test]
+    fn test_decode_raw_fields_splits_top_level_properties() {
+        let schema = EventInfo::new(
+            GUID::zeroed(),
+            1,
+            0,
+            PropertyStructInfo::new(vec![
+                PropertyInfo {
+                    length: PropertyValue::Constant(size_of::<u16>()),
+                    count: PropertyValue::Constant(1),
+                    is_array: false,
+                    value: PropertyNestedInfo::Value(
+                        "Count".to_string(),
+                        PropertyValueInfo {
+                            in_type: InType::UInt16,
+                            out_type: OutType::UnsignedShort,
+                            map_name: None,
+                            handle: Some(0),
+                        },
+                    ),
+                },
+                PropertyInfo {
+                    length: PropertyValue::Constant(size_of::<u32>()),
+                    count: PropertyValue::Reference(0),
+                    is_array: true,
+                    value: PropertyNestedInfo::Value(
+                        "Pids".to_string(),
+                        PropertyValueInfo {
+                            in_type: InType::UInt32,
+                            out_type: OutType::Pid,
+                            map_name: None,
+                            handle: None,
+                        },
+                    ),
+                },
+                PropertyInfo {
+                    length: PropertyValue::Constant(0),
+                    count: PropertyValue::Constant(1),
+                    is_array: false,
+                    value: PropertyNestedInfo::Struct(
+                        "Tail".to_string(),
+                        PropertyStructInfo::new(vec![PropertyInfo {
+                            length: PropertyValue::Constant(size_of::<u8>()),
+                            count: PropertyValue::Constant(1),
+                            is_array: false,
+                            value: PropertyNestedInfo::Value(
+                                "Flag".to_string(),
+                                PropertyValueInfo {
+                                    in_type: InType::UInt8,
+                                    out_type: OutType::UnsignedByte,
+                                    map_name: None,
+                                    handle: None,
+                                },
+                            ),
+                        }]),
+                    ),
+                },
+            ]),
+        );
+        let userdata = [2u8, 0, 4, 0, 0, 0, 8, 0, 0, 0, 1];
+
+        let fields = schema.decode_raw_fields(&userdata).unwrap();
+        assert_eq!(fields.len(), 3);
+        assert_eq!((fields[0].name, fields[0].data), ("Count", &userdata[..2]));
+        assert_eq!((fields[0].in_type, fields[0].out_type), (InType::UInt16, Some(OutType::UnsignedShort)));
+        assert_eq!((fields[1].name, fields[1].data), ("Pids", &userdata[2..10]));
+        assert_eq!((fields[1].in_type, fields[1].out_type), (InType::UInt32, Some(OutType::Pid)));
+        assert_eq!((fields[2].name, fields[2].data), ("Tail", &userdata[10..]));
+        assert_eq!((fields[2].in_type, fields[2].out_type), (InType::Null, None));
+
+        assert!(schema.decode_raw_fields(&userdata[..6]).is_err());
+    }
+
+    #[test]
+    fn test_properties_flat() {
+        let value = |name: &str, in_type, out_type, is_array| PropertyInfo {
+            length: PropertyValue::Constant(0),
+            count: PropertyValue::Constant(1),
+            is_array,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type,
+                    out_type,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        };
+        let structure = |name: &str, fields, is_array| PropertyInfo {
+            length: PropertyValue::Constant(0),
+            count: PropertyValue::Constant(1),
+            is_array,
+            value: PropertyNestedInfo::Struct(name.to_string(), PropertyStructInfo::new(fields)),
+        };
+        let schema = EventInfo::new(
+            GUID::zeroed(),
+            1,
+            0,
+            PropertyStructInfo::new(vec![
+                structure(
+                    "Header",
+                    vec![
+                        value("ProcessId", InType::UInt32, OutType::Pid, false),
+                        structure("Image", vec![value("Name", InType::UnicodeString, OutType::String, false)], false),
+                    ],
+                    false,
+                ),
+                value("Ports", InType::UInt16, OutType::Port, true),
+                structure("Threads", vec![value("ThreadId", InType::UInt32, OutType::Tid, false)], true),
+            ]),
+        );
+
+        let properties = schema.properties_flat().collect::<Vec<_>>();
+
+        let summary = properties
+            .iter()
+            .map(|property| (property.path.as_str(), property.in_type, property.out_type, property.is_array))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("Header.ProcessId", InType::UInt32, Some(OutType::Pid), false),
+                ("Header.Image.Name", InType::UnicodeString, Some(OutType::String), false),
+                ("Ports", InType::UInt16, Some(OutType::Port), true),
+                ("Threads", InType::Null, None, true),
+            ]
+        );
+        assert!(properties[..3].iter().all(|property| !property.is_struct_array()));
+        let threads = properties[3].struct_array.unwrap();
+        assert_eq!(threads.fields[0].value.name(), "ThreadId");
+    }
+
+    fn empty_event_info() -> EventInfo {
+        EventInfo::new(
+            GUID::try_from("22FB2CD6-0E7B-422B-A0C7-2FAD1FD0E716").unwrap(),
+            1,
+            0,
+            PropertyStructInfo::new(Vec::new()),
+        )
+    }
+
+    #[test]
+    fn test_decode_userdata_with_synthetic_schema() {
+        let schema = EventInfo::new(
+            GUID::zeroed(),
+            1,
+            0,
+            PropertyStructInfo::new(vec![PropertyInfo {
+                length: PropertyValue::Constant(size_of::<u16>()),
+                count: PropertyValue::Constant(1),
+                is_array: false,
+                value: PropertyNestedInfo::Value(
+                    "Port".to_string(),
+                    PropertyValueInfo {
+                        in_type: InType::UInt16,
+                        out_type: OutType::Port,
+                        map_name: None,
+                        handle: None,
+                    },
+                ),
+            }]),
+        );
+        let schema = schema.clone();
+
+        let StringOrStruct::Struct(struc) = schema.decode_userdata(&[0x01, 0xbb]).unwrap() else {
+            panic!("Expected a structured payload");
+        };
+        let StructOrValue::Value(Value {
+            value: InValue::UInt16(port),
+            ..
+        }) = &struc.values[0]
+        else {
+            panic!("Expected UInt16");
+        };
+        assert_eq!(port.get(0), Some(0xbb01));
+    }
+
+    #[test]
+    fn test_decoded_struct_fields_by_name() {
+        let field = |name: &str, in_type: InType, out_type: OutType, length: usize| PropertyInfo {
+            length: PropertyValue::Constant(length),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo { in_type, out_type, map_name: None, handle: None },
+            ),
+        };
+        let element = PropertyStructInfo::new(vec![field("Port", InType::UInt16, OutType::Port, 2)]);
+        let schema = EventInfo::new(
+            GUID::zeroed(),
+            1,
+            0,
+            PropertyStructInfo::new(vec![
+                field("ProcessId", InType::UInt32, OutType::Pid, 4),
+                PropertyInfo {
+                    length: PropertyValue::Constant(0),
+                    count: PropertyValue::Constant(1),
+                    is_array: false,
+                    value: PropertyNestedInfo::Struct("Endpoint".to_string(), element),
+                },
+            ]),
+        );
+
+        let data = schema.decode_userdata(&[0x2a, 0, 0, 0, 0x01, 0xbb]).unwrap();
+        let Some(StructOrValue::Value(Value { value: InValue::UInt32(pid), .. })) = data.get("ProcessId") else {
+            panic!("Expected ProcessId, got {:?}", data.get("ProcessId"));
+        };
+        assert_eq!(pid.get(0), Some(42));
+        assert!(data.get("ThreadId").is_none());
+
+        let StringOrStruct::Struct(struc) = &data else {
+            panic!("Expected a structured payload");
+        };
+        assert_eq!(struc.iter_named().map(|(name, _)| name).collect::<Vec<_>>(), ["ProcessId", "Endpoint"]);
+        let Some(StructOrValue::Struct(endpoint)) = struc.get("Endpoint") else {
+            panic!("Expected the Endpoint struct");
+        };
+        let Some(StructOrValue::Value(Value { value: InValue::UInt16(port), .. })) = endpoint.values[0].get("Port") else {
+            panic!("Expected Port");
+        };
+        assert_eq!(port.get(0), Some(0xbb01));
+    }
+
+    fn three_uint32_event_info() -> EventInfo {
+        let field = |name: &str| PropertyInfo {
+            length: PropertyValue::Constant(size_of::<u32>()),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                name.to_string(),
+                PropertyValueInfo {
+                    in_type: InType::UInt32,
+                    out_type: OutType::UnsignedInt,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        };
+        EventInfo::new(
+            GUID::zeroed(),
+            1,
+            0,
+            PropertyStructInfo::new(vec![field("A"), field("B"), field("C")]),
+        )
+    }
+
+    #[test]
+    fn test_decode_resolves_mapped_values() {
+        let mut schema = three_uint32_event_info();
+        for field in &mut schema.properties.fields[..2] {
+            let PropertyNestedInfo::Value(_, value_info) = &mut field.value else {
+                unreachable!();
+            };
+            value_info.map_name = Some("StateMap".to_string());
+        }
+        schema.maps.insert(
+            "StateMap".to_string(),
+            StringOrIntegerMap::Integer(HashMap::from([(0, "Disabled".to_string()), (1, "Enabled".to_string())])),
+        );
+        let userdata = [1u32, 7, 1].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+
+        let StringOrStruct::Struct(struc) = schema.decode_userdata(&userdata).unwrap() else {
+            panic!("Expected a struct");
+        };
+        let mapped = struc
+            .values
+            .iter()
+            .map(|value| match value {
+                StructOrValue::Value(value) => value.mapped.as_ref().map(ToString::to_string),
+                StructOrValue::Struct(_) => panic!("Expected a value"),
+            })
+            .collect::<Vec<_>>();
+        // The third property has no map, the second falls back to the number
+        assert_eq!(mapped, vec![Some("Enabled".to_string()), Some("0x7 (unknown)".to_string()), None]);
+    }
+
+    #[test]
+    fn test_owned_event_outlives_record() {
+        let mut schema = three_uint32_event_info();
+        let mut fields = schema.properties.fields.clone();
+        fields.push(PropertyInfo {
+            length: PropertyValue::Constant(0),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                "Name".to_string(),
+                PropertyValueInfo {
+                    in_type: InType::UnicodeString,
+                    out_type: OutType::String,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        });
+        fields.push(PropertyInfo {
+            length: PropertyValue::Constant(size_of::<GUID>()),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::Value(
+                "Id".to_string(),
+                PropertyValueInfo {
+                    in_type: InType::Guid,
+                    out_type: OutType::Guid,
+                    map_name: None,
+                    handle: None,
+                },
+            ),
+        });
+        schema.properties = PropertyStructInfo::new(fields);
+        let guid = GUID::from_u128(0x6a3e1f90_2b7c_4d15_8e0a_9c4b7f2d1e63);
+
+        let owned = {
+            let mut userdata = [1u32, 2, 3].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+            userdata.extend("name\0".encode_utf16().flat_map(u16::to_le_bytes));
+            userdata.extend(guid.to_u128().to_le_bytes());
+            let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+            event_record.EventHeader.ProcessId = 42;
+            event_record.UserDataLength = userdata.len().try_into().unwrap();
+            event_record.UserData = userdata.as_mut_ptr() as *mut _;
+
+            let event = schema.decode(&event_record).unwrap();
+            EventOwned::from(&event)
+        };
+
+        assert_eq!(owned.header.process_id, 42);
+        let StringOrStructOwned::Struct(struc) = &owned.data else {
+            panic!("Expected a struct, got {:?}", owned.data);
+        };
+        let values = struc
+            .iter()
+            .map(|value| match value {
+                StructOrValueOwned::Value(value) => value.value.clone(),
+                StructOrValueOwned::Struct(_) => panic!("Expected a value"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                InValueOwned::UInt32(vec![1]),
+                InValueOwned::UInt32(vec![2]),
+                InValueOwned::UInt32(vec![3]),
+                InValueOwned::UnicodeString(vec!["name".to_string()]),
+                InValueOwned::Guid(vec![guid]),
+            ]
+        );
+        let Some(StructOrValueOwned::Value(name)) = owned.data.get("Name") else {
+            panic!("Expected a Name value, got {:?}", owned.data);
+        };
+        assert_eq!(name.value, InValueOwned::UnicodeString(vec!["name".to_string()]));
+
+        // Owned events can be queued for another thread
+        let received = std::thread::spawn(move || owned).join().unwrap();
+        assert_eq!(received.data, StringOrStructOwned::Struct(struc.clone()));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_owned_value_serializes_to_json() {
+        use crate::values::{compound::StructOwned, value::ValueOwned};
+
+        let guid = GUID::from_u128(0x6a3e1f90_2b7c_4d15_8e0a_9c4b7f2d1e63);
+        let data = StringOrStructOwned::Struct(StructOwned {
+            values: vec![StructOrValueOwned::Value(ValueOwned {
+                value: InValueOwned::Guid(vec![guid]),
+                is_array: false,
+                out_type: Some(OutType::Guid),
+                mapped: None,
+            })],
+            names: None,
+        });
+        let json = serde_json::to_value(&data).unwrap();
+        assert_eq!(
+            json["Struct"]["values"][0]["Value"]["value"]["Guid"][0],
+            "6a3e1f90-2b7c-4d15-8e0a-9c4b7f2d1e63"
+        );
+        let round_trip: StringOrStructOwned = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip, data);
+    }
+
+    #[test]
+    fn test_custom_schema_protocol_and_schema() {
+        let property = PropertyNestedInfo::CustomSchema("Payload".to_string(), vec![2, 0, 2, 0, 0xab, 0xcd]);
+        assert_eq!(property.protocol(), Some(2));
+        assert_eq!(property.custom_schema(), Some(&[0xab, 0xcd][..]));
+
+        let truncated = PropertyNestedInfo::CustomSchema("Payload".to_string(), vec![2, 0, 3, 0, 0xab]);
+        assert_eq!(truncated.protocol(), Some(2));
+        assert_eq!(truncated.custom_schema(), None);
+
+        let PropertyInfo { value, .. } = &three_uint32_event_info().properties.fields[0];
+        assert_eq!(value.protocol(), None);
+        assert_eq!(value.custom_schema(), None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_custom_schema_property_round_trips_json() {
+        let property = PropertyInfo {
+            length: PropertyValue::Reference(1),
+            count: PropertyValue::Constant(1),
+            is_array: false,
+            value: PropertyNestedInfo::CustomSchema("Payload".to_string(), vec![2, 0, 2, 0, 0xab, 0xcd]),
+        };
+        let json = serde_json::to_value(&property).unwrap();
+        let round_trip: PropertyInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip, property);
+    }
+
+    #[test]
+    fn test_tolerant_decode_returns_prefix_of_short_payload() {
+        let schema = three_uint32_event_info();
+        let userdata = [1u8, 0, 0, 0, 2, 0, 0, 0, 3, 0];
+        assert!(schema.decode_userdata(&userdata).is_err());
+
+        let StringOrStruct::Partial(struc, truncation) = schema.decode_userdata_tolerant(&userdata).unwrap() else {
+            panic!("Expected a partial payload");
+        };
+        assert_eq!(struc.values.len(), 2);
+        assert_eq!(
+            truncation,
+            Truncation {
+                decoded_fields: 2,
+                declared_fields: 3,
+                offset: 8
+            }
+        );
+        let StructOrValue::Value(Value {
+            value: InValue::UInt32(b),
+            ..
+        }) = &struc.values[1]
+        else {
+            panic!("Expected UInt32");
+        };
+        assert_eq!(b.get(0), Some(2));
+    }
+
+    #[test]
+    fn test_tolerant_decode_of_complete_payload_is_struct() {
+        let schema = three_uint32_event_info();
+        let userdata = [1u8, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0];
+        let StringOrStruct::Struct(struc) = schema.decode_userdata_tolerant(&userdata).unwrap() else {
+            panic!("Expected a complete payload");
+        };
+        assert_eq!(struc.values.len(), 3);
+    }
+
+    #[test]
+    fn test_tolerant_decode_keeps_other_errors() {
+        let mut schema = three_uint32_event_info();
+        schema.properties.fields[1].length = PropertyValue::Constant(2);
+        let err = schema.decode_userdata_tolerant(&[0u8; 12]).unwrap_err();
+        assert!(matches!(err.root_cause(), ParseError::UnexpectedSize), "{err:?}");
+    }
+
+    #[test]
+    fn test_decode_zero_property_event_with_payload_is_opaque() {
+        let schema = empty_event_info();
+        assert!(schema.is_opaque());
+
+        let mut userdata = vec![1u8, 2, 3, 4];
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.UserDataLength = userdata.len().try_into().unwrap();
+        event_record.UserData = userdata.as_mut_ptr() as *mut _;
+
+        let event = schema.decode(&event_record).unwrap();
+        let StringOrStruct::Opaque(data) = event.data else {
+            panic!("Expected an opaque payload, got {:?}", event.data);
+        };
+        assert_eq!(data, &[1u8, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_decode_null_userdata_with_length_is_malformed() {
+        let schema = empty_event_info();
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.UserDataLength = 4;
+
+        assert!(matches!(
+            schema.decode(&event_record),
+            Err(ParseError::MalformedRecord(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_zero_property_event_without_payload_is_empty_struct() {
+        let schema = empty_event_info();
+        let mut userdata: Vec<u8> = Vec::new();
+        let mut event_record = unsafe { std::mem::zeroed::<EVENT_RECORD>() };
+        event_record.UserData = userdata.as_mut_ptr() as *mut _;
+
+        let event = schema.decode(&event_record).unwrap();
+        let StringOrStruct::Struct(struc) = event.data else {
+            panic!("Expected an empty struct, got {:?}", event.data);
+        };
+        assert!(struc.values.is_empty());
     }
 
     #[test]
@@ -849,6 +2837,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_captures_provider_name() {
+        let schema = kernel_process_v4_schema();
+        assert_eq!(schema.provider_name(), Some("Microsoft-Windows-Kernel-Process"));
+        assert_eq!(empty_event_info().provider_name(), None);
+    }
+
     #[test]
     fn test_decode_kernel_process_v4_log_samples_parse_fully() {
         let schema = kernel_process_v4_schema();
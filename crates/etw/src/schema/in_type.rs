@@ -1,6 +1,7 @@
 use std::{
     fmt,
     mem::size_of,
+    str::FromStr,
 };
 
 use windows::{
@@ -24,6 +25,8 @@ use windows::{
     },
 };
 
+use crate::error::ParseError;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
@@ -111,6 +114,24 @@ impl fmt::Display for InType {
     }
 }
 
+/// Parses the names written by [`fmt::Display`], ignoring case, e.g. `UnicodeString`,
+/// `unicodestring` or `Unknown(400)`.
+impl FromStr for InType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(in_type) = Self::NAMED.iter().find(|in_type| in_type.to_string().eq_ignore_ascii_case(s)) {
+            return Ok(*in_type);
+        }
+        s.to_ascii_lowercase()
+            .strip_prefix("unknown(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .and_then(|value| value.parse::<u16>().ok())
+            .map(Self::from)
+            .ok_or_else(|| ParseError::UnknownTypeName(s.to_string()))
+    }
+}
+
 impl From<u16> for InType {
     fn from(val: u16) -> Self {
         match _TDH_IN_TYPE(val.into()) {
@@ -204,8 +225,51 @@ impl From<InType> for _TDH_IN_TYPE {
 }
 
 impl InType {
+    /// All variants except [`InType::Unknown`].
+    const NAMED: &'static [Self] = &[
+        Self::Null,
+        Self::UnicodeString,
+        Self::AnsiString,
+        Self::Int8,
+        Self::UInt8,
+        Self::Int16,
+        Self::UInt16,
+        Self::Int32,
+        Self::UInt32,
+        Self::Int64,
+        Self::UInt64,
+        Self::Float,
+        Self::Double,
+        Self::Boolean,
+        Self::Binary,
+        Self::Guid,
+        Self::Pointer,
+        Self::FileTime,
+        Self::SystemTime,
+        Self::Sid,
+        Self::HexInt32,
+        Self::HexInt64,
+        Self::CountedString,
+        Self::CountedAnsiString,
+        Self::ReversedCountedString,
+        Self::ReversedCountedAnsiString,
+        Self::NonNullTerminatedString,
+        Self::NonNullTerminatedAnsiString,
+        Self::UnicodeChar,
+        Self::AnsiChar,
+        Self::SizeT,
+        Self::HexDump,
+        Self::WbemSid,
+        Self::ManifestCountedBinary,
+        Self::ManifestCountedString,
+        Self::ManifestCountedAnsiString,
+    ];
+
     /// Returns the size of a given type,
     /// or None if the size is not known at compile time.
+    ///
+    /// Pointers have the size of this process's pointers, events of other processes
+    /// may use a different one (see [`crate::schema::cache::DecodeContext`]).
     pub fn size(&self) -> Option<usize> {
         match self {
             InType::Null => None,
@@ -248,3 +312,26 @@ impl InType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::System::Diagnostics::Etw::_TDH_IN_TYPE;
+
+    use super::InType;
+
+    #[test]
+    fn test_name_round_trip() {
+        for in_type in InType::NAMED.iter().copied().chain([InType::Unknown(400)]) {
+            assert_eq!(in_type.to_string().parse::<InType>().unwrap(), in_type);
+        }
+        assert_eq!("unicodestring".parse::<InType>().unwrap(), InType::UnicodeString);
+        assert_eq!("UINT32".parse::<InType>().unwrap(), InType::UInt32);
+        assert!("unicode".parse::<InType>().is_err());
+    }
+
+    #[test]
+    fn test_unknown_name_resolves_known_values() {
+        let value = _TDH_IN_TYPE::from(InType::Guid).0 as u16;
+        assert_eq!(format!("Unknown({})", value).parse::<InType>().unwrap(), InType::Guid);
+    }
+}
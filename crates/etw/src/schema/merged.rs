@@ -0,0 +1,338 @@
+//! Schemas of all versions of an event merged into one.
+//!
+//! Properties are matched by name across versions. Each merged property records the
+//! versions it's present in as a bitmap, so presence tests don't need string lookups
+//! per version.
+
+use std::{fmt, str::FromStr};
+
+use crate::error::ParseError;
+
+use super::cache::{EventInfo, PropertyInfo};
+
+/// Set of event versions 0 to 63.
+///
+/// Displayed and parsed as comma separated ranges, e.g. `1-3,5`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct VersionSet(u64);
+
+impl VersionSet {
+    /// Number of versions a set can hold. Versions from this one up are ignored.
+    pub const CAPACITY: u8 = 64;
+
+    pub fn new() -> Self {
+        Self(0)
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns false if the version is beyond [`VersionSet::CAPACITY`].
+    pub fn insert(&mut self, version: u8) -> bool {
+        if version >= Self::CAPACITY {
+            return false;
+        }
+        self.0 |= 1 << version;
+        true
+    }
+
+    pub fn contains(&self, version: u8) -> bool {
+        version < Self::CAPACITY && self.0 & (1 << version) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn min(&self) -> Option<u8> {
+        (!self.is_empty()).then(|| self.0.trailing_zeros() as u8)
+    }
+
+    pub fn max(&self) -> Option<u8> {
+        (!self.is_empty()).then(|| (u64::BITS - 1 - self.0.leading_zeros()) as u8)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> {
+        let bits = self.0;
+        (0..Self::CAPACITY).filter(move |version| bits & (1 << version) != 0)
+    }
+
+    /// Contiguous ranges of versions, in ascending order.
+    pub fn ranges(&self) -> impl Iterator<Item = (u8, u8)> {
+        let mut bits = self.0;
+        std::iter::from_fn(move || {
+            if bits == 0 {
+                return None;
+            }
+            let start = bits.trailing_zeros();
+            let len = (bits >> start).trailing_ones();
+            let end = start + len - 1;
+            bits &= !(u64::MAX >> (u64::BITS - len) << start);
+            Some((start as u8, end as u8))
+        })
+    }
+}
+
+impl FromIterator<u8> for VersionSet {
+    fn from_iter<T: IntoIterator<Item = u8>>(iter: T) -> Self {
+        let mut set = Self::new();
+        for version in iter {
+            set.insert(version);
+        }
+        set
+    }
+}
+
+impl fmt::Display for VersionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, (start, end)) in self.ranges().enumerate() {
+            if idx != 0 {
+                f.write_str(",")?;
+            }
+            if start == end {
+                write!(f, "{}", start)?;
+            } else {
+                write!(f, "{}-{}", start, end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for VersionSet {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = Self::new();
+        if s.is_empty() {
+            return Ok(set);
+        }
+        let invalid = || ParseError::InvalidVersionRange(s.to_string());
+        for range in s.split(',') {
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start, end),
+                None => (range, range),
+            };
+            let start = start.trim().parse::<u8>().map_err(|_| invalid())?;
+            let end = end.trim().parse::<u8>().map_err(|_| invalid())?;
+            if start > end || end >= Self::CAPACITY {
+                return Err(invalid());
+            }
+            (start..=end).for_each(|version| {
+                set.insert(version);
+            });
+        }
+        Ok(set)
+    }
+}
+
+/// A property of a merged event with the versions it's present in.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct NullablePropertyInfo {
+    pub min_version: u8,
+    /// Last version with the property, None if it's present in the latest version.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub max_version: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde::version_set"))]
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub versions: VersionSet,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub property_info: PropertyInfo,
+}
+
+impl NullablePropertyInfo {
+    pub fn name(&self) -> &str {
+        self.property_info.value.name()
+    }
+}
+
+/// All versions of an event with their properties merged by name.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MergedEvent {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde::version_set"))]
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub versions: VersionSet,
+    /// Properties in the order they first appear in.
+    pub properties: Vec<NullablePropertyInfo>,
+    /// Properties whose definition differs between versions, with the first version
+    /// that differs. The merged property keeps the earliest definition.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub conflicts: Vec<(String, u8)>,
+}
+
+impl MergedEvent {
+    /// Merge schemas of one event, in any order.
+    pub fn merge<'a>(schemas: impl IntoIterator<Item = &'a EventInfo>) -> Self {
+        let mut schemas = schemas.into_iter().collect::<Vec<_>>();
+        schemas.sort_by_key(|schema| schema.event_version);
+
+        let mut merged = Self::default();
+        for schema in schemas {
+            let version = schema.event_version;
+            if !merged.versions.insert(version) {
+                log::warn!(
+                    "Event provider {:?} id {} version {} is beyond the versions tracked in merged schemas",
+                    schema.provider_guid,
+                    schema.event_id,
+                    version
+                );
+                continue;
+            }
+            for field in &schema.properties.fields {
+                let name = field.value.name();
+                match merged.properties.iter_mut().find(|prop| prop.name() == name) {
+                    Some(prop) => {
+                        if &prop.property_info != field && !merged.conflicts.iter().any(|(conflict, _)| conflict == name) {
+                            merged.conflicts.push((name.to_string(), version));
+                        }
+                        prop.versions.insert(version);
+                    }
+                    None => {
+                        merged.properties.push(NullablePropertyInfo {
+                            min_version: version,
+                            max_version: None,
+                            versions: VersionSet::from_iter([version]),
+                            property_info: field.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        let latest = merged.versions.max();
+        for prop in &mut merged.properties {
+            let last = prop.versions.max();
+            prop.max_version = if last == latest { None } else { last };
+        }
+        merged
+    }
+
+    pub fn has_field(&self, version: u8, name: &str) -> bool {
+        self.properties
+            .iter()
+            .any(|prop| prop.name() == name && prop.versions.contains(version))
+    }
+
+    /// Properties present in `version`, in order.
+    pub fn fields_for_version(&self, version: u8) -> impl Iterator<Item = &NullablePropertyInfo> {
+        self.properties
+            .iter()
+            .filter(move |prop| prop.versions.contains(version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::core::GUID;
+
+    use crate::schema::{
+        cache::{EventInfo, PropertyInfo, PropertyNestedInfo, PropertyStructInfo},
+        in_type::InType,
+        out_type::OutType,
+    };
+
+    use super::{MergedEvent, VersionSet};
+
+    fn schema(version: u8, fields: &[&str]) -> EventInfo {
+        EventInfo::new(
+            GUID::zeroed(),
+            1,
+            version,
+            PropertyStructInfo::new(
+                fields
+                    .iter()
+                    .map(|name| PropertyInfo::value(name, InType::UInt32, OutType::UnsignedInt))
+                    .collect(),
+            ),
+        )
+    }
+
+    #[test]
+    fn test_version_set_ranges_round_trip() {
+        for (versions, text) in [
+            (vec![], ""),
+            (vec![0], "0"),
+            (vec![1, 2, 3, 5], "1-3,5"),
+            (vec![0, 2, 4, 5, 6, 63], "0,2,4-6,63"),
+        ] {
+            let set = versions.iter().copied().collect::<VersionSet>();
+            assert_eq!(set.to_string(), text);
+            assert_eq!(text.parse::<VersionSet>().unwrap(), set);
+            assert_eq!(set.iter().collect::<Vec<_>>(), versions);
+        }
+        assert_eq!(VersionSet::from_bits(u64::MAX).to_string(), "0-63");
+        assert_eq!("0-63".parse::<VersionSet>().unwrap().bits(), u64::MAX);
+    }
+
+    #[test]
+    fn test_version_set_rejects_invalid_ranges() {
+        for text in ["3-1", "64", "1,", "a-b", "-1"] {
+            assert!(text.parse::<VersionSet>().is_err(), "{text} should not parse");
+        }
+        assert!(!VersionSet::new().insert(64));
+    }
+
+    #[test]
+    fn test_merge_tracks_removed_and_readded_fields() {
+        let schemas = [
+            schema(3, &["Pid", "Flags", "Tid"]),
+            schema(1, &["Pid", "Flags"]),
+            schema(2, &["Pid"]),
+            schema(4, &["Pid", "Tid"]),
+        ];
+        let merged = MergedEvent::merge(&schemas);
+
+        assert_eq!(merged.versions.to_string(), "1-4");
+        let names = merged.properties.iter().map(|prop| prop.name()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Pid", "Flags", "Tid"]);
+
+        let flags = &merged.properties[1];
+        assert_eq!(flags.versions.to_string(), "1,3");
+        assert_eq!((flags.min_version, flags.max_version), (1, Some(3)));
+        let tid = &merged.properties[2];
+        assert_eq!((tid.min_version, tid.max_version), (3, None));
+
+        assert!(merged.has_field(1, "Flags"));
+        assert!(!merged.has_field(2, "Flags"));
+        assert!(merged.has_field(3, "Flags"));
+        assert!(!merged.has_field(4, "Flags"));
+        assert!(!merged.has_field(1, "Unknown"));
+        assert_eq!(
+            merged.fields_for_version(2).map(|prop| prop.name()).collect::<Vec<_>>(),
+            vec!["Pid"]
+        );
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_reports_conflicting_definitions() {
+        let mut changed = schema(2, &["Pid"]);
+        if let PropertyNestedInfo::Value(_, info) = &mut changed.properties.fields[0].value {
+            info.out_type = OutType::Pid;
+        }
+        let merged = MergedEvent::merge([&schema(1, &["Pid"]), &changed]);
+        assert_eq!(merged.conflicts, vec![("Pid".to_string(), 2)]);
+        assert_eq!(merged.properties[0].versions.to_string(), "1-2");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_merged_event_serializes_versions_as_ranges() {
+        let merged = MergedEvent::merge(&[schema(1, &["Pid"]), schema(2, &[]), schema(3, &["Pid"])]);
+        let json = serde_json::to_value(&merged).unwrap();
+        assert_eq!(json["versions"], "1-3");
+        assert_eq!(json["properties"][0]["versions"], "1,3");
+        assert_eq!(json["properties"][0]["max_version"], serde_json::Value::Null);
+        let round_trip: MergedEvent = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip, merged);
+    }
+}
@@ -1,3 +1,4 @@
 pub mod cache;
 pub mod in_type;
+pub mod merged;
 pub mod out_type;
@@ -1,3 +1,5 @@
+use std::{fmt, str::FromStr};
+
 use windows::Win32::System::Diagnostics::Etw::{
     TDH_OUTTYPE_BOOLEAN, TDH_OUTTYPE_BYTE, TDH_OUTTYPE_CIMDATETIME,
     TDH_OUTTYPE_CULTURE_INSENSITIVE_DATETIME, TDH_OUTTYPE_DATETIME, TDH_OUTTYPE_DOUBLE,
@@ -12,6 +14,8 @@ use windows::Win32::System::Diagnostics::Etw::{
     _TDH_OUT_TYPE,
 };
 
+use crate::error::ParseError;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
@@ -58,6 +62,114 @@ pub enum OutType {
     Unknown(u16),
 }
 
+impl OutType {
+    /// All variants except [`OutType::Unknown`].
+    const NAMED: &'static [Self] = &[
+        Self::Null,
+        Self::String,
+        Self::DateTime,
+        Self::Byte,
+        Self::UnsignedByte,
+        Self::Short,
+        Self::UnsignedShort,
+        Self::Int,
+        Self::UnsignedInt,
+        Self::Long,
+        Self::UnsignedLong,
+        Self::Float,
+        Self::Double,
+        Self::Boolean,
+        Self::Guid,
+        Self::HexBinary,
+        Self::HexInt8,
+        Self::HexInt16,
+        Self::HexInt32,
+        Self::HexInt64,
+        Self::Pid,
+        Self::Tid,
+        Self::Port,
+        Self::IpV4,
+        Self::IpV6,
+        Self::SocketAddress,
+        Self::CimDateTime,
+        Self::EtwTime,
+        Self::Xml,
+        Self::ErrorCode,
+        Self::Win32Error,
+        Self::NtStatus,
+        Self::CultureInsensitiveDateTime,
+        Self::Json,
+        Self::Utf8,
+        Self::HResult,
+        Self::ReducedString,
+        Self::NoPrint,
+    ];
+}
+
+impl fmt::Display for OutType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("Null"),
+            Self::String => f.write_str("String"),
+            Self::DateTime => f.write_str("DateTime"),
+            Self::Byte => f.write_str("Byte"),
+            Self::UnsignedByte => f.write_str("UnsignedByte"),
+            Self::Short => f.write_str("Short"),
+            Self::UnsignedShort => f.write_str("UnsignedShort"),
+            Self::Int => f.write_str("Int"),
+            Self::UnsignedInt => f.write_str("UnsignedInt"),
+            Self::Long => f.write_str("Long"),
+            Self::UnsignedLong => f.write_str("UnsignedLong"),
+            Self::Float => f.write_str("Float"),
+            Self::Double => f.write_str("Double"),
+            Self::Boolean => f.write_str("Boolean"),
+            Self::Guid => f.write_str("Guid"),
+            Self::HexBinary => f.write_str("HexBinary"),
+            Self::HexInt8 => f.write_str("HexInt8"),
+            Self::HexInt16 => f.write_str("HexInt16"),
+            Self::HexInt32 => f.write_str("HexInt32"),
+            Self::HexInt64 => f.write_str("HexInt64"),
+            Self::Pid => f.write_str("Pid"),
+            Self::Tid => f.write_str("Tid"),
+            Self::Port => f.write_str("Port"),
+            Self::IpV4 => f.write_str("IpV4"),
+            Self::IpV6 => f.write_str("IpV6"),
+            Self::SocketAddress => f.write_str("SocketAddress"),
+            Self::CimDateTime => f.write_str("CimDateTime"),
+            Self::EtwTime => f.write_str("EtwTime"),
+            Self::Xml => f.write_str("Xml"),
+            Self::ErrorCode => f.write_str("ErrorCode"),
+            Self::Win32Error => f.write_str("Win32Error"),
+            Self::NtStatus => f.write_str("NtStatus"),
+            Self::CultureInsensitiveDateTime => f.write_str("CultureInsensitiveDateTime"),
+            Self::Json => f.write_str("Json"),
+            Self::Utf8 => f.write_str("Utf8"),
+            Self::HResult => f.write_str("HResult"),
+            Self::ReducedString => f.write_str("ReducedString"),
+            Self::NoPrint => f.write_str("NoPrint"),
+            Self::Unknown(out_type) => f.write_fmt(format_args!("Unknown({})", *out_type)),
+        }
+    }
+}
+
+/// Parses the names written by [`fmt::Display`], ignoring case, e.g. `IpV4`, `ipv4` or
+/// `Unknown(400)`.
+impl FromStr for OutType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(out_type) = Self::NAMED.iter().find(|out_type| out_type.to_string().eq_ignore_ascii_case(s)) {
+            return Ok(*out_type);
+        }
+        s.to_ascii_lowercase()
+            .strip_prefix("unknown(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .and_then(|value| value.parse::<u16>().ok())
+            .map(Self::from)
+            .ok_or_else(|| ParseError::UnknownTypeName(s.to_string()))
+    }
+}
+
 impl From<u16> for OutType {
     fn from(val: u16) -> Self {
         match _TDH_OUT_TYPE(val.into()) {
@@ -159,3 +271,18 @@ impl From<OutType> for _TDH_OUT_TYPE {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::OutType;
+
+    #[test]
+    fn test_name_round_trip() {
+        for out_type in OutType::NAMED.iter().copied().chain([OutType::Unknown(400)]) {
+            assert_eq!(out_type.to_string().parse::<OutType>().unwrap(), out_type);
+        }
+        assert_eq!("ipv4".parse::<OutType>().unwrap(), OutType::IpV4);
+        assert_eq!("HEXINT32".parse::<OutType>().unwrap(), OutType::HexInt32);
+        assert!("ipv5".parse::<OutType>().is_err());
+    }
+}
@@ -27,7 +27,7 @@ pub mod guid {
     }
     
     /// Helper function to parse a GUID string into a `GUID`.
-    fn parse_guid(guid_str: &str) -> Result<GUID, String> {
+    pub(crate) fn parse_guid(guid_str: &str) -> Result<GUID, String> {
         // Check if the GUID string is the correct length
         if guid_str.len() != 36 {
             return Err("Invalid GUID string length".to_string());
@@ -65,4 +65,89 @@ pub mod guid {
             data4,
         })
     }
-}
\ No newline at end of file
+}
+
+pub mod guid_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use windows::core::GUID;
+
+    pub fn serialize<S>(guid: &Option<GUID>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match guid {
+            Some(guid) => super::guid::serialize(guid, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<GUID>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(deserialize_with = "super::guid::deserialize")] GUID);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(guid)| guid))
+    }
+}
+
+pub mod guid_vec {
+    use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+    use windows::core::GUID;
+
+    struct Wrapper<'a>(&'a GUID);
+
+    impl Serialize for Wrapper<'_> {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            super::guid::serialize(self.0, serializer)
+        }
+    }
+
+    pub fn serialize<S>(guids: &[GUID], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(guids.len()))?;
+        for guid in guids {
+            seq.serialize_element(&Wrapper(guid))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<GUID>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Owned(#[serde(deserialize_with = "super::guid::deserialize")] GUID);
+
+        Ok(Vec::<Owned>::deserialize(deserializer)?.into_iter().map(|Owned(guid)| guid).collect())
+    }
+}
+
+pub mod version_set {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::schema::merged::VersionSet;
+
+    /// Serialize a version set as ranges, e.g. `"1-3,5"`.
+    pub fn serialize<S>(versions: &VersionSet, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(versions)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<VersionSet, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
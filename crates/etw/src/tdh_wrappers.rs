@@ -4,22 +4,26 @@ use windows::{
     Win32::{
         Foundation::{ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS, WIN32_ERROR},
         System::Diagnostics::Etw::{
-            DecodingSourceTlg, DecodingSourceWPP, DecodingSourceWbem, DecodingSourceXMLFile, EventChannelInformation, EventKeywordInformation, EventLevelInformation, EventOpcodeInformation, EventTaskInformation, TdhEnumerateManifestProviderEvents, TdhEnumerateProviderFieldInformation, TdhEnumerateProviders, TdhGetEventInformation, TdhGetEventMapInformation, TdhGetManifestEventInformation, DECODING_SOURCE, EVENT_DESCRIPTOR, EVENT_FIELD_TYPE, EVENT_MAP_ENTRY, EVENT_MAP_INFO, EVENT_PROPERTY_INFO, EVENT_RECORD, PROVIDER_ENUMERATION_INFO, PROVIDER_EVENT_INFO, PROVIDER_FIELD_INFO, PROVIDER_FIELD_INFOARRAY, TRACE_EVENT_INFO, TRACE_PROVIDER_INFO
+            DecodingSourceTlg, DecodingSourceWPP, PropertyHasCustomSchema, PropertyHasTags, PropertyParamCount, PropertyParamFixedCount, PropertyParamFixedLength, PropertyParamLength, PropertyStruct, PropertyWBEMXmlFragment, DecodingSourceWbem, DecodingSourceXMLFile, EventChannelInformation, EventKeywordInformation, EventLevelInformation, EventOpcodeInformation, EventTaskInformation, TdhEnumerateManifestProviderEvents, TdhEnumerateProviderFieldInformation, TdhEnumerateProviders, TdhGetEventInformation, TdhGetEventMapInformation, TdhGetManifestEventInformation, DECODING_SOURCE, EVENT_DESCRIPTOR, EVENT_FIELD_TYPE, EVENT_MAP_ENTRY, EVENT_MAP_INFO, EVENT_PROPERTY_INFO, EVENT_RECORD, PROVIDER_ENUMERATION_INFO, PROVIDER_EVENT_INFO, PROVIDER_FIELD_INFO, PROVIDER_FIELD_INFOARRAY, TRACE_EVENT_INFO, TRACE_PROVIDER_INFO
         },
     },
 };
 
-use std::{fmt, mem, slice};
+use std::{collections::HashMap, fmt, mem, ops::{Deref, DerefMut}, slice};
 use std::os::windows::ffi::OsStringExt;
 use std::{ffi, mem::size_of};
 
-use crate::schema::{in_type::InType, out_type::OutType};
+use crate::{
+    error::{ParseError, TraceError},
+    schema::{in_type::InType, out_type::OutType},
+};
 
 // So that we can use usize::try_from(val).unwrap() and be sure it doesn't
 // panic at runtime.
 static_assertions::const_assert!(size_of::<usize>() >= size_of::<u32>());
 
 const ERROR_NOT_SUPPORTED: WIN32_ERROR = WIN32_ERROR(50);
+const ERROR_NOT_FOUND: WIN32_ERROR = WIN32_ERROR(1168);
 
 pub struct Providers {
     buffer: Vec<u8>,
@@ -80,6 +84,21 @@ impl Providers {
     pub fn get(&self, index: usize) -> Option<&TRACE_PROVIDER_INFO> {
         self.data().TraceProviderInfoArray.get(index)
     }
+
+    /// The GUID of the provider named `name`, compared case-insensitively.
+    ///
+    /// Names that aren't valid UTF-16 are compared after a lossy conversion.
+    pub fn find_by_name(&self, name: &str) -> Option<GUID> {
+        self.iter()
+            .find(|provider| crate::provider::name_matches(&provider.name().to_string_lossy(), name))
+            .map(|provider| provider.guid())
+    }
+
+    /// Providers whose name matches a glob pattern, see [`crate::provider::glob_match`].
+    pub fn find_matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = Provider<'a>> {
+        self.iter()
+            .filter(move |provider| crate::provider::glob_match(pattern, &provider.name().to_string_lossy()))
+    }
 }
 
 pub struct Provider<'a> {
@@ -142,6 +161,70 @@ impl Provider<'_> {
     pub fn event_descriptors(&self) -> windows::core::Result<ProviderEventDescriptors>  {
         ProviderEventDescriptors::new(&self.guid())
     }
+
+    /// Channels defined in the provider's manifest.
+    ///
+    /// Empty for providers without a manifest or without channels.
+    pub fn channels(&self) -> Vec<ChannelInfo> {
+        match ProviderFieldInformation::new(&self.guid(), &EventFieldType::ChannelInformation) {
+            Ok(field_info) => field_info
+                .iter()
+                .map(|info| {
+                    let name = info.name().to_string_lossy().into_owned();
+                    ChannelInfo {
+                        channel_type: ChannelType::classify(&name, info.value()),
+                        value: info.value(),
+                        name,
+                    }
+                })
+                .collect(),
+            Err(err) => {
+                log::debug!("No channels for provider {:?}: {}", self.guid(), err);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// The type of an event log channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelType {
+    Admin,
+    Operational,
+    Analytic,
+    Debug,
+}
+
+impl ChannelType {
+    /// Classify a channel by the conventional suffix of its name, e.g.
+    /// `Microsoft-Windows-DNS-Client/Operational`.
+    ///
+    /// TDH doesn't report the type declared in the manifest. The well-known System,
+    /// Application and Security channels are admin channels.
+    pub fn classify(name: &str, value: u64) -> Option<ChannelType> {
+        const SYSTEM: u64 = 8;
+        const SECURITY: u64 = 10;
+        if (SYSTEM..=SECURITY).contains(&value) {
+            return Some(ChannelType::Admin);
+        }
+        let (_, suffix) = name.rsplit_once('/')?;
+        match suffix.to_ascii_lowercase().as_str() {
+            "admin" => Some(ChannelType::Admin),
+            "operational" => Some(ChannelType::Operational),
+            "analytic" => Some(ChannelType::Analytic),
+            "debug" => Some(ChannelType::Debug),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub name: String,
+    /// Channel value as used in the `Channel` field of event descriptors.
+    pub value: u64,
+    /// None if the type couldn't be recovered, see [`ChannelType::classify`].
+    pub channel_type: Option<ChannelType>,
 }
 
 impl fmt::Debug for Provider<'_> {
@@ -285,8 +368,47 @@ pub enum ValueSource {
     Reference(Box<EventPropertyInfo>),
 }
 
+/// A byte buffer aligned for `TRACE_EVENT_INFO`, which a `Vec<u8>` doesn't guarantee.
+struct AlignedBuffer {
+    words: Vec<u64>,
+    len: usize,
+}
+
+static_assertions::const_assert!(mem::align_of::<TRACE_EVENT_INFO>() <= mem::align_of::<u64>());
+
+impl AlignedBuffer {
+    fn zeroed(len: usize) -> AlignedBuffer {
+        AlignedBuffer {
+            words: vec![0u64; len.div_ceil(size_of::<u64>())],
+            len,
+        }
+    }
+}
+
+impl From<&[u8]> for AlignedBuffer {
+    fn from(bytes: &[u8]) -> Self {
+        let mut buffer = AlignedBuffer::zeroed(bytes.len());
+        buffer.copy_from_slice(bytes);
+        buffer
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+    }
+}
+
 pub struct TraceEventInfo {
-    buffer: Vec<u8>,
+    buffer: AlignedBuffer,
 }
 
 impl TraceEventInfo {
@@ -300,7 +422,7 @@ impl TraceEventInfo {
                 return Err(WIN32_ERROR(status).into());
             }
 
-            let mut buffer = vec![0u8; buffersize.try_into().unwrap()];
+            let mut buffer = AlignedBuffer::zeroed(buffersize.try_into().unwrap());
             HRESULT::from_win32(TdhGetEventInformation(
                 event,
                 None,
@@ -328,7 +450,7 @@ impl TraceEventInfo {
                 err => return Err(err.into()),
             }
 
-            let mut buffer = vec![0u8; buffer_size.try_into().unwrap()];
+            let mut buffer = AlignedBuffer::zeroed(buffer_size.try_into().unwrap());
 
             HRESULT::from_win32(TdhGetManifestEventInformation(
                 provider_guid,
@@ -340,6 +462,100 @@ impl TraceEventInfo {
         }
     }
 
+    /// Wrap a `TRACE_EVENT_INFO` buffer captured elsewhere, e.g. dumped by another tool.
+    ///
+    /// The buffer is checked for a complete header and property array and for
+    /// offsets and property indices within bounds, so the result can be passed to
+    /// [`crate::schema::cache::EventInfo::parse`] like one returned by TDH. The bytes
+    /// are copied into a buffer aligned for `TRACE_EVENT_INFO`, so they can come from
+    /// anywhere, e.g. a slice into a larger file.
+    pub fn from_buffer(buffer: &[u8]) -> Result<TraceEventInfo, ParseError> {
+        let invalid = |reason: String| Err(ParseError::InvalidTraceEventInfo(reason));
+        let header_size = mem::offset_of!(TRACE_EVENT_INFO, EventPropertyInfoArray);
+        if buffer.len() < header_size {
+            return invalid(format!("{} bytes is shorter than the {} byte header", buffer.len(), header_size));
+        }
+        let info = TraceEventInfo { buffer: AlignedBuffer::from(buffer) };
+        info.validate()?;
+        Ok(info)
+    }
+
+    /// The raw `TRACE_EVENT_INFO` buffer, e.g. to store it for [`TraceEventInfo::from_buffer`].
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn validate(&self) -> Result<(), ParseError> {
+        let invalid = |reason: String| Err(ParseError::InvalidTraceEventInfo(reason));
+        let data = self.data();
+        let property_count = self.property_count();
+        let properties_end = property_count
+            .checked_mul(size_of::<EVENT_PROPERTY_INFO>())
+            .and_then(|size| size.checked_add(mem::offset_of!(TRACE_EVENT_INFO, EventPropertyInfoArray)));
+        if properties_end.is_none_or(|end| end > self.buffer.len()) {
+            return invalid(format!("{} properties don't fit into {} bytes", property_count, self.buffer.len()));
+        }
+        if self.top_level_property_count() > property_count {
+            return invalid(format!(
+                "{} top level properties but only {} properties",
+                self.top_level_property_count(),
+                property_count
+            ));
+        }
+
+        let check_offset = |field: &str, offset: u32| {
+            if usize::try_from(offset).unwrap() > self.buffer.len() {
+                invalid(format!("{} offset {} is beyond the buffer of {} bytes", field, offset, self.buffer.len()))
+            } else if offset % 2 != 0 {
+                // Names, messages and custom schemas all start with u16s.
+                invalid(format!("{} offset {} is not aligned to u16", field, offset))
+            } else {
+                Ok(())
+            }
+        };
+        let check_index = |field: &str, index: usize| {
+            if index > property_count {
+                invalid(format!("{} {} is beyond the {} properties", field, index, property_count))
+            } else {
+                Ok(())
+            }
+        };
+        check_offset("ProviderNameOffset", data.ProviderNameOffset)?;
+        check_offset("LevelNameOffset", data.LevelNameOffset)?;
+        check_offset("ChannelNameOffset", data.ChannelNameOffset)?;
+        check_offset("KeywordsNameOffset", data.KeywordsNameOffset)?;
+        check_offset("TaskNameOffset", data.TaskNameOffset)?;
+        check_offset("OpcodeNameOffset", data.OpcodeNameOffset)?;
+        check_offset("EventMessageOffset", data.EventMessageOffset)?;
+        check_offset("ProviderMessageOffset", data.ProviderMessageOffset)?;
+        check_offset("EventNameOffset", unsafe { data.Anonymous1.EventNameOffset })?;
+        check_offset("EventAttributesOffset", unsafe { data.Anonymous2.EventAttributesOffset })?;
+
+        for index in 0..property_count {
+            let property = self.get_raw_property(index).unwrap();
+            let flags = property.Flags.0;
+            check_offset("NameOffset", property.NameOffset)?;
+            unsafe {
+                if flags & PropertyStruct.0 != 0 {
+                    let start = usize::from(property.Anonymous1.structType.StructStartIndex);
+                    let members = usize::from(property.Anonymous1.structType.NumOfStructMembers);
+                    check_index("StructStartIndex + NumOfStructMembers", start + members)?;
+                } else if flags & PropertyHasCustomSchema.0 != 0 {
+                    check_offset("CustomSchemaOffset", property.Anonymous1.customSchemaType.CustomSchemaOffset)?;
+                } else {
+                    check_offset("MapNameOffset", property.Anonymous1.nonStructType.MapNameOffset)?;
+                }
+                if flags & PropertyParamLength.0 != 0 {
+                    check_index("lengthPropertyIndex", usize::from(property.Anonymous3.lengthPropertyIndex) + 1)?;
+                }
+                if flags & PropertyParamCount.0 != 0 {
+                    check_index("countPropertyIndex", usize::from(property.Anonymous2.countPropertyIndex) + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn data(&self) -> &TRACE_EVENT_INFO {
         unsafe {
@@ -349,6 +565,15 @@ impl TraceEventInfo {
         }
     }
 
+    /// The custom schema of a property with `PropertyHasCustomSchema`, starting with
+    /// its protocol (u16) and length (u16), followed by the schema itself.
+    pub fn custom_schema(&self, offset: u32) -> Option<&[u8]> {
+        let offset = usize::try_from(offset).ok()?;
+        let data = self.buffer.get(offset..)?;
+        let length = usize::from(u16::from_le_bytes(data.get(2..4)?.try_into().ok()?));
+        data.get(..4 + length)
+    }
+
     pub(crate) unsafe fn offset_string(&self, offset: u32, with_null_terminator: bool) -> Option<&[u16]> {
         // Unwrap is safe because we have a compile-time assert that size(u32) >= size(usize)
         let offset = usize::try_from(offset).unwrap();
@@ -429,6 +654,42 @@ impl TraceEventInfo {
         unsafe { self.offset_string(self.data().EventMessageOffset, with_null_terminator) }
     }
 
+    pub fn provider_name_string(&self) -> Option<String> {
+        self.provider_name(false).map(String::from_utf16_lossy)
+    }
+
+    pub fn level_name_string(&self) -> Option<String> {
+        self.level_name(false).map(String::from_utf16_lossy)
+    }
+
+    pub fn channel_name_string(&self) -> Option<String> {
+        self.channel_name(false).map(String::from_utf16_lossy)
+    }
+
+    /// The names of the event's keywords, separated by spaces. See [`TraceEventInfo::keyword_names`].
+    pub fn keyword_name_string(&self) -> Option<String> {
+        self.keyword_name(false).map(String::from_utf16_lossy)
+    }
+
+    pub fn task_name_string(&self) -> Option<String> {
+        self.task_name(false).map(String::from_utf16_lossy)
+    }
+
+    pub fn event_name_string(&self) -> Option<String> {
+        self.event_name(false).map(String::from_utf16_lossy)
+    }
+
+    pub fn event_message_string(&self) -> Option<String> {
+        self.event_message(false).map(String::from_utf16_lossy)
+    }
+
+    /// The names of the event's keywords, which TDH lists in a single space separated string.
+    pub fn keyword_names(&self) -> Vec<String> {
+        self.keyword_name_string()
+            .map(|names| names.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
     pub fn property_count(&self) -> usize {
         self.data().PropertyCount.try_into().unwrap()
     }
@@ -455,19 +716,117 @@ impl TraceEventInfo {
     }
 }
 
+const PROPERTY_FLAG_NAMES: [(i32, &str); 8] = [
+    (PropertyStruct.0, "PropertyStruct"),
+    (PropertyParamLength.0, "PropertyParamLength"),
+    (PropertyParamCount.0, "PropertyParamCount"),
+    (PropertyWBEMXmlFragment.0, "PropertyWBEMXmlFragment"),
+    (PropertyParamFixedLength.0, "PropertyParamFixedLength"),
+    (PropertyParamFixedCount.0, "PropertyParamFixedCount"),
+    (PropertyHasTags.0, "PropertyHasTags"),
+    (PropertyHasCustomSchema.0, "PropertyHasCustomSchema"),
+];
+
+/// Type specific part of a [`RawPropertyInfo`], depending on the property flags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RawPropertyType {
+    Struct {
+        struct_start_index: u16,
+        num_of_struct_members: u16,
+    },
+    CustomSchema {
+        in_type: u16,
+        out_type: u16,
+        custom_schema_offset: u32,
+    },
+    Value {
+        in_type: u16,
+        out_type: u16,
+        map_name_offset: u32,
+        map_name: Option<String>,
+    },
+}
+
+/// The `EVENT_PROPERTY_INFO` of a property as returned by TDH, without any interpretation.
+///
+/// Meant for diagnosing decode mismatches; use [`crate::schema::cache::EventInfo`] for decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawPropertyInfo {
+    pub index: usize,
+    pub flags: u32,
+    pub flag_names: Vec<String>,
+    pub name_offset: u32,
+    pub name: Option<String>,
+    pub property_type: RawPropertyType,
+    /// Element count, or the index of the count property if `PropertyParamCount` is set.
+    pub count_or_count_property_index: u16,
+    /// Length, or the index of the length property if `PropertyParamLength` is set.
+    pub length_or_length_property_index: u16,
+    pub tags: u32,
+}
+
+impl TraceEventInfo {
+    /// Dump all entries of the property array as TDH returned them.
+    pub fn raw_properties(&self) -> Vec<RawPropertyInfo> {
+        (0..self.property_count())
+            .filter_map(|index| self.get_raw_property(index).map(|property| (index, property)))
+            .map(|(index, property)| unsafe {
+                let flags = property.Flags.0;
+                let property_type = if flags & PropertyStruct.0 != 0 {
+                    RawPropertyType::Struct {
+                        struct_start_index: property.Anonymous1.structType.StructStartIndex,
+                        num_of_struct_members: property.Anonymous1.structType.NumOfStructMembers,
+                    }
+                } else if flags & PropertyHasCustomSchema.0 != 0 {
+                    RawPropertyType::CustomSchema {
+                        in_type: property.Anonymous1.customSchemaType.InType,
+                        out_type: property.Anonymous1.customSchemaType.OutType,
+                        custom_schema_offset: property.Anonymous1.customSchemaType.CustomSchemaOffset,
+                    }
+                } else {
+                    let map_name_offset = property.Anonymous1.nonStructType.MapNameOffset;
+                    RawPropertyType::Value {
+                        in_type: property.Anonymous1.nonStructType.InType,
+                        out_type: property.Anonymous1.nonStructType.OutType,
+                        map_name_offset,
+                        map_name: self.offset_string(map_name_offset, false).map(String::from_utf16_lossy),
+                    }
+                };
+                RawPropertyInfo {
+                    index,
+                    flags: flags as u32,
+                    flag_names: PROPERTY_FLAG_NAMES
+                        .iter()
+                        .filter(|(flag, _)| flags & flag != 0)
+                        .map(|(_, name)| name.to_string())
+                        .collect(),
+                    name_offset: property.NameOffset,
+                    name: self.offset_string(property.NameOffset, false).map(String::from_utf16_lossy),
+                    property_type,
+                    count_or_count_property_index: property.Anonymous2.count,
+                    length_or_length_property_index: property.Anonymous3.length,
+                    tags: property.Anonymous4.Anonymous._bitfield & 0x0fff_ffff,
+                }
+            })
+            .collect()
+    }
+}
+
 impl fmt::Debug for TraceEventInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("EventManifestInformation")
             .field("provider_guid", &self.provider_guid())
             .field("event_guid", &self.event_guid())
             .field("decoding_source", &self.decoding_source())
-            .field("provider_name", &self.provider_name(false))
-            .field("level_name", &self.level_name(false))
-            .field("channel_name", &self.channel_name(false))
-            .field("keyword_name", &self.keyword_name(false))
-            .field("task_name", &self.task_name(false))
-            .field("event_name", &self.event_name(false))
-            .field("event_message", &self.event_message(false))
+            .field("provider_name", &self.provider_name_string())
+            .field("level_name", &self.level_name_string())
+            .field("channel_name", &self.channel_name_string())
+            .field("keyword_names", &self.keyword_names())
+            .field("task_name", &self.task_name_string())
+            .field("event_name", &self.event_name_string())
+            .field("event_message", &self.event_message_string())
             .finish()
     }
 }
@@ -537,6 +896,10 @@ impl EventFieldType {
 pub enum ProviderFieldInformationError {
     #[error("Not Supported")]
     NotSupported,
+    #[error("Provider doesn't define fields of this type")]
+    NotFound,
+    #[error("Windows API error: {0}")]
+    Windows(#[from] windows::core::Error),
 }
 
 pub struct ProviderFieldInformation {
@@ -560,7 +923,12 @@ impl ProviderFieldInformation {
             if status == ERROR_NOT_SUPPORTED {
                 return Err(ProviderFieldInformationError::NotSupported);
             }
-            assert_eq!(status, ERROR_INSUFFICIENT_BUFFER);
+            if status == ERROR_NOT_FOUND {
+                return Err(ProviderFieldInformationError::NotFound);
+            }
+            if status != ERROR_INSUFFICIENT_BUFFER {
+                return Err(windows::core::Error::from(status).into());
+            }
             let mut buffer = vec![0u8; buffer_size.try_into().unwrap()];
 
             let status = TdhEnumerateProviderFieldInformation(
@@ -569,7 +937,7 @@ impl ProviderFieldInformation {
                 Some(buffer.as_mut_ptr() as *mut PROVIDER_FIELD_INFOARRAY),
                 &mut buffer_size,
             );
-            assert_eq!(WIN32_ERROR(status), ERROR_SUCCESS);
+            HRESULT::from_win32(status).ok()?;
             Ok(ProviderFieldInformation { buffer })
         }
     }
@@ -667,6 +1035,52 @@ impl fmt::Debug for ProviderFieldInfo<'_> {
     }
 }
 
+/// Names and descriptions of the fields a provider's manifest defines, by value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderMetadata {
+    pub keywords: HashMap<u64, (String, String)>,
+    pub levels: HashMap<u64, (String, String)>,
+    pub channels: HashMap<u64, (String, String)>,
+    pub tasks: HashMap<u64, (String, String)>,
+    /// Values as reported by TDH, with the task in the upper bits.
+    pub opcodes: HashMap<u64, (String, String)>,
+}
+
+impl ProviderMetadata {
+    /// Collect the fields of all types of `provider`.
+    ///
+    /// Field types the provider doesn't define, and all of them for providers
+    /// without a manifest, are left empty. Other failures of TDH are returned.
+    pub fn new(provider: &GUID) -> Result<Self, TraceError> {
+        let fields = |field_type: EventFieldType| match ProviderFieldInformation::new(provider, &field_type) {
+            Ok(field_info) => Ok(field_info
+                .iter()
+                .map(|info| {
+                    (
+                        info.value(),
+                        (
+                            info.name().to_string_lossy().into_owned(),
+                            info.description().to_string_lossy().into_owned(),
+                        ),
+                    )
+                })
+                .collect()),
+            Err(err @ (ProviderFieldInformationError::NotSupported | ProviderFieldInformationError::NotFound)) => {
+                log::debug!("No {:?} for provider {:?}: {}", field_type, provider, err);
+                Ok(HashMap::new())
+            }
+            Err(ProviderFieldInformationError::Windows(err)) => Err(TraceError::Windows(err)),
+        };
+        Ok(Self {
+            keywords: fields(EventFieldType::KeywordInformation)?,
+            levels: fields(EventFieldType::LevelInformation)?,
+            channels: fields(EventFieldType::ChannelInformation)?,
+            tasks: fields(EventFieldType::TaskInformation)?,
+            opcodes: fields(EventFieldType::OpcodeInformation)?,
+        })
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(remote = "GUID")]
 struct GUIDDef {
@@ -738,7 +1152,20 @@ impl EventMapInfo {
     }
 
     pub fn get(&self, idx: usize) -> Option<&EVENT_MAP_ENTRY> {
-        self.data().MapEntryArray.get(idx)
+        // The entries trail the header, `MapEntryArray` only declares the first one, so
+        // derive the pointer from the whole buffer rather than from that field
+        let offset = mem::offset_of!(EVENT_MAP_INFO, MapEntryArray) + idx * mem::size_of::<EVENT_MAP_ENTRY>();
+        if idx >= self.len() || offset + mem::size_of::<EVENT_MAP_ENTRY>() > self.buffer.len() {
+            return None;
+        }
+        let entry = self.buffer[offset..].as_ptr() as *const EVENT_MAP_ENTRY;
+
+        #[cfg(not(feature = "unchecked_cast"))]
+        if !entry.is_aligned() {
+            return None;
+        }
+
+        unsafe { entry.as_ref() }
     }
 
     pub fn len(&self) -> usize {
@@ -776,9 +1203,296 @@ impl EventMapInfo {
 
 #[cfg(test)]
 mod tests {
-    use windows::core::GUID;
+    use std::{mem, ptr};
+
+    use windows::{
+        core::GUID,
+        Win32::System::Diagnostics::Etw::{
+            PropertyHasCustomSchema, PropertyParamLength, EVENT_PROPERTY_INFO, TDH_INTYPE_BINARY, TDH_INTYPE_UINT32, TDH_INTYPE_UNICODESTRING,
+            TDH_OUTTYPE_HEXBINARY, TDH_OUTTYPE_PID, TDH_OUTTYPE_STRING, TRACE_EVENT_INFO,
+        },
+    };
+
+    use crate::{
+        error::ParseError,
+        schema::{
+            cache::{DecodeContext, EventInfo, PropertyNestedInfo, PropertyValue},
+            in_type::InType,
+            out_type::OutType,
+        },
+        values::{compound::StructOrValue, in_value::InValue},
+    };
+
+    use super::{ChannelType, ProviderEventDescriptors, RawPropertyType, TraceEventInfo};
+
+    const PROVIDER: GUID = GUID::from_u128(0x22fb2cd6_0e7b_422b_a0c7_2fad1fd0e716);
+
+    /// Properties as (name, in-type, out-type, length property index).
+    type SyntheticProperty<'a> = (&'a str, i32, i32, Option<u16>);
+
+    /// Lay out a `TRACE_EVENT_INFO` like TDH does: header, property array, then strings.
+    fn synthetic_trace_event_info(provider_name: &str, properties: &[SyntheticProperty]) -> Vec<u8> {
+        let header_size = mem::offset_of!(TRACE_EVENT_INFO, EventPropertyInfoArray);
+        let mut buffer = vec![0u8; header_size + properties.len() * mem::size_of::<EVENT_PROPERTY_INFO>()];
+        let push_string = |buffer: &mut Vec<u8>, text: &str| {
+            let offset = buffer.len() as u32;
+            buffer.extend(text.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+            offset
+        };
+
+        let mut info = TRACE_EVENT_INFO {
+            ProviderGuid: PROVIDER,
+            PropertyCount: properties.len() as u32,
+            TopLevelPropertyCount: properties.len() as u32,
+            ..Default::default()
+        };
+        info.EventDescriptor.Id = 1;
+        info.EventDescriptor.Version = 2;
+        info.ProviderNameOffset = push_string(&mut buffer, provider_name);
+
+        for (idx, (name, in_type, out_type, length_index)) in properties.iter().enumerate() {
+            let mut property = EVENT_PROPERTY_INFO {
+                NameOffset: push_string(&mut buffer, name),
+                ..Default::default()
+            };
+            property.Anonymous1.nonStructType.InType = *in_type as u16;
+            property.Anonymous1.nonStructType.OutType = *out_type as u16;
+            property.Anonymous2.count = 1;
+            match length_index {
+                Some(length_index) => {
+                    property.Flags = PropertyParamLength;
+                    property.Anonymous3.lengthPropertyIndex = *length_index;
+                }
+                None => property.Anonymous3.length = 0,
+            }
+            let offset = header_size + idx * mem::size_of::<EVENT_PROPERTY_INFO>();
+            unsafe { ptr::write_unaligned(buffer.as_mut_ptr().add(offset) as *mut EVENT_PROPERTY_INFO, property) };
+        }
+        unsafe { ptr::write_unaligned(buffer.as_mut_ptr() as *mut TRACE_EVENT_INFO, info) };
+        buffer
+    }
+
+    fn process_properties() -> [SyntheticProperty<'static>; 4] {
+        [
+            ("ProcessID", TDH_INTYPE_UINT32.0, TDH_OUTTYPE_PID.0, None),
+            ("ImageName", TDH_INTYPE_UNICODESTRING.0, TDH_OUTTYPE_STRING.0, None),
+            ("DataLength", TDH_INTYPE_UINT32.0, 0, None),
+            ("Data", TDH_INTYPE_BINARY.0, TDH_OUTTYPE_HEXBINARY.0, Some(2)),
+        ]
+    }
+
+    #[test]
+    fn test_parse_schema_from_buffer() {
+        let buffer = synthetic_trace_event_info("Microsoft-Windows-Kernel-Process", &process_properties());
+        let trace_event_info = TraceEventInfo::from_buffer(&buffer).unwrap();
+        assert_eq!(trace_event_info.as_bytes(), &buffer[..]);
+
+        let schema = EventInfo::parse(&trace_event_info, None).unwrap();
+        assert_eq!(schema.provider_guid, PROVIDER);
+        assert_eq!((schema.event_id, schema.event_version), (1, 2));
+        assert_eq!(schema.provider_name(), Some("Microsoft-Windows-Kernel-Process"));
+
+        let fields = schema
+            .properties
+            .fields
+            .iter()
+            .map(|field| match &field.value {
+                PropertyNestedInfo::Value(name, info) => (name.as_str(), info.in_type, info.out_type),
+                PropertyNestedInfo::Struct(name, _) => panic!("Unexpected struct {}", name),
+                PropertyNestedInfo::CustomSchema(name, _) => panic!("Unexpected custom schema {}", name),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                ("ProcessID", InType::UInt32, OutType::Pid),
+                ("ImageName", InType::UnicodeString, OutType::String),
+                ("DataLength", InType::UInt32, OutType::from(0)),
+                ("Data", InType::Binary, OutType::HexBinary),
+            ]
+        );
+        assert!(matches!(schema.properties.fields[3].length, PropertyValue::Reference(2)));
+    }
+
+    #[test]
+    fn test_string_accessors() {
+        let mut buffer = synthetic_trace_event_info("Microsoft-Windows-Kernel-Process", &process_properties());
+        let keywords_offset = buffer.len() as u32;
+        buffer.extend("WINEVENT_KEYWORD_PROCESS WINEVENT_KEYWORD_THREAD ".encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        unsafe {
+            let info_ptr = buffer.as_mut_ptr() as *mut TRACE_EVENT_INFO;
+            let mut info = ptr::read_unaligned(info_ptr);
+            info.KeywordsNameOffset = keywords_offset;
+            ptr::write_unaligned(info_ptr, info);
+        }
+        let trace_event_info = TraceEventInfo::from_buffer(&buffer).unwrap();
+
+        assert_eq!(trace_event_info.provider_name_string().as_deref(), Some("Microsoft-Windows-Kernel-Process"));
+        assert_eq!(trace_event_info.provider_name(true).map(<[u16]>::len), Some(33));
+        assert_eq!(trace_event_info.task_name_string(), None);
+        assert_eq!(trace_event_info.keyword_names(), ["WINEVENT_KEYWORD_PROCESS", "WINEVENT_KEYWORD_THREAD"]);
+        assert!(format!("{:?}", trace_event_info).contains("\"Microsoft-Windows-Kernel-Process\""));
+    }
+
+    #[test]
+    fn test_parse_custom_schema_property() {
+        let mut buffer = synthetic_trace_event_info("Microsoft.Windows.Sample", &process_properties());
+        let schema_blob = [2u8, 0, 3, 0, 0xa1, 0xa2, 0xa3];
+        let schema_offset = buffer.len() as u32;
+        buffer.extend_from_slice(&schema_blob);
+        let property_offset =
+            mem::offset_of!(TRACE_EVENT_INFO, EventPropertyInfoArray) + 3 * mem::size_of::<EVENT_PROPERTY_INFO>();
+        unsafe {
+            let property_ptr = buffer.as_mut_ptr().add(property_offset) as *mut EVENT_PROPERTY_INFO;
+            let mut property = ptr::read_unaligned(property_ptr);
+            property.Flags = PropertyParamLength | PropertyHasCustomSchema;
+            property.Anonymous1.customSchemaType.CustomSchemaOffset = schema_offset;
+            ptr::write_unaligned(property_ptr, property);
+        }
+        let trace_event_info = TraceEventInfo::from_buffer(&buffer).unwrap();
+        assert_eq!(trace_event_info.custom_schema(schema_offset), Some(&schema_blob[..]));
+
+        let schema = EventInfo::parse(&trace_event_info, None).unwrap();
+        assert_eq!(
+            schema.properties.fields[3].value,
+            PropertyNestedInfo::CustomSchema("Data".to_string(), schema_blob.to_vec())
+        );
+
+        let mut userdata = 4u32.to_le_bytes().to_vec();
+        userdata.extend("a\0".encode_utf16().flat_map(u16::to_le_bytes));
+        userdata.extend(3u32.to_le_bytes());
+        userdata.extend([1, 2, 3]);
+        let (data, remainder) = schema.properties.decode(&userdata, &mut DecodeContext::default()).unwrap();
+        assert!(remainder.is_empty());
+        let StructOrValue::Value(value) = &data.values[3] else {
+            panic!("Expected a value, got {:?}", data.values[3]);
+        };
+        assert!(matches!(&value.value, InValue::Binary(bytes) if bytes == &[1, 2, 3]));
+
+        // A schema running past the end of the buffer fails instead of panicking
+        let mut truncated = trace_event_info.as_bytes().to_vec();
+        truncated.truncate(truncated.len() - 2);
+        let truncated = TraceEventInfo::from_buffer(&truncated).unwrap();
+        assert!(matches!(
+            EventInfo::parse(&truncated, None),
+            Err(ParseError::InvalidTraceEventInfo(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_buffer_rejects_malformed_buffers() {
+        let buffer = synthetic_trace_event_info("Provider", &process_properties());
+        let header_size = mem::offset_of!(TRACE_EVENT_INFO, EventPropertyInfoArray);
+        let assert_invalid = |buffer: Vec<u8>| {
+            assert!(matches!(
+                TraceEventInfo::from_buffer(&buffer),
+                Err(ParseError::InvalidTraceEventInfo(_))
+            ));
+        };
 
-    use super::ProviderEventDescriptors;
+        assert_invalid(Vec::new());
+        assert_invalid(buffer[..header_size - 1].to_vec());
+        // Property array cut off.
+        assert_invalid(buffer[..header_size + mem::size_of::<EVENT_PROPERTY_INFO>()].to_vec());
+        // Strings cut off, so the name offsets point beyond the buffer.
+        assert_invalid(buffer[..header_size + 4 * mem::size_of::<EVENT_PROPERTY_INFO>()].to_vec());
+
+        let mut too_many_properties = buffer.clone();
+        let count_offset = mem::offset_of!(TRACE_EVENT_INFO, PropertyCount);
+        too_many_properties[count_offset..count_offset + 4].copy_from_slice(&1000u32.to_le_bytes());
+        assert_invalid(too_many_properties);
+
+        let dangling_length = synthetic_trace_event_info(
+            "Provider",
+            &[("Data", TDH_INTYPE_BINARY.0, TDH_OUTTYPE_HEXBINARY.0, Some(5))],
+        );
+        assert_invalid(dangling_length);
+
+        let mut odd_name_offset = buffer.clone();
+        let name_offset = mem::offset_of!(TRACE_EVENT_INFO, EventPropertyInfoArray) + mem::offset_of!(EVENT_PROPERTY_INFO, NameOffset);
+        let offset = u32::from_le_bytes(odd_name_offset[name_offset..name_offset + 4].try_into().unwrap());
+        odd_name_offset[name_offset..name_offset + 4].copy_from_slice(&(offset + 1).to_le_bytes());
+        assert_invalid(odd_name_offset);
+    }
+
+    #[test]
+    fn test_from_buffer_copies_misaligned_buffer() {
+        let buffer = synthetic_trace_event_info("Microsoft-Windows-Kernel-Process", &process_properties());
+        let mut shifted = vec![0u8];
+        shifted.extend_from_slice(&buffer);
+
+        let trace_event_info = TraceEventInfo::from_buffer(&shifted[1..]).unwrap();
+        assert_eq!(trace_event_info.as_bytes(), &buffer[..]);
+        assert_eq!(trace_event_info.provider_name_string().as_deref(), Some("Microsoft-Windows-Kernel-Process"));
+        assert_eq!(EventInfo::parse(&trace_event_info, None).unwrap().properties.fields.len(), 4);
+    }
+
+    /// `TRACE_EVENT_INFO` fixtures laid out the way TDH returns them for manifest
+    /// events, with the schema each one parses to.
+    const FIXTURES: [(&str, &[u8], &str); 3] = [
+        (
+            "kernel_process_start",
+            include_bytes!("../tests/resources/trace_event_info/kernel_process_start.bin"),
+            include_str!("../tests/resources/trace_event_info/kernel_process_start.json"),
+        ),
+        (
+            "kernel_file_create",
+            include_bytes!("../tests/resources/trace_event_info/kernel_file_create.bin"),
+            include_str!("../tests/resources/trace_event_info/kernel_file_create.json"),
+        ),
+        (
+            "struct_array",
+            include_bytes!("../tests/resources/trace_event_info/struct_array.bin"),
+            include_str!("../tests/resources/trace_event_info/struct_array.json"),
+        ),
+    ];
+
+    #[test]
+    fn test_fixture_accessors() {
+        let trace_event_info = TraceEventInfo::from_buffer(FIXTURES[0].1).unwrap();
+        assert_eq!(trace_event_info.provider_guid(), PROVIDER);
+        assert_eq!((trace_event_info.event_id(), trace_event_info.event_version()), (1, 3));
+        assert_eq!(trace_event_info.level_name_string().as_deref(), Some("Information"));
+        assert_eq!(
+            trace_event_info.channel_name_string().as_deref(),
+            Some("Microsoft-Windows-Kernel-Process/Analytic")
+        );
+        assert_eq!(trace_event_info.task_name_string().as_deref(), Some("ProcessStart"));
+        assert_eq!(
+            trace_event_info.event_message_string().as_deref(),
+            Some("Process %1 started at time %2 by parent %3 running in session %4 with name %5. ")
+        );
+        assert_eq!(trace_event_info.keyword_names(), ["WINEVENT_KEYWORD_PROCESS"]);
+        assert_eq!((trace_event_info.property_count(), trace_event_info.top_level_property_count()), (10, 10));
+
+        let struct_array = TraceEventInfo::from_buffer(FIXTURES[2].1).unwrap();
+        assert_eq!((struct_array.property_count(), struct_array.top_level_property_count()), (4, 2));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_fixtures_parse_to_golden_schemas() {
+        for (name, buffer, golden) in FIXTURES {
+            let trace_event_info = TraceEventInfo::from_buffer(buffer).unwrap();
+            let schema = EventInfo::parse(&trace_event_info, None).unwrap();
+            let golden: serde_json::Value = serde_json::from_str(golden).unwrap();
+            assert_eq!(serde_json::to_value(&schema).unwrap(), golden, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_from_buffer_round_trips_tdh_buffer() {
+        let provider_guid = GUID::try_from("1C95126E-7EEA-49A9-A3FE-A378B03DDB4D").unwrap();
+        let event_descriptors = ProviderEventDescriptors::new(&provider_guid).unwrap();
+        let event_descriptor = event_descriptors.get_id_version(3019, 0).unwrap();
+        let manifest_information = event_descriptor.manifest_information().unwrap();
+
+        let captured = TraceEventInfo::from_buffer(manifest_information.as_bytes()).unwrap();
+        let expected = EventInfo::parse(&manifest_information, None).unwrap();
+        let parsed = EventInfo::parse(&captured, None).unwrap();
+        assert_eq!(parsed.properties, expected.properties);
+        assert_eq!(parsed.provider_name(), expected.provider_name());
+    }
 
     #[test] 
     fn test_microsoft_windows_dns_client_event_descriptor_3019_first_attribute_name() {
@@ -798,4 +1512,30 @@ mod tests {
 
         assert_eq!(name, "QueryName");
     }
+
+    #[test]
+    fn test_raw_properties_of_dns_client_event_3019() {
+        let provider_guid = GUID::try_from("1C95126E-7EEA-49A9-A3FE-A378B03DDB4D").unwrap();
+        let event_descriptors = ProviderEventDescriptors::new(&provider_guid).unwrap();
+        let event_descriptor = event_descriptors.get_id_version(3019, 0).unwrap();
+        let manifest_information = event_descriptor.manifest_information().unwrap();
+        let properties = manifest_information.raw_properties();
+
+        assert_eq!(properties.len(), manifest_information.property_count());
+        assert_eq!(properties[0].index, 0);
+        assert_eq!(properties[0].name.as_deref(), Some("QueryName"));
+        assert!(matches!(properties[0].property_type, RawPropertyType::Value { .. }));
+    }
+
+    #[test]
+    fn test_channel_type_classification() {
+        assert_eq!(ChannelType::classify("Microsoft-Windows-DNS-Client/Operational", 16), Some(ChannelType::Operational));
+        assert_eq!(ChannelType::classify("Microsoft-Windows-WinINet/Analytic", 17), Some(ChannelType::Analytic));
+        assert_eq!(ChannelType::classify("Microsoft-Windows-Kernel-Process/debug", 18), Some(ChannelType::Debug));
+        assert_eq!(ChannelType::classify("Microsoft-Windows-Foo/Admin", 19), Some(ChannelType::Admin));
+        assert_eq!(ChannelType::classify("System", 8), Some(ChannelType::Admin));
+        assert_eq!(ChannelType::classify("Security", 10), Some(ChannelType::Admin));
+        assert_eq!(ChannelType::classify("Microsoft-Windows-Foo/Custom", 20), None);
+        assert_eq!(ChannelType::classify("TraceClassic", 0), None);
+    }
 }
@@ -0,0 +1,495 @@
+//! Classification of event timestamps.
+//!
+//! `ProcessTrace` converts timestamps to FILETIME ticks, but not every event carries
+//! a timestamp that can be converted to a point in time:
+//!
+//! | Session configuration                                    | Result       |
+//! |----------------------------------------------------------|--------------|
+//! | Real-time or file trace, any [`ClockResolution`]          | `Absolute`   |
+//! | Classic events (`EVENT_HEADER_FLAG_CLASSIC_HEADER`) in sessions with `WNODE_FLAG_USE_TIMESTAMP`, see [`TimestampContext::with_provider_timestamps`] | provider's FILETIME value, whatever the clock: `Absolute`, `Relative` if before 1970 |
+//! | Private sessions (`EVENT_HEADER_FLAG_PRIVATE_SESSION`) logging with `EVENT_HEADER_FLAG_NO_CPUTIME` | `Relative`; converted timestamps are `Absolute` from 1970 on |
+//! | Raw timestamps with QPC or CPU cycle clock                | `Relative`, using the clock frequency; `Absolute` if the clock value at the session start is known |
+//! | Raw timestamps with the system time clock                 | as for converted timestamps |
+//! | Timestamp of zero or less                                 | `Missing`    |
+//!
+//! [`TimestampContext::classify`] takes the header flags into account,
+//! [`TimestampContext::convert`] only the session's clock. Values before the Unix
+//! epoch are never converted, so no 1601-era dates come out of either.
+
+use std::{
+    cmp::Ordering,
+    time::{Duration, SystemTime},
+};
+
+use time::OffsetDateTime;
+use windows::Win32::System::Diagnostics::Etw::{
+    EVENT_HEADER, EVENT_HEADER_FLAG_CLASSIC_HEADER, EVENT_HEADER_FLAG_NO_CPUTIME, EVENT_HEADER_FLAG_PRIVATE_SESSION,
+    TRACE_LOGFILE_HEADER,
+};
+
+use crate::trace_session::ClockResolution;
+
+/// FILETIME ticks (100ns since 1601) of 1970-01-01.
+const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+const NANOS_PER_TICK: i128 = 100;
+const TICKS_PER_SECOND: i64 = 10_000_000;
+
+/// The time `ticks` FILETIME ticks take, zero for negative spans.
+pub(crate) fn ticks_to_duration(ticks: i64) -> Duration {
+    let ticks = ticks.max(0);
+    Duration::from_secs((ticks / TICKS_PER_SECOND) as u64)
+        + Duration::from_nanos(((ticks % TICKS_PER_SECOND) as i128 * NANOS_PER_TICK) as u64)
+}
+
+/// FILETIME ticks in `duration`, rounded down and saturating.
+pub(crate) fn duration_to_ticks(duration: Duration) -> i64 {
+    i64::try_from(duration.as_nanos() / NANOS_PER_TICK as u128).unwrap_or(i64::MAX)
+}
+
+/// Convert FILETIME ticks to a point in time, None for 0 and negative ticks.
+pub(crate) fn ticks_to_system_time(ticks: i64) -> Option<SystemTime> {
+    if ticks <= 0 {
+        return None;
+    }
+    match ticks.checked_sub(UNIX_EPOCH_TICKS)? {
+        since_epoch @ 0.. => SystemTime::UNIX_EPOCH.checked_add(ticks_to_duration(since_epoch)),
+        before_epoch => SystemTime::UNIX_EPOCH.checked_sub(ticks_to_duration(-before_epoch)),
+    }
+}
+
+/// Convert a point in time to FILETIME ticks, saturating for times outside their range.
+pub(crate) fn system_time_to_ticks(time: SystemTime) -> i64 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since_epoch) => UNIX_EPOCH_TICKS.saturating_add(duration_to_ticks(since_epoch)),
+        Err(error) => UNIX_EPOCH_TICKS - duration_to_ticks(error.duration()).min(UNIX_EPOCH_TICKS),
+    }
+}
+
+/// Timestamp of an event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", content = "value", rename_all = "snake_case"))]
+pub enum Timestamp {
+    Absolute(OffsetDateTime),
+    /// Time since an unknown reference, e.g. boot or the start of the session.
+    Relative(Duration),
+    /// The event has no usable timestamp.
+    #[default]
+    Missing,
+}
+
+impl Timestamp {
+    pub fn absolute(&self) -> Option<OffsetDateTime> {
+        match self {
+            Self::Absolute(time) => Some(*time),
+            _ => None,
+        }
+    }
+
+    pub fn is_missing(&self) -> bool {
+        matches!(self, Self::Missing)
+    }
+}
+
+/// How the timestamps of a trace are to be interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimestampContext {
+    raw_clock: Option<(ClockResolution, u64)>,
+    /// FILETIME ticks of the session start, which raw QPC and CPU cycle timestamps
+    /// convert relative to.
+    start_time: Option<i64>,
+    /// Raw clock value at `start_time`.
+    start_ticks: Option<i64>,
+    /// Classic providers set their own timestamps with `WNODE_FLAG_USE_TIMESTAMP`.
+    provider_timestamps: bool,
+}
+
+impl TimestampContext {
+    /// Timestamps converted to FILETIME by `ProcessTrace`, the way [`crate::trace::Trace`] opens traces.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Timestamps in units of the session's clock, as delivered with
+    /// `PROCESS_TRACE_MODE_RAW_TIMESTAMP`. `frequency` is the number of clock ticks per
+    /// second, i.e. `PerfFreq` of the logfile header for QPC and the CPU speed for cycle counters.
+    pub fn raw(clock: ClockResolution, frequency: u64) -> Self {
+        Self {
+            raw_clock: Some((clock, frequency)),
+            ..Self::default()
+        }
+    }
+
+    /// Raw timestamps of a session that started at `start_time` (FILETIME ticks), when
+    /// its clock read `start_ticks`, so QPC and CPU cycle timestamps convert to absolute times.
+    pub fn raw_since_start(clock: ClockResolution, frequency: u64, start_time: i64, start_ticks: i64) -> Self {
+        Self::raw(clock, frequency).with_start_time(start_time).with_start_ticks(start_ticks)
+    }
+
+    /// The session started at `start_time` (FILETIME ticks), e.g. `StartTime` of the logfile header.
+    pub fn with_start_time(mut self, start_time: i64) -> Self {
+        self.start_time = (start_time > 0).then_some(start_time);
+        self
+    }
+
+    /// The raw clock value at the session start, e.g. the timestamp of a trace's first
+    /// event, which the session logs when it starts.
+    pub fn with_start_ticks(mut self, start_ticks: i64) -> Self {
+        self.start_ticks = Some(start_ticks);
+        self
+    }
+
+    /// Classic providers of the session log with `WNODE_FLAG_USE_TIMESTAMP`, so their
+    /// events carry the provider's FILETIME value rather than one from the session's clock.
+    pub fn with_provider_timestamps(mut self, provider_timestamps: bool) -> Self {
+        self.provider_timestamps = provider_timestamps;
+        self
+    }
+
+    /// The context of a trace opened with the given logfile header, as filled in by
+    /// `OpenTraceW`. `raw_timestamps` tells whether the trace was opened with
+    /// `PROCESS_TRACE_MODE_RAW_TIMESTAMP`; otherwise the header's clock doesn't matter.
+    ///
+    /// The clock type is taken from `ReservedFlags`, the QPC frequency from `PerfFreq`,
+    /// the cycle counter frequency from `CpuSpeedInMHz` and the session start from
+    /// `StartTime`. This has limits:
+    ///
+    /// - The header doesn't hold the raw clock value at `StartTime`, so timestamps stay
+    ///   [`Timestamp::Relative`] until it is set with [`TimestampContext::with_start_ticks`].
+    ///   [`crate::trace::Trace::timestamp_context`] sets it from the trace's header event.
+    /// - `CpuSpeedInMHz` is the nominal speed in whole MHz, not the measured cycle counter
+    ///   frequency, so converted cycle counter timestamps drift over long traces.
+    /// - `BootTime`, which QPC counts from, is only filled in for global logger traces and
+    ///   isn't used.
+    pub fn from_logfile_header(header: &TRACE_LOGFILE_HEADER, raw_timestamps: bool) -> Self {
+        if !raw_timestamps {
+            return Self::new();
+        }
+        let Some(clock) = ClockResolution::from_client_context(header.ReservedFlags) else {
+            log::warn!("Unknown clock type {} in logfile header", header.ReservedFlags);
+            return Self::new();
+        };
+        let frequency = match clock {
+            ClockResolution::QueryPerformanceCounter => u64::try_from(header.PerfFreq).unwrap_or(0),
+            ClockResolution::CpuCycleCounter => {
+                u64::from(unsafe { header.Anonymous2.Anonymous.CpuSpeedInMHz }) * 1_000_000
+            }
+            ClockResolution::SystemTime => 0,
+        };
+        Self::raw(clock, frequency).with_start_time(header.StartTime)
+    }
+
+    /// Classify the timestamp of the event with `header`, see the [module documentation](self).
+    pub fn classify(&self, header: &EVENT_HEADER) -> Timestamp {
+        let timestamp = header.TimeStamp;
+        let flags = u32::from(header.Flags);
+        if timestamp <= 0 {
+            return Timestamp::Missing;
+        }
+        if self.provider_timestamps && flags & EVENT_HEADER_FLAG_CLASSIC_HEADER != 0 {
+            return filetime_to_timestamp(timestamp);
+        }
+        let private_no_cputime = EVENT_HEADER_FLAG_PRIVATE_SESSION | EVENT_HEADER_FLAG_NO_CPUTIME;
+        if flags & private_no_cputime == private_no_cputime {
+            // Private loggers without CPU times don't count like the session's clock, so
+            // its start doesn't make them absolute
+            return match self.raw_clock {
+                None | Some((ClockResolution::SystemTime, _)) => filetime_to_timestamp(timestamp),
+                Some((_, 0)) => Timestamp::Missing,
+                Some((_, frequency)) => Timestamp::Relative(clock_duration(timestamp as u64, frequency)),
+            };
+        }
+        self.convert(timestamp)
+    }
+
+    pub fn convert(&self, timestamp: i64) -> Timestamp {
+        if timestamp <= 0 {
+            return Timestamp::Missing;
+        }
+        match self.raw_clock {
+            None | Some((ClockResolution::SystemTime, _)) => filetime_to_timestamp(timestamp),
+            Some((_, 0)) => Timestamp::Missing,
+            Some((_, frequency)) => {
+                let (Some(start_time), Some(start_ticks)) = (self.start_time, self.start_ticks) else {
+                    return Timestamp::Relative(clock_duration(timestamp as u64, frequency));
+                };
+                // Events logged before the start, e.g. by rundowns, are fine as well
+                let elapsed = (i128::from(timestamp) - i128::from(start_ticks)) * (1_000_000_000 / NANOS_PER_TICK)
+                    / i128::from(frequency);
+                match i64::try_from(i128::from(start_time) + elapsed) {
+                    Ok(ticks) if ticks > 0 => filetime_to_timestamp(ticks),
+                    _ => Timestamp::Missing,
+                }
+            }
+        }
+    }
+
+    /// The time between the timestamps `from` and `to`, zero if `to` is earlier.
+    ///
+    /// Unlike [`TimestampContext::convert`] this doesn't need the session start, only the
+    /// clock frequency; None if that is unknown.
+    pub fn elapsed(&self, from: i64, to: i64) -> Option<Duration> {
+        let ticks = to.saturating_sub(from).max(0);
+        match self.raw_clock {
+            None | Some((ClockResolution::SystemTime, _)) => Some(ticks_to_duration(ticks)),
+            Some((_, 0)) => None,
+            Some((_, frequency)) => Some(clock_duration(ticks as u64, frequency)),
+        }
+    }
+
+    /// Convert `timestamp` to a point in time, if it is one.
+    ///
+    /// Timestamps converted by `ProcessTrace` and raw system time timestamps are
+    /// FILETIME ticks and convert directly, at the resolution the session's clock had.
+    /// Raw QPC and CPU cycle timestamps only convert if the context knows the session
+    /// start and the clock value at that time, and are as precise as the clock frequency. Timestamps
+    /// [`TimestampContext::convert`] doesn't return as [`Timestamp::Absolute`] give None.
+    pub fn to_system_time(&self, timestamp: i64) -> Option<SystemTime> {
+        self.convert(timestamp).absolute().map(SystemTime::from)
+    }
+}
+
+/// The time `ticks` of a clock with `frequency` ticks per second take.
+fn clock_duration(ticks: u64, frequency: u64) -> Duration {
+    let nanos = u128::from(ticks % frequency) * 1_000_000_000 / u128::from(frequency);
+    Duration::from_secs(ticks / frequency) + Duration::from_nanos(nanos as u64)
+}
+
+fn filetime_to_timestamp(ticks: i64) -> Timestamp {
+    if ticks < UNIX_EPOCH_TICKS {
+        return Timestamp::Relative(ticks_to_duration(ticks));
+    }
+    let nanos = i128::from(ticks - UNIX_EPOCH_TICKS) * NANOS_PER_TICK;
+    match OffsetDateTime::from_unix_timestamp_nanos(nanos) {
+        Ok(time) => Timestamp::Absolute(time),
+        Err(_) => Timestamp::Missing,
+    }
+}
+
+/// Sort `items`, given in arrival order, by their timestamps.
+///
+/// Items with a [`Timestamp::Missing`] timestamp stay behind the item that arrived
+/// before them. Relative timestamps sort before absolute ones. The sort is stable.
+pub fn sort_by_timestamp<T>(items: &mut Vec<T>, timestamp: impl Fn(&T) -> Timestamp) {
+    let mut previous = None;
+    let mut keyed = items
+        .drain(..)
+        .map(|item| {
+            let key = match timestamp(&item) {
+                Timestamp::Missing => previous,
+                Timestamp::Relative(duration) => Some((0, duration.as_nanos() as i128)),
+                Timestamp::Absolute(time) => Some((1, time.unix_timestamp_nanos())),
+            };
+            previous = key;
+            (key, item)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    items.extend(keyed.into_iter().map(|(_, item)| item));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use time::OffsetDateTime;
+    use windows::Win32::System::Diagnostics::Etw::{
+        EVENT_HEADER, EVENT_HEADER_FLAG_CLASSIC_HEADER, EVENT_HEADER_FLAG_NO_CPUTIME, EVENT_HEADER_FLAG_PRIVATE_SESSION,
+        TRACE_LOGFILE_HEADER,
+    };
+
+    use crate::trace_session::ClockResolution;
+
+    use super::{
+        duration_to_ticks, sort_by_timestamp, system_time_to_ticks, ticks_to_duration, ticks_to_system_time,
+        Timestamp, TimestampContext, UNIX_EPOCH_TICKS,
+    };
+
+    /// 2023-05-01 12:00:00 UTC.
+    const TICKS_2023: i64 = 133_274_160_000_000_000;
+
+    fn header(flags: u32, timestamp: i64) -> EVENT_HEADER {
+        EVENT_HEADER {
+            Flags: flags as u16,
+            TimeStamp: timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_classification_matrix() {
+        const PRIVATE_NO_CPUTIME: u32 = EVENT_HEADER_FLAG_PRIVATE_SESSION | EVENT_HEADER_FLAG_NO_CPUTIME;
+        let one_second = Duration::from_secs(1);
+        let time_2023 = OffsetDateTime::from_unix_timestamp(1_682_942_400).unwrap();
+        let converted = TimestampContext::new();
+        let provider = TimestampContext::new().with_provider_timestamps(true);
+        let qpc = TimestampContext::raw_since_start(ClockResolution::QueryPerformanceCounter, 10_000_000, TICKS_2023, 0);
+        let qpc_provider = qpc.with_provider_timestamps(true);
+        let cycles = TimestampContext::raw(ClockResolution::CpuCycleCounter, 0);
+
+        let cases = [
+            (converted, 0, TICKS_2023, Timestamp::Absolute(time_2023)),
+            (converted, 0, 10_000_000, Timestamp::Relative(one_second)),
+            (converted, PRIVATE_NO_CPUTIME, TICKS_2023, Timestamp::Absolute(time_2023)),
+            (converted, PRIVATE_NO_CPUTIME, 10_000_000, Timestamp::Relative(one_second)),
+            (provider, EVENT_HEADER_FLAG_CLASSIC_HEADER, TICKS_2023, Timestamp::Absolute(time_2023)),
+            (qpc, 0, 10_000_000, Timestamp::Absolute(time_2023 + one_second)),
+            (qpc, EVENT_HEADER_FLAG_CLASSIC_HEADER, 10_000_000, Timestamp::Absolute(time_2023 + one_second)),
+            (qpc, EVENT_HEADER_FLAG_PRIVATE_SESSION, 10_000_000, Timestamp::Absolute(time_2023 + one_second)),
+            (qpc, PRIVATE_NO_CPUTIME, 10_000_000, Timestamp::Relative(one_second)),
+            (qpc_provider, 0, 10_000_000, Timestamp::Absolute(time_2023 + one_second)),
+            (qpc_provider, EVENT_HEADER_FLAG_CLASSIC_HEADER, TICKS_2023, Timestamp::Absolute(time_2023)),
+            (qpc_provider, EVENT_HEADER_FLAG_CLASSIC_HEADER, 10_000_000, Timestamp::Relative(one_second)),
+            (cycles, 0, 10_000_000, Timestamp::Missing),
+            (cycles, PRIVATE_NO_CPUTIME, 10_000_000, Timestamp::Missing),
+            (qpc_provider, EVENT_HEADER_FLAG_CLASSIC_HEADER, 0, Timestamp::Missing),
+            (converted, PRIVATE_NO_CPUTIME, -1, Timestamp::Missing),
+        ];
+        for (context, flags, timestamp, expected) in cases {
+            assert_eq!(context.classify(&header(flags, timestamp)), expected, "{context:?} {flags:#x} {timestamp}");
+        }
+
+        // No combination lets a pre-1970 date through
+        let flag_sets = [0, EVENT_HEADER_FLAG_CLASSIC_HEADER, EVENT_HEADER_FLAG_PRIVATE_SESSION, PRIVATE_NO_CPUTIME];
+        let contexts = [converted, provider, qpc, qpc_provider, cycles];
+        for flags in flag_sets {
+            for context in contexts {
+                for timestamp in [i64::MIN, -1, 0, 1, UNIX_EPOCH_TICKS - 1, UNIX_EPOCH_TICKS, TICKS_2023, i64::MAX] {
+                    if let Timestamp::Absolute(time) = context.classify(&header(flags, timestamp)) {
+                        assert!(time.year() >= 1970, "{context:?} {flags:#x} {timestamp}: {time}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filetime_conversion() {
+        let context = TimestampContext::new();
+        assert_eq!(context.convert(TICKS_2023), Timestamp::Absolute(OffsetDateTime::from_unix_timestamp(1_682_942_400).unwrap()));
+        assert_eq!(context.convert(UNIX_EPOCH_TICKS), Timestamp::Absolute(OffsetDateTime::UNIX_EPOCH));
+        assert_eq!(context.convert(10_000_000), Timestamp::Relative(Duration::from_secs(1)));
+        assert_eq!(context.convert(i64::MAX), Timestamp::Missing);
+        assert_eq!(
+            TimestampContext::raw(ClockResolution::SystemTime, 0).convert(TICKS_2023),
+            context.convert(TICKS_2023)
+        );
+    }
+
+    #[test]
+    fn test_raw_clock_conversion() {
+        let qpc = TimestampContext::raw(ClockResolution::QueryPerformanceCounter, 10_000_000);
+        assert_eq!(qpc.convert(TICKS_2023), Timestamp::Relative(Duration::from_nanos(TICKS_2023 as u64 * 100)));
+        let cycles = TimestampContext::raw(ClockResolution::CpuCycleCounter, 3_000_000_000);
+        assert_eq!(cycles.convert(4_500_000_000), Timestamp::Relative(Duration::from_millis(1500)));
+        let unknown = TimestampContext::raw(ClockResolution::CpuCycleCounter, 0);
+        assert_eq!(unknown.convert(4_500_000_000), Timestamp::Missing);
+    }
+
+    #[test]
+    fn test_raw_clock_since_start_is_absolute() {
+        let start = TICKS_2023;
+        let qpc = TimestampContext::raw_since_start(ClockResolution::QueryPerformanceCounter, 3_000_000, start, 3_000_000);
+        assert_eq!(
+            qpc.convert(7_500_000),
+            Timestamp::Absolute(OffsetDateTime::from_unix_timestamp(1_682_942_401).unwrap() + Duration::from_millis(500))
+        );
+        assert_eq!(
+            qpc.to_system_time(6_000_000),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_682_942_401))
+        );
+        // Before the session start, e.g. from a rundown
+        assert_eq!(
+            qpc.to_system_time(1_500_000),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_682_942_399_500))
+        );
+        let cycles = TimestampContext::raw_since_start(ClockResolution::CpuCycleCounter, 2_000_000_000, start, 0);
+        assert_eq!(
+            cycles.to_system_time(1_000_000_000),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_682_942_400_500))
+        );
+        let system_time = TimestampContext::raw_since_start(ClockResolution::SystemTime, 0, start, 0);
+        assert_eq!(
+            system_time.to_system_time(TICKS_2023),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_682_942_400))
+        );
+        assert_eq!(qpc.to_system_time(0), None);
+        let unknown_start = TimestampContext::raw(ClockResolution::QueryPerformanceCounter, 3_000_000).with_start_ticks(0);
+        assert_eq!(unknown_start.to_system_time(4_500_000), None);
+        let unknown_ticks = TimestampContext::raw(ClockResolution::QueryPerformanceCounter, 3_000_000).with_start_time(start);
+        assert_eq!(unknown_ticks.convert(4_500_000), Timestamp::Relative(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_context_from_logfile_header() {
+        let mut header = TRACE_LOGFILE_HEADER {
+            PerfFreq: 10_000_000,
+            StartTime: TICKS_2023,
+            BootTime: TICKS_2023 - 36_000_000_000,
+            ReservedFlags: ClockResolution::QueryPerformanceCounter as u32,
+            ..Default::default()
+        };
+        assert_eq!(TimestampContext::from_logfile_header(&header, false), TimestampContext::new());
+        let context = TimestampContext::from_logfile_header(&header, true);
+        assert_eq!(
+            context,
+            TimestampContext::raw(ClockResolution::QueryPerformanceCounter, 10_000_000).with_start_time(TICKS_2023)
+        );
+        assert_eq!(
+            context.with_start_ticks(50_000_000).to_system_time(60_000_000),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_682_942_401))
+        );
+        header.ReservedFlags = ClockResolution::CpuCycleCounter as u32;
+        header.Anonymous2.Anonymous.CpuSpeedInMHz = 3000;
+        assert_eq!(
+            TimestampContext::from_logfile_header(&header, true),
+            TimestampContext::raw(ClockResolution::CpuCycleCounter, 3_000_000_000).with_start_time(TICKS_2023)
+        );
+    }
+
+    #[test]
+    fn test_tick_conversions() {
+        assert_eq!(ticks_to_duration(15_000_001), Duration::new(1, 500_000_100));
+        assert_eq!(ticks_to_duration(-1), Duration::ZERO);
+        assert_eq!(duration_to_ticks(Duration::new(1, 500_000_199)), 15_000_001);
+        assert_eq!(ticks_to_system_time(UNIX_EPOCH_TICKS), Some(SystemTime::UNIX_EPOCH));
+        assert_eq!(ticks_to_system_time(0), None);
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_682_942_400);
+        assert_eq!(system_time_to_ticks(time), TICKS_2023);
+        assert_eq!(ticks_to_system_time(TICKS_2023), Some(time));
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(ticks_to_system_time(system_time_to_ticks(before_epoch)), Some(before_epoch));
+    }
+
+    #[test]
+    fn test_sort_keeps_missing_in_arrival_position() {
+        let context = TimestampContext::new();
+        let mut items = vec![
+            ("c", TICKS_2023 + 30),
+            ("c-missing", 0),
+            ("a", TICKS_2023 + 10),
+            ("relative", 5),
+            ("b", TICKS_2023 + 20),
+        ];
+        sort_by_timestamp(&mut items, |(_, ticks)| context.convert(*ticks));
+        let names = items.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["relative", "a", "b", "c", "c-missing"]);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_serialization_is_tagged() {
+        let json = serde_json::to_value(Timestamp::Relative(Duration::from_secs(1))).unwrap();
+        assert_eq!(json["kind"], "relative");
+        let json = serde_json::to_value(Timestamp::Missing).unwrap();
+        assert_eq!(json, serde_json::json!({"kind": "missing"}));
+        let absolute = TimestampContext::new().convert(TICKS_2023);
+        let round_trip: Timestamp = serde_json::from_value(serde_json::to_value(absolute).unwrap()).unwrap();
+        assert_eq!(round_trip, absolute);
+    }
+}
@@ -1,30 +1,37 @@
 use core::slice;
 use std::{
-    cell::OnceCell, collections::HashSet, ffi::{c_void, OsStr, OsString}, fmt::{self, Write}, iter, mem::size_of, os::windows::prelude::{OsStrExt, OsStringExt}, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}, sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+    cell::OnceCell, ffi::{c_void, OsStr, OsString}, fmt::{self, Write}, iter, mem::{self, size_of}, os::windows::prelude::{OsStrExt, OsStringExt}, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}, sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
     }, thread::{self, JoinHandle}, time::{Duration, SystemTime}
 };
 
 use windows::{
     core::{GUID, HRESULT, PWSTR},
     Win32::{
-        Foundation::{ERROR_CTX_CLOSE_PENDING, FILETIME},
+        Foundation::{ERROR_CANCELLED, ERROR_CTX_CLOSE_PENDING, FILETIME},
         System::Diagnostics::Etw::{
-            CloseTrace, OpenTraceW, ProcessTrace, EVENT_HEADER, EVENT_RECORD, EVENT_TRACE_LOGFILEW,
-            PROCESSTRACE_HANDLE, PROCESS_TRACE_MODE_EVENT_RECORD, PROCESS_TRACE_MODE_REAL_TIME,
+            CloseTrace, OpenTraceW, ProcessTrace, EVENT_HEADER, EVENT_RECORD, EVENT_TRACE_LOGFILEW, TRACE_LOGFILE_HEADER,
+            PROCESSTRACE_HANDLE, PROCESS_TRACE_MODE_EVENT_RECORD, PROCESS_TRACE_MODE_RAW_TIMESTAMP,
+            PROCESS_TRACE_MODE_REAL_TIME,
         },
     },
 };
 
 use crate::{
-    error::TraceError, provider::Provider, schema::cache::EventInfo, trace_session::TraceSession, values::event::Event
+    checkpoint::{event_hash, Checkpoint, CheckpointTracker}, clock_check::ClockSanityChecker, error::{ParseError, TraceError}, failures::FailureRing, provider::Provider, replay::{ReplayControl, ReplayDriver}, schema::cache::{EventInfo, SchemaCache}, timestamp::{system_time_to_ticks, ticks_to_duration, ticks_to_system_time, TimestampContext}, trace_session::{ClockResolution, EnableProviderTimeout, EventFilters, LogFileMode, SessionFlusher, TraceSession}, values::event::{Event, EventRecord}
 };
+#[cfg(feature = "async")]
+use crate::{event_stream::{Backpressure, EventChannel, EventStream}, values::event::EventOwned};
+#[cfg(feature = "test-util")]
+use crate::mock::MockEventSource;
 
 const INVALID_PROCESSTRACE_HANDLE: PROCESSTRACE_HANDLE = PROCESSTRACE_HANDLE {
     Value: usize::MAX as u64,
 };
 const EVENT_TRACE_GUID: GUID = GUID::from_u128(0x68FDD900_4A3E_11D1_84F4_0000F80464E3);
+/// Most log files a single `ProcessTrace` call accepts.
+pub const MAX_LOG_FILES: usize = 64;
 
 #[derive(Default)]
 pub struct EventTraceLogfile {
@@ -76,6 +83,11 @@ impl EventTraceLogfile {
         self.log_file_name = log_file_name;
     }
 
+    fn logger_name(&self) -> OsString {
+        let len = self.logger_name.iter().take_while(|x| **x != 0).count();
+        OsString::from_wide(&self.logger_name[..len])
+    }
+
     pub fn set_logger_name<S: AsRef<OsStr>>(&mut self, logger_name: S) {
         let mut logger_name = logger_name
             .as_ref()
@@ -87,29 +99,198 @@ impl EventTraceLogfile {
     }
 }
 
+/// Event and buffer loss of a trace, see [`Trace::statistics`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceStatistics {
+    /// Number of events that couldn't be written, e.g. because all buffers were full.
+    pub events_lost: u32,
+    /// Number of buffers that couldn't be written to the log file or delivered to
+    /// the real-time consumer.
+    pub buffers_lost: u32,
+    pub buffers_written: u32,
+    /// Number of buffers this trace processed so far.
+    pub buffers_read: u32,
+}
+
+/// Counters ETW keeps in a logfile, as filled in by `OpenTraceW` and updated in the
+/// copy passed to the buffer callback.
+fn logfile_statistics(logfile: &EVENT_TRACE_LOGFILEW) -> TraceStatistics {
+    TraceStatistics {
+        events_lost: unsafe { logfile.LogfileHeader.Anonymous2.Anonymous.EventsLost },
+        buffers_lost: logfile.LogfileHeader.BuffersLost,
+        buffers_written: logfile.LogfileHeader.BuffersWritten,
+        buffers_read: logfile.BuffersRead,
+    }
+}
+
+/// The header of a log file, as filled in by `OpenTraceW`, see [`Trace::logfile_header`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogfileHeader {
+    /// Size of the buffers in bytes.
+    pub buffer_size: u32,
+    /// Major, minor, sub and subminor version of the OS that recorded the trace.
+    pub os_version: (u8, u8, u8, u8),
+    /// Build number of the OS that recorded the trace.
+    pub provider_version: u32,
+    pub number_of_processors: u32,
+    /// Size of pointers in the events, 4 for traces recorded on 32-bit systems.
+    pub pointer_size: usize,
+    pub start_time: Option<SystemTime>,
+    pub end_time: Option<SystemTime>,
+    pub boot_time: Option<SystemTime>,
+    /// Resolution of the system timer.
+    pub timer_resolution: Duration,
+    /// The clock of the timestamps, None if the header names an unknown one.
+    pub clock_resolution: Option<ClockResolution>,
+    /// Frequency of the performance counter, in ticks per second.
+    pub perf_freq: i64,
+    pub events_lost: u32,
+    pub buffers_lost: u32,
+    pub buffers_written: u32,
+}
+
+impl From<&TRACE_LOGFILE_HEADER> for LogfileHeader {
+    fn from(header: &TRACE_LOGFILE_HEADER) -> Self {
+        let (version, details) = unsafe { (header.Anonymous1.VersionDetail, header.Anonymous2.Anonymous) };
+        LogfileHeader {
+            buffer_size: header.BufferSize,
+            os_version: (version.MajorVersion, version.MinorVersion, version.SubVersion, version.SubMinorVersion),
+            provider_version: header.ProviderVersion,
+            number_of_processors: header.NumberOfProcessors,
+            pointer_size: details.PointerSize as usize,
+            start_time: ticks_to_system_time(header.StartTime),
+            end_time: ticks_to_system_time(header.EndTime),
+            boot_time: ticks_to_system_time(header.BootTime),
+            timer_resolution: ticks_to_duration(i64::from(header.TimerResolution)),
+            clock_resolution: ClockResolution::from_client_context(header.ReservedFlags),
+            perf_freq: header.PerfFreq,
+            events_lost: details.EventsLost,
+            buffers_lost: header.BuffersLost,
+            buffers_written: header.BuffersWritten,
+        }
+    }
+}
+
+/// Events a trace delivered and failed to decode, see [`Trace::process_blocking`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessSummary {
+    /// Events handed to the handler, counted before it runs, so events that fail to
+    /// decode are included. Events skipped before a resume checkpoint or a replay seek
+    /// target aren't counted, and neither are the `EventTrace` events ETW adds, like
+    /// the log file header.
+    pub events_processed: u64,
+    /// Events that failed to decode, see [`FailureRing::failed_events`].
+    pub events_failed: u64,
+}
+
 pub type HandlerFn = dyn FnMut(& EVENT_RECORD) + Send;
+pub type EventHandlerFn = dyn FnMut(Event, Arc<EventInfo>, &EVENT_RECORD) + Send;
+pub type BufferPredicateFn = dyn FnMut(&Checkpoint) -> bool + Send;
 pub type ProvidersEvents = Vec<(Provider, Vec<u16>)>;
 
+/// Checker the events of a trace are recorded with, see [`TraceBuilder::clock_check`].
+struct ClockCheck {
+    checker: Arc<Mutex<ClockSanityChecker>>,
+    session: String,
+}
+
+/// Handler for the events of one provider, see [`TraceBuilder::add_provider_handler`].
+struct Subscription {
+    provider: GUID,
+    event_ids: Option<Vec<u16>>,
+    handler: Box<EventHandlerFn>,
+}
+
+impl Subscription {
+    fn matches(&self, header: &EVENT_HEADER) -> bool {
+        header.ProviderId == self.provider
+            && self
+                .event_ids
+                .as_ref()
+                .is_none_or(|event_ids| event_ids.contains(&header.EventDescriptor.Id))
+    }
+}
+
 pub struct HandlerData {
-    stop_trace: AtomicBool,
+    pub(crate) stop_trace: AtomicBool,
     handler: Mutex<Box<HandlerFn>>,
+    checkpoint: Mutex<CheckpointTracker>,
+    /// Set while the current buffer lies wholly before the resume checkpoint, so that
+    /// its events are dropped without taking the checkpoint lock.
+    skip_buffer: AtomicBool,
+    /// Counters of the logfile passed to the last buffer callback.
+    statistics: Mutex<Option<TraceStatistics>>,
+    /// See [`ProcessSummary::events_processed`].
+    events_processed: AtomicU64,
+    /// Set once the trace is opened, see [`HandlerData::timestamp_context`].
+    timestamp_context: OnceLock<TimestampContext>,
+    /// Raw clock value of the trace's header event, 0 until it arrived.
+    start_ticks: AtomicI64,
+    /// Pointer size of the logfile, from its header once opened and then from the last
+    /// buffer callback, 0 until known. Shared with the decoding handlers.
+    pointer_size: Arc<AtomicUsize>,
+    buffer_predicate: Option<Mutex<Box<BufferPredicateFn>>>,
+    replay: Option<Mutex<ReplayDriver>>,
+    /// Kept outside the driver's lock, which is held while an event is held back.
+    replay_control: Option<ReplayControl>,
+    clock_check: Option<ClockCheck>,
+}
+
+impl HandlerData {
+    /// See [`Trace::timestamp_context`].
+    fn timestamp_context(&self) -> TimestampContext {
+        let context = self.timestamp_context.get().copied().unwrap_or_default();
+        match self.start_ticks.load(Ordering::Relaxed) {
+            0 => context,
+            start_ticks => context.with_start_ticks(start_ticks),
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct TraceBuilder {
     handler: OnceCell<Box<HandlerFn>>,
-    providers: HashSet<GUID>,
-    file: Option<PathBuf>,
+    subscriptions: Vec<Subscription>,
+    unmatched_events: Arc<AtomicU64>,
+    providers: Vec<(Provider, Option<EventFilters>)>,
+    files: Vec<PathBuf>,
     session: Option<TraceSession>,
+    resume: Option<Checkpoint>,
+    buffer_predicate: Option<Box<BufferPredicateFn>>,
+    replay: Option<ReplayDriver>,
+    failures: Arc<FailureRing>,
+    tolerant: Arc<AtomicBool>,
+    /// Pointer size of the opened log file, for records that don't flag theirs.
+    pointer_size: Arc<AtomicUsize>,
+    /// Set by [`TraceBuilder::schema_cache`], [`SchemaCache::global`] is used otherwise.
+    schema_cache: Arc<OnceLock<Arc<SchemaCache>>>,
+    auto_flush: Option<Duration>,
+    raw_timestamps: bool,
+    clock_check: Option<ClockCheck>,
+    #[cfg(feature = "async")]
+    event_channel: Option<Arc<EventChannel>>,
+    #[cfg(feature = "test-util")]
+    mock: Option<MockEventSource>,
 }
 
 impl fmt::Debug for TraceBuilder {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("TraceBuilder")
+        let mut debug = f.debug_struct("TraceBuilder");
+        debug
             .field("providers", &self.providers)
-            .field("file", &self.file)
+            .field("subscriptions", &self.subscriptions.iter().map(|sub| (sub.provider, &sub.event_ids)).collect::<Vec<_>>())
+            .field("files", &self.files)
             .field("session", &self.session)
-            .finish()
+            .field("resume", &self.resume)
+            .field("replay", &self.replay)
+            .field("tolerant", &self.tolerant.load(Ordering::Relaxed))
+            .field("auto_flush", &self.auto_flush)
+            .field("raw_timestamps", &self.raw_timestamps)
+            .field("clock_check", &self.clock_check.as_ref().map(|clock_check| &clock_check.session));
+        #[cfg(feature = "test-util")]
+        debug.field("mock", &self.mock);
+        debug.finish_non_exhaustive()
     }
 }
 
@@ -124,63 +305,16 @@ impl TraceBuilder {
         self,
         mut handler: impl FnMut(Event, Arc<EventInfo>, &EVENT_RECORD) + Send + 'static,
     ) -> Result<Self, TraceError> {
+        let failures = Arc::clone(&self.failures);
+        let tolerant = Arc::clone(&self.tolerant);
+        let pointer_size = Arc::clone(&self.pointer_size);
+        let schema_cache = Arc::clone(&self.schema_cache);
 
         let handler: Box<dyn FnMut(&EVENT_RECORD) + Send + 'static> = Box::new(move |event_record: &EVENT_RECORD| {
             if event_record.EventHeader.ProviderId == EVENT_TRACE_GUID {
                 return;
             }
-            log::trace!("Event record handler called: activity: {:?} GUID {:?} descriptor: {:?} version: {} userdata_len: {}", event_record.EventHeader.ActivityId, event_record.EventHeader.ProviderId, event_record.EventHeader.EventDescriptor, event_record.EventHeader.EventDescriptor.Version, event_record.UserDataLength);
-            let event_data = unsafe {
-                slice::from_raw_parts(
-                    event_record.UserData as *const u8,
-                    event_record.UserDataLength as usize,
-                )
-            };
-            let event_data = event_data.iter().fold(String::new(), |mut output, b| {
-                let _ = write!(output, "{b:02x}");
-                output
-            });
-            log::trace!("Event record userdata: {}", event_data);
-            match Event::parse(event_record) {
-                Ok((schema, event)) => handler(event, schema, event_record),
-                Err(err) => {
-                    log::warn!(
-                        "failed to parse provider {:?} event {} record: {}",
-                        event_record.EventHeader.ProviderId,
-                        event_record.EventHeader.EventDescriptor.Id,
-                        err
-                    );
-                    if log::log_enabled!(log::Level::Info) {
-                        let header = unsafe {
-                            slice::from_raw_parts(
-                                &event_record.EventHeader as *const _ as *const u8,
-                                size_of::<EVENT_HEADER>(),
-                            )
-                        };
-                        let header = header.iter().fold(String::new(), |mut output, b| {
-                            let _ = write!(output, "{b:02x}");
-                            output
-                        });
-                        let userdata = unsafe {
-                            slice::from_raw_parts(
-                                event_record.UserData as *const u8,
-                                event_record.UserDataLength as usize,
-                            )
-                        };
-                        let userdata = userdata.iter().fold(String::new(), |mut output, b| {
-                            let _ = write!(output, "{b:02x}");
-                            output
-                        });
-                        log::info!(
-                            "Failed to parse provider {:?} event {} header: {} userdata: {}",
-                            event_record.EventHeader.ProviderId,
-                            event_record.EventHeader.EventDescriptor.Id,
-                            header,
-                            userdata
-                        );
-                    }
-                }
-            };
+            decode_event(event_record, &schema_cache, &tolerant, &pointer_size, &failures, &mut handler);
         });
 
         self.handler.set(handler).map_err(|_| TraceError::Configuration(
@@ -191,6 +325,45 @@ impl TraceBuilder {
         Ok(self)
     }
 
+    /// Deliver the decoded events as an [`EventStream`] instead of to a handler.
+    ///
+    /// Up to `capacity` events are queued for the stream; `backpressure` decides what
+    /// happens to events arriving while the queue is full. Dropped events are counted in
+    /// [`Trace::dropped_events`]. The stream ends when the trace is closed or its
+    /// processing ends.
+    #[cfg(feature = "async")]
+    pub fn into_stream(self, capacity: usize, backpressure: Backpressure) -> Result<(Self, EventStream), TraceError> {
+        if capacity == 0 {
+            return Err(TraceError::Configuration("Event stream capacity is zero".to_string()));
+        }
+        let channel = Arc::new(EventChannel::new(capacity, backpressure));
+        let sender = Arc::clone(&channel);
+        let mut builder = self.set_handler(move |event, _schema, _record| sender.push(EventOwned::from(&event)))?;
+        builder.event_channel = Some(Arc::clone(&channel));
+        Ok((builder, EventStream::new(channel)))
+    }
+
+    /// Deliver the events of `provider` to `handler`, or only the events with one of
+    /// `event_ids` if given.
+    ///
+    /// Can be called multiple times; each event goes to the first matching handler in
+    /// the order they were added. Events matching no handler go to the handler set with
+    /// [`TraceBuilder::set_handler`] or [`TraceBuilder::set_raw_handler`] if there is
+    /// one, and are otherwise dropped and counted in [`Trace::unmatched_events`].
+    pub fn add_provider_handler(
+        mut self,
+        provider: GUID,
+        event_ids: Option<Vec<u16>>,
+        handler: impl FnMut(Event, Arc<EventInfo>, &EVENT_RECORD) + Send + 'static,
+    ) -> Self {
+        self.subscriptions.push(Subscription {
+            provider,
+            event_ids,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
     pub fn set_raw_handler(
         self,
         handler: impl FnMut(&EVENT_RECORD) + Send + 'static,
@@ -203,19 +376,167 @@ impl TraceBuilder {
         Ok(self)
     }
 
-    pub fn file<P: AsRef<Path>>(mut self, file: P) -> Result<Self, TraceError> {
+    /// Flush the session every `interval` while the trace is processing.
+    ///
+    /// The session's flush timer delivers partially filled buffers eventually, but
+    /// for low-rate providers the last events can wait for it a long time. Only
+    /// applies to traces of a session; failed flushes are logged and counted in
+    /// [`Trace::auto_flush_errors`]. Fails for a zero interval.
+    pub fn auto_flush(mut self, interval: Duration) -> Result<Self, TraceError> {
+        if interval.is_zero() {
+            return Err(TraceError::Configuration("Auto flush interval is zero".to_string()));
+        }
+        self.auto_flush = Some(interval);
+        Ok(self)
+    }
+
+    /// Deliver timestamps in units of the session's clock instead of converting them to
+    /// FILETIME, which saves the conversion per event. Convert them with
+    /// [`Trace::timestamp_context`].
+    pub fn raw_timestamps(mut self, enabled: bool) -> Self {
+        self.raw_timestamps = enabled;
+        self
+    }
+
+    /// Pass events whose payload ends before all top-level properties of their schema
+    /// to the handler set with [`TraceBuilder::set_handler`] as
+    /// [`crate::values::compound::StringOrStruct::Partial`] instead of dropping them.
+    pub fn tolerant_decoding(self, enabled: bool) -> Self {
+        self.tolerant.store(enabled, Ordering::Relaxed);
+        self
+    }
+
+    /// Decode events with the schemas of `cache` instead of [`SchemaCache::global`].
+    ///
+    /// Schemas missing from the cache are still looked up with TDH and added to it, so
+    /// a cache pre-populated with [`SchemaCache::insert`] or
+    /// [`SchemaCache::from_serialized`] can decode log files without the providers'
+    /// manifests, and one cache can be shared by several traces.
+    pub fn schema_cache(self, cache: Arc<SchemaCache>) -> Result<Self, TraceError> {
+        self.schema_cache.set(cache).map_err(|_| TraceError::Configuration(
+            "Tried to set a schema cache when a schema cache was already present"
+                .to_string(),
+        ))?;
+        Ok(self)
+    }
+
+    /// Keep the last `capacity` events that the handler set with
+    /// [`TraceBuilder::set_handler`] failed to decode, with at most `max_userdata`
+    /// payload bytes each. See [`Trace::failure_ring`].
+    pub fn capture_failures(self, capacity: usize, max_userdata: usize) -> Self {
+        self.failures.set_bounds(capacity, max_userdata);
+        self
+    }
+
+    /// Add the ETL file `file`, see [`TraceBuilder::files`].
+    pub fn file<P: AsRef<Path>>(self, file: P) -> Result<Self, TraceError> {
+        self.files(iter::once(file.as_ref().to_path_buf()))
+    }
+
+    /// Process the events of several ETL files together, merged in timestamp order,
+    /// e.g. the numbered files of a session in [`LogFileMode::FILE_MODE_NEWFILE`].
+    ///
+    /// Adds to the files set before; at most [`MAX_LOG_FILES`] files are accepted.
+    pub fn files<I: IntoIterator<Item = PathBuf>>(mut self, files: I) -> Result<Self, TraceError> {
         if self.session.is_some() {
-            Err(TraceError::Configuration(
+            return Err(TraceError::Configuration(
                 "Tried to set a filename when a session was already present".to_string(),
+            ));
+        }
+        self.files.extend(files);
+        if self.files.len() > MAX_LOG_FILES {
+            return Err(TraceError::Configuration(format!(
+                "Tried to process {} log files, at most {} can be processed together",
+                self.files.len(),
+                MAX_LOG_FILES
+            )));
+        }
+        Ok(self)
+    }
+
+    /// Resume processing a file trace from a checkpoint of a previous run.
+    ///
+    /// Buffers before the checkpoint are skipped as a whole, their events are dropped
+    /// before any locking, hashing or decoding. See [`Checkpoint`] for the exactly-once
+    /// caveats at the resume boundary.
+    pub fn resume_from(mut self, checkpoint: Checkpoint) -> Result<Self, TraceError> {
+        if self.session.is_some() {
+            Err(TraceError::Configuration(
+                "Tried to resume from a checkpoint for a realtime session".to_string(),
+            ))
+        } else {
+            self.resume = Some(checkpoint);
+            Ok(self)
+        }
+    }
+
+    /// Called after every delivered buffer with the current position.
+    ///
+    /// Returning false stops processing; the position can then be persisted and
+    /// passed to [`TraceBuilder::resume_from`] later.
+    pub fn buffer_predicate(
+        mut self,
+        predicate: impl FnMut(&Checkpoint) -> bool + Send + 'static,
+    ) -> Self {
+        self.buffer_predicate = Some(Box::new(predicate));
+        self
+    }
+
+    /// Pace the delivery of events from a file trace with `driver`.
+    ///
+    /// Keep a [`crate::replay::ReplayControl`] from the driver to pause, seek and watch
+    /// the progress of the replay.
+    pub fn replay(mut self, driver: ReplayDriver) -> Result<Self, TraceError> {
+        if self.session.is_some() {
+            Err(TraceError::Configuration(
+                "Tried to replay a realtime session".to_string(),
             ))
         } else {
-            self.file = Some(file.as_ref().to_path_buf());
+            self.replay = Some(driver);
+            Ok(self)
+        }
+    }
+
+    /// Record the timestamps of the trace's events with `checker`, under the name `session`.
+    ///
+    /// Give the traces to be compared the same checker and a name each. Their timestamps
+    /// are converted with [`Trace::timestamp_context`], and the clock is registered from
+    /// the session's properties or the logfile header when the trace is opened.
+    pub fn clock_check(mut self, checker: Arc<Mutex<ClockSanityChecker>>, session: impl Into<String>) -> Self {
+        self.clock_check = Some(ClockCheck {
+            checker,
+            session: session.into(),
+        });
+        self
+    }
+
+    /// Enable `provider` with `event_filters` on the session when the trace is opened.
+    ///
+    /// All providers are enabled before the trace starts consuming the session; if any
+    /// fails, the others are disabled again and [`TraceBuilder::open`] fails with
+    /// [`TraceError::EnableProviders`]. Only applies to traces of a session.
+    pub fn add_provider(mut self, provider: Provider, event_filters: Option<EventFilters>) -> Result<Self, TraceError> {
+        if self.providers.iter().any(|(added, _)| added.id() == provider.id()) {
+            Err(TraceError::Configuration(format!(
+                "Provider {:?} was already added",
+                provider.id()
+            )))
+        } else {
+            self.providers.push((provider, event_filters));
             Ok(self)
         }
     }
 
     pub fn session(mut self, session: TraceSession) -> Result<Self, TraceError> {
-        if self.file.is_some() {
+        if self.replay.is_some() {
+            Err(TraceError::Configuration(
+                "Tried to set a session when replaying a file".to_string(),
+            ))
+        } else if self.resume.is_some() {
+            Err(TraceError::Configuration(
+                "Tried to set a session when resuming from a checkpoint".to_string(),
+            ))
+        } else if !self.files.is_empty() {
             Err(TraceError::Configuration(
                 "Tried to set a session when a filename was already present".to_string(),
             ))
@@ -225,32 +546,158 @@ impl TraceBuilder {
         }
     }
 
+    /// Deliver the events of `source` instead of events from a session or file.
+    ///
+    /// Open the trace with [`TraceBuilder::open_mock`]; [`TraceBuilder::open`] refuses
+    /// mock traces so a test setup can't end up in production code by accident.
+    #[cfg(feature = "test-util")]
+    pub fn mock(mut self, source: MockEventSource) -> Result<Self, TraceError> {
+        if self.session.is_some() || !self.files.is_empty() {
+            Err(TraceError::Configuration(
+                "Tried to mock a trace with a session or file".to_string(),
+            ))
+        } else {
+            self.mock = Some(source);
+            Ok(self)
+        }
+    }
+
+    /// Open a trace set up with [`TraceBuilder::mock`].
+    #[cfg(feature = "test-util")]
+    pub fn open_mock(mut self) -> Result<Trace, TraceError> {
+        log::debug!("TraceBuilder::open_mock() called: {:?}", self);
+        let Some(mut source) = self.mock.take() else {
+            return Err(TraceError::Configuration("No mock event source set".to_string()));
+        };
+        // A cache of its own, so the schemas of one mock trace don't leak into others
+        let cache = self.schema_cache.get_or_init(|| Arc::new(SchemaCache::new()));
+        source.inject_schemas(cache);
+        let handler_data = self.handler_data()?;
+        Ok(Trace {
+            handles: Vec::new(),
+            _event_trace_logfiles: vec![EventTraceLogfile::new()],
+            thread: None,
+            _handler_data: handler_data,
+            _controller: None,
+            shut_down: false,
+            closed: AtomicBool::new(false),
+            failures: self.failures,
+            unmatched_events: self.unmatched_events,
+            auto_flush: None,
+            #[cfg(feature = "async")]
+            event_channel: self.event_channel,
+            mock: Some(source),
+        })
+    }
+
+    fn handler_data(&mut self) -> Result<Arc<HandlerData>, TraceError> {
+        let handler = match self.handler.take() {
+            fallback if self.subscriptions.is_empty() => fallback,
+            fallback => Some(self.dispatcher(fallback)),
+        };
+        let Some(handler) = handler else {
+            return Err(TraceError::Configuration("No handlers set".to_string()));
+        };
+        let checkpoint = CheckpointTracker::new(self.resume.take());
+        #[allow(clippy::arc_with_non_send_sync)]
+        Ok(Arc::new(HandlerData {
+            handler: Mutex::new(handler),
+            stop_trace: AtomicBool::new(false),
+            skip_buffer: AtomicBool::new(checkpoint.skips_buffer()),
+            checkpoint: Mutex::new(checkpoint),
+            statistics: Mutex::new(None),
+            events_processed: AtomicU64::new(0),
+            timestamp_context: OnceLock::new(),
+            start_ticks: AtomicI64::new(0),
+            pointer_size: Arc::clone(&self.pointer_size),
+            buffer_predicate: self.buffer_predicate.take().map(Mutex::new),
+            replay_control: self.replay.as_ref().map(ReplayDriver::control),
+            replay: self.replay.take().map(Mutex::new),
+            clock_check: self.clock_check.take(),
+        }))
+    }
+
+    /// Route events to the handlers added with [`TraceBuilder::add_provider_handler`],
+    /// falling back to `fallback`.
+    fn dispatcher(&mut self, mut fallback: Option<Box<HandlerFn>>) -> Box<HandlerFn> {
+        let mut subscriptions = mem::take(&mut self.subscriptions);
+        let failures = Arc::clone(&self.failures);
+        let tolerant = Arc::clone(&self.tolerant);
+        let pointer_size = Arc::clone(&self.pointer_size);
+        let schema_cache = Arc::clone(&self.schema_cache);
+        let unmatched_events = Arc::clone(&self.unmatched_events);
+
+        Box::new(move |event_record: &EVENT_RECORD| {
+            if event_record.EventHeader.ProviderId == EVENT_TRACE_GUID {
+                return;
+            }
+            match subscriptions.iter_mut().find(|sub| sub.matches(&event_record.EventHeader)) {
+                Some(subscription) => decode_event(event_record, &schema_cache, &tolerant, &pointer_size, &failures, &mut *subscription.handler),
+                None => match &mut fallback {
+                    Some(fallback) => fallback(event_record),
+                    None => {
+                        unmatched_events.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+            }
+        })
+    }
+
     pub fn open(mut self) -> Result<Trace, TraceError> {
         log::debug!("TraceBuilder::open() called: {:?}", self);
-        assert!(self.file.is_none() || self.session.is_none());
-        let mut event_trace_logfile = EventTraceLogfile::new();
+        assert!(self.files.is_empty() || self.session.is_none());
+        #[cfg(feature = "test-util")]
+        if self.mock.is_some() {
+            return Err(TraceError::Configuration(
+                "Mock traces must be opened with open_mock".to_string(),
+            ));
+        }
+        let mut event_trace_logfiles = Vec::new();
+        let mut auto_flush = None;
 
-        let controller = if let Some(session) = self.session.take() {
+        let controller = if let Some(mut session) = self.session.take() {
+            let mut event_trace_logfile = EventTraceLogfile::new();
             event_trace_logfile.set_logger_name(session.name());
+            auto_flush = self
+                .auto_flush
+                .map(|interval| AutoFlush::new(session.flusher(), interval));
 
             unsafe {
                 event_trace_logfile.data.Anonymous1.ProcessTraceMode |=
                     PROCESS_TRACE_MODE_REAL_TIME | PROCESS_TRACE_MODE_EVENT_RECORD;
             }
             event_trace_logfile.data.BufferCallback = Some(buffer_handler);
+            event_trace_logfiles.push(event_trace_logfile);
 
-            if let Some(_) = self.handler.get() {
+            if self.handler.get().is_some() || !self.subscriptions.is_empty() {
+                session.enable_filtered_providers(mem::take(&mut self.providers), EnableProviderTimeout::Infinite)?;
                 Some(TraceController::RealtimeTraceSession(session))
             }
             else {
                 return Err(TraceError::Configuration("No handler set".to_string()));
             }
-        } else if let Some(file) = &self.file {
-            unsafe {
-                event_trace_logfile.data.Anonymous1.ProcessTraceMode |=
-                    PROCESS_TRACE_MODE_EVENT_RECORD;
+        } else if !self.files.is_empty() {
+            if !self.providers.is_empty() {
+                return Err(TraceError::Configuration(
+                    "Providers can only be enabled for traces of a session".to_string(),
+                ));
+            }
+            if self.files.len() > 1 && (self.resume.is_some() || self.replay.is_some()) {
+                // Checkpoints count buffers, which doesn't identify a position in several files
+                return Err(TraceError::Configuration(
+                    "Checkpoints and replay only support traces of a single file".to_string(),
+                ));
+            }
+            for file in &self.files {
+                let mut event_trace_logfile = EventTraceLogfile::new();
+                unsafe {
+                    event_trace_logfile.data.Anonymous1.ProcessTraceMode |=
+                        PROCESS_TRACE_MODE_EVENT_RECORD;
+                }
+                event_trace_logfile.set_log_file_name(file);
+                event_trace_logfile.data.BufferCallback = Some(buffer_handler);
+                event_trace_logfiles.push(event_trace_logfile);
             }
-            event_trace_logfile.set_log_file_name(file);
             None
         } else {
             return Err(TraceError::Configuration(
@@ -259,41 +706,106 @@ impl TraceBuilder {
         };
 
         // Set up handlers
-        let handler_data = if let Some(handler) = self.handler.take() {
-            #[allow(clippy::arc_with_non_send_sync)]
-            let handler_data = Arc::new(HandlerData {
-                handler: Mutex::new(handler),
-                stop_trace: AtomicBool::new(false),
-            });
-
+        let handler_data = self.handler_data()?;
+        let mut handles = Vec::with_capacity(event_trace_logfiles.len());
+        for (idx, event_trace_logfile) in event_trace_logfiles.iter_mut().enumerate() {
+            if self.raw_timestamps {
+                unsafe {
+                    event_trace_logfile.data.Anonymous1.ProcessTraceMode |= PROCESS_TRACE_MODE_RAW_TIMESTAMP;
+                }
+            }
             event_trace_logfile.data.Context =
                 Arc::into_raw(Arc::clone(&handler_data)) as *mut c_void;
             event_trace_logfile.data.Anonymous2.EventRecordCallback =
                 Some(event_record_handler);
-            handler_data
-        } else {
-            return Err(TraceError::Configuration("No handlers set".to_string()));
-        };
 
-        unsafe {
-            log::trace!("OpenTraceW({:?})", &event_trace_logfile);
-            let handle = OpenTraceW(event_trace_logfile.as_mut_ptr());
-            if handle == INVALID_PROCESSTRACE_HANDLE {
-                let err: TraceError = windows::core::Error::from_win32().into();
-                log::warn!("OpenTraceW returned error: {:?}", err);
-                return Err(err);
-            }
-
-            log::trace!("OpenTraceW returned OK");
-
-            Ok(Trace {
-                handle,
-                _event_trace_logfile: event_trace_logfile,
-                thread: None,
-                _handler_data: handler_data,
-                _controller: controller,
-            })
+            unsafe {
+                log::trace!("OpenTraceW({:?})", &event_trace_logfile);
+                let handle = OpenTraceW(event_trace_logfile.as_mut_ptr());
+                if handle == INVALID_PROCESSTRACE_HANDLE {
+                    let source = windows::core::Error::from_win32();
+                    log::warn!("OpenTraceW returned error: {:?}", source);
+                    let target = match self.files.get(idx) {
+                        Some(file) => file.display().to_string(),
+                        None => format!("{:?}", event_trace_logfile.logger_name()),
+                    };
+                    for handle in handles {
+                        let _ = CloseTrace(handle);
+                    }
+                    return Err(TraceError::OpenTrace { target, source });
+                }
+                log::trace!("OpenTraceW returned OK");
+                handles.push(handle);
+            }
+            // Known before the first buffer callback, so that the first buffer's records decode
+            // with it too
+            let pointer_size = unsafe { event_trace_logfile.data.LogfileHeader.Anonymous2.Anonymous.PointerSize } as usize;
+            if pointer_size != 0 && handler_data.pointer_size.load(Ordering::Relaxed) == 0 {
+                handler_data.pointer_size.store(pointer_size, Ordering::Relaxed);
+            }
         }
+
+        // The files of a trace are recorded with the same clock, so the first one describes all
+        let header = &event_trace_logfiles[0].data.LogfileHeader;
+        let timestamp_context = TimestampContext::from_logfile_header(header, self.raw_timestamps);
+        let _ = handler_data.timestamp_context.set(timestamp_context);
+        if let Some(replay) = &handler_data.replay {
+            replay.lock().unwrap_or_else(|err| err.into_inner()).set_timestamp_context(timestamp_context);
+        }
+        if let Some(clock_check) = &handler_data.clock_check {
+            // Sessions opened with open_existing don't know their clock, the header does
+            let clock = match &controller {
+                Some(TraceController::RealtimeTraceSession(session)) => session.clock_resolution(),
+                None => None,
+            }
+            .or_else(|| ClockResolution::from_client_context(header.ReservedFlags));
+            let mut checker = clock_check.checker.lock().unwrap_or_else(|err| err.into_inner());
+            checker.register_session(&clock_check.session, clock);
+        }
+
+        Ok(Trace {
+            handles,
+            _event_trace_logfiles: event_trace_logfiles,
+            thread: None,
+            _handler_data: handler_data,
+            _controller: controller,
+            shut_down: false,
+            closed: AtomicBool::new(false),
+            failures: self.failures,
+            unmatched_events: self.unmatched_events,
+            auto_flush,
+            #[cfg(feature = "async")]
+            event_channel: self.event_channel,
+            #[cfg(feature = "test-util")]
+            mock: None,
+        })
+    }
+}
+
+fn log_undecodable_event_record(event_record: &EVENT_RECORD) {
+    if log::log_enabled!(log::Level::Info) {
+        let header = unsafe {
+            slice::from_raw_parts(
+                &event_record.EventHeader as *const _ as *const u8,
+                size_of::<EVENT_HEADER>(),
+            )
+        };
+        let header = header.iter().fold(String::new(), |mut output, b| {
+            let _ = write!(output, "{b:02x}");
+            output
+        });
+        let userdata = EventRecord(event_record).userdata();
+        let userdata = userdata.iter().fold(String::new(), |mut output, b| {
+            let _ = write!(output, "{b:02x}");
+            output
+        });
+        log::info!(
+            "Failed to parse provider {:?} event {} header: {} userdata: {}",
+            event_record.EventHeader.ProviderId,
+            event_record.EventHeader.EventDescriptor.Id,
+            header,
+            userdata
+        );
     }
 }
 
@@ -303,29 +815,175 @@ pub enum TraceController {
 
 pub struct Trace {
     _controller: Option<TraceController>,
-    handle: PROCESSTRACE_HANDLE,
-    _event_trace_logfile: EventTraceLogfile,
+    /// One handle per log file, or the handle of the session.
+    handles: Vec<PROCESSTRACE_HANDLE>,
+    /// Never empty, the first one describes the trace.
+    _event_trace_logfiles: Vec<EventTraceLogfile>,
     thread: Option<JoinHandle<Result<(), TraceError>>>,
     _handler_data: Arc<HandlerData>,
+    shut_down: bool,
+    closed: AtomicBool,
+    failures: Arc<FailureRing>,
+    unmatched_events: Arc<AtomicU64>,
+    auto_flush: Option<AutoFlush>,
+    /// Queue of the stream set up with [`TraceBuilder::into_stream`].
+    #[cfg(feature = "async")]
+    event_channel: Option<Arc<EventChannel>>,
+    #[cfg(feature = "test-util")]
+    mock: Option<MockEventSource>,
+}
+
+/// Flushes the session of a trace on an interval while the trace is processing.
+struct AutoFlush {
+    flusher: SessionFlusher,
+    interval: Duration,
+    errors: Arc<AtomicU64>,
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AutoFlush {
+    fn new(flusher: SessionFlusher, interval: Duration) -> Self {
+        Self {
+            flusher,
+            interval,
+            errors: Arc::new(AtomicU64::new(0)),
+            stop: Arc::new((Mutex::new(false), Condvar::new())),
+            thread: None,
+        }
+    }
+
+    fn start(&mut self) {
+        if self.thread.is_some() {
+            return;
+        }
+        let flusher = self.flusher.clone();
+        let interval = self.interval;
+        let errors = Arc::clone(&self.errors);
+        let stop = Arc::clone(&self.stop);
+        self.thread = Some(thread::spawn(move || {
+            let (stopped, condvar) = &*stop;
+            loop {
+                let guard = stopped.lock().unwrap_or_else(|err| err.into_inner());
+                let (guard, _) = condvar
+                    .wait_timeout_while(guard, interval, |stopped| !*stopped)
+                    .unwrap_or_else(|err| err.into_inner());
+                if *guard {
+                    return;
+                }
+                drop(guard);
+                if let Err(err) = flusher.flush() {
+                    log::warn!("Automatic flush failed: {:?}", err);
+                    errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }));
+    }
+
+    fn stop(&mut self) {
+        let (stopped, condvar) = &*self.stop;
+        *stopped.lock().unwrap_or_else(|err| err.into_inner()) = true;
+        condvar.notify_all();
+        if let Some(thread) = self.thread.take()
+            && thread.join().is_err()
+        {
+            log::error!("Automatic flush thread panicked");
+        }
+    }
 }
 
+/// How long dropping a [`Trace`] waits for the processing thread.
+const DROP_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 impl Drop for Trace {
     fn drop(&mut self) {
         log::trace!("Trace::drop called");
-        if let Err(err) = self.close() {
-            log::error!("Failed to close trace: {:?}", err);
+        if !self.shut_down {
+            if let Err(err) = self.shutdown_in_place(DROP_SHUTDOWN_TIMEOUT) {
+                log::error!("Failed to shut down trace: {:?}", err);
+            }
+        }
+    }
+}
+
+/// The steps of tearing down a trace, separate from [`Trace`] so their order can be tested.
+trait Teardown {
+    fn signal_stop(&mut self);
+    fn close_trace(&mut self) -> Result<(), TraceError>;
+    fn flush_session(&mut self) -> Result<(), TraceError>;
+    fn join_processing(&mut self, timeout: Duration) -> Result<(), TraceError>;
+    fn stop_session(&mut self) -> Result<(), TraceError>;
+}
+
+/// Run all teardown steps in order, returning the first error.
+///
+/// The stop flag makes the buffer callback end processing, and flushing the session
+/// delivers its buffers right away, so the processing thread returns without waiting
+/// for the flush timer. The session is stopped last, once nobody consumes it anymore.
+fn teardown<T: Teardown>(steps: &mut T, timeout: Duration) -> Result<(), TraceError> {
+    steps.signal_stop();
+    let close = steps.close_trace();
+    let flush = steps.flush_session();
+    let join = steps.join_processing(timeout);
+    let stop = steps.stop_session();
+    close.and(flush).and(join).and(stop)
+}
+
+impl Teardown for Trace {
+    fn signal_stop(&mut self) {
+        self.request_stop();
+        if let Some(auto_flush) = &mut self.auto_flush {
+            auto_flush.stop();
+        }
+    }
+
+    fn close_trace(&mut self) -> Result<(), TraceError> {
+        self.close()
+    }
+
+    fn flush_session(&mut self) -> Result<(), TraceError> {
+        match &mut self._controller {
+            Some(TraceController::RealtimeTraceSession(session)) => session.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn join_processing(&mut self, timeout: Duration) -> Result<(), TraceError> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        let deadline = std::time::Instant::now() + timeout;
+        while !thread.is_finished() {
+            if std::time::Instant::now() >= deadline {
+                // The thread is detached; stopping the session ends it eventually
+                return Err(TraceError::ShutdownTimeout(timeout));
+            }
+            thread::sleep(JOIN_POLL_INTERVAL);
+        }
+        match thread.join().map_err(|_| TraceError::ThreadJoin)? {
+            Err(TraceError::Windows(err)) if err.code() == HRESULT::from(ERROR_CANCELLED) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn stop_session(&mut self) -> Result<(), TraceError> {
+        match self._controller.take() {
+            Some(TraceController::RealtimeTraceSession(mut session)) => session.stop(),
+            None => Ok(()),
         }
     }
 }
 
-const WINDOWS_TO_UNIX_EPOCH_OFFSET: Duration = Duration::from_secs(11644473600);
+/// Timestamp of a trace's header event, the `EventTrace` info event that comes first.
+fn header_event_ticks(header: &EVENT_HEADER) -> Option<i64> {
+    let is_header = header.ProviderId == EVENT_TRACE_GUID && header.EventDescriptor.Opcode == 0;
+    (is_header && header.TimeStamp > 0).then_some(header.TimeStamp)
+}
 
 fn system_time_to_filetime(time: SystemTime) -> FILETIME {
-    let time = time
-        .duration_since(SystemTime::UNIX_EPOCH - WINDOWS_TO_UNIX_EPOCH_OFFSET)
-        .unwrap();
-    let ticks = time.as_nanos() / 100;
-    let low = (ticks & u128::from(u32::MAX)) as u32;
+    let ticks = system_time_to_ticks(time) as u64;
+    let low = (ticks & u64::from(u32::MAX)) as u32;
     let high = u32::try_from(ticks >> 32).unwrap();
     FILETIME {
         dwLowDateTime: low,
@@ -334,15 +992,14 @@ fn system_time_to_filetime(time: SystemTime) -> FILETIME {
 }
 
 fn process_trace<FN: FnOnce() + Send>(
-    handle: PROCESSTRACE_HANDLE,
+    handlearray: &[PROCESSTRACE_HANDLE],
     start: Option<SystemTime>,
     end: Option<SystemTime>,
     notify: Option<FN>,
 ) -> Result<(), TraceError> {
-    log::trace!("Trace::process_trace({:?}, {:?}, {:?})", handle, start, end);
+    log::trace!("Trace::process_trace({:?}, {:?}, {:?})", handlearray, start, end);
     let start: Option<FILETIME> = start.map(system_time_to_filetime);
     let end = end.map(system_time_to_filetime);
-    let handlearray = &[handle];
     let starttime = start.as_ref().map(|x| x as *const _);
     let endtime = end.as_ref().map(|x| x as *const _);
     unsafe {
@@ -384,31 +1041,301 @@ impl Trace {
         end: Option<SystemTime>,
         notify: Option<FN>,
     ) {
-        let handle = self.handle;
+        #[cfg(feature = "async")]
+        let event_channel = self.event_channel.clone();
+        #[cfg(feature = "test-util")]
+        if let Some(source) = self.mock.take() {
+            let handler_data = Arc::clone(&self._handler_data);
+            self.thread = Some(thread::spawn(move || {
+                let result = source.process(handler_data, start, end);
+                #[cfg(feature = "async")]
+                if let Some(channel) = event_channel {
+                    channel.close();
+                }
+                if let Some(notify) = notify {
+                    notify();
+                }
+                result
+            }));
+            return;
+        }
+        let handles = self.handles.clone();
         self.thread = Some(thread::spawn(move || {
-            process_trace(handle, start, end, notify)
+            let result = process_trace(&handles, start, end, notify);
+            #[cfg(feature = "async")]
+            if let Some(channel) = event_channel {
+                channel.close();
+            }
+            result
         }));
+        if let Some(auto_flush) = &mut self.auto_flush {
+            auto_flush.start();
+        }
+    }
+
+    /// Process the trace on the calling thread until it ends, e.g. at the end of its
+    /// log file, and count the events.
+    ///
+    /// Use this instead of [`Trace::start_processing`] to read a log file offline. Traces
+    /// of a session are processed until stopped from another thread.
+    pub fn process_blocking(&mut self) -> Result<ProcessSummary, TraceError> {
+        #[cfg(feature = "test-util")]
+        if let Some(source) = self.mock.take() {
+            let result = source.process(Arc::clone(&self._handler_data), None, None);
+            #[cfg(feature = "async")]
+            self.close_event_stream();
+            result?;
+            return Ok(self.summary());
+        }
+        Self::process_group_blocking(&[&*self])?;
+        Ok(self.summary())
+    }
+
+    /// Process `traces` together on the calling thread, like [`Trace::process_blocking`].
+    ///
+    /// A single `ProcessTrace` call delivers the events of all traces, those of log
+    /// files merged in timestamp order. Each trace's events go to its own handlers.
+    /// Returns the counts of each trace, in the order of `traces`.
+    ///
+    /// The group is validated with [`Trace::check_group_compatible`] before any of its
+    /// log files are opened by `ProcessTrace`.
+    pub fn process_group_blocking(traces: &[&Trace]) -> Result<Vec<ProcessSummary>, TraceError> {
+        if traces.iter().any(|trace| trace.thread.is_some() || trace.handles.is_empty()) {
+            return Err(TraceError::Configuration(
+                "Traces processed in a group must be open and not processing already".to_string(),
+            ));
+        }
+        Self::check_group_compatible(traces)?;
+        let handles = traces.iter().flat_map(|trace| trace.handles.iter().copied()).collect::<Vec<_>>();
+        let result = process_trace(&handles, None, None, None::<fn()>);
+        #[cfg(feature = "async")]
+        for trace in traces {
+            trace.close_event_stream();
+        }
+        match result {
+            // A buffer callback returning false cancels processing
+            Err(TraceError::Windows(err))
+                if traces.iter().any(|trace| trace.stop_requested())
+                    && err.code() == HRESULT::from(ERROR_CANCELLED) => {}
+            result => result?,
+        }
+        Ok(traces.iter().map(|trace| trace.summary()).collect())
+    }
+
+    /// The events processed and failed to decode so far.
+    pub fn summary(&self) -> ProcessSummary {
+        ProcessSummary {
+            events_processed: self._handler_data.events_processed.load(Ordering::Relaxed),
+            events_failed: self.failures.failed_events(),
+        }
+    }
+
+    /// Stop processing and tear down the trace, reporting failures.
+    ///
+    /// Sets the stop flag, closes the trace, flushes an owned session, waits up to
+    /// `timeout` for the processing thread and finally stops an owned session. Dropping
+    /// a trace runs the same sequence, but can only log failures.
+    pub fn shutdown(mut self, timeout: Duration) -> Result<(), TraceError> {
+        self.shutdown_in_place(timeout)
     }
 
+    fn shutdown_in_place(&mut self, timeout: Duration) -> Result<(), TraceError> {
+        self.shut_down = true;
+        teardown(self, timeout)
+    }
+
+    /// Ask the processing thread to stop at the next buffer.
+    ///
+    /// The buffer callback then returns false and `ProcessTrace` returns, after which
+    /// [`Trace::wait`] returns `Ok(())`. Events of the current buffer are still handled.
+    pub fn request_stop(&self) {
+        self._handler_data.stop_trace.store(true, Ordering::Release);
+        if let Some(control) = &self._handler_data.replay_control {
+            control.cancel();
+        }
+    }
+
+    fn stop_requested(&self) -> bool {
+        self._handler_data.stop_trace.load(Ordering::Acquire)
+    }
+
+    /// Request a stop and close the trace handle.
     pub fn close(&self) -> Result<(), TraceError> {
-        //TODO: signal stop
-        unsafe {
-            match CloseTrace(self.handle).ok() {
-                Ok(()) => Ok(()),
-                Err(err) if err.code() == HRESULT::from(ERROR_CTX_CLOSE_PENDING) => Ok(()),
-                Err(err) => Err(TraceError::from(err)),
+        self.request_stop();
+        #[cfg(feature = "async")]
+        self.close_event_stream();
+        if self.closed.swap(true, Ordering::AcqRel) {
+            // Closing twice fails; mock traces have nothing to close, they stop at the stop flag
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for handle in &self.handles {
+            unsafe {
+                match CloseTrace(*handle).ok() {
+                    Ok(()) => (),
+                    Err(err) if err.code() == HRESULT::from(ERROR_CTX_CLOSE_PENDING) => (),
+                    Err(err) => {
+                        if result.is_ok() {
+                            result = Err(TraceError::from(err));
+                        }
+                    }
+                }
             }
         }
+        result
+    }
+
+    /// Stop processing at the next buffer and close the trace.
+    ///
+    /// Returns without waiting for the processing thread, see [`Trace::stop_and_wait`].
+    /// An owned session keeps running until the trace is shut down or dropped.
+    pub fn stop(&self) -> Result<(), TraceError> {
+        self.close()
+    }
+
+    /// Stop processing like [`Trace::stop`] and wait up to `timeout` for the processing
+    /// thread to exit.
+    ///
+    /// An owned session is flushed so the last buffer arrives without waiting for the
+    /// flush timer. Fails with [`TraceError::ShutdownTimeout`] if the thread is still
+    /// running after `timeout`.
+    pub fn stop_and_wait(&mut self, timeout: Duration) -> Result<(), TraceError> {
+        let stop = self.stop();
+        if let Some(auto_flush) = &mut self.auto_flush {
+            auto_flush.stop();
+        }
+        let flush = self.flush_session();
+        let join = self.join_processing(timeout);
+        stop.and(flush).and(join)
     }
 
     pub fn wait(&mut self) -> Result<(), TraceError> {
         if let Some(thread) = self.thread.take() {
-            thread.join().map_err(|_| TraceError::ThreadJoin)??;
+            match thread.join().map_err(|_| TraceError::ThreadJoin)? {
+                // A buffer callback returning false cancels processing
+                Err(TraceError::Windows(err))
+                    if self.stop_requested() && err.code() == HRESULT::from(ERROR_CANCELLED) => {}
+                result => result?,
+            }
         }
 
         Ok(())
     }
 
+    /// The current processing position, to resume a file trace later.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self._handler_data
+            .checkpoint
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .position()
+    }
+
+    /// Write the events buffered in memory by the session of this trace to `path`.
+    ///
+    /// See [`TraceSession::flush_buffered_to`].
+    pub fn flush_buffered_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), TraceError> {
+        match &mut self._controller {
+            Some(TraceController::RealtimeTraceSession(session)) => session.flush_buffered_to(path),
+            None => Err(TraceError::Configuration(
+                "Only traces of a session can be flushed".to_string(),
+            )),
+        }
+    }
+
+    /// Lost events and buffers, to tell whether the handler keeps up.
+    ///
+    /// For traces of a session the loss counters are queried from the session; for
+    /// file traces they come from the log file header. The number of buffers read is
+    /// updated with every buffer the trace processes.
+    pub fn statistics(&self) -> Result<TraceStatistics, TraceError> {
+        let logfile = self
+            ._handler_data
+            .statistics
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .unwrap_or_else(|| {
+                self._event_trace_logfiles
+                    .iter()
+                    .map(|logfile| logfile_statistics(&logfile.data))
+                    .fold(TraceStatistics::default(), |total, file| TraceStatistics {
+                        events_lost: total.events_lost + file.events_lost,
+                        buffers_lost: total.buffers_lost + file.buffers_lost,
+                        buffers_written: total.buffers_written + file.buffers_written,
+                        buffers_read: total.buffers_read + file.buffers_read,
+                    })
+            });
+        match &self._controller {
+            Some(TraceController::RealtimeTraceSession(session)) => {
+                let session = session.query()?;
+                Ok(TraceStatistics {
+                    events_lost: session.events_lost,
+                    buffers_lost: session.log_buffers_lost + session.real_time_buffers_lost,
+                    buffers_written: session.buffers_written,
+                    buffers_read: logfile.buffers_read,
+                })
+            }
+            None => Ok(logfile),
+        }
+    }
+
+    /// The header of the trace's log file, the first one if it has several.
+    ///
+    /// For traces of a session only some fields are filled in.
+    pub fn logfile_header(&self) -> LogfileHeader {
+        LogfileHeader::from(&self.primary_logfile().data.LogfileHeader)
+    }
+
+    /// How to interpret the timestamps of this trace's events.
+    ///
+    /// Without [`TraceBuilder::raw_timestamps`] these are FILETIME ticks, whatever
+    /// the session's clock. With them, the context is built from the logfile header,
+    /// see [`TimestampContext::from_logfile_header`]. QPC and CPU cycle timestamps
+    /// convert to absolute times once the trace's header event, which the session logs
+    /// when it starts, has been processed: its timestamp is the clock value at the
+    /// session start.
+    pub fn timestamp_context(&self) -> TimestampContext {
+        self._handler_data.timestamp_context()
+    }
+
+    /// Convert an event timestamp of this trace to a point in time, see
+    /// [`TimestampContext::to_system_time`].
+    pub fn timestamp_to_system_time(&self, timestamp: i64) -> Option<SystemTime> {
+        self.timestamp_context().to_system_time(timestamp)
+    }
+
+    /// Number of failed flushes requested by [`TraceBuilder::auto_flush`].
+    pub fn auto_flush_errors(&self) -> u64 {
+        self.auto_flush
+            .as_ref()
+            .map_or(0, |auto_flush| auto_flush.errors.load(Ordering::Relaxed))
+    }
+
+    /// Number of events dropped because no handler added with
+    /// [`TraceBuilder::add_provider_handler`] matched and no catch-all handler was set.
+    pub fn unmatched_events(&self) -> u64 {
+        self.unmatched_events.load(Ordering::Relaxed)
+    }
+
+    /// Number of events dropped because the queue of the stream set up with
+    /// [`TraceBuilder::into_stream`] was full or the stream was dropped.
+    #[cfg(feature = "async")]
+    pub fn dropped_events(&self) -> u64 {
+        self.event_channel.as_ref().map_or(0, |channel| channel.dropped_events())
+    }
+
+    #[cfg(feature = "async")]
+    fn close_event_stream(&self) {
+        if let Some(channel) = &self.event_channel {
+            channel.close();
+        }
+    }
+
+    /// Events that failed to decode, if enabled with [`TraceBuilder::capture_failures`].
+    pub fn failure_ring(&self) -> Arc<FailureRing> {
+        Arc::clone(&self.failures)
+    }
+
     pub fn is_finished(&self) -> bool {
         if let Some(thread) = &self.thread {
             thread.is_finished()
@@ -416,9 +1343,165 @@ impl Trace {
             true
         }
     }
+
+    fn primary_logfile(&self) -> &EventTraceLogfile {
+        &self._event_trace_logfiles[0]
+    }
+
+    /// The ETL file of a file trace, the first one if it has several.
+    pub fn log_file_name(&self) -> Option<PathBuf> {
+        self.log_file_names().into_iter().next()
+    }
+
+    /// The ETL files of a file trace, see [`TraceBuilder::files`].
+    pub fn log_file_names(&self) -> Vec<PathBuf> {
+        self._event_trace_logfiles
+            .iter()
+            .filter_map(|logfile| {
+                let len = logfile.log_file_name.iter().take_while(|x| **x != 0).count();
+                (len > 0).then(|| PathBuf::from(OsString::from_wide(&logfile.log_file_name[..len])))
+            })
+            .collect()
+    }
+
+    /// The log file mode from the header, filled in by `OpenTraceW`.
+    pub fn log_file_mode(&self) -> LogFileMode {
+        LogFileMode::from_bits_retain(self.primary_logfile().data.LogfileHeader.LogFileMode)
+    }
+
+    /// True if the trace was recorded by the NT kernel logger.
+    pub fn is_kernel_trace(&self) -> bool {
+        self.primary_logfile().data.IsKernelTrace != 0
+    }
+
+    /// True if the trace was recorded by a private (in-process) session.
+    pub fn is_private_trace(&self) -> bool {
+        self.log_file_mode()
+            .intersects(LogFileMode::PRIVATE_LOGGER_MODE | LogFileMode::PRIVATE_IN_PROC)
+    }
+
+    fn group_member(&self) -> TraceGroupMember {
+        TraceGroupMember {
+            name: match self.log_file_name() {
+                Some(file) => file.display().to_string(),
+                None => format!("{:?}", self.primary_logfile().logger_name()),
+            },
+            handles: self.handles.len(),
+            realtime: self._controller.is_some(),
+            kernel: self.is_kernel_trace(),
+            private: self.is_private_trace(),
+        }
+    }
+
+    /// Check that `traces` can be processed together by a single `ProcessTrace` call.
+    ///
+    /// The group must not be empty, have at most [`MAX_LOG_FILES`] handles in total and
+    /// not mix realtime sessions with files or private session traces with kernel
+    /// traces. `ProcessTrace` only reports a generic error for incompatible handles once
+    /// all of them are open; this names the offending traces instead.
+    pub fn check_group_compatible(traces: &[&Trace]) -> Result<(), TraceError> {
+        check_group_compatible(&traces.iter().map(|trace| trace.group_member()).collect::<Vec<_>>())
+    }
+}
+
+/// What `ProcessTrace` cares about when combining traces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TraceGroupMember {
+    name: String,
+    /// Number of trace handles, one per log file or session.
+    handles: usize,
+    realtime: bool,
+    kernel: bool,
+    private: bool,
+}
+
+fn check_group_compatible(members: &[TraceGroupMember]) -> Result<(), TraceError> {
+    fn names<'a>(members: impl Iterator<Item = &'a TraceGroupMember>) -> String {
+        members.map(|member| member.name.as_str()).collect::<Vec<_>>().join(", ")
+    }
+
+    if members.is_empty() {
+        return Err(TraceError::Configuration("Can't process an empty group of traces".to_string()));
+    }
+    let handles = members.iter().map(|member| member.handles).sum::<usize>();
+    if handles > MAX_LOG_FILES {
+        return Err(TraceError::Configuration(format!(
+            "Can't process {} log files and sessions together, at most {} are supported",
+            handles,
+            MAX_LOG_FILES
+        )));
+    }
+    if members.iter().any(|member| member.realtime) && members.iter().any(|member| !member.realtime) {
+        return Err(TraceError::Configuration(format!(
+            "Can't process realtime sessions ({}) together with files ({})",
+            names(members.iter().filter(|member| member.realtime)),
+            names(members.iter().filter(|member| !member.realtime)),
+        )));
+    }
+    if members.iter().any(|member| member.private) && members.iter().any(|member| member.kernel) {
+        return Err(TraceError::Configuration(format!(
+            "Can't process private session traces ({}) together with kernel traces ({})",
+            names(members.iter().filter(|member| member.private)),
+            names(members.iter().filter(|member| member.kernel)),
+        )));
+    }
+    Ok(())
+}
+
+/// Decode `event_record` and pass it to `handler`, logging and recording events that
+/// fail to decode in `failures`.
+fn decode_event(
+    event_record: &EVENT_RECORD,
+    schema_cache: &OnceLock<Arc<SchemaCache>>,
+    tolerant: &AtomicBool,
+    pointer_size: &AtomicUsize,
+    failures: &FailureRing,
+    handler: &mut dyn FnMut(Event, Arc<EventInfo>, &EVENT_RECORD),
+) {
+    log::trace!("Event record handler called: activity: {:?} GUID {:?} descriptor: {:?} version: {} userdata_len: {}", event_record.EventHeader.ActivityId, event_record.EventHeader.ProviderId, event_record.EventHeader.EventDescriptor, event_record.EventHeader.EventDescriptor.Version, event_record.UserDataLength);
+    let event_data = EventRecord(event_record).userdata();
+    let event_data = event_data.iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{b:02x}");
+        output
+    });
+    log::trace!("Event record userdata: {}", event_data);
+    let schema_cache = schema_cache.get().map_or(SchemaCache::global(), |cache| cache.as_ref());
+    let parsed = Event::parse_with_pointer_size(
+        event_record,
+        schema_cache,
+        tolerant.load(Ordering::Relaxed),
+        pointer_size.load(Ordering::Relaxed),
+    );
+    match parsed {
+        Ok((schema, event)) => handler(event, schema, event_record),
+        Err(TraceError::Decode(ParseError::Property { path, offset, source })) => {
+            log::warn!(
+                "failed to parse provider {:?} event {} version {} property {} at offset {}/{}: {}",
+                event_record.EventHeader.ProviderId,
+                event_record.EventHeader.EventDescriptor.Id,
+                event_record.EventHeader.EventDescriptor.Version,
+                path,
+                offset,
+                event_record.UserDataLength,
+                source
+            );
+            log_undecodable_event_record(event_record);
+            failures.record(event_record, &ParseError::Property { path, offset, source });
+        }
+        Err(err) => {
+            log::warn!(
+                "failed to parse provider {:?} event {} record: {}",
+                event_record.EventHeader.ProviderId,
+                event_record.EventHeader.EventDescriptor.Id,
+                err
+            );
+            log_undecodable_event_record(event_record);
+            failures.record(event_record, &err);
+        }
+    }
 }
 
-unsafe extern "system" fn event_record_handler(event_record: *mut EVENT_RECORD) {
+pub(crate) unsafe extern "system" fn event_record_handler(event_record: *mut EVENT_RECORD) {
     let unwinding_code = || {
         log::trace!("compound_event_record_handler called");
         unsafe {
@@ -431,12 +1514,49 @@ unsafe extern "system" fn event_record_handler(event_record: *mut EVENT_RECORD)
             Arc::increment_strong_count(context);
             let data = Arc::from_raw(context);
 
-            match data.handler.lock() {
-                Ok(mut handler) => handler(event_record),
-                Err(err) => {
-                    log::error!("event record handler lock poisoned: {:?}", err);
+            // Of a trace of several files, the first one's header describes the clock
+            if let Some(ticks) = header_event_ticks(&event_record.EventHeader) {
+                let _ = data.start_ticks.compare_exchange(0, ticks, Ordering::Relaxed, Ordering::Relaxed);
+            }
+            if data.skip_buffer.load(Ordering::Relaxed) {
+                return;
+            }
+            let deliver = data
+                .checkpoint
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .on_event(|| event_hash(event_record));
+            if !deliver {
+                return;
+            }
+
+            if let Some(clock_check) = &data.clock_check {
+                clock_check.checker.lock().unwrap_or_else(|err| err.into_inner()).record_event(
+                    &clock_check.session,
+                    &data.timestamp_context(),
+                    &EventRecord(event_record),
+                );
+            }
+
+            if let Some(replay) = &data.replay {
+                let mut replay = replay.lock().unwrap_or_else(|err| err.into_inner());
+                if !replay.pace(event_record.EventHeader.TimeStamp) {
+                    return;
                 }
-            };
+            }
+
+            if event_record.EventHeader.ProviderId != EVENT_TRACE_GUID {
+                data.events_processed.fetch_add(1, Ordering::Relaxed);
+            }
+
+            // A panic of the handler for a previous event poisons the lock; the handler
+            // itself is still usable, so keep delivering events to it.
+            let mut handler = data.handler.lock().unwrap_or_else(|err| {
+                log::warn!("event record handler panicked before, continuing with next event");
+                data.handler.clear_poison();
+                err.into_inner()
+            });
+            handler(event_record);
         }
     };
     match panic::catch_unwind(AssertUnwindSafe(unwinding_code)) {
@@ -454,12 +1574,7 @@ unsafe extern "system" fn event_record_handler(event_record: *mut EVENT_RECORD)
                     let _ = write!(output, "{b:02x}");
                     output
                 });
-                let userdata = unsafe {
-                    slice::from_raw_parts(
-                        (*event_record).UserData as *const u8,
-                        (*event_record).UserDataLength as usize,
-                    )
-                };
+                let userdata = EventRecord(unsafe { &*event_record }).userdata();
                 let userdata = userdata.iter().fold(String::new(), |mut output, b| {
                     let _ = write!(output, "{b:02x}");
                     output
@@ -475,7 +1590,7 @@ unsafe extern "system" fn event_record_handler(event_record: *mut EVENT_RECORD)
     }
 }
 
-unsafe extern "system" fn buffer_handler(logfile: *mut EVENT_TRACE_LOGFILEW) -> u32 {
+pub(crate) unsafe extern "system" fn buffer_handler(logfile: *mut EVENT_TRACE_LOGFILEW) -> u32 {
     unsafe {
         let Some(logfile) = logfile.as_mut() else {
             log::error!("logfile was null");
@@ -486,10 +1601,395 @@ unsafe extern "system" fn buffer_handler(logfile: *mut EVENT_TRACE_LOGFILEW) ->
         let context = logfile.Context as *const HandlerData;
         Arc::increment_strong_count(context);
         let context = Arc::from_raw(context);
+        *context.statistics.lock().unwrap_or_else(|err| err.into_inner()) = Some(logfile_statistics(logfile));
+        let pointer_size = logfile.LogfileHeader.Anonymous2.Anonymous.PointerSize as usize;
+        if pointer_size != 0 {
+            context.pointer_size.store(pointer_size, Ordering::Relaxed);
+        }
         if context.stop_trace.load(Ordering::Acquire) {
             return false.into();
         }
 
+        let position = {
+            let mut checkpoint = context.checkpoint.lock().unwrap_or_else(|err| err.into_inner());
+            checkpoint.on_buffer();
+            if checkpoint.is_resuming() {
+                log::trace!("skipped buffer before resume checkpoint");
+            }
+            context.skip_buffer.store(checkpoint.skips_buffer(), Ordering::Relaxed);
+            checkpoint.position()
+        };
+        if let Some(predicate) = &context.buffer_predicate {
+            let mut predicate = predicate.lock().unwrap_or_else(|err| err.into_inner());
+            if !predicate(&position) {
+                log::debug!("buffer predicate stopped processing at {:?}", position);
+                return false.into();
+            }
+        }
+
         u32::from(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize},
+            Arc, OnceLock,
+        },
+        time::{Duration, SystemTime},
+    };
+
+    use windows::{
+        core::GUID,
+        Win32::System::Diagnostics::Etw::{EVENT_HEADER, EVENT_RECORD, TRACE_LOGFILE_HEADER},
+    };
+
+    use super::{
+        check_group_compatible, decode_event, header_event_ticks, teardown, LogfileHeader,
+        Teardown, TraceBuilder, TraceGroupMember, EVENT_TRACE_GUID, MAX_LOG_FILES,
+    };
+    use crate::{
+        checkpoint::Checkpoint,
+        error::TraceError,
+        failures::FailureRing,
+        provider::ProviderBuilder,
+        schema::{
+            cache::{EventInfo, PropertyInfo, PropertyStructInfo, SchemaCache},
+            in_type::InType,
+            out_type::OutType,
+        },
+        timestamp::{system_time_to_ticks, TimestampContext},
+        trace_session::ClockResolution,
+        values::{compound::StructOrValue, in_value::InValue},
+        well_known::KERNEL_PROCESS_PROVIDER,
+    };
+
+    fn member(name: &str, realtime: bool, kernel: bool, private: bool) -> TraceGroupMember {
+        TraceGroupMember {
+            name: name.to_string(),
+            handles: 1,
+            realtime,
+            kernel,
+            private,
+        }
+    }
+
+    #[test]
+    fn test_group_compatibility_matrix() {
+        let kernel_file = member("kernel.etl", false, true, false);
+        let private_file = member("private.etl", false, false, true);
+        let user_file = member("user.etl", false, false, false);
+        let realtime = member("\"session\"", true, false, false);
+        let kernel_realtime = member("\"NT Kernel Logger\"", true, true, false);
+
+        let compatible: &[&[&TraceGroupMember]] = &[
+            &[&kernel_file],
+            &[&private_file],
+            &[&kernel_file, &user_file],
+            &[&private_file, &user_file],
+            &[&realtime, &kernel_realtime],
+        ];
+        for group in compatible {
+            let group = group.iter().map(|m| (*m).clone()).collect::<Vec<_>>();
+            assert!(check_group_compatible(&group).is_ok(), "{:?}", group);
+        }
+
+        let incompatible: &[(&[&TraceGroupMember], &[&str])] = &[
+            (&[], &["empty"]),
+            (&[&kernel_file, &private_file], &["kernel.etl", "private.etl"]),
+            (&[&user_file, &realtime], &["user.etl", "\"session\""]),
+            (&[&kernel_realtime, &private_file], &["NT Kernel Logger", "private.etl"]),
+        ];
+        for (group, offenders) in incompatible {
+            let group = group.iter().map(|m| (*m).clone()).collect::<Vec<_>>();
+            let message = match check_group_compatible(&group) {
+                Err(crate::error::TraceError::Configuration(message)) => message,
+                result => panic!("expected configuration error for {:?}, got {:?}", group, result),
+            };
+            for offender in *offenders {
+                assert!(message.contains(offender), "{} doesn't name {}", message, offender);
+            }
+        }
+    }
+
+    #[test]
+    fn test_group_size_limit() {
+        let group = (0..65)
+            .map(|i| member(&format!("{}.etl", i), false, false, false))
+            .collect::<Vec<_>>();
+        assert!(check_group_compatible(&group).is_err());
+        assert!(check_group_compatible(&group[..64]).is_ok());
+
+        // Traces of several files count once per file
+        let mut files = member("files", false, false, false);
+        files.handles = 60;
+        let group = (0..5)
+            .map(|i| member(&format!("{}.etl", i), false, false, false))
+            .chain([files])
+            .collect::<Vec<_>>();
+        assert!(check_group_compatible(&group).is_err());
+        assert!(check_group_compatible(&group[1..]).is_ok());
+    }
+
+    #[derive(Default)]
+    struct RecordingTeardown {
+        steps: Vec<&'static str>,
+        failing: Vec<&'static str>,
+    }
+
+    impl RecordingTeardown {
+        fn step(&mut self, name: &'static str) -> Result<(), TraceError> {
+            self.steps.push(name);
+            if self.failing.contains(&name) {
+                Err(TraceError::Configuration(name.to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Teardown for RecordingTeardown {
+        fn signal_stop(&mut self) {
+            self.steps.push("signal_stop");
+        }
+
+        fn close_trace(&mut self) -> Result<(), TraceError> {
+            self.step("close_trace")
+        }
+
+        fn flush_session(&mut self) -> Result<(), TraceError> {
+            self.step("flush_session")
+        }
+
+        fn join_processing(&mut self, _timeout: Duration) -> Result<(), TraceError> {
+            self.step("join_processing")
+        }
+
+        fn stop_session(&mut self) -> Result<(), TraceError> {
+            self.step("stop_session")
+        }
+    }
+
+    const ALL_STEPS: [&str; 5] = ["signal_stop", "close_trace", "flush_session", "join_processing", "stop_session"];
+
+    #[test]
+    fn test_teardown_order() {
+        let mut steps = RecordingTeardown::default();
+        teardown(&mut steps, Duration::from_secs(1)).unwrap();
+        assert_eq!(steps.steps, ALL_STEPS);
+    }
+
+    #[test]
+    fn test_teardown_runs_all_steps_and_reports_first_error() {
+        let mut steps = RecordingTeardown {
+            failing: vec!["flush_session", "stop_session"],
+            ..Default::default()
+        };
+        match teardown(&mut steps, Duration::from_secs(1)) {
+            Err(TraceError::Configuration(step)) => assert_eq!(step, "flush_session"),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(steps.steps, ALL_STEPS);
+    }
+
+    #[test]
+    fn test_auto_flush_rejects_zero_interval() {
+        assert!(matches!(
+            TraceBuilder::new().auto_flush(Duration::ZERO),
+            Err(TraceError::Configuration(_))
+        ));
+        assert!(TraceBuilder::new().auto_flush(Duration::from_millis(1)).is_ok());
+    }
+
+    #[test]
+    fn test_add_provider_rejects_duplicates_and_file_traces() {
+        let provider = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER).build();
+        let builder = TraceBuilder::new().add_provider(provider, None).unwrap();
+        assert!(matches!(
+            builder.add_provider(provider, None),
+            Err(TraceError::Configuration(_))
+        ));
+
+        let result = TraceBuilder::new()
+            .add_provider(provider, None)
+            .unwrap()
+            .file("missing.etl")
+            .unwrap()
+            .set_raw_handler(|_| ())
+            .unwrap()
+            .open();
+        match result {
+            Err(TraceError::Configuration(message)) => assert!(message.contains("session"), "{}", message),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("file trace with providers opened"),
+        }
+    }
+
+    #[test]
+    fn test_files_are_limited_and_need_a_single_file_to_resume() {
+        let files = |count: usize| (0..count).map(|idx| PathBuf::from(format!("trace_{idx}.etl")));
+        let builder = TraceBuilder::new().files(files(MAX_LOG_FILES)).unwrap();
+        assert_eq!(builder.files.len(), MAX_LOG_FILES);
+        match builder.file("one-too-many.etl") {
+            Err(TraceError::Configuration(message)) => assert!(message.contains("at most 64"), "{}", message),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+
+        let result = TraceBuilder::new()
+            .files(files(2))
+            .unwrap()
+            .resume_from(Checkpoint::default())
+            .unwrap()
+            .set_raw_handler(|_| ())
+            .unwrap()
+            .open();
+        match result {
+            Err(TraceError::Configuration(message)) => assert!(message.contains("single file"), "{}", message),
+            Err(err) => panic!("unexpected error {:?}", err),
+            Ok(_) => panic!("resumed a trace of several files"),
+        }
+    }
+
+    #[test]
+    fn test_logfile_header_is_decoded() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut header = TRACE_LOGFILE_HEADER {
+            BufferSize: 64 * 1024,
+            ProviderVersion: 19045,
+            NumberOfProcessors: 8,
+            StartTime: system_time_to_ticks(start),
+            TimerResolution: 156_250,
+            ReservedFlags: 1,
+            PerfFreq: 10_000_000,
+            BuffersLost: 2,
+            ..Default::default()
+        };
+        header.Anonymous1.VersionDetail.MajorVersion = 10;
+        header.Anonymous2.Anonymous.PointerSize = 4;
+        header.Anonymous2.Anonymous.EventsLost = 3;
+
+        let decoded = LogfileHeader::from(&header);
+        assert_eq!(decoded.buffer_size, 64 * 1024);
+        assert_eq!(decoded.os_version, (10, 0, 0, 0));
+        assert_eq!(decoded.pointer_size, 4);
+        assert_eq!(decoded.start_time, Some(start));
+        assert_eq!(decoded.end_time, None);
+        assert_eq!(decoded.timer_resolution, Duration::from_micros(15_625));
+        assert_eq!(decoded.clock_resolution, Some(ClockResolution::QueryPerformanceCounter));
+        assert_eq!((decoded.events_lost, decoded.buffers_lost), (3, 2));
+    }
+
+    /// The context of a raw timestamp trace with `header`, once its header event
+    /// logged at `start_ticks` arrived.
+    fn raw_context(header: &TRACE_LOGFILE_HEADER, start_ticks: i64) -> TimestampContext {
+        let context = TimestampContext::from_logfile_header(header, true);
+        let header_event = EVENT_HEADER {
+            ProviderId: EVENT_TRACE_GUID,
+            TimeStamp: start_ticks,
+            ..Default::default()
+        };
+        context.with_start_ticks(header_event_ticks(&header_event).unwrap())
+    }
+
+    #[test]
+    fn test_raw_qpc_timestamps_convert_from_header_event() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let header = TRACE_LOGFILE_HEADER {
+            StartTime: system_time_to_ticks(start),
+            ReservedFlags: 1,
+            PerfFreq: 3_000_000,
+            ..Default::default()
+        };
+        assert_eq!(TimestampContext::from_logfile_header(&header, true).to_system_time(11_000_000), None);
+        let context = raw_context(&header, 5_000_000);
+        assert_eq!(context.to_system_time(11_000_000), Some(start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_raw_system_time_timestamps_convert() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let header = TRACE_LOGFILE_HEADER {
+            StartTime: system_time_to_ticks(start),
+            ReservedFlags: 2,
+            ..Default::default()
+        };
+        let later = start + Duration::from_secs(2);
+        let context = raw_context(&header, system_time_to_ticks(start));
+        assert_eq!(context.to_system_time(system_time_to_ticks(later)), Some(later));
+    }
+
+    #[test]
+    fn test_raw_cycle_counter_timestamps_convert_from_header_event() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut header = TRACE_LOGFILE_HEADER {
+            StartTime: system_time_to_ticks(start),
+            ReservedFlags: 3,
+            ..Default::default()
+        };
+        header.Anonymous2.Anonymous.CpuSpeedInMHz = 3000;
+        assert_eq!(TimestampContext::from_logfile_header(&header, true).to_system_time(7_000_000_000), None);
+        let context = raw_context(&header, 1_000_000_000);
+        assert_eq!(context.to_system_time(7_000_000_000), Some(start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_only_the_header_event_has_start_ticks() {
+        let mut header = EVENT_HEADER {
+            ProviderId: EVENT_TRACE_GUID,
+            TimeStamp: 42,
+            ..Default::default()
+        };
+        assert_eq!(header_event_ticks(&header), Some(42));
+        header.EventDescriptor.Opcode = 32;
+        assert_eq!(header_event_ticks(&header), None);
+        header.EventDescriptor.Opcode = 0;
+        header.ProviderId = GUID::from_u128(1);
+        assert_eq!(header_event_ticks(&header), None);
+    }
+
+    #[test]
+    fn test_missing_pointer_size_is_taken_from_logfile() {
+        const PROVIDER: GUID = GUID::from_u128(0x8a3d_41c2_6f1e_4b90_a2d7_03c5_e1f4_9b68);
+        let cache = SchemaCache::new();
+        cache.insert(
+            PROVIDER,
+            1,
+            EventInfo::new(
+                PROVIDER,
+                1,
+                0,
+                PropertyStructInfo::new(vec![PropertyInfo::value("Address", InType::Pointer, OutType::HexInt64)]),
+            ),
+        );
+        let schema_cache = OnceLock::from(Arc::new(cache));
+
+        let mut userdata = 0x1000u32.to_le_bytes();
+        let mut record = EVENT_RECORD::default();
+        record.EventHeader.ProviderId = PROVIDER;
+        record.EventHeader.EventDescriptor.Id = 1;
+        record.UserData = userdata.as_mut_ptr().cast();
+        record.UserDataLength = userdata.len() as u16;
+
+        let mut address = None;
+        decode_event(
+            &record,
+            &schema_cache,
+            &AtomicBool::new(false),
+            &AtomicUsize::new(4),
+            &FailureRing::new(1, 16),
+            &mut |event, _, _| {
+                if let Some(StructOrValue::Value(value)) = event.data.get("Address") {
+                    if let InValue::Pointer(pointer) = &value.value {
+                        address = pointer.get(0);
+                    }
+                }
+            },
+        );
+
+        assert_eq!(address, Some(0x1000));
+        assert_eq!(record.EventHeader.Flags, 0);
+    }
+}
@@ -1,27 +1,47 @@
 use std::{
-    ffi::{OsStr, OsString},
+    collections::HashMap,
+    ffi::{c_void, OsStr, OsString},
     fmt, iter, mem,
     os::windows::prelude::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    ptr,
+    str::FromStr,
+    sync::{Mutex, Once},
     time::Duration,
 };
 
 use windows::{
-    core::{HRESULT, PCWSTR},
+    core::{GUID, HRESULT, PCWSTR, PWSTR},
     Win32::{
-        Foundation::ERROR_ALREADY_EXISTS,
+        Foundation::{BOOL, BOOLEAN, ERROR_ALREADY_EXISTS, ERROR_TIMEOUT, ERROR_WMI_INSTANCE_NOT_FOUND},
         System::{
+            Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT},
             Diagnostics::Etw::{
-                ControlTraceW, EnableTraceEx2, StartTraceW, CONTROLTRACE_HANDLE, ENABLE_TRACE_PARAMETERS, ENABLE_TRACE_PARAMETERS_VERSION_2, EVENT_CONTROL_CODE_DISABLE_PROVIDER, EVENT_CONTROL_CODE_ENABLE_PROVIDER, EVENT_FILTER_DESCRIPTOR, EVENT_FILTER_EVENT_ID, EVENT_FILTER_TYPE_EVENT_ID, EVENT_TRACE_ADDTO_TRIAGE_DUMP, EVENT_TRACE_ADD_HEADER_MODE, EVENT_TRACE_BUFFERING_MODE, EVENT_TRACE_CONTROL_STOP, EVENT_TRACE_DELAY_OPEN_FILE_MODE, EVENT_TRACE_FILE_MODE_APPEND, EVENT_TRACE_FILE_MODE_CIRCULAR, EVENT_TRACE_FILE_MODE_NEWFILE, EVENT_TRACE_FILE_MODE_NONE, EVENT_TRACE_FILE_MODE_PREALLOCATE, EVENT_TRACE_FILE_MODE_SEQUENTIAL, EVENT_TRACE_FLAG, EVENT_TRACE_FLAG_ALPC, EVENT_TRACE_FLAG_CSWITCH, EVENT_TRACE_FLAG_DBGPRINT, EVENT_TRACE_FLAG_DISK_FILE_IO, EVENT_TRACE_FLAG_DISK_IO, EVENT_TRACE_FLAG_DISK_IO_INIT, EVENT_TRACE_FLAG_DISPATCHER, EVENT_TRACE_FLAG_DPC, EVENT_TRACE_FLAG_DRIVER, EVENT_TRACE_FLAG_FILE_IO, EVENT_TRACE_FLAG_FILE_IO_INIT, EVENT_TRACE_FLAG_IMAGE_LOAD, EVENT_TRACE_FLAG_INTERRUPT, EVENT_TRACE_FLAG_JOB, EVENT_TRACE_FLAG_MEMORY_HARD_FAULTS, EVENT_TRACE_FLAG_MEMORY_PAGE_FAULTS, EVENT_TRACE_FLAG_NETWORK_TCPIP, EVENT_TRACE_FLAG_NO_SYSCONFIG, EVENT_TRACE_FLAG_PROCESS, EVENT_TRACE_FLAG_PROCESS_COUNTERS, EVENT_TRACE_FLAG_PROFILE, EVENT_TRACE_FLAG_REGISTRY, EVENT_TRACE_FLAG_SPLIT_IO, EVENT_TRACE_FLAG_SYSTEMCALL, EVENT_TRACE_FLAG_THREAD, EVENT_TRACE_FLAG_VAMAP, EVENT_TRACE_FLAG_VIRTUAL_ALLOC, EVENT_TRACE_INDEPENDENT_SESSION_MODE, EVENT_TRACE_MODE_RESERVED, EVENT_TRACE_NONSTOPPABLE_MODE, EVENT_TRACE_NO_PER_PROCESSOR_BUFFERING, EVENT_TRACE_PERSIST_ON_HYBRID_SHUTDOWN, EVENT_TRACE_PRIVATE_IN_PROC, EVENT_TRACE_PRIVATE_LOGGER_MODE, EVENT_TRACE_PROPERTIES, EVENT_TRACE_PROPERTIES_V2, EVENT_TRACE_REAL_TIME_MODE, EVENT_TRACE_RELOG_MODE, EVENT_TRACE_STOP_ON_HYBRID_SHUTDOWN, EVENT_TRACE_SYSTEM_LOGGER_MODE, EVENT_TRACE_USE_GLOBAL_SEQUENCE, EVENT_TRACE_USE_KBYTES_FOR_SIZE, EVENT_TRACE_USE_LOCAL_SEQUENCE, EVENT_TRACE_USE_PAGED_MEMORY, WNODE_FLAG_ALL_DATA, WNODE_FLAG_ANSI_INSTANCENAMES, WNODE_FLAG_EVENT_ITEM, WNODE_FLAG_EVENT_REFERENCE, WNODE_FLAG_FIXED_INSTANCE_SIZE, WNODE_FLAG_INSTANCES_SAME, WNODE_FLAG_INTERNAL, WNODE_FLAG_LOG_WNODE, WNODE_FLAG_METHOD_ITEM, WNODE_FLAG_NO_HEADER, WNODE_FLAG_PDO_INSTANCE_NAMES, WNODE_FLAG_PERSIST_EVENT, WNODE_FLAG_SEND_DATA_BLOCK, WNODE_FLAG_SEVERITY_MASK, WNODE_FLAG_SINGLE_INSTANCE, WNODE_FLAG_SINGLE_ITEM, WNODE_FLAG_STATIC_INSTANCE_NAMES, WNODE_FLAG_TOO_SMALL, WNODE_FLAG_TRACED_GUID, WNODE_FLAG_USE_GUID_PTR, WNODE_FLAG_USE_MOF_PTR, WNODE_FLAG_USE_TIMESTAMP, WNODE_FLAG_VERSIONED_PROPERTIES, WNODE_HEADER
+                ControlTraceW, EnableTraceEx2, StartTraceW, TdhAggregatePayloadFilters, TraceSetInformation, TraceStackTracingInfo, CLASSIC_EVENT_ID, TdhCleanupPayloadEventFilterDescriptor, TdhCreatePayloadFilter, TdhDeletePayloadFilter, CONTROLTRACE_HANDLE, ENABLE_TRACE_PARAMETERS, ENABLE_TRACE_PARAMETERS_VERSION_2, EVENT_CONTROL_CODE_CAPTURE_STATE, EVENT_CONTROL_CODE_DISABLE_PROVIDER, EVENT_CONTROL_CODE_ENABLE_PROVIDER, EVENT_DESCRIPTOR, EVENT_FILTER_DESCRIPTOR, EVENT_FILTER_EVENT_ID, EVENT_FILTER_TYPE_EVENT_ID, EVENT_FILTER_TYPE_EXECUTABLE_NAME, EVENT_FILTER_TYPE_PAYLOAD, EVENT_FILTER_TYPE_PID, EVENT_TRACE_ADDTO_TRIAGE_DUMP, EVENT_TRACE_ADD_HEADER_MODE, EVENT_TRACE_BUFFERING_MODE, EVENT_TRACE_CONTROL_FLUSH, EVENT_TRACE_CONTROL_QUERY, EVENT_TRACE_CONTROL_STOP, EVENT_TRACE_CONTROL, EVENT_TRACE_CONTROL_UPDATE, EVENT_TRACE_DELAY_OPEN_FILE_MODE, EVENT_TRACE_FILE_MODE_APPEND, EVENT_TRACE_FILE_MODE_CIRCULAR, EVENT_TRACE_FILE_MODE_NEWFILE, EVENT_TRACE_FILE_MODE_NONE, EVENT_TRACE_FILE_MODE_PREALLOCATE, EVENT_TRACE_FILE_MODE_SEQUENTIAL, EVENT_TRACE_FLAG, EVENT_TRACE_FLAG_ALPC, EVENT_TRACE_FLAG_CSWITCH, EVENT_TRACE_FLAG_DBGPRINT, EVENT_TRACE_FLAG_DISK_FILE_IO, EVENT_TRACE_FLAG_DISK_IO, EVENT_TRACE_FLAG_DISK_IO_INIT, EVENT_TRACE_FLAG_DISPATCHER, EVENT_TRACE_FLAG_DPC, EVENT_TRACE_FLAG_DRIVER, EVENT_TRACE_FLAG_FILE_IO, EVENT_TRACE_FLAG_FILE_IO_INIT, EVENT_TRACE_FLAG_IMAGE_LOAD, EVENT_TRACE_FLAG_INTERRUPT, EVENT_TRACE_FLAG_JOB, EVENT_TRACE_FLAG_MEMORY_HARD_FAULTS, EVENT_TRACE_FLAG_MEMORY_PAGE_FAULTS, EVENT_TRACE_FLAG_NETWORK_TCPIP, EVENT_TRACE_FLAG_NO_SYSCONFIG, EVENT_TRACE_FLAG_PROCESS, EVENT_TRACE_FLAG_PROCESS_COUNTERS, EVENT_TRACE_FLAG_PROFILE, EVENT_TRACE_FLAG_REGISTRY, EVENT_TRACE_FLAG_SPLIT_IO, EVENT_TRACE_FLAG_SYSTEMCALL, EVENT_TRACE_FLAG_THREAD, EVENT_TRACE_FLAG_VAMAP, EVENT_TRACE_FLAG_VIRTUAL_ALLOC, EVENT_TRACE_INDEPENDENT_SESSION_MODE, EVENT_TRACE_MODE_RESERVED, EVENT_TRACE_NONSTOPPABLE_MODE, EVENT_TRACE_NO_PER_PROCESSOR_BUFFERING, EVENT_TRACE_PERSIST_ON_HYBRID_SHUTDOWN, EVENT_TRACE_PRIVATE_IN_PROC, EVENT_TRACE_PRIVATE_LOGGER_MODE, EVENT_TRACE_PROPERTIES, EVENT_TRACE_PROPERTIES_V2, EVENT_TRACE_REAL_TIME_MODE, EVENT_TRACE_RELOG_MODE, EVENT_TRACE_STOP_ON_HYBRID_SHUTDOWN, EVENT_TRACE_SYSTEM_LOGGER_MODE, EVENT_TRACE_USE_GLOBAL_SEQUENCE, EVENT_TRACE_USE_KBYTES_FOR_SIZE, EVENT_TRACE_USE_LOCAL_SEQUENCE, EVENT_TRACE_USE_PAGED_MEMORY, MAX_EVENT_FILTER_DATA_SIZE, MAX_EVENT_FILTER_PID_COUNT, MAX_PAYLOAD_PREDICATES, PAYLOAD_FILTER_PREDICATE, WNODE_FLAG_ALL_DATA, WNODE_FLAG_ANSI_INSTANCENAMES, WNODE_FLAG_EVENT_ITEM, WNODE_FLAG_EVENT_REFERENCE, WNODE_FLAG_FIXED_INSTANCE_SIZE, WNODE_FLAG_INSTANCES_SAME, WNODE_FLAG_INTERNAL, WNODE_FLAG_LOG_WNODE, WNODE_FLAG_METHOD_ITEM, WNODE_FLAG_NO_HEADER, WNODE_FLAG_PDO_INSTANCE_NAMES, WNODE_FLAG_PERSIST_EVENT, WNODE_FLAG_SEND_DATA_BLOCK, WNODE_FLAG_SEVERITY_MASK, WNODE_FLAG_SINGLE_INSTANCE, WNODE_FLAG_SINGLE_ITEM, WNODE_FLAG_STATIC_INSTANCE_NAMES, WNODE_FLAG_TOO_SMALL, WNODE_FLAG_TRACED_GUID, WNODE_FLAG_USE_GUID_PTR, WNODE_FLAG_USE_MOF_PTR, WNODE_FLAG_USE_TIMESTAMP, WNODE_FLAG_VERSIONED_PROPERTIES, WNODE_HEADER
             },
             Threading::INFINITE,
         },
     },
 };
 
-use crate::{error::TraceError, provider::Provider};
+use crate::{
+    enable_registry::EnableRegistry,
+    error::TraceError,
+    provider::{
+        is_system_provider, query_provider_sessions, Provider, ProviderBuilder, ProviderSessionInfo, TraceLevel,
+        SYSTEM_TRACE_CONTROL_GUID,
+    },
+    watchdog::{self, Clock, Lease, LeaseKeeper, LeaseStore, ReapOutcome, SystemClock, LEASE_SESSION_PREFIX},
+};
 
 const TRACE_NAME_MAX_LEN: usize = 200;
+/// Name of the session receiving the classic kernel events, see [`TraceSessionBuilder::kernel`].
+pub const KERNEL_LOGGER_NAME: &str = "NT Kernel Logger";
+/// Buffer size used for sessions buffering in memory, in kilobytes.
+const IN_MEMORY_BUFFER_SIZE: u32 = 64;
 const LOG_FILE_NAME_MAX_LEN: usize = 1024;
+/// Largest buffer size ETW accepts, in kilobytes.
+const MAX_BUFFER_SIZE_KB: u32 = 1024;
 
 bitflags::bitflags! {
     #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -149,6 +169,18 @@ pub enum ClockResolution {
     CpuCycleCounter = 3,
 }
 
+impl ClockResolution {
+    /// Convert the `Wnode.ClientContext` value of the session properties.
+    pub fn from_client_context(client_context: u32) -> Option<ClockResolution> {
+        match client_context {
+            1 => Some(ClockResolution::QueryPerformanceCounter),
+            2 => Some(ClockResolution::SystemTime),
+            3 => Some(ClockResolution::CpuCycleCounter),
+            _ => None,
+        }
+    }
+}
+
 const DEFAULT_BUFFER_SIZE_KB: u32 = 32;
 const DEFAULT_LOG_FILE_MODE: LogFileMode =
     LogFileMode::REAL_TIME_MODE.union(LogFileMode::NO_PER_PROCESSOR_BUFFERING);
@@ -185,6 +217,7 @@ impl fmt::Debug for EventTracePropertiesInner {
             .field("data.MinimumBuffers", &self.data.MinimumBuffers)
             .field("data.MaximumBuffers", &self.data.MaximumBuffers)
             .field("data.FlushTimer", &self.data.FlushTimer)
+            .field("data.AgeLimit", &self.data.AgeLimit)
             .field("data.LoggerNameOffset", &self.data.LoggerNameOffset)
             .field("data.LogFileNameOffset", &self.data.LogFileNameOffset)
             .field("data.EnableFlags", &self.data.EnableFlags)
@@ -221,27 +254,97 @@ impl EventTraceProperties {
         self.0.as_mut() as *mut _ as *mut EVENT_TRACE_PROPERTIES
     }
 
+    #[deprecated(note = "panics if the name is too long, use `try_set_log_file_name`")]
     pub fn set_log_file_name(&mut self, name: &OsStr) {
-        let name = name.encode_wide().chain(iter::once(0)).collect::<Vec<_>>();
+        self.try_set_log_file_name(name).unwrap()
+    }
+
+    /// Fails if `name` is longer than 1024 UTF-16 code units.
+    pub fn try_set_log_file_name(&mut self, name: &OsStr) -> Result<(), TraceError> {
+        let name = encode_name("Log file name", name, LOG_FILE_NAME_MAX_LEN)?;
         self.0.log_file_name[0..name.len()].copy_from_slice(&name);
         self.0.data.LogFileNameOffset = u32::try_from(memoffset::offset_of!(
             EventTracePropertiesInner,
             log_file_name
         ))
         .unwrap();
+        Ok(())
     }
 
+    #[deprecated(note = "panics if the name is too long, use `try_set_logger_name`")]
     pub fn set_logger_name(&mut self, name: &OsStr) {
-        let name = name.encode_wide().chain(iter::once(0)).collect::<Vec<_>>();
+        self.try_set_logger_name(name).unwrap()
+    }
+
+    /// Fails if `name` is longer than 200 UTF-16 code units.
+    pub fn try_set_logger_name(&mut self, name: &OsStr) -> Result<(), TraceError> {
+        let name = encode_name("Session name", name, TRACE_NAME_MAX_LEN)?;
         self.0.logger_name[0..name.len()].copy_from_slice(&name);
         self.0.data.LoggerNameOffset = u32::try_from(memoffset::offset_of!(
             EventTracePropertiesInner,
             logger_name
         ))
         .unwrap();
+        Ok(())
     }
 }
 
+/// Encode `name` null terminated, if it has at most `max_len` UTF-16 code units.
+fn encode_name(kind: &str, name: &OsStr, max_len: usize) -> Result<Vec<u16>, TraceError> {
+    let name = name.encode_wide().chain(iter::once(0)).collect::<Vec<_>>();
+    if name.len() > max_len + 1 {
+        return Err(TraceError::Configuration(format!(
+            "{} is {} characters long, at most {} are allowed",
+            kind,
+            name.len() - 1,
+            max_len
+        )));
+    }
+    if name[..name.len() - 1].contains(&0) {
+        return Err(TraceError::Configuration(format!("{} contains a null character", kind)));
+    }
+    Ok(name)
+}
+
+fn check_buffer_size(size: u32) -> Result<u32, TraceError> {
+    if (1..=MAX_BUFFER_SIZE_KB).contains(&size) {
+        Ok(size)
+    } else {
+        Err(TraceError::Configuration(format!(
+            "Buffer size of {} KB is out of range, it must be between 1 and {} KB",
+            size, MAX_BUFFER_SIZE_KB
+        )))
+    }
+}
+
+fn flush_timer_seconds(period: Duration) -> Result<u32, TraceError> {
+    match u32::try_from(period.as_secs()) {
+        Ok(seconds) if seconds >= 1 => Ok(seconds),
+        _ => Err(TraceError::Configuration(format!(
+            "Flush timer of {:?} is out of range, it must be between 1 and {} seconds",
+            period,
+            u32::MAX
+        ))),
+    }
+}
+
+fn age_limit_minutes(age_limit: Duration) -> Result<i32, TraceError> {
+    match i32::try_from(age_limit.as_secs() / 60) {
+        Ok(minutes) if minutes >= 1 => Ok(minutes),
+        _ => Err(TraceError::Configuration(format!(
+            "Age limit of {:?} is out of range, it must be between 1 and {} minutes",
+            age_limit,
+            i32::MAX
+        ))),
+    }
+}
+
+/// Builds the properties of a session.
+///
+/// Setters whose argument can be invalid have a `try_` variant that rejects it with
+/// [`TraceError::Configuration`]. The plain setters either clamp the argument, as
+/// documented on them, or are deprecated because they passed invalid arguments to ETW.
+/// Constraints between several settings are checked by [`TraceSessionBuilder::start`].
 #[derive(Debug, Default)]
 pub struct EventTracePropertiesBuilder(EventTraceProperties);
 
@@ -260,11 +363,18 @@ impl EventTracePropertiesBuilder {
         EventTracePropertiesBuilder(event_trace_properties)
     }
 
+    #[deprecated(note = "doesn't check the size, use `try_buffer_size`")]
     pub fn buffer_size(mut self, size: u32) -> EventTracePropertiesBuilder {
         self.0 .0.data.BufferSize = size;
         self
     }
 
+    /// Set the size of each buffer in kilobytes, between 1 and 1024.
+    pub fn try_buffer_size(mut self, size: u32) -> Result<EventTracePropertiesBuilder, TraceError> {
+        self.0 .0.data.BufferSize = check_buffer_size(size)?;
+        Ok(self)
+    }
+
     pub fn minimum_buffers(mut self, num: u32) -> EventTracePropertiesBuilder {
         self.0 .0.data.MinimumBuffers = num;
         self
@@ -275,12 +385,43 @@ impl EventTracePropertiesBuilder {
         self
     }
 
+    /// Set how often ETW delivers buffers that aren't full.
+    ///
+    /// ETW stores this value in seconds, so the period is rounded down to whole seconds
+    /// and clamped to at least one second. Use [`EventTracePropertiesBuilder::try_flush_timer`]
+    /// to reject periods below one second instead.
     pub fn flush_timer(mut self, period: Duration) -> EventTracePropertiesBuilder {
         self.0 .0.data.FlushTimer =
             u32::try_from(period.as_secs().clamp(1, u64::from(u32::MAX))).unwrap();
         self
     }
 
+    /// Like [`EventTracePropertiesBuilder::flush_timer`], but fails for periods that
+    /// would be clamped.
+    pub fn try_flush_timer(mut self, period: Duration) -> Result<EventTracePropertiesBuilder, TraceError> {
+        self.0 .0.data.FlushTimer = flush_timer_seconds(period)?;
+        Ok(self)
+    }
+
+    /// Set how long unused buffers are kept before ETW frees them.
+    ///
+    /// ETW stores this value in minutes, so the duration is rounded down to whole minutes
+    /// (with a minimum of one minute). Larger values keep aged buffers around longer, which
+    /// trades memory for fewer reallocations in long running circular or file sessions.
+    /// Recent Windows versions ignore this setting.
+    pub fn age_limit(mut self, age_limit: Duration) -> EventTracePropertiesBuilder {
+        self.0 .0.data.AgeLimit =
+            i32::try_from((age_limit.as_secs() / 60).clamp(1, i32::MAX as u64)).unwrap();
+        self
+    }
+
+    /// Like [`EventTracePropertiesBuilder::age_limit`], but fails for durations below one
+    /// minute instead of clamping them.
+    pub fn try_age_limit(mut self, age_limit: Duration) -> Result<EventTracePropertiesBuilder, TraceError> {
+        self.0 .0.data.AgeLimit = age_limit_minutes(age_limit)?;
+        Ok(self)
+    }
+
     pub fn log_file_mode(mut self, log_file_mode: LogFileMode) -> EventTracePropertiesBuilder {
         self.0 .0.data.LogFileMode = log_file_mode.bits();
         self
@@ -299,14 +440,24 @@ impl EventTracePropertiesBuilder {
         self
     }
 
-    pub fn log_file_name(mut self, name: &OsStr) -> EventTracePropertiesBuilder {
-        self.0.set_log_file_name(name);
-        self
+    #[deprecated(note = "panics if the name is too long, use `try_log_file_name`")]
+    pub fn log_file_name(self, name: &OsStr) -> EventTracePropertiesBuilder {
+        self.try_log_file_name(name).unwrap()
     }
 
-    pub fn logger_name(mut self, name: &OsStr) -> EventTracePropertiesBuilder {
-        self.0.set_logger_name(name);
-        self
+    pub fn try_log_file_name(mut self, name: &OsStr) -> Result<EventTracePropertiesBuilder, TraceError> {
+        self.0.try_set_log_file_name(name)?;
+        Ok(self)
+    }
+
+    #[deprecated(note = "panics if the name is too long, use `try_logger_name`")]
+    pub fn logger_name(self, name: &OsStr) -> EventTracePropertiesBuilder {
+        self.try_logger_name(name).unwrap()
+    }
+
+    pub fn try_logger_name(mut self, name: &OsStr) -> Result<EventTracePropertiesBuilder, TraceError> {
+        self.0.try_set_logger_name(name)?;
+        Ok(self)
     }
 
     pub fn guid(mut self, guid: windows::core::GUID) -> EventTracePropertiesBuilder {
@@ -322,6 +473,18 @@ impl EventTracePropertiesBuilder {
     pub fn build(self) -> EventTraceProperties {
         self.0
     }
+
+    /// Check the constraints between settings that the setters can't check on their own.
+    fn validate(&self) -> Result<(), TraceError> {
+        let data = &self.0 .0.data;
+        if data.MaximumBuffers != 0 && data.MinimumBuffers > data.MaximumBuffers {
+            return Err(TraceError::Configuration(format!(
+                "Minimum of {} buffers is larger than the maximum of {} buffers",
+                data.MinimumBuffers, data.MaximumBuffers
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Default)]
@@ -330,9 +493,15 @@ pub struct TraceSessionBuilder {
     event_trace_properties: EventTracePropertiesBuilder,
     close_on_drop: bool,
     close_previous: bool,
+    auto_stop_after: Option<Duration>,
+    log_file: Option<PathBuf>,
+    /// Whether the log file mode was chosen by the user, rather than being the default.
+    log_file_mode_set: bool,
 }
 
 impl TraceSessionBuilder {
+    /// A session named `name`. Names longer than 200 characters make
+    /// [`TraceSessionBuilder::start`] fail, see [`TraceSessionBuilder::try_new`].
     pub fn new<S: AsRef<OsStr>>(name: S) -> TraceSessionBuilder {
         let name = name.as_ref().to_os_string();
         TraceSessionBuilder {
@@ -343,11 +512,53 @@ impl TraceSessionBuilder {
         }
     }
 
+    /// The NT Kernel Logger, receiving the classic kernel events selected by `flags`.
+    ///
+    /// There is only one NT Kernel Logger per system. Its events are MOF events, which
+    /// describe the event type by opcode rather than event id, see
+    /// [`crate::schema::cache::SchemaCache::get_from_event_record`].
+    pub fn kernel(flags: EnableFlags) -> TraceSessionBuilder {
+        let mut builder = Self::new(KERNEL_LOGGER_NAME);
+        builder.event_trace_properties = builder
+            .event_trace_properties
+            .guid(SYSTEM_TRACE_CONTROL_GUID)
+            .enable_flags(flags);
+        builder.event_trace_properties.0 .0.data.LogFileMode |= LogFileMode::SYSTEM_LOGGER_MODE.bits();
+        builder
+    }
+
+    /// Make the session a system logger, which receives the events of the Windows 11
+    /// system providers such as [`crate::provider::SYSTEM_PROCESS_PROVIDER_GUID`].
+    ///
+    /// Only system providers can be enabled on the session, see
+    /// [`crate::provider::ProviderBuilder::system_provider`].
+    pub fn system_logger(mut self) -> TraceSessionBuilder {
+        self.event_trace_properties.0 .0.data.LogFileMode |= LogFileMode::SYSTEM_LOGGER_MODE.bits();
+        self
+    }
+
+    /// Like [`TraceSessionBuilder::new`], but fails if `name` is empty or too long.
+    pub fn try_new<S: AsRef<OsStr>>(name: S) -> Result<TraceSessionBuilder, TraceError> {
+        if name.as_ref().is_empty() {
+            return Err(TraceError::Configuration("Session name is empty".to_string()));
+        }
+        encode_name("Session name", name.as_ref(), TRACE_NAME_MAX_LEN)?;
+        Ok(Self::new(name))
+    }
+
+    #[deprecated(note = "doesn't check the size, use `try_buffer_size`")]
+    #[allow(deprecated)]
     pub fn buffer_size(mut self, size: u32) -> TraceSessionBuilder {
         self.event_trace_properties = self.event_trace_properties.buffer_size(size);
         self
     }
 
+    /// See [`EventTracePropertiesBuilder::try_buffer_size`].
+    pub fn try_buffer_size(mut self, size: u32) -> Result<TraceSessionBuilder, TraceError> {
+        self.event_trace_properties = self.event_trace_properties.try_buffer_size(size)?;
+        Ok(self)
+    }
+
     pub fn close_previous(mut self) -> TraceSessionBuilder {
         self.close_previous = true;
         self
@@ -355,9 +566,26 @@ impl TraceSessionBuilder {
 
     pub fn log_file_mode(mut self, log_file_mode: LogFileMode) -> TraceSessionBuilder {
         self.event_trace_properties = self.event_trace_properties.log_file_mode(log_file_mode);
+        self.log_file_mode_set = true;
         self
     }
 
+    /// Write the events of the session to the ETL file at `path`.
+    ///
+    /// Unless a log file mode is set, the session writes the file sequentially instead of
+    /// delivering events in real time. [`TraceSessionBuilder::start`] fails if the log file
+    /// mode doesn't write a file, or uses [`LogFileMode::FILE_MODE_NEWFILE`] and `path`
+    /// doesn't contain a `%d` for the file number. Fails if the absolute path is longer
+    /// than 1024 characters.
+    pub fn log_file<P: AsRef<Path>>(mut self, path: P) -> Result<TraceSessionBuilder, TraceError> {
+        let path = std::path::absolute(path.as_ref()).map_err(|err| {
+            TraceError::Configuration(format!("Log file {:?} has no absolute path: {}", path.as_ref(), err))
+        })?;
+        encode_name("Log file name", path.as_os_str(), LOG_FILE_NAME_MAX_LEN)?;
+        self.log_file = Some(path);
+        Ok(self)
+    }
+
     pub fn wnode_flags(mut self, wnode_flags: WnodeFlag) -> TraceSessionBuilder {
         self.event_trace_properties = self
             .event_trace_properties
@@ -382,27 +610,164 @@ impl TraceSessionBuilder {
         self
     }
 
+    /// See [`EventTracePropertiesBuilder::flush_timer`].
     pub fn flush_timer(mut self, period: Duration) -> TraceSessionBuilder {
         self.event_trace_properties = self.event_trace_properties.flush_timer(period);
         self
     }
 
+    /// See [`EventTracePropertiesBuilder::try_flush_timer`].
+    pub fn try_flush_timer(mut self, period: Duration) -> Result<TraceSessionBuilder, TraceError> {
+        self.event_trace_properties = self.event_trace_properties.try_flush_timer(period)?;
+        Ok(self)
+    }
+
+    /// See [`EventTracePropertiesBuilder::age_limit`].
+    pub fn age_limit(mut self, age_limit: Duration) -> TraceSessionBuilder {
+        self.event_trace_properties = self.event_trace_properties.age_limit(age_limit);
+        self
+    }
+
+    /// See [`EventTracePropertiesBuilder::try_age_limit`].
+    pub fn try_age_limit(mut self, age_limit: Duration) -> Result<TraceSessionBuilder, TraceError> {
+        self.event_trace_properties = self.event_trace_properties.try_age_limit(age_limit)?;
+        Ok(self)
+    }
+
+    /// Keep the last `megabytes` of events in memory instead of delivering them.
+    ///
+    /// The session runs in [`LogFileMode::BUFFERING_MODE`], overwriting the oldest
+    /// buffers when full, like a flight recorder. Write the buffered events to an ETL
+    /// file with [`TraceSession::flush_buffered_to`] when something interesting happened.
+    /// Sizes below 128 KB are rounded up to the two buffers ETW needs at least.
+    pub fn in_memory(mut self, megabytes: u32) -> TraceSessionBuilder {
+        let buffers = in_memory_buffer_count(megabytes);
+        let mut event_trace_properties = self.event_trace_properties;
+        event_trace_properties.0 .0.data.BufferSize = IN_MEMORY_BUFFER_SIZE;
+        self.event_trace_properties = event_trace_properties
+            .log_file_mode(LogFileMode::BUFFERING_MODE)
+            .minimum_buffers(buffers)
+            .maximum_buffers(buffers);
+        self.log_file_mode_set = true;
+        self
+    }
+
     pub fn no_close_on_drop(mut self) -> TraceSessionBuilder {
         self.close_on_drop = false;
         self
     }
 
-    pub fn start(self) -> Result<TraceSession, TraceError> {
+    /// Stop the session automatically if this process dies without dropping it.
+    ///
+    /// The session holds a lease that a background thread renews while the
+    /// [`TraceSession`] is alive. Once the lease is older than `duration`, the next process
+    /// that starts a session through this crate stops the orphaned session (see
+    /// [`crate::watchdog`]). The session name must start with [`LEASE_SESSION_PREFIX`].
+    ///
+    /// This complements [`LogFileMode::STOP_ON_HYBRID_SHUTDOWN`], which only covers system
+    /// shutdown, not a crashed collector. Teardown is best-effort: nothing is stopped
+    /// until some process using this crate starts a session after the deadline.
+    /// With [`TraceSessionBuilder::no_close_on_drop`], dropping the session stops renewing
+    /// the lease, so the session is reaped after `duration` as well.
+    pub fn auto_stop_after(mut self, duration: Duration) -> TraceSessionBuilder {
+        self.auto_stop_after = Some(duration);
+        self
+    }
+
+    /// Like [`TraceSessionBuilder::auto_stop_after`], but checks the session name and
+    /// the duration right away instead of in [`TraceSessionBuilder::start`].
+    pub fn try_auto_stop_after(mut self, duration: Duration) -> Result<TraceSessionBuilder, TraceError> {
+        self.lease_name()?;
+        if duration.is_zero() {
+            return Err(TraceError::Configuration("Auto stop duration is zero".to_string()));
+        }
+        self.auto_stop_after = Some(duration);
+        Ok(self)
+    }
+
+    /// The session name as used for its lease.
+    fn lease_name(&self) -> Result<String, TraceError> {
+        self.name
+            .to_str()
+            .filter(|name| name.starts_with(LEASE_SESSION_PREFIX))
+            .map(str::to_string)
+            .ok_or_else(|| {
+                TraceError::Configuration(format!(
+                    "Session {:?} uses auto_stop_after, its name must start with {:?}",
+                    self.name, LEASE_SESSION_PREFIX
+                ))
+            })
+    }
+
+    /// Default the log file mode of sessions with a log file, and check that the mode
+    /// matches whether there is one.
+    fn resolve_log_file_mode(&mut self) -> Result<(), TraceError> {
+        let file_modes = LogFileMode::FILE_MODE_SEQUENTIAL
+            | LogFileMode::FILE_MODE_CIRCULAR
+            | LogFileMode::FILE_MODE_APPEND
+            | LogFileMode::FILE_MODE_NEWFILE;
+        if self.log_file.is_some() && !self.log_file_mode_set {
+            let mode = DEFAULT_LOG_FILE_MODE.difference(LogFileMode::REAL_TIME_MODE) | LogFileMode::FILE_MODE_SEQUENTIAL;
+            self.event_trace_properties.0 .0.data.LogFileMode = mode.bits();
+        }
+        let mode = LogFileMode::from_bits_retain(self.event_trace_properties.0 .0.data.LogFileMode);
+        match &self.log_file {
+            Some(path) if !mode.intersects(file_modes | LogFileMode::BUFFERING_MODE) => {
+                Err(TraceError::Configuration(format!(
+                    "Session writes to {:?}, but its log file mode {:?} doesn't write a file",
+                    path, mode
+                )))
+            }
+            Some(path) if mode.contains(LogFileMode::FILE_MODE_NEWFILE) && !path.to_string_lossy().contains("%d") => {
+                Err(TraceError::Configuration(format!(
+                    "Session uses FILE_MODE_NEWFILE, but its log file {:?} has no %d for the file number",
+                    path
+                )))
+            }
+            None if mode.intersects(file_modes) => Err(TraceError::Configuration(format!(
+                "Log file mode {:?} writes a file, but the session has no log file",
+                mode
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn start(mut self) -> Result<TraceSession, TraceError> {
+        self.resolve_log_file_mode()?;
+        self.event_trace_properties.validate()?;
+        watchdog::reap_expired_sessions_once();
+        let Some(duration) = self.auto_stop_after else {
+            return self.start_trace();
+        };
+        let name = self.lease_name()?;
+        let mut session = self.start_trace()?;
+        let lease = Lease::new(&name, session.guid(), SystemClock.now(), duration);
+        match LeaseKeeper::start(LeaseStore::default(), lease, duration) {
+            Ok(keeper) => {
+                session.lease = Some(keeper);
+                Ok(session)
+            }
+            Err(err) => {
+                // Don't leave a session behind that nobody would reap
+                session.close_on_drop = true;
+                Err(TraceError::Lease(err))
+            }
+        }
+    }
+
+    fn start_trace(self) -> Result<TraceSession, TraceError> {
         log::trace!("TraceSessionBuilder::start: {:?}", self);
         let mut handle: CONTROLTRACE_HANDLE = CONTROLTRACE_HANDLE::default();
         let mut properties = self.event_trace_properties.build();
-        properties.set_logger_name(&self.name);
+        properties.try_set_logger_name(&self.name)?;
+        if let Some(path) = &self.log_file {
+            properties.try_set_log_file_name(path.as_os_str())?;
+        }
         let name = self
             .name
             .encode_wide()
             .chain(iter::once(0))
             .collect::<Vec<_>>();
-        //TODO: log file
         unsafe {
             match StartTraceW(
                 &mut handle,
@@ -418,6 +783,10 @@ impl TraceSessionBuilder {
                         name: self.name.clone(),
                         properties,
                         close_on_drop: self.close_on_drop,
+                        shared_providers: Vec::new(),
+                        enabled_providers: HashMap::new(),
+                        lease: None,
+                        log_file: self.log_file.clone(),
                     })
                 }
                 Err(err) if err.code() == HRESULT::from(ERROR_ALREADY_EXISTS) => {
@@ -451,6 +820,10 @@ impl TraceSessionBuilder {
                                         name: self.name.clone(),
                                         properties,
                                         close_on_drop: self.close_on_drop,
+                                        shared_providers: Vec::new(),
+                                        enabled_providers: HashMap::new(),
+                                        lease: None,
+                                        log_file: self.log_file.clone(),
                                     })
                                 }
                                 Err(err) => {
@@ -479,6 +852,11 @@ pub struct TraceSession {
     name: OsString,
     properties: EventTraceProperties,
     close_on_drop: bool,
+    shared_providers: Vec<Provider>,
+    /// Providers as last enabled on this session, to repeat their keywords and level.
+    enabled_providers: HashMap<GUID, Provider>,
+    lease: Option<LeaseKeeper>,
+    log_file: Option<PathBuf>,
 }
 
 impl fmt::Debug for TraceSession {
@@ -488,6 +866,10 @@ impl fmt::Debug for TraceSession {
             .field("name", &self.name)
             .field("properties", &self.properties)
             .field("close_on_drop", &self.close_on_drop)
+            .field("shared_providers", &self.shared_providers)
+            .field("enabled_providers", &self.enabled_providers)
+            .field("lease", &self.lease)
+            .field("log_file", &self.log_file)
             .finish()
     }
 }
@@ -499,18 +881,370 @@ impl TraceSession {
             name: name.into(),
             properties: EventTraceProperties::default(),
             close_on_drop: false,
+            shared_providers: Vec::new(),
+            enabled_providers: HashMap::new(),
+            lease: None,
+            log_file: None,
         }
     }
 
     pub fn name(&self) -> &OsStr {
         &self.name
     }
+
+    /// The ETL file the session writes to, if it was started with one.
+    pub fn log_file(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+
+    /// The session GUID, which identifies this instance of the session.
+    pub fn guid(&self) -> GUID {
+        self.properties.0.data.Wnode.Guid
+    }
+
+    /// The clock the session was configured with.
+    ///
+    /// Returns None for sessions opened with [`TraceSession::open_existing`], whose
+    /// properties aren't known.
+    pub fn clock_resolution(&self) -> Option<ClockResolution> {
+        ClockResolution::from_client_context(self.properties.0.data.Wnode.ClientContext)
+    }
+
+    /// Size of each buffer in kilobytes, as allocated by ETW.
+    ///
+    /// `StartTraceW` may adjust the requested buffer size and counts; these accessors
+    /// return the values it reported back. They return None for sessions opened with
+    /// [`TraceSession::open_existing`].
+    pub fn actual_buffer_size(&self) -> Option<u32> {
+        self.started_properties().map(|data| data.BufferSize)
+    }
+
+    /// Minimum number of buffers, as allocated by ETW.
+    pub fn minimum_buffers(&self) -> Option<u32> {
+        self.started_properties().map(|data| data.MinimumBuffers)
+    }
+
+    /// Maximum number of buffers, as allocated by ETW.
+    pub fn maximum_buffers(&self) -> Option<u32> {
+        self.started_properties().map(|data| data.MaximumBuffers)
+    }
+
+    /// Deliver the session's partially filled buffers to consumers now.
+    ///
+    /// Also works for sessions opened with [`TraceSession::open_existing`].
+    pub fn flush(&mut self) -> Result<(), TraceError> {
+        let mut properties = EventTraceProperties::default();
+        self.control(&mut properties, EVENT_TRACE_CONTROL_FLUSH)
+    }
+
+    /// Query the counters of the running session.
+    ///
+    /// Also works for sessions opened with [`TraceSession::open_existing`].
+    pub fn query(&self) -> Result<SessionStatistics, TraceError> {
+        let mut properties = EventTraceProperties::default();
+        self.control(&mut properties, EVENT_TRACE_CONTROL_QUERY)?;
+        Ok(SessionStatistics::from(&properties.0.data))
+    }
+
+    /// How `provider` is enabled on this session, or None if it isn't.
+    ///
+    /// Also works for sessions opened with [`TraceSession::open_existing`].
+    pub fn provider_session_info(&self, provider: &GUID) -> Result<Option<ProviderSessionInfo>, TraceError> {
+        let mut properties = EventTraceProperties::default();
+        self.control(&mut properties, EVENT_TRACE_CONTROL_QUERY)?;
+        // The query reports the session handle, whose low 16 bits are the logger id
+        let logger_id = unsafe { properties.0.data.Wnode.Anonymous1.HistoricalContext } as u16;
+        Ok(query_provider_sessions(provider)?
+            .into_iter()
+            .find(|session| session.logger_id == logger_id))
+    }
+
+    /// Returns true if `provider` is enabled on this session.
+    pub fn is_provider_enabled(&self, provider: &GUID) -> Result<bool, TraceError> {
+        Ok(self.provider_session_info(provider)?.is_some())
+    }
+
+    /// Change the settings of the running session to those of `properties`.
+    ///
+    /// ETW only updates some settings of a running session, e.g. the maximum number of
+    /// buffers, the flush timer, the enable flags of kernel sessions and the log file.
+    /// Settings it can't update are ignored. The session name of `properties` is ignored.
+    pub fn update(&mut self, properties: &EventTracePropertiesBuilder) -> Result<(), TraceError> {
+        properties.validate()?;
+        let source = &properties.0 .0;
+        let mut update = EventTraceProperties(Box::new(EventTracePropertiesInner {
+            data: source.data,
+            logger_name: [0u16; TRACE_NAME_MAX_LEN + 1],
+            log_file_name: source.log_file_name,
+        }));
+        self.control(&mut update, EVENT_TRACE_CONTROL_UPDATE)?;
+        if self.started_properties().is_some() {
+            // Keep the accessors in sync with what ETW reported back
+            self.properties.0.data = update.0.data;
+            self.properties.0.log_file_name = update.0.log_file_name;
+        }
+        Ok(())
+    }
+
+    /// Call `ControlTraceW` on this session, addressing it by handle and name so that
+    /// sessions opened by name work as well.
+    fn control(&self, properties: &mut EventTraceProperties, control_code: EVENT_TRACE_CONTROL) -> Result<(), TraceError> {
+        properties.try_set_logger_name(&self.name)?;
+        let name = self.name.encode_wide().chain(iter::once(0)).collect::<Vec<_>>();
+        unsafe {
+            ControlTraceW(self.handle, PCWSTR::from_raw(name.as_ptr()), properties.as_mut_ptr(), control_code)
+                .ok()
+                .map_err(|err| {
+                    log::warn!("ControlTraceW(_, {:?}, _, {:?}) returned error: {:?}", self.name, control_code, err);
+                    err.into()
+                })
+        }
+    }
+
+    /// Capture call stacks for the classic kernel `events`, replacing the events set
+    /// before. An empty slice turns stack walking off.
+    ///
+    /// Only works for the NT Kernel Logger and system logger sessions, see
+    /// [`TraceSessionBuilder::kernel`] and [`TraceSessionBuilder::system_logger`]. The
+    /// stacks arrive as extended data of the events, see
+    /// [`crate::values::event::ExtendedDataItem::StackTrace64`].
+    pub fn enable_stack_walk(&mut self, events: &[StackWalkEvent]) -> Result<(), TraceError> {
+        check_stack_walk(
+            &self.name,
+            self.started_properties().map(|data| data.LogFileMode),
+            events.len(),
+        )?;
+        let event_ids = events
+            .iter()
+            .map(|event| CLASSIC_EVENT_ID {
+                EventGuid: event.event_guid,
+                Type: event.opcode,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            TraceSetInformation(
+                self.handle,
+                TraceStackTracingInfo,
+                event_ids.as_ptr() as *const c_void,
+                u32::try_from(event_ids.len() * mem::size_of::<CLASSIC_EVENT_ID>()).unwrap(),
+            )
+            .ok()
+            .map_err(|err| {
+                log::warn!("TraceSetInformation({:?}, TraceStackTracingInfo) returned error: {:?}", self.name, err);
+                err.into()
+            })
+        }
+    }
+
+    /// A handle to flush this session from another thread, e.g. on a timer.
+    pub(crate) fn flusher(&self) -> SessionFlusher {
+        SessionFlusher {
+            name: self.name.clone(),
+        }
+    }
+
+    /// Write the events buffered by a session in [`LogFileMode::BUFFERING_MODE`] to
+    /// an ETL file at `path`. The session keeps buffering afterwards.
+    pub fn flush_buffered_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), TraceError> {
+        if let Some(data) = self.started_properties()
+            && !LogFileMode::from_bits_retain(data.LogFileMode).contains(LogFileMode::BUFFERING_MODE)
+        {
+            return Err(TraceError::Configuration(format!(
+                "Session {:?} doesn't buffer events in memory",
+                self.name
+            )));
+        }
+        flush_session_to(self.handle, &self.name, path.as_ref())
+    }
+
+    /// Call [`TraceSession::flush_buffered_to`] whenever Ctrl+Break is pressed in the
+    /// console of this process, overwriting `path` each time.
+    ///
+    /// Ctrl+Break doesn't terminate the process anymore once a session is registered.
+    /// The registration ends when the session is stopped.
+    pub fn flush_on_ctrl_break<P: AsRef<Path>>(&self, path: P) -> Result<(), TraceError> {
+        static INSTALL: Once = Once::new();
+        let mut result = Ok(());
+        INSTALL.call_once(|| unsafe {
+            result = SetConsoleCtrlHandler(Some(ctrl_break_handler), true);
+        });
+        result?;
+        let mut targets = CTRL_BREAK_TARGETS.lock().unwrap_or_else(|err| err.into_inner());
+        targets.retain(|(name, _)| name != &self.name);
+        targets.push((self.name.clone(), path.as_ref().to_path_buf()));
+        Ok(())
+    }
+
+    /// Stop the session now instead of on drop, reporting failures.
+    pub(crate) fn stop(&mut self) -> Result<(), TraceError> {
+        CTRL_BREAK_TARGETS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain(|(name, _)| name != &self.name);
+        self.release_shared_providers();
+        EnableRegistry::global().forget_session(&self.name);
+        self.close_on_drop = false;
+        let result = unsafe {
+            ControlTraceW(
+                self.handle,
+                None,
+                self.properties.as_mut_ptr(),
+                EVENT_TRACE_CONTROL_STOP,
+            )
+            .ok()
+        };
+        if let Some(lease) = self.lease.as_mut() {
+            lease.release(true);
+        }
+        result.map_err(Into::into)
+    }
+
+    fn release_shared_providers(&mut self) {
+        for provider in mem::take(&mut self.shared_providers) {
+            let name = self.name.clone();
+            if let Err(err) = EnableRegistry::global().release(&name, provider.id(), || {
+                self.enable_provider(&provider, false, EnableProviderTimeout::Asynchronous, None)
+            }) {
+                log::warn!("Failed to release shared provider {:?}: {:?}", provider.id(), err);
+            }
+        }
+    }
+
+    fn started_properties(&self) -> Option<&EVENT_TRACE_PROPERTIES_V2> {
+        let data = &self.properties.0.data;
+        (data.BufferSize != 0).then_some(data)
+    }
 }
 
-#[derive(Debug)]
+/// Most events `TraceSetInformation` accepts for stack walking.
+const MAX_STACK_WALK_EVENTS: usize = 256;
+
+/// A classic kernel event, identified by its event class GUID and opcode, e.g.
+/// [`crate::provider::THREAD_GUID`] and 36 for context switches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackWalkEvent {
+    pub event_guid: GUID,
+    pub opcode: u8,
+}
+
+impl StackWalkEvent {
+    pub fn new(event_guid: GUID, opcode: u8) -> Self {
+        Self { event_guid, opcode }
+    }
+}
+
+/// Stack walking is only available for kernel events, so the session has to receive them.
+///
+/// `log_file_mode` is None for sessions opened by name, which are only accepted if
+/// they are the NT Kernel Logger.
+fn check_stack_walk(name: &OsStr, log_file_mode: Option<u32>, event_count: usize) -> Result<(), TraceError> {
+    let kernel_session = match log_file_mode {
+        Some(mode) => {
+            name == KERNEL_LOGGER_NAME
+                || LogFileMode::from_bits_retain(mode).contains(LogFileMode::SYSTEM_LOGGER_MODE)
+        }
+        None => name == KERNEL_LOGGER_NAME,
+    };
+    if !kernel_session {
+        return Err(TraceError::Configuration(format!(
+            "Session {:?} is neither the NT Kernel Logger nor a system logger, it can't walk stacks",
+            name
+        )));
+    }
+    if event_count > MAX_STACK_WALK_EVENTS {
+        return Err(TraceError::Configuration(format!(
+            "Tried to walk the stacks of {} events, at most {} are allowed",
+            event_count, MAX_STACK_WALK_EVENTS
+        )));
+    }
+    Ok(())
+}
+
+/// A system logger session only receives events of system providers.
+fn check_system_logger_provider(log_file_mode: u32, provider: &Provider) -> Result<(), TraceError> {
+    if LogFileMode::from_bits_retain(log_file_mode).contains(LogFileMode::SYSTEM_LOGGER_MODE)
+        && !is_system_provider(provider.id())
+    {
+        return Err(TraceError::Configuration(format!(
+            "Provider {:?} is not a system provider and can't be enabled on a system logger session",
+            provider.id()
+        )));
+    }
+    Ok(())
+}
+
+/// Counters of a running session, see [`TraceSession::query`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStatistics {
+    /// Number of buffers allocated for the session.
+    pub number_of_buffers: u32,
+    /// Number of allocated buffers that are unused.
+    pub free_buffers: u32,
+    /// Number of events that couldn't be written, e.g. because all buffers were full.
+    pub events_lost: u32,
+    pub buffers_written: u32,
+    /// Number of buffers that couldn't be written to the log file.
+    pub log_buffers_lost: u32,
+    /// Number of buffers that couldn't be delivered to a real-time consumer.
+    pub real_time_buffers_lost: u32,
+    /// Id of the thread writing the session's buffers.
+    pub logger_thread_id: u32,
+}
+
+impl From<&EVENT_TRACE_PROPERTIES_V2> for SessionStatistics {
+    fn from(data: &EVENT_TRACE_PROPERTIES_V2) -> Self {
+        Self {
+            number_of_buffers: data.NumberOfBuffers,
+            free_buffers: data.FreeBuffers,
+            events_lost: data.EventsLost,
+            buffers_written: data.BuffersWritten,
+            log_buffers_lost: data.LogBuffersLost,
+            real_time_buffers_lost: data.RealTimeBuffersLost,
+            // The thread id is returned in a handle sized field
+            logger_thread_id: data.LoggerThreadId.0 as usize as u32,
+        }
+    }
+}
+
+/// Arguments of an `EnableTraceEx2` call that are taken from a [`Provider`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EnableCall {
+    provider: GUID,
+    control_code: u32,
+    level: u8,
+    any: u64,
+    all: u64,
+}
+
+impl EnableCall {
+    fn new(provider: &Provider, control_code: u32) -> Self {
+        Self {
+            provider: *provider.id(),
+            control_code,
+            level: provider.level().into(),
+            any: provider.any(),
+            all: provider.all(),
+        }
+    }
+}
+
+/// How long `EnableTraceEx2` waits for providers to process an enable or disable.
+#[derive(Debug, Clone, Copy)]
 pub enum EnableProviderTimeout {
+    /// Return as soon as the request is queued.
+    ///
+    /// Providers apply the new settings some time later, and there is no way to learn
+    /// when they did: events emitted right after the call returns may still use the
+    /// previous settings.
     Asynchronous,
+    /// Wait until all providers processed the request, or fail with
+    /// [`TraceError::EnableTimeout`] once the duration has elapsed. The request stays
+    /// queued after a timeout, so retrying is safe. Durations are clamped to just below
+    /// [`EnableProviderTimeout::Infinite`].
     Timeout(Duration),
+    /// Wait until all providers processed the request.
     Infinite,
 }
 
@@ -518,12 +1252,29 @@ impl From<EnableProviderTimeout> for u32 {
     fn from(value: EnableProviderTimeout) -> Self {
         match value {
             EnableProviderTimeout::Asynchronous => 0,
-            EnableProviderTimeout::Timeout(duration) => duration.as_millis().try_into().unwrap(),
+            EnableProviderTimeout::Timeout(duration) => duration
+                .as_millis()
+                .try_into()
+                .unwrap_or(INFINITE - 1)
+                .min(INFINITE - 1),
             EnableProviderTimeout::Infinite => INFINITE,
         }
     }
 }
 
+/// Map an `EnableTraceEx2` failure, turning timeouts into [`TraceError::EnableTimeout`].
+fn enable_error(err: windows::core::Error, provider: &GUID, timeout: EnableProviderTimeout) -> TraceError {
+    match timeout {
+        EnableProviderTimeout::Timeout(timeout) if err.code() == HRESULT::from(ERROR_TIMEOUT) => {
+            TraceError::EnableTimeout {
+                provider: *provider,
+                timeout,
+            }
+        }
+        _ => err.into(),
+    }
+}
+
 pub struct EventFilterEventId {
     data: Vec<u8>,
 }
@@ -603,33 +1354,287 @@ impl AsRef<EVENT_FILTER_EVENT_ID> for EventFilterEventId {
     }
 }
 
+/// Semicolon separated, null terminated list of executable names
+pub struct EventFilterExecutableNames {
+    data: Vec<u16>,
+}
+
+impl fmt::Debug for EventFilterExecutableNames {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EventFilterExecutableNames")
+            .field(&OsString::from_wide(&self.data[..self.data.len() - 1]))
+            .finish()
+    }
+}
+
+impl EventFilterExecutableNames {
+    pub fn new(names: &[&OsStr]) -> Result<EventFilterExecutableNames, TraceError> {
+        if names.is_empty() {
+            return Err(TraceError::Configuration(
+                "No executable names given for executable name filter".to_string(),
+            ));
+        }
+        let mut data = Vec::new();
+        for (idx, name) in names.iter().enumerate() {
+            let encoded = name.encode_wide().collect::<Vec<_>>();
+            if encoded.is_empty() {
+                return Err(TraceError::Configuration("Executable name is empty".to_string()));
+            }
+            if encoded.iter().any(|c| *c == 0 || *c == u16::from(b';')) {
+                return Err(TraceError::Configuration(format!(
+                    "Executable name {:?} contains a null or semicolon character",
+                    name
+                )));
+            }
+            if idx != 0 {
+                data.push(u16::from(b';'));
+            }
+            data.extend(encoded);
+        }
+        data.push(0);
+        let size = data.len() * mem::size_of::<u16>();
+        if size > MAX_EVENT_FILTER_DATA_SIZE as usize {
+            return Err(TraceError::Configuration(format!(
+                "Executable name filter is {} bytes long, at most {} are allowed",
+                size, MAX_EVENT_FILTER_DATA_SIZE
+            )));
+        }
+        Ok(EventFilterExecutableNames { data })
+    }
+
+    pub fn as_ptr(&self) -> *const u16 {
+        self.data.as_ptr()
+    }
+
+    pub fn size(&self) -> u32 {
+        u32::try_from(self.data.len() * mem::size_of::<u16>()).unwrap()
+    }
+}
+
+/// Comparison of a [`PayloadPredicate`], see `PAYLOAD_OPERATOR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadOperator {
+    Equal,
+    NotEqual,
+    LessOrEqual,
+    Greater,
+    Less,
+    GreaterOrEqual,
+    /// The value is two numbers separated by a comma, the bounds are inclusive.
+    Between,
+    NotBetween,
+    /// The field modulo the value is zero.
+    Modulo,
+    Contains,
+    DoesntContain,
+    /// String comparison of the field, after mapping it with its value map.
+    Is,
+    IsNot,
+}
+
+impl PayloadOperator {
+    fn code(self) -> u16 {
+        match self {
+            PayloadOperator::Equal => 0,
+            PayloadOperator::NotEqual => 1,
+            PayloadOperator::LessOrEqual => 2,
+            PayloadOperator::Greater => 3,
+            PayloadOperator::Less => 4,
+            PayloadOperator::GreaterOrEqual => 5,
+            PayloadOperator::Between => 6,
+            PayloadOperator::NotBetween => 7,
+            PayloadOperator::Modulo => 8,
+            PayloadOperator::Contains => 20,
+            PayloadOperator::DoesntContain => 21,
+            PayloadOperator::Is => 30,
+            PayloadOperator::IsNot => 31,
+        }
+    }
+}
+
+/// Compares the top level property `field_name` of an event with `value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadPredicate {
+    pub field_name: String,
+    pub operator: PayloadOperator,
+    pub value: String,
+}
+
+impl PayloadPredicate {
+    pub fn new<N: Into<String>, V: Into<String>>(field_name: N, operator: PayloadOperator, value: V) -> Self {
+        Self {
+            field_name: field_name.into(),
+            operator,
+            value: value.into(),
+        }
+    }
+}
+
+/// Filter on the payload of one event of a manifest provider, built by
+/// `TdhCreatePayloadFilter`. The memory allocated by TDH is freed on drop.
+pub struct PayloadFilter {
+    descriptor: EVENT_FILTER_DESCRIPTOR,
+}
+
+impl fmt::Debug for PayloadFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PayloadFilter").field("size", &self.descriptor.Size).finish()
+    }
+}
+
+impl PayloadFilter {
+    /// Deliver the event `descriptor` of `provider` only if all `predicates` match, or
+    /// any of them if `match_any` is set. Other events of the provider aren't affected.
+    pub fn new(
+        provider: &GUID,
+        descriptor: &EVENT_DESCRIPTOR,
+        match_any: bool,
+        predicates: &[PayloadPredicate],
+    ) -> Result<PayloadFilter, TraceError> {
+        check_payload_predicates(predicates)?;
+        let encode = |value: &str| OsStr::new(value).encode_wide().chain(iter::once(0)).collect::<Vec<_>>();
+        let mut strings = predicates
+            .iter()
+            .map(|predicate| (encode(&predicate.field_name), encode(&predicate.value)))
+            .collect::<Vec<_>>();
+        let predicates = predicates
+            .iter()
+            .zip(strings.iter_mut())
+            .map(|(predicate, (field_name, value))| PAYLOAD_FILTER_PREDICATE {
+                FieldName: PWSTR::from_raw(field_name.as_mut_ptr()),
+                CompareOp: predicate.operator.code(),
+                Value: PWSTR::from_raw(value.as_mut_ptr()),
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            let mut filter = ptr::null_mut();
+            HRESULT::from_win32(TdhCreatePayloadFilter(
+                provider,
+                descriptor,
+                BOOLEAN::from(match_any),
+                &predicates,
+                &mut filter,
+            ))
+            .ok()?;
+            let mut descriptor = EVENT_FILTER_DESCRIPTOR::default();
+            let status = TdhAggregatePayloadFilters(&[filter as *const c_void], None, &mut descriptor);
+            // The aggregated descriptor holds a copy of the filter
+            TdhDeletePayloadFilter(&mut filter);
+            HRESULT::from_win32(status).ok()?;
+            Ok(PayloadFilter { descriptor })
+        }
+    }
+
+    pub fn as_ptr(&self) -> u64 {
+        self.descriptor.Ptr
+    }
+
+    pub fn size(&self) -> u32 {
+        self.descriptor.Size
+    }
+}
+
+impl Drop for PayloadFilter {
+    fn drop(&mut self) {
+        unsafe {
+            TdhCleanupPayloadEventFilterDescriptor(&mut self.descriptor);
+        }
+    }
+}
+
+fn check_payload_predicates(predicates: &[PayloadPredicate]) -> Result<(), TraceError> {
+    if predicates.is_empty() {
+        return Err(TraceError::Configuration("No predicates given for payload filter".to_string()));
+    }
+    if predicates.len() > MAX_PAYLOAD_PREDICATES as usize {
+        return Err(TraceError::Configuration(format!(
+            "Payload filter has {} predicates, at most {} are allowed",
+            predicates.len(),
+            MAX_PAYLOAD_PREDICATES
+        )));
+    }
+    if let Some(predicate) = predicates.iter().find(|predicate| {
+        predicate.field_name.is_empty() || predicate.field_name.contains('\0') || predicate.value.contains('\0')
+    }) {
+        return Err(TraceError::Configuration(format!(
+            "Payload predicate {:?} has an empty field name or contains a null character",
+            predicate
+        )));
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum EventFilter {
     EventId(EventFilterEventId),
+    ProcessId(Vec<u32>),
+    ExecutableName(EventFilterExecutableNames),
+    Payload(PayloadFilter),
 }
 
 impl EventFilter {
     pub fn as_ptr(&self) -> u64 {
         match self {
             EventFilter::EventId(filter) => filter.as_ptr() as u64,
+            EventFilter::ProcessId(pids) => pids.as_ptr() as u64,
+            EventFilter::ExecutableName(filter) => filter.as_ptr() as u64,
+            EventFilter::Payload(filter) => filter.as_ptr(),
         }
     }
 
     pub fn size(&self) -> u32 {
         match self {
             EventFilter::EventId(filter) => filter.size(),
+            EventFilter::ProcessId(pids) => u32::try_from(pids.len() * mem::size_of::<u32>()).unwrap(),
+            EventFilter::ExecutableName(filter) => filter.size(),
+            EventFilter::Payload(filter) => filter.size(),
         }
     }
 
     pub fn kind(&self) -> u32 {
         match self {
             EventFilter::EventId(_) => EVENT_FILTER_TYPE_EVENT_ID,
+            EventFilter::ProcessId(_) => EVENT_FILTER_TYPE_PID,
+            EventFilter::ExecutableName(_) => EVENT_FILTER_TYPE_EXECUTABLE_NAME,
+            EventFilter::Payload(_) => EVENT_FILTER_TYPE_PAYLOAD,
         }
     }
 
     pub fn event_ids(events: &[u16]) -> EventFilter {
         EventFilter::EventId(EventFilterEventId::new(events))
     }
+
+    /// Only deliver events logged by one of the given processes.
+    ///
+    /// ETW accepts at most `MAX_EVENT_FILTER_PID_COUNT` process ids per filter.
+    pub fn process_ids(pids: &[u32]) -> Result<EventFilter, TraceError> {
+        if pids.is_empty() {
+            return Err(TraceError::Configuration("No process ids given for process id filter".to_string()));
+        }
+        if pids.len() > MAX_EVENT_FILTER_PID_COUNT as usize {
+            return Err(TraceError::Configuration(format!(
+                "Process id filter has {} entries, at most {} are allowed",
+                pids.len(),
+                MAX_EVENT_FILTER_PID_COUNT
+            )));
+        }
+        Ok(EventFilter::ProcessId(pids.to_vec()))
+    }
+
+    /// Only deliver events logged by processes running one of the given executables.
+    pub fn executable_names(names: &[&OsStr]) -> Result<EventFilter, TraceError> {
+        Ok(EventFilter::ExecutableName(EventFilterExecutableNames::new(names)?))
+    }
+
+    /// Only deliver the event `descriptor` of `provider` if all `predicates` match,
+    /// see [`PayloadFilter::new`].
+    pub fn payload(
+        provider: &GUID,
+        descriptor: &EVENT_DESCRIPTOR,
+        predicates: &[PayloadPredicate],
+    ) -> Result<EventFilter, TraceError> {
+        Ok(EventFilter::Payload(PayloadFilter::new(provider, descriptor, false, predicates)?))
+    }
 }
 
 #[derive(Default)]
@@ -728,31 +1733,144 @@ impl TraceSession {
             &timeout,
             &event_filters
         );
-        unsafe {
-            let control_code = match state {
-                false => EVENT_CONTROL_CODE_DISABLE_PROVIDER,
-                true => EVENT_CONTROL_CODE_ENABLE_PROVIDER,
-            };
-            let mut parameters = EnableParameters::new();
+        if state && let Some(data) = self.started_properties() {
+            check_system_logger_provider(data.LogFileMode, provider)?;
+        }
+        let control_code = match state {
+            false => EVENT_CONTROL_CODE_DISABLE_PROVIDER,
+            true => EVENT_CONTROL_CODE_ENABLE_PROVIDER,
+        };
+        let mut parameters = EnableParameters::new();
+
+        parameters.data.SourceId = *provider.id();
 
-            parameters.data.SourceId = *provider.id();
+        if let Some(event_filters) = &mut event_filters {
+            parameters.data.EnableFilterDesc = event_filters.as_mut_ptr();
+            parameters.data.FilterDescCount = event_filters.size();
+        }
+
+        parameters.event_filters = event_filters;
 
-            if let Some(event_filters) = &mut event_filters {
-                parameters.data.EnableFilterDesc = event_filters.as_mut_ptr();
-                parameters.data.FilterDescCount = event_filters.size();
+        self.enable_trace(EnableCall::new(provider, control_code.0), timeout, Some(&parameters))?;
+        if state {
+            self.enabled_providers.insert(*provider.id(), *provider);
+        } else {
+            self.enabled_providers.remove(provider.id());
+        }
+        Ok(())
+    }
+
+    /// Enable the provider `guid` at `level` for events matching the `any` and `all`
+    /// keywords, waiting until the provider processed the request.
+    pub fn enable_provider_by_guid(
+        &mut self,
+        guid: &GUID,
+        level: TraceLevel,
+        any: u64,
+        all: u64,
+        event_filters: Option<EventFilters>,
+    ) -> Result<(), TraceError> {
+        let provider = ProviderBuilder::from_guid(guid).level(level).any(any).all(all).build();
+        self.enable_provider(&provider, true, EnableProviderTimeout::Infinite, event_filters)
+    }
+
+    /// Disable the provider `guid` on this session, waiting until the provider processed
+    /// the request.
+    pub fn disable_provider(&mut self, guid: &GUID) -> Result<(), TraceError> {
+        let provider = self
+            .enabled_providers
+            .get(guid)
+            .copied()
+            .unwrap_or_else(|| ProviderBuilder::from_guid(guid).build());
+        self.enable_provider(&provider, false, EnableProviderTimeout::Infinite, None)
+    }
+
+    /// Enable all `providers`, or none of them.
+    ///
+    /// If enabling one fails, the providers enabled before it are disabled again and the
+    /// error is returned. Use it with the providers expanded from a
+    /// [`crate::provider::ProviderSpec`].
+    pub fn enable_providers(&mut self, providers: &[Provider], timeout: EnableProviderTimeout) -> Result<(), TraceError> {
+        for (idx, provider) in providers.iter().enumerate() {
+            if let Err(err) = self.enable_provider(provider, true, timeout, None) {
+                for enabled in providers[..idx].iter().rev() {
+                    if let Err(rollback_err) = self.enable_provider(enabled, false, timeout, None) {
+                        log::warn!("Failed to disable provider {:?} after failed enable: {:?}", enabled.id(), rollback_err);
+                    }
+                }
+                return Err(err);
             }
+        }
+        Ok(())
+    }
 
-            parameters.event_filters = event_filters;
+    /// Enable all `providers` with their filters.
+    ///
+    /// Unlike [`TraceSession::enable_providers`], all providers are tried; if any fails,
+    /// the ones that were enabled are disabled again and the failures are returned in
+    /// [`TraceError::EnableProviders`].
+    pub fn enable_filtered_providers(
+        &mut self,
+        providers: Vec<(Provider, Option<EventFilters>)>,
+        timeout: EnableProviderTimeout,
+    ) -> Result<(), TraceError> {
+        let mut enabled = Vec::new();
+        let mut failures = Vec::new();
+        for (provider, event_filters) in providers {
+            match self.enable_provider(&provider, true, timeout, event_filters) {
+                Ok(()) => enabled.push(provider),
+                Err(err) => failures.push((*provider.id(), err)),
+            }
+        }
+        if failures.is_empty() {
+            return Ok(());
+        }
+        for provider in enabled.iter().rev() {
+            if let Err(rollback_err) = self.enable_provider(provider, false, timeout, None) {
+                log::warn!("Failed to disable provider {:?} after failed enable: {:?}", provider.id(), rollback_err);
+            }
+        }
+        Err(TraceError::EnableProviders(failures))
+    }
 
+    /// Ask an enabled provider to log its current state, e.g. rundown events for
+    /// processes that were running before the session started.
+    ///
+    /// The request uses the keywords and level the provider was last enabled with on
+    /// this session, so the state events match the steady-state events.
+    pub fn capture_state(&mut self, provider_id: &GUID, timeout: EnableProviderTimeout) -> Result<(), TraceError> {
+        let Some(provider) = self.enabled_providers.get(provider_id) else {
+            return Err(TraceError::Configuration(format!(
+                "Provider {:?} was not enabled on this session",
+                provider_id
+            )));
+        };
+        let call = EnableCall::new(provider, EVENT_CONTROL_CODE_CAPTURE_STATE.0);
+        self.enable_trace(call, timeout, None)
+    }
+
+    /// The provider as last enabled on this session, if it's still enabled.
+    pub fn enabled_provider(&self, provider_id: &GUID) -> Option<&Provider> {
+        self.enabled_providers.get(provider_id)
+    }
+
+    fn enable_trace(
+        &self,
+        call: EnableCall,
+        timeout: EnableProviderTimeout,
+        parameters: Option<&EnableParameters>,
+    ) -> Result<(), TraceError> {
+        log::trace!("EnableTraceEx2({:?}, {:?})", call, timeout);
+        unsafe {
             match EnableTraceEx2(
                 self.handle,
-                provider.id(),
-                control_code.0,
-                provider.level().into(),
-                provider.any(),
-                provider.all(),
+                &call.provider,
+                call.control_code,
+                call.level,
+                call.any,
+                call.all,
                 timeout.into(),
-                Some(parameters.as_ptr()),
+                parameters.map(EnableParameters::as_ptr),
             )
             .ok()
             {
@@ -762,31 +1880,222 @@ impl TraceSession {
                 }
                 Err(err) => {
                     log::warn!("EnableTraceEx2 returned error: {:?}", err);
-                    Err(err.into())
+                    Err(enable_error(err, &call.provider, timeout))
                 }
             }
         }
     }
+
+    /// Enable a provider with a different level for each set of event ids.
+    ///
+    /// Each `EnableTraceEx2` call for a session and provider replaces the previous
+    /// event id filter, so the tiers are merged into a single call: one event id filter
+    /// with the ids of all tiers, at the most verbose level of any tier. Keywords are
+    /// taken from `provider`, its level is ignored. Tiers without event ids are skipped.
+    ///
+    /// ETW can't apply a level per event id, so events of a tier with a lower level
+    /// are delivered up to the merged level as well. The returned map holds the level
+    /// of each event id for dropping those events when consuming them. Like ETW enable
+    /// calls, tiers are last-writer-wins: an event id in several tiers gets the level
+    /// of the last one.
+    pub fn enable_provider_tiered(
+        &mut self,
+        provider: &Provider,
+        tiers: &[(TraceLevel, &[u16])],
+        timeout: EnableProviderTimeout,
+    ) -> Result<HashMap<u16, TraceLevel>, TraceError> {
+        let levels = tier_levels(tiers);
+        let Some(level) = levels.values().map(|level| u8::from(*level)).max() else {
+            return Err(TraceError::Configuration(
+                "No event ids given for tiered provider enable".to_string(),
+            ));
+        };
+        let mut event_ids = levels.keys().copied().collect::<Vec<_>>();
+        event_ids.sort_unstable();
+        let merged_provider = ProviderBuilder::from_guid(provider.id())
+            .any(provider.any())
+            .all(provider.all())
+            .level(TraceLevel::from(level))
+            .build();
+        self.enable_provider(
+            &merged_provider,
+            true,
+            timeout,
+            Some(EventFilters::from(EventFilterEventId::new(&event_ids))),
+        )?;
+        Ok(levels)
+    }
+
+    /// Enable a provider and register this session as one of its owners in the
+    /// process-wide [`EnableRegistry`].
+    ///
+    /// The provider is released again by [`TraceSession::disable_provider_shared`]
+    /// or when the session is dropped. The real disable is only issued once the
+    /// last owner inside this process has released it.
+    pub fn enable_provider_shared(
+        &mut self,
+        provider: &Provider,
+        timeout: EnableProviderTimeout,
+        event_filters: Option<EventFilters>,
+    ) -> Result<(), TraceError> {
+        let name = self.name.clone();
+        EnableRegistry::global().acquire(&name, provider.id(), || {
+            self.enable_provider(provider, true, timeout, event_filters)
+        })?;
+        self.shared_providers.push(*provider);
+        Ok(())
+    }
+
+    /// Release a provider enabled with [`TraceSession::enable_provider_shared`].
+    ///
+    /// Returns true if this was the last owner and the provider was disabled.
+    pub fn disable_provider_shared(
+        &mut self,
+        provider: &Provider,
+        timeout: EnableProviderTimeout,
+    ) -> Result<bool, TraceError> {
+        let Some(position) = self.shared_providers.iter().position(|p| p.id() == provider.id()) else {
+            return Err(TraceError::Configuration(format!(
+                "Provider {:?} was not enabled as shared provider on this session",
+                provider.id()
+            )));
+        };
+        self.shared_providers.swap_remove(position);
+        let name = self.name.clone();
+        EnableRegistry::global().release(&name, provider.id(), || {
+            self.enable_provider(provider, false, timeout, None)
+        })
+    }
 }
 
 impl Drop for TraceSession {
     fn drop(&mut self) {
+        if self.close_on_drop {
+            if let Err(err) = self.stop() {
+                log::warn!(
+                    "ControlTraceW(_, _, _, EVENT_CONTROL_TRACE_STOP) returned error: {:?}",
+                    err
+                );
+            }
+        } else {
+            self.release_shared_providers();
+            if let Some(lease) = self.lease.as_mut() {
+                lease.release(false);
+            }
+        }
+    }
+}
+
+/// The level of each event id of [`TraceSession::enable_provider_tiered`], the last tier winning.
+fn tier_levels(tiers: &[(TraceLevel, &[u16])]) -> HashMap<u16, TraceLevel> {
+    tiers
+        .iter()
+        .flat_map(|(level, event_ids)| event_ids.iter().map(move |event_id| (*event_id, *level)))
+        .collect()
+}
+
+fn in_memory_buffer_count(megabytes: u32) -> u32 {
+    // ETW needs at least two buffers per session
+    (megabytes.saturating_mul(1024) / IN_MEMORY_BUFFER_SIZE).max(2)
+}
+
+/// Sessions flushed by [`ctrl_break_handler`] and the files they're flushed to.
+static CTRL_BREAK_TARGETS: Mutex<Vec<(OsString, PathBuf)>> = Mutex::new(Vec::new());
+
+fn flush_session_to(handle: CONTROLTRACE_HANDLE, name: &OsStr, path: &Path) -> Result<(), TraceError> {
+    let mut properties = EventTraceProperties::default();
+    properties.try_set_logger_name(name)?;
+    properties.try_set_log_file_name(path.as_os_str())?;
+    unsafe {
+        ControlTraceW(handle, None, properties.as_mut_ptr(), EVENT_TRACE_CONTROL_FLUSH)
+            .ok()
+            .map_err(|err| {
+                log::warn!("ControlTraceW(_, _, _, EVENT_TRACE_CONTROL_FLUSH) to {:?} returned error: {:?}", path, err);
+                err.into()
+            })
+    }
+}
+
+/// Flushes a session by name, without access to its [`TraceSession`].
+#[derive(Debug, Clone)]
+pub(crate) struct SessionFlusher {
+    name: OsString,
+}
+
+impl SessionFlusher {
+    pub(crate) fn flush(&self) -> Result<(), TraceError> {
+        let mut properties = EventTraceProperties::default();
+        properties.try_set_logger_name(&self.name)?;
+        properties.try_set_log_file_name(OsStr::new(""))?;
         unsafe {
-            if self.close_on_drop {
-                if let Err(err) = ControlTraceW(
-                    self.handle,
-                    None,
-                    self.properties.as_mut_ptr(),
-                    EVENT_TRACE_CONTROL_STOP,
-                )
+            ControlTraceW(CONTROLTRACE_HANDLE::default(), None, properties.as_mut_ptr(), EVENT_TRACE_CONTROL_FLUSH)
                 .ok()
-                {
-                    log::warn!(
-                        "ControlTraceW(_, _, _, EVENT_CONTROL_TRACE_STOP) returned error: {:?}",
-                        err
-                    );
-                }
+                .map_err(Into::into)
+        }
+    }
+}
+
+unsafe extern "system" fn ctrl_break_handler(ctrl_type: u32) -> BOOL {
+    if ctrl_type != CTRL_BREAK_EVENT {
+        return false.into();
+    }
+    let targets = CTRL_BREAK_TARGETS.lock().unwrap_or_else(|err| err.into_inner()).clone();
+    if targets.is_empty() {
+        return false.into();
+    }
+    for (name, path) in targets {
+        // Flushing by name doesn't need the handle, which the handler doesn't have
+        match flush_session_to(CONTROLTRACE_HANDLE::default(), &name, &path) {
+            Ok(()) => log::info!("Flushed session {:?} to {:?}", name, path),
+            Err(err) => log::error!("Failed to flush session {:?} to {:?}: {:?}", name, path, err),
+        }
+    }
+    true.into()
+}
+
+/// Stop the session of an expired lease, if it is still the session the lease was taken for.
+pub(crate) fn stop_leased_session(lease: &Lease) -> Result<ReapOutcome, TraceError> {
+    let name = lease
+        .session_name
+        .encode_utf16()
+        .chain(iter::once(0))
+        .collect::<Vec<_>>();
+    let mut properties = EventTraceProperties::default();
+    properties.try_set_logger_name(OsStr::new(""))?;
+    properties.try_set_log_file_name(OsStr::new(""))?;
+    unsafe {
+        match ControlTraceW(
+            CONTROLTRACE_HANDLE::default(),
+            PCWSTR::from_raw(name.as_ptr()),
+            properties.as_mut_ptr(),
+            EVENT_TRACE_CONTROL_QUERY,
+        )
+        .ok()
+        {
+            Ok(()) => (),
+            Err(err) if err.code() == HRESULT::from(ERROR_WMI_INSTANCE_NOT_FOUND) => {
+                return Ok(ReapOutcome::Gone)
             }
+            Err(err) => return Err(err.into()),
+        }
+        if properties.0.data.Wnode.Guid != lease.session_guid {
+            return Ok(ReapOutcome::Replaced);
+        }
+        // The session could be restarted between the query and the stop; that window is
+        // accepted, as a restarted session would carry a fresh lease anyway.
+        let mut control_properties = EventTraceProperties::default();
+        control_properties.0.data.Wnode.Flags = WNODE_FLAG_TRACED_GUID;
+        match ControlTraceW(
+            CONTROLTRACE_HANDLE::default(),
+            PCWSTR::from_raw(name.as_ptr()),
+            control_properties.as_mut_ptr(),
+            EVENT_TRACE_CONTROL_STOP,
+        )
+        .ok()
+        {
+            Ok(()) => Ok(ReapOutcome::Stopped),
+            Err(err) if err.code() == HRESULT::from(ERROR_WMI_INSTANCE_NOT_FOUND) => Ok(ReapOutcome::Gone),
+            Err(err) => Err(err.into()),
         }
     }
 }
@@ -829,6 +2138,27 @@ impl EnableFlags {
     pub fn value(&self) -> EVENT_TRACE_FLAG {
         EVENT_TRACE_FLAG(self.bits())
     }
+
+    /// Names of the flags in the set, in declaration order. Bits without a name are skipped.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> {
+        self.iter_names().map(|(name, _)| name)
+    }
+}
+
+/// Renders the set as `PROCESS | THREAD | IMAGE_LOAD`, the format of the schema pattern.
+/// Bits without a name are appended as hex, so the output parses back to the same set.
+impl fmt::Display for EnableFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        bitflags::parser::to_writer(self, f)
+    }
+}
+
+impl FromStr for EnableFlags {
+    type Err = bitflags::parser::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        bitflags::parser::from_str(s)
+    }
 }
 
 #[cfg(feature = "schemars")]
@@ -851,3 +2181,490 @@ impl schemars::JsonSchema for EnableFlags {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, time::Duration};
+
+    use windows::{
+        core::GUID,
+        Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_TIMEOUT},
+    };
+
+    use windows::Win32::System::Diagnostics::Etw::{
+        EVENT_FILTER_TYPE_EXECUTABLE_NAME, EVENT_FILTER_TYPE_PID, MAX_EVENT_FILTER_DATA_SIZE, MAX_EVENT_FILTER_PID_COUNT, MAX_PAYLOAD_PREDICATES,
+    };
+
+    use super::{
+        check_payload_predicates, check_stack_walk, check_system_logger_provider, enable_error, in_memory_buffer_count,
+        tier_levels, EnableFlags, EnableProviderTimeout, EventFilter, EventFilters, EventTraceProperties,
+        EventTracePropertiesBuilder, LogFileMode, PayloadOperator, PayloadPredicate, SessionStatistics, TraceSession,
+        TraceSessionBuilder, MAX_BUFFER_SIZE_KB,
+    };
+    use crate::provider::{ProviderBuilder, TraceLevel, SYSTEM_PROCESS_KW_GENERAL, SYSTEM_PROCESS_PROVIDER_GUID};
+    use crate::error::TraceError;
+    use crate::watchdog::LEASE_SESSION_PREFIX;
+    use crate::well_known::KERNEL_PROCESS_PROVIDER;
+
+    const PROVIDER: GUID = GUID::from_u128(0x1c95126e_7eea_49a9_a3fe_a378b03ddb4d);
+
+    #[test]
+    fn test_enable_timeout_is_distinct_error() {
+        let timeout = Duration::from_millis(50);
+        match enable_error(ERROR_TIMEOUT.into(), &PROVIDER, EnableProviderTimeout::Timeout(timeout)) {
+            TraceError::EnableTimeout { provider, timeout: reported } => {
+                assert_eq!(provider, PROVIDER);
+                assert_eq!(reported, timeout);
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+        assert!(matches!(
+            enable_error(ERROR_ACCESS_DENIED.into(), &PROVIDER, EnableProviderTimeout::Timeout(timeout)),
+            TraceError::Windows(_)
+        ));
+        assert!(matches!(
+            enable_error(ERROR_TIMEOUT.into(), &PROVIDER, EnableProviderTimeout::Infinite),
+            TraceError::Windows(_)
+        ));
+    }
+
+    #[test]
+    fn test_enable_timeout_is_clamped() {
+        assert_eq!(u32::from(EnableProviderTimeout::Asynchronous), 0);
+        assert_eq!(u32::from(EnableProviderTimeout::Timeout(Duration::from_millis(1500))), 1500);
+        assert_eq!(
+            u32::from(EnableProviderTimeout::Timeout(Duration::from_secs(u64::MAX))),
+            u32::MAX - 1
+        );
+    }
+
+    #[test]
+    fn test_enable_flags_display_round_trip() {
+        let flags = EnableFlags::PROCESS | EnableFlags::THREAD | EnableFlags::IMAGE_LOAD;
+        assert_eq!(flags.names().collect::<Vec<_>>(), vec!["IMAGE_LOAD", "PROCESS", "THREAD"]);
+        assert_eq!(flags.to_string(), "IMAGE_LOAD | PROCESS | THREAD");
+        assert_eq!("PROCESS | THREAD|IMAGE_LOAD".parse::<EnableFlags>().unwrap(), flags);
+
+        assert_eq!(EnableFlags::empty().to_string(), "");
+        assert_eq!("".parse::<EnableFlags>().unwrap(), EnableFlags::empty());
+
+        let unknown = EnableFlags::from_bits_retain(EnableFlags::PROCESS.bits() | 0x8000_0000);
+        assert_eq!(unknown.names().collect::<Vec<_>>(), vec!["PROCESS"]);
+        assert_eq!(unknown.to_string().parse::<EnableFlags>().unwrap(), unknown);
+        assert!("PROCESS | NOT_A_FLAG".parse::<EnableFlags>().is_err());
+    }
+
+    #[test]
+    fn test_session_statistics_from_queried_properties() {
+        let mut properties = EventTraceProperties::default();
+        let data = &mut properties.0.data;
+        data.NumberOfBuffers = 8;
+        data.FreeBuffers = 3;
+        data.EventsLost = 2;
+        data.BuffersWritten = 40;
+        data.LogBuffersLost = 1;
+        data.RealTimeBuffersLost = 4;
+        data.LoggerThreadId.0 = 0x1234 as _;
+        assert_eq!(
+            SessionStatistics::from(&properties.0.data),
+            SessionStatistics {
+                number_of_buffers: 8,
+                free_buffers: 3,
+                events_lost: 2,
+                buffers_written: 40,
+                log_buffers_lost: 1,
+                real_time_buffers_lost: 4,
+                logger_thread_id: 0x1234,
+            }
+        );
+    }
+
+    #[test]
+    fn test_in_memory_buffer_count() {
+        assert_eq!(in_memory_buffer_count(0), 2);
+        assert_eq!(in_memory_buffer_count(1), 16);
+        assert_eq!(in_memory_buffer_count(100), 1600);
+        assert_eq!(in_memory_buffer_count(u32::MAX), u32::MAX / 64);
+    }
+
+    #[test]
+    fn test_tier_levels_are_last_writer_wins() {
+        let levels = tier_levels(&[
+            (TraceLevel::ERROR, &[1, 2]),
+            (TraceLevel::VERBOSE, &[]),
+            (TraceLevel::INFORMATION, &[2, 3]),
+        ]);
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[&1], TraceLevel::ERROR);
+        assert_eq!(levels[&2], TraceLevel::INFORMATION);
+        assert_eq!(levels[&3], TraceLevel::INFORMATION);
+        assert!(tier_levels(&[(TraceLevel::VERBOSE, &[])]).is_empty());
+    }
+
+    #[test]
+    fn test_capture_state_requires_enabled_provider() {
+        let mut session = TraceSession::open_existing("etw-rs-capture-state-test");
+        assert!(matches!(
+            session.capture_state(&PROVIDER, EnableProviderTimeout::Asynchronous),
+            Err(TraceError::Configuration(_))
+        ));
+    }
+
+    #[test]
+    fn test_process_id_filter() {
+        let filter = EventFilter::process_ids(&[4, 1234]).unwrap();
+        assert_eq!(filter.kind(), EVENT_FILTER_TYPE_PID);
+        assert_eq!(filter.size(), 8);
+        let pids = unsafe { std::slice::from_raw_parts(filter.as_ptr() as *const u32, 2) };
+        assert_eq!(pids, &[4, 1234]);
+
+        let too_many = (0..=MAX_EVENT_FILTER_PID_COUNT).collect::<Vec<_>>();
+        match EventFilter::process_ids(&too_many) {
+            Err(TraceError::Configuration(message)) => assert!(message.contains("at most 8"), "{}", message),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(EventFilter::process_ids(&[]), Err(TraceError::Configuration(_))));
+    }
+
+    #[test]
+    fn test_executable_name_filter() {
+        let filter = EventFilter::executable_names(&[OsStr::new("cmd.exe"), OsStr::new("svchost.exe")]).unwrap();
+        assert_eq!(filter.kind(), EVENT_FILTER_TYPE_EXECUTABLE_NAME);
+        let expected = "cmd.exe;svchost.exe\0".encode_utf16().collect::<Vec<_>>();
+        assert_eq!(filter.size() as usize, expected.len() * 2);
+        let data = unsafe { std::slice::from_raw_parts(filter.as_ptr() as *const u16, expected.len()) };
+        assert_eq!(data, expected.as_slice());
+
+        assert!(matches!(EventFilter::executable_names(&[]), Err(TraceError::Configuration(_))));
+        assert!(matches!(
+            EventFilter::executable_names(&[OsStr::new("a.exe;b.exe")]),
+            Err(TraceError::Configuration(_))
+        ));
+        let long_name = "a".repeat(MAX_EVENT_FILTER_DATA_SIZE as usize);
+        assert!(matches!(
+            EventFilter::executable_names(&[OsStr::new(&long_name)]),
+            Err(TraceError::Configuration(_))
+        ));
+    }
+
+    #[test]
+    fn test_payload_predicates_are_checked() {
+        let predicate = PayloadPredicate::new("ProcessID", PayloadOperator::Equal, "1234");
+        assert!(check_payload_predicates(std::slice::from_ref(&predicate)).is_ok());
+        assert_eq!(PayloadOperator::Between.code(), 6);
+        assert_eq!(PayloadOperator::IsNot.code(), 31);
+
+        let message = |predicates: &[PayloadPredicate]| match check_payload_predicates(predicates) {
+            Err(TraceError::Configuration(message)) => message,
+            other => panic!("unexpected result {:?}", other),
+        };
+        assert!(message(&[]).contains("No predicates"));
+        let too_many = vec![predicate; MAX_PAYLOAD_PREDICATES as usize + 1];
+        assert!(message(&too_many).contains("at most 8"));
+        assert!(message(&[PayloadPredicate::new("", PayloadOperator::Equal, "1")]).contains("empty field name"));
+        assert!(message(&[PayloadPredicate::new("ProcessID", PayloadOperator::Equal, "1\0")]).contains("null"));
+    }
+
+    #[test]
+    fn test_event_filters_keep_filter_data_alive() {
+        let filter = EventFilter::process_ids(&[42]).unwrap();
+        let ptr = filter.as_ptr();
+        let mut filters = EventFilters::from(vec![
+            filter,
+            EventFilter::executable_names(&[OsStr::new("cmd.exe")]).unwrap(),
+        ]);
+        assert_eq!(filters.size(), 2);
+        let descriptors = unsafe { std::slice::from_raw_parts(filters.as_mut_ptr(), 2) };
+        assert_eq!(descriptors[0].Ptr, ptr);
+        assert_eq!(descriptors[0].Type, EVENT_FILTER_TYPE_PID);
+        assert_eq!(descriptors[1].Type, EVENT_FILTER_TYPE_EXECUTABLE_NAME);
+        assert_eq!(unsafe { *(descriptors[0].Ptr as *const u32) }, 42);
+    }
+
+    type Setter = fn(EventTracePropertiesBuilder) -> Result<EventTracePropertiesBuilder, TraceError>;
+
+    fn message<T>(result: Result<T, TraceError>) -> Option<String> {
+        match result {
+            Ok(_) => None,
+            Err(TraceError::Configuration(message)) => Some(message),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_properties_setters_boundaries() {
+        let long_name = "a".repeat(201);
+        let long_file_name = "a".repeat(1025);
+        let cases: Vec<(&str, Setter, Option<&str>)> = vec![
+            (
+                "buffer size 0",
+                |builder| builder.try_buffer_size(0),
+                Some("Buffer size of 0 KB is out of range, it must be between 1 and 1024 KB"),
+            ),
+            ("buffer size 1", |builder| builder.try_buffer_size(1), None),
+            ("buffer size max", |builder| builder.try_buffer_size(MAX_BUFFER_SIZE_KB), None),
+            (
+                "buffer size max + 1",
+                |builder| builder.try_buffer_size(MAX_BUFFER_SIZE_KB + 1),
+                Some("Buffer size of 1025 KB is out of range, it must be between 1 and 1024 KB"),
+            ),
+            (
+                "flush timer 0",
+                |builder| builder.try_flush_timer(Duration::ZERO),
+                Some("Flush timer of 0ns is out of range, it must be between 1 and 4294967295 seconds"),
+            ),
+            (
+                "flush timer 999ms",
+                |builder| builder.try_flush_timer(Duration::from_millis(999)),
+                Some("Flush timer of 999ms is out of range, it must be between 1 and 4294967295 seconds"),
+            ),
+            ("flush timer 1s", |builder| builder.try_flush_timer(Duration::from_secs(1)), None),
+            (
+                "flush timer u32::MAX s",
+                |builder| builder.try_flush_timer(Duration::from_secs(u64::from(u32::MAX))),
+                None,
+            ),
+            (
+                "flush timer u32::MAX + 1 s",
+                |builder| builder.try_flush_timer(Duration::from_secs(u64::from(u32::MAX) + 1)),
+                Some("Flush timer of 4294967296s is out of range, it must be between 1 and 4294967295 seconds"),
+            ),
+            (
+                "age limit 59s",
+                |builder| builder.try_age_limit(Duration::from_secs(59)),
+                Some("Age limit of 59s is out of range, it must be between 1 and 2147483647 minutes"),
+            ),
+            ("age limit 60s", |builder| builder.try_age_limit(Duration::from_secs(60)), None),
+            ("empty logger name", |builder| builder.try_logger_name(OsStr::new("")), None),
+            (
+                "logger name with null",
+                |builder| builder.try_logger_name(OsStr::new("a\0b")),
+                Some("Session name contains a null character"),
+            ),
+            (
+                "log file name with null",
+                |builder| builder.try_log_file_name(OsStr::new("a\0b")),
+                Some("Log file name contains a null character"),
+            ),
+        ];
+        for (name, setter, expected) in cases {
+            assert_eq!(
+                message(setter(EventTracePropertiesBuilder::new())).as_deref(),
+                expected,
+                "{}",
+                name
+            );
+        }
+
+        let name_cases = [
+            (&long_name[..200], None),
+            (&long_name[..], Some("Session name is 201 characters long, at most 200 are allowed")),
+        ];
+        for (name, expected) in name_cases {
+            let result = EventTracePropertiesBuilder::new().try_logger_name(OsStr::new(name));
+            assert_eq!(message(result).as_deref(), expected, "logger name of {}", name.len());
+        }
+        let file_name_cases = [
+            (&long_file_name[..1024], None),
+            (&long_file_name[..], Some("Log file name is 1025 characters long, at most 1024 are allowed")),
+        ];
+        for (name, expected) in file_name_cases {
+            let result = EventTracePropertiesBuilder::new().try_log_file_name(OsStr::new(name));
+            assert_eq!(message(result).as_deref(), expected, "log file name of {}", name.len());
+        }
+    }
+
+    #[test]
+    fn test_clamping_setters_document_their_clamps() {
+        let data = |builder: EventTracePropertiesBuilder| {
+            let data = &builder.0 .0.data;
+            (data.FlushTimer, data.AgeLimit)
+        };
+        assert_eq!(
+            data(EventTracePropertiesBuilder::new()
+                .flush_timer(Duration::ZERO)
+                .age_limit(Duration::ZERO)),
+            (1, 1)
+        );
+        assert_eq!(
+            data(EventTracePropertiesBuilder::new()
+                .flush_timer(Duration::from_millis(2500))
+                .age_limit(Duration::from_secs(150))),
+            (2, 2)
+        );
+        assert_eq!(
+            data(EventTracePropertiesBuilder::new()
+                .try_flush_timer(Duration::from_millis(2500))
+                .unwrap()
+                .try_age_limit(Duration::from_secs(150))
+                .unwrap()),
+            (2, 2)
+        );
+    }
+
+    #[test]
+    fn test_session_builder_boundaries() {
+        let lease_name = format!("{}test", LEASE_SESSION_PREFIX);
+        let cases: Vec<(&str, Result<TraceSessionBuilder, TraceError>, Option<String>)> = vec![
+            (
+                "empty name",
+                TraceSessionBuilder::try_new(""),
+                Some("Session name is empty".to_string()),
+            ),
+            ("name of 200", TraceSessionBuilder::try_new("a".repeat(200)), None),
+            (
+                "name of 201",
+                TraceSessionBuilder::try_new("a".repeat(201)),
+                Some("Session name is 201 characters long, at most 200 are allowed".to_string()),
+            ),
+            (
+                "buffer size 0",
+                TraceSessionBuilder::new("test").try_buffer_size(0),
+                Some("Buffer size of 0 KB is out of range, it must be between 1 and 1024 KB".to_string()),
+            ),
+            (
+                "flush timer 0",
+                TraceSessionBuilder::new("test").try_flush_timer(Duration::ZERO),
+                Some("Flush timer of 0ns is out of range, it must be between 1 and 4294967295 seconds".to_string()),
+            ),
+            (
+                "age limit 0",
+                TraceSessionBuilder::new("test").try_age_limit(Duration::ZERO),
+                Some("Age limit of 0ns is out of range, it must be between 1 and 2147483647 minutes".to_string()),
+            ),
+            (
+                "auto stop without prefix",
+                TraceSessionBuilder::new("test").try_auto_stop_after(Duration::from_secs(60)),
+                Some(format!(
+                    "Session \"test\" uses auto_stop_after, its name must start with {:?}",
+                    LEASE_SESSION_PREFIX
+                )),
+            ),
+            (
+                "auto stop after 0",
+                TraceSessionBuilder::new(&lease_name).try_auto_stop_after(Duration::ZERO),
+                Some("Auto stop duration is zero".to_string()),
+            ),
+            (
+                "auto stop after 1s",
+                TraceSessionBuilder::new(&lease_name).try_auto_stop_after(Duration::from_secs(1)),
+                None,
+            ),
+        ];
+        for (name, result, expected) in cases {
+            assert_eq!(message(result), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_start_rejects_minimum_above_maximum_buffers() {
+        let cases = [(4, 0, None), (4, 4, None), (5, 4, Some("Minimum of 5 buffers is larger than the maximum of 4 buffers"))];
+        for (minimum, maximum, expected) in cases {
+            let builder = EventTracePropertiesBuilder::new()
+                .minimum_buffers(minimum)
+                .maximum_buffers(maximum);
+            assert_eq!(message(builder.validate()).as_deref(), expected, "{}/{}", minimum, maximum);
+        }
+        // Fails before ETW is called
+        let result = TraceSessionBuilder::new("etw-rs-buffer-validation-test")
+            .minimum_buffers(5)
+            .maximum_buffers(4)
+            .start();
+        assert!(matches!(result, Err(TraceError::Configuration(_))));
+    }
+
+    #[test]
+    fn test_stack_walk_needs_a_kernel_session() {
+        let system_logger = LogFileMode::SYSTEM_LOGGER_MODE | LogFileMode::REAL_TIME_MODE;
+        assert!(check_stack_walk(OsStr::new("NT Kernel Logger"), None, 1).is_ok());
+        assert!(check_stack_walk(OsStr::new("test"), Some(system_logger.bits()), 1).is_ok());
+        assert!(check_stack_walk(OsStr::new("test"), Some(system_logger.bits()), 0).is_ok());
+
+        let regular = LogFileMode::REAL_TIME_MODE.bits();
+        assert!(message(check_stack_walk(OsStr::new("test"), Some(regular), 1)).unwrap().contains("walk stacks"));
+        assert!(message(check_stack_walk(OsStr::new("test"), None, 1)).is_some());
+        let too_many = message(check_stack_walk(OsStr::new("test"), Some(system_logger.bits()), 257)).unwrap();
+        assert!(too_many.contains("at most 256"), "{too_many}");
+    }
+
+    #[test]
+    fn test_system_logger_only_accepts_system_providers() {
+        let builder = TraceSessionBuilder::new("test").system_logger();
+        let mode = builder.event_trace_properties.0 .0.data.LogFileMode;
+        assert!(LogFileMode::from_bits_retain(mode).contains(LogFileMode::SYSTEM_LOGGER_MODE));
+
+        let system = ProviderBuilder::system_provider(&SYSTEM_PROCESS_PROVIDER_GUID, SYSTEM_PROCESS_KW_GENERAL)
+            .unwrap()
+            .build();
+        let regular = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER).build();
+        assert!(check_system_logger_provider(mode, &system).is_ok());
+        let message = message(check_system_logger_provider(mode, &regular)).unwrap();
+        assert!(message.contains("not a system provider"), "{message}");
+
+        let regular_mode = TraceSessionBuilder::new("test").event_trace_properties.0 .0.data.LogFileMode;
+        assert!(check_system_logger_provider(regular_mode, &regular).is_ok());
+    }
+
+    #[test]
+    fn test_log_file_defaults_to_sequential_file_mode() {
+        let mode = |mut builder: TraceSessionBuilder| {
+            builder.resolve_log_file_mode().map(|()| {
+                LogFileMode::from_bits_retain(builder.event_trace_properties.0 .0.data.LogFileMode)
+            })
+        };
+        let file = TraceSessionBuilder::new("test").log_file("trace.etl").unwrap();
+        assert!(file.log_file.as_ref().unwrap().is_absolute());
+        assert_eq!(
+            mode(file).unwrap(),
+            LogFileMode::FILE_MODE_SEQUENTIAL | LogFileMode::NO_PER_PROCESSOR_BUFFERING
+        );
+        assert_eq!(
+            mode(TraceSessionBuilder::new("test")).unwrap(),
+            LogFileMode::REAL_TIME_MODE | LogFileMode::NO_PER_PROCESSOR_BUFFERING
+        );
+        // Both real-time and to a file
+        let both = LogFileMode::REAL_TIME_MODE | LogFileMode::FILE_MODE_CIRCULAR;
+        let builder = TraceSessionBuilder::new("test").log_file_mode(both).log_file("trace.etl").unwrap();
+        assert_eq!(mode(builder).unwrap(), both);
+    }
+
+    #[test]
+    fn test_log_file_mode_must_match_log_file() {
+        let cases = [
+            (
+                Some("trace.etl"),
+                LogFileMode::REAL_TIME_MODE,
+                Some("doesn't write a file"),
+            ),
+            (
+                Some("trace.etl"),
+                LogFileMode::FILE_MODE_NEWFILE,
+                Some("has no %d for the file number"),
+            ),
+            (Some("trace-%d.etl"), LogFileMode::FILE_MODE_NEWFILE, None),
+            (None, LogFileMode::FILE_MODE_SEQUENTIAL, Some("but the session has no log file")),
+            (None, LogFileMode::BUFFERING_MODE, None),
+        ];
+        for (file, log_file_mode, expected) in cases {
+            let mut builder = TraceSessionBuilder::new("test").log_file_mode(log_file_mode);
+            if let Some(file) = file {
+                builder = builder.log_file(file).unwrap();
+            }
+            let result = message(builder.resolve_log_file_mode());
+            match expected {
+                Some(expected) => assert!(
+                    result.as_deref().is_some_and(|message| message.contains(expected)),
+                    "{:?} {:?}: {:?}",
+                    file,
+                    log_file_mode,
+                    result
+                ),
+                None => assert_eq!(result, None, "{:?} {:?}", file, log_file_mode),
+            }
+        }
+
+        let long_name = format!("{}.etl", "a".repeat(1025));
+        assert!(message(TraceSessionBuilder::new("test").log_file(long_name))
+            .is_some_and(|message| message.starts_with("Log file name is")));
+    }
+}
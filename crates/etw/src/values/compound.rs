@@ -1,7 +1,20 @@
-use std::{collections::BTreeMap, ffi, fmt};
+use std::{collections::BTreeMap, ffi, fmt, ops::Index, slice, sync::Arc, vec};
 use std::os::windows::ffi::OsStringExt;
 
-use super::value::Value;
+use windows::{
+    core::GUID,
+    Win32::System::Diagnostics::Etw::{
+        TRACE_MESSAGE_COMPONENTID, TRACE_MESSAGE_FLAGS, TRACE_MESSAGE_GUID, TRACE_MESSAGE_SEQUENCE,
+        TRACE_MESSAGE_SYSTEMINFO, TRACE_MESSAGE_TIMESTAMP,
+    },
+};
+
+use crate::schema::cache::{PropertyInfo, PropertyNestedInfo, PropertyStructInfo, PropertyValueInfo};
+
+use super::{
+    primitives::{GuidRef, Int64Ref, UInt16Ref, UInt32Ref},
+    value::{Value, ValueOwned},
+};
 
 pub enum Property<'a> {
     Scalar(Value<'a>),
@@ -53,11 +66,239 @@ impl fmt::Debug for RawU16StringRef<'_> {
 pub enum StringOrStruct<'a> {
     String(RawU16StringRef<'a>),
     Struct(Struct<'a>),
+    /// Payload of an event whose schema doesn't describe any properties.
+    Opaque(&'a [u8]),
+    /// Leading top-level properties of an event whose payload ended early, decoded
+    /// in tolerant mode. See [`crate::schema::cache::EventInfo::decode_userdata_tolerant`].
+    Partial(Struct<'a>, Truncation),
+    /// A WPP (software trace preprocessor) message, see [`WppMessage`].
+    Wpp(WppMessage<'a>),
+}
+
+/// A WPP message. Its format is only described by TMF files, so the arguments are
+/// left undecoded unless a formatter was passed to [`crate::values::event::Event::parse_wpp`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WppMessage<'a> {
+    /// GUID of the message's TMF format, delivered as the provider id of the event.
+    pub message_guid: GUID,
+    pub message_number: u16,
+    pub level: u8,
+    /// The trace flags the message was logged with.
+    pub flags: u64,
+    /// The `TraceMessage` header at the start of the user data, if the record has one.
+    pub header: Option<TraceMessageHeader>,
+    /// The message arguments as logged, without type information.
+    pub arguments: &'a [u8],
+    /// The message formatted through a TMF lookup.
+    pub formatted: Option<String>,
+}
+
+impl WppMessage<'_> {
+    /// Sequence number of the message, if it was logged with `TRACE_MESSAGE_SEQUENCE`.
+    pub fn sequence(&self) -> Option<u32> {
+        self.header.and_then(|header| header.sequence)
+    }
+}
+
+/// The header of a message logged with `TraceMessage`, in the `MESSAGE_TRACE_HEADER`
+/// layout: size and message number and flags, followed by the fields the message
+/// flags select in the order sequence number, message GUID or component id,
+/// timestamp and thread and process id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceMessageHeader {
+    /// The `TRACE_MESSAGE_*` flags the message was logged with.
+    pub message_flags: u16,
+    pub sequence: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serde::guid_option"))]
+    pub message_guid: Option<GUID>,
+    pub component_id: Option<u32>,
+    pub timestamp: Option<i64>,
+    pub thread_id: Option<u32>,
+    pub process_id: Option<u32>,
+}
+
+impl TraceMessageHeader {
+    const SIZE: usize = 8;
+
+    /// Split the header off the user data of the message with `message_number`.
+    ///
+    /// Returns None if `data` doesn't start with a header for that message, whose size
+    /// field must match the length of `data`.
+    pub fn parse(data: &[u8], message_number: u16) -> Option<(Self, &[u8])> {
+        let fixed = UInt16Ref { data: data.get(..Self::SIZE)? };
+        if usize::from(fixed.get(0)?) != data.len() || fixed.get(2)? != message_number {
+            return None;
+        }
+        let message_flags = fixed.get(3)?;
+        let has = |flag: TRACE_MESSAGE_FLAGS| u32::from(message_flags) & flag.0 != 0;
+        if has(TRACE_MESSAGE_GUID) && has(TRACE_MESSAGE_COMPONENTID) {
+            return None;
+        }
+
+        let mut remainder = &data[Self::SIZE..];
+        let mut take = |flag: TRACE_MESSAGE_FLAGS, len: usize| {
+            if !has(flag) {
+                return Some(None);
+            }
+            let (field, rest) = remainder.split_at_checked(len)?;
+            remainder = rest;
+            Some(Some(field))
+        };
+        let sequence = take(TRACE_MESSAGE_SEQUENCE, 4)?.and_then(|data| UInt32Ref { data }.get(0));
+        let message_guid = take(TRACE_MESSAGE_GUID, 16)?.and_then(|data| GuidRef { data }.get(0));
+        let component_id = take(TRACE_MESSAGE_COMPONENTID, 4)?.and_then(|data| UInt32Ref { data }.get(0));
+        let timestamp = take(TRACE_MESSAGE_TIMESTAMP, 8)?.and_then(|data| Int64Ref { data }.get(0));
+        let system_info = take(TRACE_MESSAGE_SYSTEMINFO, 8)?.map(|data| UInt32Ref { data });
+        Some((
+            Self {
+                message_flags,
+                sequence,
+                message_guid,
+                component_id,
+                timestamp,
+                thread_id: system_info.as_ref().and_then(|ids| ids.get(0)),
+                process_id: system_info.as_ref().and_then(|ids| ids.get(1)),
+            },
+            remainder,
+        ))
+    }
+}
+
+/// Where the payload of a [`StringOrStruct::Partial`] event ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Truncation {
+    /// Number of top-level properties that were decoded.
+    pub decoded_fields: usize,
+    /// Number of top-level properties the schema declares.
+    pub declared_fields: usize,
+    /// Payload offset of the first property that couldn't be decoded.
+    pub offset: usize,
 }
 
 #[derive(Debug)]
 pub struct Struct<'a> {
     pub values: Vec<StructOrValue<'a>>,
+    /// Field names in schema order, shared by the values of a struct array. None for
+    /// structs that weren't decoded through a schema.
+    pub names: Option<Arc<[String]>>,
+}
+
+impl<'a> Struct<'a> {
+    /// Fields in schema order.
+    pub fn iter(&self) -> slice::Iter<'_, StructOrValue<'a>> {
+        self.values.iter()
+    }
+
+    /// The value of the field `name`.
+    ///
+    /// Returns None if there is no such field, or it wasn't decoded because the payload
+    /// ended early.
+    pub fn get(&self, name: &str) -> Option<&StructOrValue<'a>> {
+        self.iter_named().find(|(field, _)| *field == name).map(|(_, value)| value)
+    }
+
+    /// Fields with their names, in schema order.
+    ///
+    /// Empty for structs without [`Struct::names`].
+    pub fn iter_named(&self) -> impl Iterator<Item = (&str, &StructOrValue<'a>)> {
+        self.names.iter().flat_map(|names| names.iter().map(String::as_str)).zip(&self.values)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Call `visit` with the path, property schema and value of each value in the
+    /// struct, depth first.
+    ///
+    /// `schema` is the schema the struct was decoded with. Paths are like
+    /// `Outer[2].Inner`, as in [`crate::error::ParseError::Property`]. Properties with
+    /// a custom schema have no [`PropertyValueInfo`] and are skipped.
+    pub fn walk_values(&self, schema: &PropertyStructInfo, visit: &mut dyn FnMut(&str, &PropertyValueInfo, &Value<'a>)) {
+        self.walk_values_at("", schema, visit);
+    }
+
+    fn walk_values_at(
+        &self,
+        prefix: &str,
+        schema: &PropertyStructInfo,
+        visit: &mut dyn FnMut(&str, &PropertyValueInfo, &Value<'a>),
+    ) {
+        for (field, value) in schema.fields.iter().zip(&self.values) {
+            value.walk_values_at(&format!("{}{}", prefix, field.value.name()), field, visit);
+        }
+    }
+}
+
+impl<'a> StringOrStruct<'a> {
+    /// The value of the top-level field `name` of a structured payload, see [`Struct::get`].
+    pub fn get(&self, name: &str) -> Option<&StructOrValue<'a>> {
+        match self {
+            Self::Struct(struc) | Self::Partial(struc, _) => struc.get(name),
+            Self::String(_) | Self::Opaque(_) | Self::Wpp(_) => None,
+        }
+    }
+}
+
+impl<'a> StructOrValue<'a> {
+    /// Like [`Struct::walk_values`], for the value of `property`.
+    pub fn walk_values(&self, property: &PropertyInfo, visit: &mut dyn FnMut(&str, &PropertyValueInfo, &Value<'a>)) {
+        self.walk_values_at(property.value.name(), property, visit);
+    }
+
+    fn walk_values_at(
+        &self,
+        path: &str,
+        property: &PropertyInfo,
+        visit: &mut dyn FnMut(&str, &PropertyValueInfo, &Value<'a>),
+    ) {
+        match (&property.value, self) {
+            (PropertyNestedInfo::Value(_, value_info), StructOrValue::Value(value)) => visit(path, value_info, value),
+            (PropertyNestedInfo::Struct(_, struct_info), StructOrValue::Struct(members)) => {
+                for (idx, member) in members.values.iter().enumerate() {
+                    let prefix = if members.is_array {
+                        format!("{}[{}].", path, idx)
+                    } else {
+                        format!("{}.", path)
+                    };
+                    member.walk_values_at(&prefix, struct_info, visit);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl<'a> Index<usize> for Struct<'a> {
+    type Output = StructOrValue<'a>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.values[index]
+    }
+}
+
+impl<'s, 'a> IntoIterator for &'s Struct<'a> {
+    type Item = &'s StructOrValue<'a>;
+    type IntoIter = slice::Iter<'s, StructOrValue<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.iter()
+    }
+}
+
+impl<'a> IntoIterator for Struct<'a> {
+    type Item = StructOrValue<'a>;
+    type IntoIter = vec::IntoIter<StructOrValue<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.into_iter()
+    }
 }
 
 #[derive(Debug)]
@@ -71,3 +312,220 @@ pub enum StructOrValue<'a> {
     Struct(StructArray<'a>),
     Value(Value<'a>),
 }
+
+/// A [`StringOrStruct`] with its data copied out of the event record.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StringOrStructOwned {
+    String(String),
+    Struct(StructOwned),
+    Opaque(Vec<u8>),
+    Partial(StructOwned, Truncation),
+    Wpp(WppMessageOwned),
+}
+
+impl StringOrStructOwned {
+    /// The value of the top-level field `name` of a structured payload, see [`StructOwned::get`].
+    pub fn get(&self, name: &str) -> Option<&StructOrValueOwned> {
+        match self {
+            Self::Struct(struc) | Self::Partial(struc, _) => struc.get(name),
+            Self::String(_) | Self::Opaque(_) | Self::Wpp(_) => None,
+        }
+    }
+}
+
+impl From<&StringOrStruct<'_>> for StringOrStructOwned {
+    fn from(value: &StringOrStruct<'_>) -> Self {
+        match value {
+            StringOrStruct::String(string) => {
+                Self::String(String::from_utf16_lossy(&string.to_vec()).trim_end_matches('\0').to_string())
+            }
+            StringOrStruct::Struct(struc) => Self::Struct(struc.into()),
+            StringOrStruct::Opaque(data) => Self::Opaque(data.to_vec()),
+            StringOrStruct::Partial(struc, truncation) => Self::Partial(struc.into(), *truncation),
+            StringOrStruct::Wpp(message) => Self::Wpp(message.into()),
+        }
+    }
+}
+
+/// A [`WppMessage`] with its arguments copied out of the event record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WppMessageOwned {
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde::guid"))]
+    pub message_guid: GUID,
+    pub message_number: u16,
+    pub level: u8,
+    pub flags: u64,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub header: Option<TraceMessageHeader>,
+    pub arguments: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub formatted: Option<String>,
+}
+
+impl From<&WppMessage<'_>> for WppMessageOwned {
+    fn from(value: &WppMessage<'_>) -> Self {
+        Self {
+            message_guid: value.message_guid,
+            message_number: value.message_number,
+            level: value.level,
+            flags: value.flags,
+            header: value.header,
+            arguments: value.arguments.to_vec(),
+            formatted: value.formatted.clone(),
+        }
+    }
+}
+
+/// A [`Struct`] with its data copied out of the event record.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructOwned {
+    pub values: Vec<StructOrValueOwned>,
+    /// Field names in schema order, see [`Struct::names`].
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub names: Option<Arc<[String]>>,
+}
+
+impl StructOwned {
+    /// Fields in schema order.
+    pub fn iter(&self) -> slice::Iter<'_, StructOrValueOwned> {
+        self.values.iter()
+    }
+
+    /// The value of the field `name`, see [`Struct::get`].
+    pub fn get(&self, name: &str) -> Option<&StructOrValueOwned> {
+        self.iter_named().find(|(field, _)| *field == name).map(|(_, value)| value)
+    }
+
+    /// Fields with their names, in schema order, see [`Struct::iter_named`].
+    pub fn iter_named(&self) -> impl Iterator<Item = (&str, &StructOrValueOwned)> {
+        self.names.iter().flat_map(|names| names.iter().map(String::as_str)).zip(&self.values)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Index<usize> for StructOwned {
+    type Output = StructOrValueOwned;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.values[index]
+    }
+}
+
+impl From<&Struct<'_>> for StructOwned {
+    fn from(value: &Struct<'_>) -> Self {
+        Self {
+            values: value.iter().map(StructOrValueOwned::from).collect(),
+            names: value.names.clone(),
+        }
+    }
+}
+
+/// A [`StructArray`] with its data copied out of the event record.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StructArrayOwned {
+    pub values: Vec<StructOwned>,
+    pub is_array: bool,
+}
+
+impl From<&StructArray<'_>> for StructArrayOwned {
+    fn from(value: &StructArray<'_>) -> Self {
+        Self {
+            values: value.values.iter().map(StructOwned::from).collect(),
+            is_array: value.is_array,
+        }
+    }
+}
+
+/// A [`StructOrValue`] with its data copied out of the event record.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StructOrValueOwned {
+    Struct(StructArrayOwned),
+    Value(ValueOwned),
+}
+
+impl From<&StructOrValue<'_>> for StructOrValueOwned {
+    fn from(value: &StructOrValue<'_>) -> Self {
+        match value {
+            StructOrValue::Struct(array) => Self::Struct(array.into()),
+            StructOrValue::Value(value) => Self::Value(value.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        schema::{
+            cache::{DecodeContext, PropertyInfo, PropertyNestedInfo, PropertyStructInfo, PropertyValue},
+            in_type::InType,
+            out_type::OutType,
+        },
+        values::value::Value,
+    };
+
+    use super::{Struct, StructOrValue};
+
+    fn field(data: &[u8]) -> StructOrValue<'_> {
+        StructOrValue::Value(Value::parse(data, InType::UInt16, 2, 1, false).unwrap().0)
+    }
+
+    #[test]
+    fn test_struct_positional_access() {
+        let first = 1u16.to_le_bytes();
+        let second = 2u16.to_le_bytes();
+        let struc = Struct {
+            values: vec![field(&first), field(&second)],
+            names: None,
+        };
+
+        assert_eq!(struc.len(), 2);
+        let StructOrValue::Value(value) = &struc[1] else {
+            panic!("Expected a value");
+        };
+        assert_eq!(value.raw, &second);
+
+        let mut visited = 0;
+        for (idx, field) in (&struc).into_iter().enumerate() {
+            assert!(std::ptr::eq(field, &struc[idx]));
+            visited += 1;
+        }
+        assert_eq!(visited, 2);
+        assert_eq!(struc.into_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_walk_values_with_schema() {
+        let schema = PropertyStructInfo::new(vec![
+            PropertyInfo::value("Id", InType::UInt16, OutType::UnsignedShort),
+            PropertyInfo {
+                length: PropertyValue::Constant(0),
+                count: PropertyValue::Constant(2),
+                is_array: true,
+                value: PropertyNestedInfo::Struct(
+                    "Endpoints".to_string(),
+                    PropertyStructInfo::new(vec![PropertyInfo::value("Port", InType::UInt16, OutType::Port)]),
+                ),
+            },
+        ]);
+        let data = [7, 0, 0x00, 0x50, 0x01, 0xbb];
+        let (struc, _) = schema.decode(&data, &mut DecodeContext::default()).unwrap();
+
+        let mut rendered = Vec::new();
+        struc.walk_values(&schema, &mut |path, value_info, value| {
+            rendered.push(format!("{}={}", path, value.format(value_info.out_type).unwrap()));
+        });
+        assert_eq!(rendered, vec!["Id=7", "Endpoints[0].Port=80", "Endpoints[1].Port=443"]);
+    }
+}
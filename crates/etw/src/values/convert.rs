@@ -0,0 +1,411 @@
+//! Conversions of decoded values to native Rust types.
+//!
+//! The scalar accessors like [`InValue::as_u64`] fail for arrays with more or less
+//! than one element; the iterator forms like [`InValue::iter_u64`] accept any count.
+//! Elements are read through the reference types, so they honor the
+//! `unchecked_cast` feature.
+
+use std::iter;
+
+use windows::core::GUID;
+
+use crate::error::ConversionError;
+
+use super::{in_value::InValue, strings::utf16_lossy, value::Value};
+
+impl<'a> InValue<'a> {
+    /// Number of elements of the value.
+    pub fn element_count(&self) -> usize {
+        match self {
+            Self::Null => 0,
+            Self::UnicodeString(strings) => strings.len(),
+            Self::AnsiString(strings) => strings.len(),
+            Self::Int8(value) => value.len(),
+            Self::UInt8(value) | Self::AnsiChar(value) => value.len(),
+            Self::Int16(value) => value.len(),
+            Self::UInt16(value) | Self::UnicodeChar(value) => value.len(),
+            Self::Int32(value) => value.len(),
+            Self::UInt32(value) | Self::Boolean(value) | Self::HexInt32(value) => value.len(),
+            Self::Int64(value) => value.len(),
+            Self::UInt64(value) | Self::HexInt64(value) => value.len(),
+            Self::Float(value) => value.len(),
+            Self::Double(value) => value.len(),
+            Self::Binary(values) => values.len(),
+            Self::Guid(value) => value.len(),
+            Self::Pointer(value) | Self::SizeT(value) => value.len(),
+            Self::FileTime(value) => value.len(),
+            Self::SystemTime(value) => value.len(),
+            Self::Sid(sids) => sids.len(),
+            Self::WbemSid(sids) => sids.len(),
+            Self::CountedString(strings) | Self::ReversedCountedString(strings) => strings.len(),
+            Self::CountedAnsiString(strings) | Self::ReversedCountedAnsiString(strings) => strings.len(),
+            Self::NonNullTerminatedString(_) | Self::NonNullTerminatedAnsiString(_) | Self::HexDump(_) => 1,
+        }
+    }
+
+    fn scalar(&self) -> Result<(), ConversionError> {
+        match self.element_count() {
+            1 => Ok(()),
+            count => Err(ConversionError::NotAScalar(count)),
+        }
+    }
+
+    fn mismatch(&self, to: &'static str) -> ConversionError {
+        ConversionError::TypeMismatch {
+            from: self.datatype(),
+            to,
+        }
+    }
+
+    /// Element `idx` of an unsigned integer, hex integer, pointer or size value.
+    fn unsigned_element(&self, idx: usize) -> Option<u64> {
+        match self {
+            Self::UInt8(value) => value.get(idx).map(u64::from),
+            Self::UInt16(value) => value.get(idx).map(u64::from),
+            Self::UInt32(value) | Self::HexInt32(value) => value.get(idx).map(u64::from),
+            Self::UInt64(value) | Self::HexInt64(value) => value.get(idx),
+            Self::Pointer(value) | Self::SizeT(value) => value.get(idx),
+            _ => None,
+        }
+    }
+
+    fn is_unsigned(&self) -> bool {
+        matches!(
+            self,
+            Self::UInt8(_)
+                | Self::UInt16(_)
+                | Self::UInt32(_)
+                | Self::HexInt32(_)
+                | Self::UInt64(_)
+                | Self::HexInt64(_)
+                | Self::Pointer(_)
+                | Self::SizeT(_)
+        )
+    }
+
+    /// Element `idx` of a signed integer value.
+    fn signed_element(&self, idx: usize) -> Option<i64> {
+        match self {
+            Self::Int8(value) => value.get(idx).map(i64::from),
+            Self::Int16(value) => value.get(idx).map(i64::from),
+            Self::Int32(value) => value.get(idx).map(i64::from),
+            Self::Int64(value) => value.get(idx),
+            _ => None,
+        }
+    }
+
+    fn is_signed(&self) -> bool {
+        matches!(self, Self::Int8(_) | Self::Int16(_) | Self::Int32(_) | Self::Int64(_))
+    }
+
+    /// The value of an unsigned integer, hex integer, pointer or size value.
+    pub fn as_u64(&self) -> Result<u64, ConversionError> {
+        if !self.is_unsigned() {
+            return Err(self.mismatch("u64"));
+        }
+        self.scalar()?;
+        self.unsigned_element(0).ok_or(ConversionError::NotAScalar(0))
+    }
+
+    /// The value of a signed integer, or of an unsigned integer value that fits.
+    pub fn as_i64(&self) -> Result<i64, ConversionError> {
+        if self.is_signed() {
+            self.scalar()?;
+            return self.signed_element(0).ok_or(ConversionError::NotAScalar(0));
+        }
+        let value = self.as_u64().map_err(|err| match err {
+            ConversionError::TypeMismatch { .. } => self.mismatch("i64"),
+            err => err,
+        })?;
+        i64::try_from(value).map_err(|_| ConversionError::OutOfRange {
+            value: value.to_string(),
+            to: "i64",
+        })
+    }
+
+    /// The value of a float or double value.
+    pub fn as_f64(&self) -> Result<f64, ConversionError> {
+        let value = match self {
+            Self::Float(value) => {
+                self.scalar()?;
+                value.get(0).map(f64::from)
+            }
+            Self::Double(value) => {
+                self.scalar()?;
+                value.get(0)
+            }
+            _ => return Err(self.mismatch("f64")),
+        };
+        value.ok_or(ConversionError::NotAScalar(0))
+    }
+
+    /// The value of a boolean value; any value but 0 is true.
+    pub fn as_bool(&self) -> Result<bool, ConversionError> {
+        let Self::Boolean(value) = self else {
+            return Err(self.mismatch("bool"));
+        };
+        self.scalar()?;
+        value.get(0).map(|value| value != 0).ok_or(ConversionError::NotAScalar(0))
+    }
+
+    /// The text of a string value, converted lossily and without a trailing null.
+    pub fn as_string(&self) -> Result<String, ConversionError> {
+        let mut strings = self.iter_strings()?;
+        self.scalar()?;
+        Ok(strings.next().unwrap_or_default())
+    }
+
+    /// The value of a GUID value.
+    pub fn as_guid(&self) -> Result<GUID, ConversionError> {
+        let Self::Guid(value) = self else {
+            return Err(self.mismatch("GUID"));
+        };
+        self.scalar()?;
+        value.get(0).ok_or(ConversionError::NotAScalar(0))
+    }
+
+    /// The string form, like `S-1-5-18`, of a SID or WBEM SID value.
+    pub fn as_sid_string(&self) -> Result<String, ConversionError> {
+        let psid = match self {
+            Self::Sid(sids) => {
+                self.scalar()?;
+                sids[0].psid()
+            }
+            Self::WbemSid(sids) => {
+                self.scalar()?;
+                sids[0].sid().psid()
+            }
+            _ => return Err(self.mismatch("SID string")),
+        };
+        String::try_from(&crate::windows::Sid(psid)).map_err(ConversionError::Sid)
+    }
+
+    /// The bytes of a binary or hex dump value.
+    pub fn as_bytes(&self) -> Result<&'a [u8], ConversionError> {
+        match self {
+            Self::Binary(values) => {
+                self.scalar()?;
+                Ok(values[0])
+            }
+            Self::HexDump(data) => Ok(*data),
+            _ => Err(self.mismatch("bytes")),
+        }
+    }
+
+    /// The elements of an unsigned integer, hex integer, pointer or size value.
+    pub fn iter_u64(&self) -> Result<impl Iterator<Item = u64> + '_, ConversionError> {
+        if !self.is_unsigned() {
+            return Err(self.mismatch("u64"));
+        }
+        Ok((0..self.element_count()).filter_map(|idx| self.unsigned_element(idx)))
+    }
+
+    /// The elements of a signed integer value.
+    pub fn iter_i64(&self) -> Result<impl Iterator<Item = i64> + '_, ConversionError> {
+        if !self.is_signed() {
+            return Err(self.mismatch("i64"));
+        }
+        Ok((0..self.element_count()).filter_map(|idx| self.signed_element(idx)))
+    }
+
+    /// The text of each element of a string value, see [`InValue::as_string`].
+    pub fn iter_strings(&self) -> Result<impl Iterator<Item = String> + '_, ConversionError> {
+        let strings: Box<dyn Iterator<Item = String> + '_> = match self {
+            Self::UnicodeString(strings) => Box::new(strings.iter().map(utf16_lossy)),
+            Self::AnsiString(strings) => {
+                Box::new(strings.iter().map(|string| String::from_utf8_lossy(string.content()).into_owned()))
+            }
+            Self::CountedString(strings) | Self::ReversedCountedString(strings) => {
+                Box::new(strings.iter().map(|string| String::from_utf16_lossy(string.data())))
+            }
+            Self::CountedAnsiString(strings) | Self::ReversedCountedAnsiString(strings) => {
+                Box::new(strings.iter().map(|string| String::from_utf8_lossy(string.data()).into_owned()))
+            }
+            Self::NonNullTerminatedString(string) => Box::new(iter::once(String::from_utf16_lossy(string))),
+            Self::NonNullTerminatedAnsiString(string) => {
+                Box::new(iter::once(String::from_utf8_lossy(string).into_owned()))
+            }
+            _ => return Err(self.mismatch("String")),
+        };
+        Ok(strings)
+    }
+}
+
+/// Shorthands for the conversions of [`Value::value`].
+impl<'a> Value<'a> {
+    pub fn as_u64(&self) -> Result<u64, ConversionError> {
+        self.value.as_u64()
+    }
+
+    pub fn as_i64(&self) -> Result<i64, ConversionError> {
+        self.value.as_i64()
+    }
+
+    pub fn as_f64(&self) -> Result<f64, ConversionError> {
+        self.value.as_f64()
+    }
+
+    pub fn as_bool(&self) -> Result<bool, ConversionError> {
+        self.value.as_bool()
+    }
+
+    pub fn as_string(&self) -> Result<String, ConversionError> {
+        self.value.as_string()
+    }
+
+    pub fn as_guid(&self) -> Result<GUID, ConversionError> {
+        self.value.as_guid()
+    }
+
+    pub fn as_sid_string(&self) -> Result<String, ConversionError> {
+        self.value.as_sid_string()
+    }
+
+    pub fn as_bytes(&self) -> Result<&'a [u8], ConversionError> {
+        self.value.as_bytes()
+    }
+
+    pub fn iter_u64(&self) -> Result<impl Iterator<Item = u64> + '_, ConversionError> {
+        self.value.iter_u64()
+    }
+
+    pub fn iter_i64(&self) -> Result<impl Iterator<Item = i64> + '_, ConversionError> {
+        self.value.iter_i64()
+    }
+
+    pub fn iter_strings(&self) -> Result<impl Iterator<Item = String> + '_, ConversionError> {
+        self.value.iter_strings()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::core::GUID;
+
+    use crate::{
+        error::ConversionError,
+        schema::in_type::InType,
+        values::{in_value::InValue, strings::CountedEtwString, value::Value},
+    };
+
+    fn parse(data: &[u8], in_type: InType, length: usize, count: usize) -> Value<'_> {
+        Value::parse(data, in_type, length, count, count != 1).unwrap().0
+    }
+
+    fn utf16z(string: &str) -> Vec<u8> {
+        string.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn test_unsigned_integers() {
+        assert_eq!(parse(&[0xfe], InType::UInt8, 1, 1).as_u64().unwrap(), 0xfe);
+        assert_eq!(parse(&0xbeefu16.to_le_bytes(), InType::UInt16, 2, 1).as_u64().unwrap(), 0xbeef);
+        assert_eq!(parse(&7u32.to_le_bytes(), InType::UInt32, 4, 1).as_u64().unwrap(), 7);
+        assert_eq!(parse(&7u32.to_le_bytes(), InType::HexInt32, 4, 1).as_u64().unwrap(), 7);
+        assert_eq!(parse(&u64::MAX.to_le_bytes(), InType::UInt64, 8, 1).as_u64().unwrap(), u64::MAX);
+        assert_eq!(parse(&9u64.to_le_bytes(), InType::HexInt64, 8, 1).as_u64().unwrap(), 9);
+        let pointer = 0x1000usize.to_le_bytes();
+        assert_eq!(parse(&pointer, InType::Pointer, pointer.len(), 1).as_u64().unwrap(), 0x1000);
+        assert_eq!(parse(&pointer, InType::SizeT, pointer.len(), 1).as_u64().unwrap(), 0x1000);
+    }
+
+    #[test]
+    fn test_signed_integers() {
+        assert_eq!(parse(&[0xff], InType::Int8, 1, 1).as_i64().unwrap(), -1);
+        assert_eq!(parse(&(-2i16).to_le_bytes(), InType::Int16, 2, 1).as_i64().unwrap(), -2);
+        assert_eq!(parse(&(-3i32).to_le_bytes(), InType::Int32, 4, 1).as_i64().unwrap(), -3);
+        assert_eq!(parse(&i64::MIN.to_le_bytes(), InType::Int64, 8, 1).as_i64().unwrap(), i64::MIN);
+        assert_eq!(parse(&5u32.to_le_bytes(), InType::UInt32, 4, 1).as_i64().unwrap(), 5);
+        assert!(matches!(
+            parse(&u64::MAX.to_le_bytes(), InType::UInt64, 8, 1).as_i64(),
+            Err(ConversionError::OutOfRange { to: "i64", .. })
+        ));
+        assert!(matches!(
+            parse(&[0xff], InType::Int8, 1, 1).as_u64(),
+            Err(ConversionError::TypeMismatch { from: InType::Int8, to: "u64" })
+        ));
+    }
+
+    #[test]
+    fn test_floats_and_booleans() {
+        assert_eq!(parse(&1.5f32.to_le_bytes(), InType::Float, 4, 1).as_f64().unwrap(), 1.5);
+        assert_eq!(parse(&(-0.25f64).to_le_bytes(), InType::Double, 8, 1).as_f64().unwrap(), -0.25);
+        assert!(parse(&1u32.to_le_bytes(), InType::Boolean, 4, 1).as_bool().unwrap());
+        assert!(!parse(&0u32.to_le_bytes(), InType::Boolean, 4, 1).as_bool().unwrap());
+        assert!(matches!(
+            parse(&1u32.to_le_bytes(), InType::UInt32, 4, 1).as_bool(),
+            Err(ConversionError::TypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(parse(&utf16z("wide"), InType::UnicodeString, 0, 1).as_string().unwrap(), "wide");
+        assert_eq!(parse(b"narrow\0", InType::AnsiString, 0, 1).as_string().unwrap(), "narrow");
+        // Invalid UTF-16 is replaced instead of failing the conversion
+        let invalid = [0x00, 0xd8, 0x00, 0x00];
+        assert_eq!(parse(&invalid, InType::UnicodeString, 0, 1).as_string().unwrap(), "\u{fffd}");
+        assert!(matches!(
+            parse(&[1], InType::UInt8, 1, 1).as_string(),
+            Err(ConversionError::TypeMismatch { to: "String", .. })
+        ));
+    }
+
+    #[test]
+    fn test_every_string_variant() {
+        let wide = utf16z("wide");
+        let wide_units = "wide".encode_utf16().collect::<Vec<_>>();
+        let narrow = &b"narrow"[..];
+        let values = [
+            (parse(&wide, InType::UnicodeString, 0, 1).value, "wide"),
+            (parse(b"narrow\0", InType::AnsiString, 0, 1).value, "narrow"),
+            (InValue::CountedString(vec![CountedEtwString { data: &wide_units }]), "wide"),
+            (InValue::ReversedCountedString(vec![CountedEtwString { data: &wide_units }]), "wide"),
+            (InValue::CountedAnsiString(vec![CountedEtwString { data: narrow }]), "narrow"),
+            (InValue::ReversedCountedAnsiString(vec![CountedEtwString { data: narrow }]), "narrow"),
+            (InValue::NonNullTerminatedString(&wide_units), "wide"),
+            (InValue::NonNullTerminatedAnsiString(narrow), "narrow"),
+        ];
+        for (value, expected) in &values {
+            assert_eq!(value.iter_strings().unwrap().collect::<Vec<_>>(), vec![*expected], "{:?}", value);
+            assert_eq!(value.as_string().unwrap(), *expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_guid_sid_and_bytes() {
+        let guid = GUID::from_u128(0x22fb2cd6_0e7b_422b_a0c7_2fad1fd0e716);
+        let guid_bytes = guid.to_u128().to_le_bytes();
+        assert_eq!(parse(&guid_bytes, InType::Guid, 16, 1).as_guid().unwrap(), guid);
+        // S-1-5-18
+        let sid = [1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+        assert_eq!(parse(&sid, InType::Sid, 0, 1).as_sid_string().unwrap(), "S-1-5-18");
+        let mut wbem_sid = vec![0; 2 * size_of::<usize>()];
+        wbem_sid.extend_from_slice(&sid);
+        assert_eq!(parse(&wbem_sid, InType::WbemSid, 0, 1).as_sid_string().unwrap(), "S-1-5-18");
+        assert_eq!(parse(&[1, 2, 3], InType::Binary, 3, 1).as_bytes().unwrap(), &[1, 2, 3]);
+        assert!(matches!(
+            parse(&guid_bytes, InType::Guid, 16, 1).as_bytes(),
+            Err(ConversionError::TypeMismatch { from: InType::Guid, .. })
+        ));
+    }
+
+    #[test]
+    fn test_arrays() {
+        let data = [1u32, 2, 3].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+        let value = parse(&data, InType::UInt32, 4, 3);
+        assert_eq!(value.iter_u64().unwrap().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(matches!(value.as_u64(), Err(ConversionError::NotAScalar(3))));
+        assert!(value.iter_i64().is_err());
+
+        let data = [-1i16, 2].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+        let value = parse(&data, InType::Int16, 2, 2);
+        assert_eq!(value.iter_i64().unwrap().collect::<Vec<_>>(), vec![-1, 2]);
+
+        let mut strings = utf16z("a");
+        strings.extend(utf16z("bc"));
+        let value = parse(&strings, InType::UnicodeString, 0, 2);
+        assert_eq!(value.iter_strings().unwrap().collect::<Vec<_>>(), vec!["a", "bc"]);
+        assert!(matches!(value.as_string(), Err(ConversionError::NotAScalar(2))));
+    }
+}
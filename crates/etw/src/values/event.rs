@@ -1,20 +1,23 @@
 use std::{
-    fmt, mem::size_of, slice, sync::Arc
+    collections::HashMap, fmt, mem::size_of, slice, sync::{Arc, RwLock}
 };
 
 use once_cell::sync::Lazy;
 use windows::{
     core::GUID,
     Win32::System::Diagnostics::Etw::{
-        EVENT_DESCRIPTOR, EVENT_HEADER, EVENT_HEADER_FLAG_PRIVATE_SESSION, EVENT_RECORD,
+        EVENT_DESCRIPTOR, EVENT_HEADER, EVENT_HEADER_EXTENDED_DATA_ITEM, EVENT_HEADER_FLAG_PRIVATE_SESSION, EVENT_RECORD,
         EVENT_HEADER_FLAG_32_BIT_HEADER, EVENT_HEADER_FLAG_64_BIT_HEADER,
         EVENT_HEADER_FLAG_CLASSIC_HEADER, EVENT_HEADER_FLAG_EXTENDED_INFO,
         EVENT_HEADER_FLAG_NO_CPUTIME,
         EVENT_HEADER_FLAG_STRING_ONLY, EVENT_HEADER_FLAG_TRACE_MESSAGE,
+        EVENT_HEADER_EXT_TYPE_CONTAINER_ID, EVENT_HEADER_EXT_TYPE_EVENT_KEY, EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL,
+        EVENT_HEADER_EXT_TYPE_INSTANCE_INFO, EVENT_HEADER_EXT_TYPE_PROCESS_START_KEY, EVENT_HEADER_EXT_TYPE_PROV_TRAITS, EVENT_HEADER_EXT_TYPE_RELATED_ACTIVITYID, EVENT_HEADER_EXT_TYPE_SID,
+        EVENT_HEADER_EXT_TYPE_STACK_TRACE32, EVENT_HEADER_EXT_TYPE_STACK_TRACE64, EVENT_HEADER_EXT_TYPE_TS_ID,
     },
 };
 
-use crate::{error::{ParseError, TraceError}, schema::cache::{EventInfo, SchemaCache}, values::compound::StringOrStruct};
+use crate::{error::{ParseError, TraceError}, schema::cache::{EventInfo, PropertyStructInfo, SchemaCache}, timestamp::{Timestamp, TimestampContext}, values::{compound::{RawU16StringRef, StringOrStruct, StringOrStructOwned, TraceMessageHeader, WppMessage}, misc::Sid}};
 
 #[repr(transparent)]
 pub struct EventDescriptor<'a>(&'a EVENT_DESCRIPTOR);
@@ -206,6 +209,9 @@ pub struct HeaderOwned {
     pub thread_id: u32,
     pub process_id: u32,
     pub timestamp: i64,
+    /// `timestamp` classified, see [`crate::timestamp`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub time: Timestamp,
     #[cfg_attr(feature = "serde", serde(with = "GUIDDef"))]
     pub provider_id: ::windows::core::GUID,
     pub event_descriptor: EventDescriptorOwned,
@@ -214,6 +220,16 @@ pub struct HeaderOwned {
     pub activity_id: ::windows::core::GUID,
 }
 
+impl HeaderOwned {
+    /// Copy the header, classifying the timestamp with `context`.
+    pub fn with_context(value: &Header, context: &TimestampContext) -> Self {
+        Self {
+            time: context.classify(value.0),
+            ..Self::from(value)
+        }
+    }
+}
+
 impl From<&Header<'_>> for HeaderOwned {
     fn from(value: &Header) -> Self {
         Self {
@@ -224,6 +240,7 @@ impl From<&Header<'_>> for HeaderOwned {
             thread_id: value.thread_id(),
             process_id: value.process_id(),
             timestamp: value.timestamp(),
+            time: TimestampContext::new().classify(value.0),
             provider_id: *value.provider_id(),
             event_descriptor: value.event_descriptor().into(),
             elapsed_execution_time: value.elapsed_execution_time(),
@@ -283,67 +300,184 @@ pub struct Event<'a> {
     pub data: StringOrStruct<'a>,
 }
 
+/// An [`Event`] with its header and payload copied out of the event record, to keep
+/// it after the callback returned or to send it to another thread.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct EventOwned {
+    pub header: HeaderOwned,
+    pub data: StringOrStructOwned,
+}
+
+impl From<&Event<'_>> for EventOwned {
+    fn from(value: &Event<'_>) -> Self {
+        Self {
+            header: HeaderOwned::from(&value.header),
+            data: StringOrStructOwned::from(&value.data),
+        }
+    }
+}
+
 impl<'a> Event<'a> {
     pub fn parse(event_record: &EVENT_RECORD) -> Result<(Arc<EventInfo>, Event<'_>), TraceError> {
+        Self::parse_with_cache(event_record, SchemaCache::global(), false)
+    }
+
+    /// Like [`Event::parse`], but returns the leading properties of events whose payload
+    /// is shorter than their schema as [`StringOrStruct::Partial`].
+    pub fn parse_tolerant(event_record: &EVENT_RECORD) -> Result<(Arc<EventInfo>, Event<'_>), TraceError> {
+        Self::parse_with_cache(event_record, SchemaCache::global(), true)
+    }
+
+    /// Like [`Event::parse`] or [`Event::parse_tolerant`], but looks up schemas in `cache`
+    /// instead of [`SchemaCache::global`].
+    pub fn parse_with_cache<'b>(
+        event_record: &'b EVENT_RECORD,
+        cache: &SchemaCache,
+        tolerant: bool,
+    ) -> Result<(Arc<EventInfo>, Event<'b>), TraceError> {
+        Self::parse_with_pointer_size(event_record, cache, tolerant, 0)
+    }
+
+    /// Like [`Event::parse_with_cache`], but decodes the pointers of records without a
+    /// pointer size flag with `pointer_size`, e.g. the one of the log file, see
+    /// [`EventRecord::pointer_size_or`].
+    pub fn parse_with_pointer_size<'b>(
+        event_record: &'b EVENT_RECORD,
+        cache: &SchemaCache,
+        tolerant: bool,
+        pointer_size: usize,
+    ) -> Result<(Arc<EventInfo>, Event<'b>), TraceError> {
         let event = EventRecord(event_record);
 
         if event.is_wpp_event() {
-            Self::parse_wpp_event(event_record)
+            Self::parse_wpp(event_record, None)
         }
         else {
-            Self::parse_non_wpp_event(event_record)
+            Self::parse_non_wpp_event(event_record, cache, tolerant, pointer_size)
         }
     }
 
-    fn parse_wpp_event(_event_record: &EVENT_RECORD) -> Result<(Arc<EventInfo>, Event<'_>), TraceError> {
-        todo!()
+    /// Parse a WPP event, i.e. one with `EVENT_HEADER_FLAG_TRACE_MESSAGE`, as [`StringOrStruct::Wpp`].
+    ///
+    /// A [`TraceMessageHeader`] at the start of the user data is split off the
+    /// arguments. `formatter` looks up the TMF format of the message to format it;
+    /// without one, or if it returns None, only the raw arguments are available. The
+    /// returned schema has no properties.
+    pub fn parse_wpp<'b>(
+        event_record: &'b EVENT_RECORD,
+        formatter: Option<&dyn Fn(&WppMessage) -> Option<String>>,
+    ) -> Result<(Arc<EventInfo>, Event<'b>), TraceError> {
+        let header = &event_record.EventHeader;
+        let userdata = EventRecord(event_record).validated_userdata()?;
+        let (message_header, arguments) = match TraceMessageHeader::parse(userdata, header.EventDescriptor.Id) {
+            Some((message_header, arguments)) => (Some(message_header), arguments),
+            None => (None, userdata),
+        };
+        let mut message = WppMessage {
+            message_guid: header.ProviderId,
+            message_number: header.EventDescriptor.Id,
+            level: header.EventDescriptor.Level,
+            flags: header.EventDescriptor.Keyword,
+            header: message_header,
+            arguments,
+            formatted: None,
+        };
+        message.formatted = formatter.and_then(|formatter| formatter(&message));
+        Ok((
+            empty_schema(header),
+            Event {
+                header: Header::from(header),
+                data: StringOrStruct::Wpp(message),
+            },
+        ))
     }
 
-    fn parse_non_wpp_event(event_record: &EVENT_RECORD) -> Result<(Arc<EventInfo>, Event<'_>), TraceError> {
+    fn parse_non_wpp_event<'b>(event_record: &'b EVENT_RECORD, cache: &SchemaCache, tolerant: bool, pointer_size: usize) -> Result<(Arc<EventInfo>, Event<'b>), TraceError> {
         let event = EventRecord(event_record);
 
         if event.is_string_event() {
-            let string = unsafe {
-                slice::from_raw_parts(event_record.UserData as *const u8, event_record.UserDataLength.into())
-            };
-            let chunks = string.chunks_exact(2);
-            let remainder = chunks.remainder();
-            let string = chunks.map(|chunk| u16::from_le_bytes(chunk.try_into().unwrap())).collect::<Vec<_>>();
-            let _string = String::from_utf16(&string).map_err(|e| ParseError::from(e))?;
-            if !remainder.is_empty() {
-                Err(ParseError::DataLeftAfterDecoding.into())
-            }
-            else {
-                todo!()
-            }
+            Self::parse_string_event(event_record)
         }
         else {
-            Self::parse_properties(event_record)
+            Self::parse_properties(event_record, cache, tolerant, pointer_size)
         }
     }
-    fn parse_properties<'b, 'c>(event_record: &'b EVENT_RECORD) -> Result<(Arc<EventInfo>, Event<'c>), TraceError> where 'b: 'c {
-        static EVENT_SCHEMAS: Lazy<SchemaCache> = Lazy::new(|| SchemaCache::new());
 
+    /// Parse an event with `EVENT_HEADER_FLAG_STRING_ONLY`, whose payload is a null terminated
+    /// UTF-16 string, as [`StringOrStruct::String`]. The returned schema has no properties.
+    fn parse_string_event(event_record: &EVENT_RECORD) -> Result<(Arc<EventInfo>, Event<'_>), TraceError> {
+        let userdata = EventRecord(event_record).validated_userdata()?;
+        let string_len = userdata
+            .chunks_exact(2)
+            .position(|chunk| chunk == [0, 0])
+            .map_or(userdata.len() - userdata.len() % 2, |idx| (idx + 1) * 2);
+        if string_len != userdata.len() {
+            return Err(ParseError::DataLeftAfterDecoding.into());
+        }
+        let header = &event_record.EventHeader;
+        Ok((
+            empty_schema(header),
+            Event {
+                header: Header::from(header),
+                data: StringOrStruct::String(RawU16StringRef::new(userdata)),
+            },
+        ))
+    }
+
+    fn parse_properties<'b, 'c>(event_record: &'b EVENT_RECORD, cache: &SchemaCache, tolerant: bool, pointer_size: usize) -> Result<(Arc<EventInfo>, Event<'c>), TraceError> where 'b: 'c {
         // Get event description from cache if we have already fetched it, otherwise fetch it and add it to the cache
-        let schema = EVENT_SCHEMAS.get_from_event_record(event_record)?;
+        let schema = cache.get_from_event_record(event_record)?;
 
-        let struc = schema.decode(event_record)?;
+        let pointer_size = EventRecord(event_record).pointer_size_or(pointer_size);
+        let struc = schema.decode_record(event_record, pointer_size, tolerant)?;
         Ok((schema, struc))
     }
 }
 
+/// Schemas without properties of string only and WPP events, which don't have a schema
+/// of their own, by provider, id and version.
+static EMPTY_SCHEMAS: Lazy<RwLock<HashMap<(GUID, u16, u8), Arc<EventInfo>>>> = Lazy::new(Default::default);
+
+/// The schema without properties for the event with `header`, shared by all its events.
+fn empty_schema(header: &EVENT_HEADER) -> Arc<EventInfo> {
+    let key = (header.ProviderId, header.EventDescriptor.Id, header.EventDescriptor.Version);
+    if let Some(schema) = EMPTY_SCHEMAS.read().unwrap_or_else(|err| err.into_inner()).get(&key) {
+        return Arc::clone(schema);
+    }
+    let mut schemas = EMPTY_SCHEMAS.write().unwrap_or_else(|err| err.into_inner());
+    Arc::clone(
+        schemas
+            .entry(key)
+            .or_insert_with(|| Arc::new(EventInfo::new(key.0, key.1, key.2, PropertyStructInfo::new(Vec::new())))),
+    )
+}
+
+/// Upper bound of the size of an event, header included.
+const MAX_EVENT_SIZE: usize = 64 * 1024;
+
 #[repr(transparent)]
 pub struct EventRecord<'a>(pub &'a EVENT_RECORD);
 
 impl<'a> EventRecord<'a> {
     #[inline]
     pub fn pointer_size(&self) -> usize {
+        self.pointer_size_or(0)
+    }
+
+    /// The pointer size flagged in the header, or `fallback` if there is none and it is
+    /// 4 or 8, e.g. the pointer size of the log file the record was read from.
+    #[inline]
+    pub fn pointer_size_or(&self, fallback: usize) -> usize {
         if (u32::from(self.0.EventHeader.Flags) & EVENT_HEADER_FLAG_32_BIT_HEADER) == EVENT_HEADER_FLAG_32_BIT_HEADER {
             size_of::<u32>()
         }
         else if (u32::from(self.0.EventHeader.Flags) & EVENT_HEADER_FLAG_64_BIT_HEADER) == EVENT_HEADER_FLAG_64_BIT_HEADER {
             size_of::<u64>()
         }
+        else if fallback == size_of::<u32>() || fallback == size_of::<u64>() {
+            fallback
+        }
         else {
             log::warn!("Unknown pointer size");
             size_of::<usize>()
@@ -355,6 +489,13 @@ impl<'a> EventRecord<'a> {
         (u32::from(self.0.EventHeader.Flags) & EVENT_HEADER_FLAG_TRACE_MESSAGE) == EVENT_HEADER_FLAG_TRACE_MESSAGE
     }
 
+    /// Whether the event was logged with the classic (MOF) event tracing API, like the
+    /// events of the NT Kernel Logger.
+    #[inline]
+    pub fn is_classic_event(&self) -> bool {
+        (u32::from(self.0.EventHeader.Flags) & EVENT_HEADER_FLAG_CLASSIC_HEADER) == EVENT_HEADER_FLAG_CLASSIC_HEADER
+    }
+
     #[inline]
     pub fn is_string_event(&self) -> bool {
         (u32::from(self.0.EventHeader.Flags) & EVENT_HEADER_FLAG_STRING_ONLY) == EVENT_HEADER_FLAG_STRING_ONLY
@@ -376,9 +517,669 @@ impl<'a> EventRecord<'a> {
     }
 
     #[inline]
-    pub fn userdata(&self) -> &[u8] {
-        unsafe {
-            slice::from_raw_parts(self.0.UserData as *const u8, self.0.UserDataLength.into())
+    pub fn opcode(&self) -> u8 {
+        self.0.EventHeader.EventDescriptor.Opcode
+    }
+
+    /// The payload, or an empty slice if the record is malformed.
+    ///
+    /// Use [`EventRecord::validated_userdata`] to tell malformed records from empty payloads.
+    #[inline]
+    pub fn userdata(&self) -> &'a [u8] {
+        self.validated_userdata().unwrap_or_default()
+    }
+
+    /// The payload, after checking that the record's pointer and length describe one.
+    ///
+    /// This is the only place the payload of a raw record is turned into a slice; a
+    /// buggy provider can deliver a null `UserData` with a nonzero length.
+    pub fn validated_userdata(&self) -> Result<&'a [u8], ParseError> {
+        let length = usize::from(self.0.UserDataLength);
+        if length == 0 {
+            return Ok(&[]);
+        }
+        if self.0.UserData.is_null() {
+            return Err(ParseError::MalformedRecord("UserData is null but UserDataLength is not zero"));
+        }
+        if length > MAX_EVENT_SIZE - size_of::<EVENT_HEADER>() {
+            return Err(ParseError::MalformedRecord("UserDataLength exceeds the maximum event size"));
+        }
+        Ok(unsafe { slice::from_raw_parts(self.0.UserData as *const u8, length) })
+    }
+
+    /// The extended data items, after checking that their pointers and counts are consistent.
+    pub fn validated_extended_data(&self) -> Result<&'a [EVENT_HEADER_EXTENDED_DATA_ITEM], ParseError> {
+        let items = self.extended_data_items()?;
+        if !items.iter().all(has_valid_data) {
+            return Err(ParseError::MalformedRecord("extended data item is null but its size is not zero"));
+        }
+        Ok(items)
+    }
+
+    /// The extended data item array, without checking the items.
+    fn extended_data_items(&self) -> Result<&'a [EVENT_HEADER_EXTENDED_DATA_ITEM], ParseError> {
+        let count = usize::from(self.0.ExtendedDataCount);
+        if count == 0 {
+            return Ok(&[]);
+        }
+        if self.0.ExtendedData.is_null() {
+            return Err(ParseError::MalformedRecord("ExtendedData is null but ExtendedDataCount is not zero"));
+        }
+        Ok(unsafe { slice::from_raw_parts(self.0.ExtendedData, count) })
+    }
+
+    /// The extended data items whose data can be read, skipping the others with a warning.
+    fn valid_extended_data_items(&self) -> impl Iterator<Item = &'a EVENT_HEADER_EXTENDED_DATA_ITEM> + use<'a> {
+        let provider = self.provider_guid();
+        let items = self.extended_data_items().unwrap_or_else(|err| {
+            log::warn!("Ignoring extended data of provider {:?}: {}", provider, err);
+            &[]
+        });
+        items.iter().filter(move |item| {
+            let valid = has_valid_data(item);
+            if !valid {
+                log::warn!(
+                    "Skipping extended data item of type {} of provider {:?}: data is null but its size is not zero",
+                    item.ExtType,
+                    provider
+                );
+            }
+            valid
+        })
+    }
+
+    /// Returns the data of the first extended data item of the given `EVENT_HEADER_EXT_TYPE_*` type.
+    ///
+    /// Items whose data pointer is null although their size isn't zero are skipped.
+    pub fn extended_data_item(&self, ext_type: u32) -> Option<&'a [u8]> {
+        self.valid_extended_data_items()
+            .find(|item| u32::from(item.ExtType) == ext_type)
+            .map(extended_data_item_bytes)
+    }
+
+    /// All extended data items, decoded where the type is known.
+    ///
+    /// Items whose data pointer is null although their size isn't zero are skipped.
+    pub fn extended_data(&self) -> impl Iterator<Item = ExtendedDataItem<'a>> + use<'a> {
+        self.valid_extended_data_items()
+            .map(|item| ExtendedDataItem::parse(item.ExtType, extended_data_item_bytes(item)))
+    }
+
+    /// The id of the activity that caused the event's activity, for correlating activities.
+    pub fn related_activity_id(&self) -> Option<GUID> {
+        self.extended_data_item(EVENT_HEADER_EXT_TYPE_RELATED_ACTIVITYID)
+            .filter(|bytes| bytes.len() == size_of::<GUID>())
+            .and_then(guid_from_le_bytes)
+    }
+
+    /// Provider traits of TraceLogging events.
+    pub fn provider_traits(&self) -> Option<ProviderTraits> {
+        self.extended_data_item(EVENT_HEADER_EXT_TYPE_PROV_TRAITS)
+            .and_then(ProviderTraits::parse)
+    }
+
+    /// TraceLogging event metadata, which describes the layout of the event's payload.
+    pub fn tracelogging_schema(&self) -> Option<&'a [u8]> {
+        self.extended_data_item(EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL)
+    }
+}
+
+fn has_valid_data(item: &EVENT_HEADER_EXTENDED_DATA_ITEM) -> bool {
+    item.DataPtr != 0 || item.DataSize == 0
+}
+
+fn extended_data_item_bytes<'a>(item: &EVENT_HEADER_EXTENDED_DATA_ITEM) -> &'a [u8] {
+    if item.DataSize == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(item.DataPtr as usize as *const u8, item.DataSize.into()) }
+    }
+}
+
+fn guid_from_le_bytes(data: &[u8]) -> Option<GUID> {
+    Some(GUID::from_values(
+        u32::from_le_bytes(data.get(0..4)?.try_into().ok()?),
+        u16::from_le_bytes(data.get(4..6)?.try_into().ok()?),
+        u16::from_le_bytes(data.get(6..8)?.try_into().ok()?),
+        data.get(8..16)?.try_into().ok()?,
+    ))
+}
+
+/// An extended data item attached to an event record.
+#[derive(Debug)]
+pub enum ExtendedDataItem<'a> {
+    RelatedActivityId(GUID),
+    /// Security identifier of the user that logged the event.
+    Sid(Sid<'a>),
+    /// Terminal session the event was logged in.
+    TsId(u32),
+    /// Call stack of a 32-bit process. Events of the same stack share the `matched_id`.
+    StackTrace32 { matched_id: u64, addresses: Vec<u32> },
+    /// Call stack of a 64-bit process. Events of the same stack share the `matched_id`.
+    StackTrace64 { matched_id: u64, addresses: Vec<u64> },
+    ProvTraits(ProviderTraits),
+    /// Identifies the process that logged the event for the lifetime of the system.
+    ProcessStartKey(u64),
+    /// Identifies the event for the lifetime of the system.
+    EventKey(u64),
+    /// Instance of a provider, see `EVENT_EXTENDED_ITEM_INSTANCE`.
+    InstanceInfo {
+        instance_id: u32,
+        parent_instance_id: u32,
+        parent_guid: GUID,
+    },
+    /// Id of the container the event was logged in.
+    ContainerId(GUID),
+    /// An item of unknown type, or one whose data doesn't match its type.
+    Raw { ext_type: u16, bytes: &'a [u8] },
+}
+
+impl<'a> ExtendedDataItem<'a> {
+    /// Decode the data of an item with the given `EVENT_HEADER_EXT_TYPE_*` type.
+    pub fn parse(ext_type: u16, bytes: &'a [u8]) -> Self {
+        Self::parse_known(ext_type, bytes).unwrap_or(ExtendedDataItem::Raw { ext_type, bytes })
+    }
+
+    fn parse_known(ext_type: u16, bytes: &'a [u8]) -> Option<Self> {
+        let u64_at = |offset: usize| Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?));
+        let item = match u32::from(ext_type) {
+            EVENT_HEADER_EXT_TYPE_RELATED_ACTIVITYID if bytes.len() == size_of::<GUID>() => {
+                ExtendedDataItem::RelatedActivityId(guid_from_le_bytes(bytes)?)
+            }
+            EVENT_HEADER_EXT_TYPE_SID => ExtendedDataItem::Sid(Sid::new(bytes)?),
+            EVENT_HEADER_EXT_TYPE_TS_ID if bytes.len() == size_of::<u32>() => {
+                ExtendedDataItem::TsId(u32::from_le_bytes(bytes.try_into().ok()?))
+            }
+            EVENT_HEADER_EXT_TYPE_STACK_TRACE32 if (bytes.len().checked_sub(8)?) % size_of::<u32>() == 0 => {
+                ExtendedDataItem::StackTrace32 {
+                    matched_id: u64_at(0)?,
+                    addresses: bytes[8..]
+                        .chunks_exact(size_of::<u32>())
+                        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+                        .collect(),
+                }
+            }
+            EVENT_HEADER_EXT_TYPE_STACK_TRACE64 if (bytes.len().checked_sub(8)?) % size_of::<u64>() == 0 => {
+                ExtendedDataItem::StackTrace64 {
+                    matched_id: u64_at(0)?,
+                    addresses: bytes[8..]
+                        .chunks_exact(size_of::<u64>())
+                        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                        .collect(),
+                }
+            }
+            EVENT_HEADER_EXT_TYPE_PROV_TRAITS => ExtendedDataItem::ProvTraits(ProviderTraits::parse(bytes)?),
+            EVENT_HEADER_EXT_TYPE_PROCESS_START_KEY if bytes.len() == 8 => ExtendedDataItem::ProcessStartKey(u64_at(0)?),
+            EVENT_HEADER_EXT_TYPE_EVENT_KEY if bytes.len() == 8 => ExtendedDataItem::EventKey(u64_at(0)?),
+            EVENT_HEADER_EXT_TYPE_INSTANCE_INFO if bytes.len() == 8 + size_of::<GUID>() => {
+                ExtendedDataItem::InstanceInfo {
+                    instance_id: u32::from_le_bytes(bytes[0..4].try_into().ok()?),
+                    parent_instance_id: u32::from_le_bytes(bytes[4..8].try_into().ok()?),
+                    parent_guid: guid_from_le_bytes(&bytes[8..])?,
+                }
+            }
+            EVENT_HEADER_EXT_TYPE_CONTAINER_ID if bytes.len() == size_of::<GUID>() => {
+                ExtendedDataItem::ContainerId(guid_from_le_bytes(bytes)?)
+            }
+            _ => return None,
+        };
+        Some(item)
+    }
+}
+
+const PROVIDER_TRAIT_TYPE_GROUP: u8 = 1;
+
+/// Provider traits as attached to TraceLogging events.
+///
+/// TraceLogging providers carry their name and optionally the GUID of the provider
+/// group they belong to in every event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderTraits {
+    pub name: Option<String>,
+    pub group_guid: Option<GUID>,
+}
+
+impl ProviderTraits {
+    /// Parse the `EVENT_HEADER_EXT_TYPE_PROV_TRAITS` blob.
+    ///
+    /// The blob starts with its total size (u16), followed by the null-terminated UTF-8
+    /// provider name and a list of traits, each with its size (u16, including the header)
+    /// and type (u8).
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let total_size = usize::from(u16::from_le_bytes(data.get(..2)?.try_into().ok()?));
+        let data = data.get(2..total_size.min(data.len()))?;
+        let name_len = data.iter().position(|c| *c == 0)?;
+        let name = (name_len != 0).then(|| String::from_utf8_lossy(&data[..name_len]).into_owned());
+
+        let mut group_guid = None;
+        let mut traits = &data[name_len + 1..];
+        while traits.len() >= 3 {
+            let size = usize::from(u16::from_le_bytes([traits[0], traits[1]]));
+            if size < 3 || size > traits.len() {
+                break;
+            }
+            let trait_data = &traits[3..size];
+            if traits[2] == PROVIDER_TRAIT_TYPE_GROUP && trait_data.len() == size_of::<GUID>() {
+                group_guid = Some(guid_from_le_bytes(trait_data)?);
+            }
+            traits = &traits[size..];
+        }
+
+        Some(Self { name, group_guid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ptr, sync::Arc};
+
+    use windows::{
+        core::GUID,
+        Win32::System::Diagnostics::Etw::{
+            EVENT_HEADER_EXTENDED_DATA_ITEM, EVENT_HEADER_EXT_TYPE_CONTAINER_ID, EVENT_HEADER_EXT_TYPE_INSTANCE_INFO,
+            EVENT_HEADER_EXT_TYPE_PROV_TRAITS, EVENT_HEADER_EXT_TYPE_RELATED_ACTIVITYID, EVENT_HEADER_EXT_TYPE_SID, EVENT_HEADER_EXT_TYPE_STACK_TRACE32, EVENT_HEADER_EXT_TYPE_STACK_TRACE64,
+            EVENT_HEADER_EXT_TYPE_TS_ID, EVENT_HEADER_FLAG_STRING_ONLY, EVENT_HEADER_FLAG_TRACE_MESSAGE, EVENT_RECORD,
+        },
+    };
+
+    use crate::{
+        error::{ParseError, TraceError},
+        failures::decode_hex,
+        values::compound::{StringOrStruct, TraceMessageHeader, WppMessage},
+    };
+
+    use super::{Event, EventRecord, ExtendedDataItem, ProviderTraits};
+
+    fn assert_malformed<T: std::fmt::Debug>(result: Result<T, ParseError>) {
+        assert!(matches!(result, Err(ParseError::MalformedRecord(_))), "{result:?}");
+    }
+
+    #[test]
+    fn test_null_userdata_with_length_is_malformed() {
+        let event_record = EVENT_RECORD {
+            UserData: ptr::null_mut(),
+            UserDataLength: 16,
+            ..Default::default()
+        };
+        let record = EventRecord(&event_record);
+        assert_malformed(record.validated_userdata());
+        assert!(record.userdata().is_empty());
+    }
+
+    #[test]
+    fn test_empty_userdata_is_valid() {
+        let event_record = EVENT_RECORD::default();
+        assert_eq!(EventRecord(&event_record).validated_userdata().unwrap(), &[] as &[u8]);
+
+        let mut userdata = [1u8, 2, 3];
+        let event_record = EVENT_RECORD {
+            UserData: userdata.as_mut_ptr() as *mut _,
+            UserDataLength: 3,
+            ..Default::default()
+        };
+        assert_eq!(EventRecord(&event_record).validated_userdata().unwrap(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_userdata_beyond_event_size_is_malformed() {
+        let mut userdata = vec![0u8; usize::from(u16::MAX)];
+        let event_record = EVENT_RECORD {
+            UserData: userdata.as_mut_ptr() as *mut _,
+            UserDataLength: u16::MAX,
+            ..Default::default()
+        };
+        assert_malformed(EventRecord(&event_record).validated_userdata());
+    }
+
+    #[test]
+    fn test_inconsistent_extended_data_is_malformed() {
+        let event_record = EVENT_RECORD {
+            ExtendedData: ptr::null_mut(),
+            ExtendedDataCount: 2,
+            ..Default::default()
+        };
+        let record = EventRecord(&event_record);
+        assert_malformed(record.validated_extended_data());
+        assert!(record.extended_data_item(EVENT_HEADER_EXT_TYPE_PROV_TRAITS).is_none());
+
+        let mut items = [EVENT_HEADER_EXTENDED_DATA_ITEM {
+            ExtType: EVENT_HEADER_EXT_TYPE_PROV_TRAITS as u16,
+            DataSize: 8,
+            DataPtr: 0,
+            ..Default::default()
+        }];
+        let event_record = EVENT_RECORD {
+            ExtendedData: items.as_mut_ptr(),
+            ExtendedDataCount: 1,
+            ..Default::default()
+        };
+        let record = EventRecord(&event_record);
+        assert_malformed(record.validated_extended_data());
+        assert!(record.provider_traits().is_none());
+    }
+
+    #[test]
+    fn test_malformed_string_event_fails_to_parse() {
+        let mut event_record = EVENT_RECORD {
+            UserData: ptr::null_mut(),
+            UserDataLength: 4,
+            ..Default::default()
+        };
+        event_record.EventHeader.Flags = EVENT_HEADER_FLAG_STRING_ONLY as u16;
+        assert!(matches!(
+            Event::parse(&event_record),
+            Err(TraceError::Decode(ParseError::MalformedRecord(_)))
+        ));
+    }
+
+    fn string_event(userdata: &mut [u8]) -> EVENT_RECORD {
+        let mut event_record = EVENT_RECORD {
+            UserData: userdata.as_mut_ptr() as *mut _,
+            UserDataLength: userdata.len().try_into().unwrap(),
+            ..Default::default()
+        };
+        event_record.EventHeader.Flags = EVENT_HEADER_FLAG_STRING_ONLY as u16;
+        event_record
+    }
+
+    #[test]
+    fn test_string_event_parses_to_string() {
+        let mut userdata = "auto flush\0".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let event_record = string_event(&mut userdata);
+        let (schema, event) = Event::parse(&event_record).unwrap();
+        assert!(schema.is_opaque());
+        let StringOrStruct::String(string) = &event.data else {
+            panic!("Expected a string, got {:?}", event.data);
+        };
+        assert_eq!(string.to_string().unwrap(), "auto flush");
+        assert!(Arc::ptr_eq(&schema, &Event::parse(&event_record).unwrap().0));
+    }
+
+    #[test]
+    fn test_string_event_with_trailing_bytes_fails_to_parse() {
+        for mut userdata in [
+            "abc\0x".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>(),
+            vec![b'a', 0, b'b'],
+        ] {
+            let event_record = string_event(&mut userdata);
+            assert!(matches!(
+                Event::parse(&event_record),
+                Err(TraceError::Decode(ParseError::DataLeftAfterDecoding))
+            ));
+        }
+    }
+
+    fn traits_blob(name: &str, traits: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0, 0];
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        for (trait_type, trait_data) in traits {
+            data.extend_from_slice(&u16::try_from(trait_data.len() + 3).unwrap().to_le_bytes());
+            data.push(*trait_type);
+            data.extend_from_slice(trait_data);
+        }
+        let total_size = u16::try_from(data.len()).unwrap().to_le_bytes();
+        data[..2].copy_from_slice(&total_size);
+        data
+    }
+
+    #[test]
+    fn test_provider_traits_with_group() {
+        let group = GUID::from_u128(0x4f50731a_89cf_4782_b3e0_dce8c90476ba);
+        let mut group_bytes = Vec::new();
+        group_bytes.extend_from_slice(&group.data1.to_le_bytes());
+        group_bytes.extend_from_slice(&group.data2.to_le_bytes());
+        group_bytes.extend_from_slice(&group.data3.to_le_bytes());
+        group_bytes.extend_from_slice(&group.data4);
+
+        let data = traits_blob("MyCompany.MyComponent", &[(2, &[1, 2, 3]), (1, &group_bytes)]);
+        let traits = ProviderTraits::parse(&data).unwrap();
+        assert_eq!(traits.name.as_deref(), Some("MyCompany.MyComponent"));
+        assert_eq!(traits.group_guid, Some(group));
+    }
+
+    #[test]
+    fn test_provider_traits_without_group() {
+        let data = traits_blob("MyProvider", &[]);
+        let traits = ProviderTraits::parse(&data).unwrap();
+        assert_eq!(traits.name.as_deref(), Some("MyProvider"));
+        assert_eq!(traits.group_guid, None);
+    }
+
+    #[test]
+    fn test_provider_traits_truncated() {
+        assert!(ProviderTraits::parse(&[]).is_none());
+        // Total size claims more data than present and the name isn't terminated
+        assert!(ProviderTraits::parse(&[0x10, 0x00, b'a', b'b']).is_none());
+    }
+
+    #[test]
+    fn test_wpp_event_carries_raw_arguments() {
+        let message_guid = GUID::from_u128(0x3f6a2b1c_5d4e_4f70_8a9b_0c1d2e3f4a5b);
+        let mut arguments = 42u32.to_le_bytes();
+        let mut event_record = EVENT_RECORD {
+            UserData: arguments.as_mut_ptr() as *mut _,
+            UserDataLength: 4,
+            ..Default::default()
+        };
+        event_record.EventHeader.Flags = EVENT_HEADER_FLAG_TRACE_MESSAGE as u16;
+        event_record.EventHeader.ProviderId = message_guid;
+        event_record.EventHeader.EventDescriptor.Id = 17;
+        event_record.EventHeader.EventDescriptor.Level = 4;
+        event_record.EventHeader.EventDescriptor.Keyword = 0x2;
+
+        let (schema, event) = Event::parse(&event_record).unwrap();
+        assert!(schema.is_opaque());
+        let StringOrStruct::Wpp(message) = &event.data else {
+            panic!("Expected a WPP message, got {:?}", event.data);
+        };
+        assert_eq!(
+            *message,
+            WppMessage {
+                message_guid,
+                message_number: 17,
+                level: 4,
+                flags: 0x2,
+                header: None,
+                arguments: &42u32.to_le_bytes(),
+                formatted: None,
+            }
+        );
+
+        let formatter = |message: &WppMessage| {
+            let value = u32::from_le_bytes(message.arguments.try_into().ok()?);
+            Some(format!("message {} value {}", message.message_number, value))
+        };
+        let (formatted_schema, event) = Event::parse_wpp(&event_record, Some(&formatter)).unwrap();
+        assert!(Arc::ptr_eq(&schema, &formatted_schema));
+        let StringOrStruct::Wpp(message) = event.data else {
+            panic!("Expected a WPP message");
+        };
+        assert_eq!(message.formatted.as_deref(), Some("message 17 value 42"));
+    }
+
+    #[test]
+    fn test_wpp_event_splits_trace_message_header() {
+        // MESSAGE_TRACE_HEADER of message 17 with sequence, GUID, timestamp and system
+        // info, followed by a u32 argument
+        let mut userdata = decode_hex(concat!(
+            "3000", "00", "00", "1100", "2b00",
+            "07000000",
+            "1c2b6a3f4e5d704f8a9b0c1d2e3f4a5b",
+            "0080d6de2e7cd901",
+            "e8030000", "d0070000",
+            "2a000000",
+        ))
+        .unwrap();
+        let mut event_record = EVENT_RECORD {
+            UserData: userdata.as_mut_ptr() as *mut _,
+            UserDataLength: userdata.len().try_into().unwrap(),
+            ..Default::default()
+        };
+        event_record.EventHeader.Flags = EVENT_HEADER_FLAG_TRACE_MESSAGE as u16;
+        event_record.EventHeader.EventDescriptor.Id = 17;
+
+        let (_, event) = Event::parse(&event_record).unwrap();
+        let StringOrStruct::Wpp(message) = &event.data else {
+            panic!("Expected a WPP message, got {:?}", event.data);
+        };
+        assert_eq!(
+            message.header,
+            Some(TraceMessageHeader {
+                message_flags: 0x2b,
+                sequence: Some(7),
+                message_guid: Some(GUID::from_u128(0x3f6a2b1c_5d4e_4f70_8a9b_0c1d2e3f4a5b)),
+                component_id: None,
+                timestamp: Some(0x01d9_7c2e_ded6_8000),
+                thread_id: Some(1000),
+                process_id: Some(2000),
+            })
+        );
+        assert_eq!(message.sequence(), Some(7));
+        assert_eq!(message.arguments, &42u32.to_le_bytes());
+
+        // A header of another message is left to the arguments
+        event_record.EventHeader.EventDescriptor.Id = 18;
+        let (_, event) = Event::parse(&event_record).unwrap();
+        let StringOrStruct::Wpp(message) = event.data else {
+            panic!("Expected a WPP message");
+        };
+        assert_eq!(message.header, None);
+        assert_eq!(message.arguments.len(), userdata.len());
+    }
+
+    fn extended_item(ext_type: u32, data: &[u8]) -> EVENT_HEADER_EXTENDED_DATA_ITEM {
+        EVENT_HEADER_EXTENDED_DATA_ITEM {
+            ExtType: ext_type as u16,
+            DataSize: data.len().try_into().unwrap(),
+            DataPtr: data.as_ptr() as u64,
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn test_extended_data_items_are_decoded() {
+        let activity = GUID::from_u128(0x0d3e5f4a_1b2c_4d5e_8f90_a1b2c3d4e5f6);
+        let mut activity_bytes = Vec::new();
+        activity_bytes.extend_from_slice(&activity.data1.to_le_bytes());
+        activity_bytes.extend_from_slice(&activity.data2.to_le_bytes());
+        activity_bytes.extend_from_slice(&activity.data3.to_le_bytes());
+        activity_bytes.extend_from_slice(&activity.data4);
+        // S-1-5-18
+        let sid = [1u8, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+        let stack64 = [7u64, 0x7ff6_1234_5678, 0x7ff6_8765_4321]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut stack32 = 9u64.to_le_bytes().to_vec();
+        stack32.extend_from_slice(&0x0040_1000u32.to_le_bytes());
+        let ts_id = 3u32.to_le_bytes();
+        let unknown = [0xaau8, 0xbb];
+        let truncated_ts_id = [1u8];
+
+        let mut items = [
+            extended_item(EVENT_HEADER_EXT_TYPE_RELATED_ACTIVITYID, &activity_bytes),
+            extended_item(EVENT_HEADER_EXT_TYPE_SID, &sid),
+            extended_item(EVENT_HEADER_EXT_TYPE_STACK_TRACE64, &stack64),
+            extended_item(EVENT_HEADER_EXT_TYPE_STACK_TRACE32, &stack32),
+            extended_item(EVENT_HEADER_EXT_TYPE_TS_ID, &ts_id),
+            extended_item(0x7ff0, &unknown),
+            extended_item(EVENT_HEADER_EXT_TYPE_TS_ID, &truncated_ts_id),
+        ];
+        let event_record = EVENT_RECORD {
+            ExtendedData: items.as_mut_ptr(),
+            ExtendedDataCount: items.len().try_into().unwrap(),
+            ..Default::default()
+        };
+        let items = EventRecord(&event_record).extended_data().collect::<Vec<_>>();
+        assert_eq!(items.len(), 7);
+        assert!(matches!(items[0], ExtendedDataItem::RelatedActivityId(guid) if guid == activity));
+        assert!(matches!(&items[1], ExtendedDataItem::Sid(sid) if sid.size() == 12));
+        assert!(matches!(
+            &items[2],
+            ExtendedDataItem::StackTrace64 { matched_id: 7, addresses } if addresses == &[0x7ff6_1234_5678, 0x7ff6_8765_4321]
+        ));
+        assert!(matches!(
+            &items[3],
+            ExtendedDataItem::StackTrace32 { matched_id: 9, addresses } if addresses == &[0x0040_1000]
+        ));
+        assert!(matches!(items[4], ExtendedDataItem::TsId(3)));
+        assert!(matches!(items[5], ExtendedDataItem::Raw { ext_type: 0x7ff0, bytes: [0xaa, 0xbb] }));
+        assert!(matches!(
+            items[6],
+            ExtendedDataItem::Raw { ext_type, bytes: [1] } if u32::from(ext_type) == EVENT_HEADER_EXT_TYPE_TS_ID
+        ));
+    }
+
+    fn guid_bytes(guid: &GUID) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&guid.data1.to_le_bytes());
+        bytes.extend_from_slice(&guid.data2.to_le_bytes());
+        bytes.extend_from_slice(&guid.data3.to_le_bytes());
+        bytes.extend_from_slice(&guid.data4);
+        bytes
+    }
+
+    #[test]
+    fn test_malformed_extended_data_items_are_skipped() {
+        let activity = GUID::from_u128(0x0d3e5f4a_1b2c_4d5e_8f90_a1b2c3d4e5f6);
+        let container = GUID::from_u128(0x11112222_3333_4444_5555_666677778888);
+        let parent = GUID::from_u128(0x42);
+        let activity_bytes = guid_bytes(&activity);
+        let container_bytes = guid_bytes(&container);
+        let mut instance = 5u32.to_le_bytes().to_vec();
+        instance.extend_from_slice(&4u32.to_le_bytes());
+        instance.extend_from_slice(&guid_bytes(&parent));
+
+        let mut items = [
+            EVENT_HEADER_EXTENDED_DATA_ITEM {
+                ExtType: EVENT_HEADER_EXT_TYPE_SID as u16,
+                DataSize: 12,
+                DataPtr: 0,
+                ..Default::default()
+            },
+            extended_item(EVENT_HEADER_EXT_TYPE_INSTANCE_INFO, &instance),
+            extended_item(EVENT_HEADER_EXT_TYPE_CONTAINER_ID, &container_bytes),
+            extended_item(EVENT_HEADER_EXT_TYPE_RELATED_ACTIVITYID, &activity_bytes),
+        ];
+        let event_record = EVENT_RECORD {
+            ExtendedData: items.as_mut_ptr(),
+            ExtendedDataCount: items.len().try_into().unwrap(),
+            ..Default::default()
+        };
+        let record = EventRecord(&event_record);
+        let items = record.extended_data().collect::<Vec<_>>();
+        assert_eq!(items.len(), 3);
+        assert!(matches!(
+            items[0],
+            ExtendedDataItem::InstanceInfo { instance_id: 5, parent_instance_id: 4, parent_guid } if parent_guid == parent
+        ));
+        assert!(matches!(items[1], ExtendedDataItem::ContainerId(guid) if guid == container));
+        assert!(matches!(items[2], ExtendedDataItem::RelatedActivityId(guid) if guid == activity));
+        // The typed accessors skip the same items
+        assert_eq!(record.related_activity_id(), Some(activity));
+        assert_malformed(record.validated_extended_data());
+
+        let event_record = EVENT_RECORD {
+            ExtendedData: ptr::null_mut(),
+            ExtendedDataCount: 2,
+            ..Default::default()
+        };
+        assert_eq!(EventRecord(&event_record).extended_data().count(), 0);
+        assert_eq!(EventRecord(&event_record).related_activity_id(), None);
+    }
+
+    #[test]
+    fn test_related_activity_id() {
+        let activity = GUID::from_u128(0x0d3e5f4a_1b2c_4d5e_8f90_a1b2c3d4e5f6);
+        let activity_bytes = guid_bytes(&activity);
+        let mut items = [extended_item(EVENT_HEADER_EXT_TYPE_RELATED_ACTIVITYID, &activity_bytes)];
+        let event_record = EVENT_RECORD {
+            ExtendedData: items.as_mut_ptr(),
+            ExtendedDataCount: 1,
+            ..Default::default()
+        };
+        assert_eq!(EventRecord(&event_record).related_activity_id(), Some(activity));
+        assert_eq!(EventRecord(&EVENT_RECORD::default()).related_activity_id(), None);
+    }
 }
@@ -0,0 +1,640 @@
+//! A flattened representation of decoded events, with properties keyed by name.
+//!
+//! Unlike [`crate::values::event::EventOwned`], which mirrors the decoded structure
+//! and round-trips through serde, [`FlatEvent`] is meant for output: serialized with
+//! the `serde` feature, integers become numbers, strings and GUIDs strings, binary
+//! data hex strings unless [`RenderOptions::binary`] says otherwise, and arrays
+//! sequences. With the `json` feature, [`Event::to_json`] builds a JSON object from
+//! it, and embedded documents of string properties marked [`OutType::Json`] become
+//! nested JSON values.
+
+use std::collections::HashMap;
+
+use windows::core::GUID;
+
+use crate::{
+    keywords::{KeywordResolver, Keywords},
+    schema::{
+        cache::{EventInfo, PropertyInfo, PropertyNestedInfo, PropertyStructInfo, StringOrIntegerMap},
+        out_type::OutType,
+    },
+};
+
+use super::{
+    compound::{StringOrStruct, Struct, StructOrValue},
+    event::Event,
+    in_value::InValue,
+    mapped::MappedValues,
+    render::RenderOptions,
+    value::Value,
+};
+
+/// How [`FlatEvent::with_options`] flattens values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlatOptions {
+    /// Replace values whose property has a value map in [`EventInfo::maps`] with the
+    /// names the map assigns to them.
+    pub resolve_maps: bool,
+    /// Keep the values replaced by names as well, as `<name>_raw`.
+    pub raw_values: bool,
+    /// Name the keywords of the event in [`FlatEvent::keywords`]. The provider's keywords
+    /// are looked up with [`KeywordResolver::global`], which queries TDH on first use.
+    pub resolve_keywords: bool,
+    /// The special cases applied to values rendered as strings, see [`RenderOptions`].
+    pub render: RenderOptions,
+}
+
+/// The maps of the event being flattened, if they are resolved.
+#[derive(Clone, Copy)]
+struct Maps<'s> {
+    maps: &'s HashMap<String, StringOrIntegerMap>,
+    raw_values: bool,
+}
+
+/// A value flattened to the representations self-describing formats like JSON have.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(untagged))]
+pub enum FlatValue {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Guid(#[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde::guid::serialize"))] GUID),
+    String(String),
+    Array(Vec<FlatValue>),
+    Struct(FlatStruct),
+    /// The parsed document of a string property marked [`OutType::Json`], so it isn't
+    /// escaped into a string again.
+    #[cfg(feature = "json")]
+    Json(serde_json::Value),
+}
+
+impl FlatValue {
+    /// Flatten each element of `value`.
+    ///
+    /// Integers, floats, booleans, GUIDs and strings keep their type, binary data is
+    /// rendered as [`RenderOptions::binary`] says and all other values are rendered
+    /// with their out-type, see [`Value::render_with_options`].
+    fn elements(value: &Value<'_>, render: &RenderOptions) -> Vec<Self> {
+        let in_value = &value.value;
+        if let Ok(elements) = in_value.iter_u64() {
+            return elements.map(Self::Unsigned).collect();
+        }
+        if let Ok(elements) = in_value.iter_i64() {
+            return elements.map(Self::Signed).collect();
+        }
+        if let Ok(elements) = in_value.iter_strings() {
+            return elements.map(Self::String).collect();
+        }
+        let count = in_value.element_count();
+        match in_value {
+            InValue::Null => Vec::new(),
+            InValue::Boolean(value) => (0..count).filter_map(|idx| value.get(idx)).map(|value| Self::Bool(value != 0)).collect(),
+            InValue::Float(value) => (0..count).filter_map(|idx| value.get(idx)).map(|value| Self::Float(value.into())).collect(),
+            InValue::Double(value) => (0..count).filter_map(|idx| value.get(idx)).map(Self::Float).collect(),
+            InValue::Guid(value) => (0..count).filter_map(|idx| value.get(idx)).map(Self::Guid).collect(),
+            InValue::Binary(blobs) => blobs.iter().map(|blob| Self::String(render.binary.render(blob))).collect(),
+            InValue::HexDump(data) => vec![Self::String(render.binary.render(data))],
+            _ => match value.render_with_options(value.out_type.unwrap_or(OutType::Null), render) {
+                Ok(rendered) => rendered.elements().iter().cloned().map(Self::String).collect(),
+                Err(_) => vec![Self::String(render.binary.render(value.raw()))],
+            },
+        }
+    }
+
+    fn from_mapped(mapped: &MappedValues) -> Self {
+        match mapped {
+            MappedValues::Scalar(value) => Self::String(value.to_string()),
+            MappedValues::Array(values) => Self::Array(values.iter().map(|value| Self::String(value.to_string())).collect()),
+            MappedValues::String(value) => Self::String(value.to_string()),
+            MappedValues::StringArray(values) => {
+                Self::Array(values.iter().map(|value| Self::String(value.to_string())).collect())
+            }
+        }
+    }
+
+    /// Arrays become [`FlatValue::Array`], scalars their single element.
+    fn from_value(value: &Value<'_>, render: &RenderOptions) -> Self {
+        if render.multi_sz
+            && value.out_type == Some(OutType::HexBinary)
+            && !value.is_array
+            && let Some(strings) = value.as_multi_sz()
+        {
+            return Self::Array(strings.into_iter().map(|string| Self::String(string.into_owned())).collect());
+        }
+        #[cfg(feature = "json")]
+        if let Some(Ok(document)) = value.out_type.and_then(|out_type| value.embedded_json(out_type)) {
+            return Self::Json(document);
+        }
+        let mut elements = Self::elements(value, render);
+        if value.is_array {
+            Self::Array(elements)
+        } else if elements.is_empty() {
+            Self::Null
+        } else {
+            elements.swap_remove(0)
+        }
+    }
+
+    fn from_struct_or_value(
+        property: &PropertyInfo,
+        value: &StructOrValue<'_>,
+        maps: Option<Maps<'_>>,
+        render: &RenderOptions,
+    ) -> Self {
+        match (&property.value, value) {
+            (_, StructOrValue::Value(value)) => Self::from_value(value, render),
+            (PropertyNestedInfo::Struct(_, schema), StructOrValue::Struct(array)) => {
+                let mut members = array
+                    .values
+                    .iter()
+                    .map(|member| Self::Struct(FlatStruct::build(schema, member, maps, render)));
+                if array.is_array {
+                    Self::Array(members.collect())
+                } else {
+                    members.next().unwrap_or(Self::Null)
+                }
+            }
+            // A struct decoded without a struct schema has no member names.
+            (_, StructOrValue::Struct(_)) => Self::Null,
+        }
+    }
+}
+
+/// Arrays become [`FlatValue::Array`], scalars their single element.
+impl From<&Value<'_>> for FlatValue {
+    fn from(value: &Value<'_>) -> Self {
+        Self::from_value(value, &RenderOptions::default())
+    }
+}
+
+/// The fields of a struct by name, in schema order. Serialized as a map.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlatStruct {
+    pub fields: Vec<(String, FlatValue)>,
+}
+
+impl FlatStruct {
+    /// Flatten `values`, which were decoded with `schema`.
+    pub fn new(schema: &PropertyStructInfo, values: &Struct<'_>) -> Self {
+        Self::build(schema, values, None, &RenderOptions::default())
+    }
+
+    fn build(
+        schema: &PropertyStructInfo,
+        values: &Struct<'_>,
+        maps: Option<Maps<'_>>,
+        render: &RenderOptions,
+    ) -> Self {
+        let mut fields = Vec::with_capacity(values.len());
+        for (property, value) in schema.fields.iter().zip(values.iter()) {
+            let name = property.value.name();
+            let mapped = match (maps, &property.value, value) {
+                (Some(maps), PropertyNestedInfo::Value(_, value_info), StructOrValue::Value(value)) => value_info
+                    .map_name
+                    .as_ref()
+                    .and_then(|map_name| maps.maps.get(map_name))
+                    .and_then(|map| value.resolve_map(map))
+                    .map(|mapped| (maps.raw_values, value, mapped)),
+                _ => None,
+            };
+            match mapped {
+                Some((raw_values, value, mapped)) => {
+                    fields.push((name.to_string(), FlatValue::from_mapped(&mapped)));
+                    if raw_values {
+                        fields.push((format!("{}_raw", name), FlatValue::from_value(value, render)));
+                    }
+                }
+                None => fields.push((name.to_string(), FlatValue::from_struct_or_value(property, value, maps, render))),
+            }
+        }
+        Self { fields }
+    }
+
+    /// The first field named `name`.
+    pub fn get(&self, name: &str) -> Option<&FlatValue> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FlatStruct {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for (name, value) in &self.fields {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+/// An [`Event`] flattened with its schema, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FlatEvent {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serde::guid::serialize"))]
+    pub provider_id: GUID,
+    pub event_id: u16,
+    pub version: u8,
+    pub process_id: u32,
+    pub thread_id: u32,
+    pub timestamp: i64,
+    /// The message of string and formatted WPP events.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub message: Option<String>,
+    /// Payload of events without properties and of unformatted WPP events, rendered as
+    /// [`RenderOptions::binary`] says.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub payload: Option<String>,
+    /// The decoded top-level properties; only the decoded ones for partial events.
+    pub properties: FlatStruct,
+    /// The event's keywords, if [`FlatOptions::resolve_keywords`] is set. Displayed like
+    /// `Connection|Errors (0x30)`, serialized as the array of [`Keywords::elements`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(skip_serializing_if = "Option::is_none", serialize_with = "serialize_keywords")
+    )]
+    pub keywords: Option<Keywords>,
+}
+
+#[cfg(feature = "serde")]
+fn serialize_keywords<S>(keywords: &Option<Keywords>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serde::Serialize::serialize(&keywords.as_ref().map(Keywords::elements), serializer)
+}
+
+impl FlatEvent {
+    /// Flatten `event`, which was decoded with `schema`.
+    pub fn new(event: &Event<'_>, schema: &EventInfo) -> Self {
+        Self::with_options(event, schema, &FlatOptions::default())
+    }
+
+    /// Like [`FlatEvent::new`], with the values flattened as `options` says.
+    pub fn with_options(event: &Event<'_>, schema: &EventInfo, options: &FlatOptions) -> Self {
+        let maps = options.resolve_maps.then_some(Maps {
+            maps: &schema.maps,
+            raw_values: options.raw_values,
+        });
+        let header = &event.header;
+        let descriptor = header.event_descriptor();
+        let mut flat = Self {
+            provider_id: *header.provider_id(),
+            event_id: descriptor.id(),
+            version: descriptor.version(),
+            process_id: header.process_id(),
+            thread_id: header.thread_id(),
+            timestamp: header.timestamp(),
+            message: None,
+            payload: None,
+            properties: FlatStruct::default(),
+            keywords: options
+                .resolve_keywords
+                .then(|| KeywordResolver::global().resolve(header.provider_id(), descriptor.keyword())),
+        };
+        match &event.data {
+            StringOrStruct::String(string) => {
+                flat.message = Some(String::from_utf16_lossy(&string.to_vec()).trim_end_matches('\0').to_string());
+            }
+            StringOrStruct::Struct(values) | StringOrStruct::Partial(values, _) => {
+                flat.properties = FlatStruct::build(&schema.properties, values, maps, &options.render);
+            }
+            StringOrStruct::Opaque(data) => flat.payload = Some(options.render.binary.render(data)),
+            StringOrStruct::Wpp(message) => match &message.formatted {
+                Some(formatted) => flat.message = Some(formatted.clone()),
+                None => flat.payload = Some(options.render.binary.render(message.arguments)),
+            },
+        }
+        flat
+    }
+}
+
+/// Flatten an event with its schema, as returned by [`Event::parse`].
+impl From<(&EventInfo, &Event<'_>)> for FlatEvent {
+    fn from((schema, event): (&EventInfo, &Event<'_>)) -> Self {
+        Self::new(event, schema)
+    }
+}
+
+#[cfg(feature = "json")]
+impl Event<'_> {
+    /// The event as a JSON object with its header fields and a `properties` object,
+    /// see [`FlatEvent`]. Values with a value map are replaced by their names.
+    pub fn to_json(&self, schema: &EventInfo) -> serde_json::Value {
+        let options = FlatOptions {
+            resolve_maps: true,
+            ..Default::default()
+        };
+        self.to_json_with_options(schema, &options)
+    }
+
+    /// Like [`Event::to_json`], with the values flattened as `options` says.
+    pub fn to_json_with_options(&self, schema: &EventInfo, options: &FlatOptions) -> serde_json::Value {
+        // Serializing only fails for maps with non-string keys, which FlatEvent doesn't have.
+        serde_json::to_value(FlatEvent::with_options(self, schema, options)).expect("FlatEvent is serializable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "json")]
+    use std::collections::HashMap;
+
+    use windows::{core::GUID, Win32::System::Diagnostics::Etw::EVENT_HEADER};
+
+    #[cfg(feature = "json")]
+    use crate::schema::cache::StringOrIntegerMap;
+    use crate::{
+        keywords::{KeywordResolver, KeywordTable},
+        schema::{
+            cache::{EventInfo, PropertyInfo, PropertyNestedInfo, PropertyStructInfo, PropertyValue},
+            in_type::InType,
+            out_type::OutType,
+        },
+        values::event::{Event, Header},
+    };
+
+    use super::{FlatEvent, FlatOptions, FlatValue};
+    use crate::values::{compound::StringOrStruct, render::{BinaryRendering, RenderOptions}};
+
+    fn schema() -> EventInfo {
+        let guid = GUID::from_u128(0x6a3e1f90_2b7c_4d15_8e0a_9c4b7f2d1e63);
+        EventInfo::new(
+            guid,
+            7,
+            1,
+            PropertyStructInfo::new(vec![
+                PropertyInfo::value("Count", InType::UInt32, OutType::UnsignedInt),
+                PropertyInfo::value("Delta", InType::Int16, OutType::Short),
+                PropertyInfo::value("Name", InType::UnicodeString, OutType::String),
+                PropertyInfo::value("Id", InType::Guid, OutType::Guid),
+                PropertyInfo::value("Blob", InType::Binary, OutType::HexBinary).with_length(2),
+                PropertyInfo::value("Ports", InType::UInt16, OutType::UnsignedShort).with_count(2),
+                PropertyInfo {
+                    length: PropertyValue::Constant(0),
+                    count: PropertyValue::Constant(1),
+                    is_array: false,
+                    value: PropertyNestedInfo::Struct(
+                        "Inner".to_string(),
+                        PropertyStructInfo::new(vec![PropertyInfo::value("Flag", InType::Boolean, OutType::Boolean)]),
+                    ),
+                },
+            ]),
+        )
+    }
+
+    fn userdata(schema: &EventInfo) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(42u32.to_le_bytes());
+        data.extend((-3i16).to_le_bytes());
+        data.extend("ab\0".encode_utf16().flat_map(u16::to_le_bytes));
+        let guid = schema.provider_guid;
+        data.extend(guid.data1.to_le_bytes());
+        data.extend(guid.data2.to_le_bytes());
+        data.extend(guid.data3.to_le_bytes());
+        data.extend(guid.data4);
+        data.extend([0xde, 0xad]);
+        data.extend([1, 0, 2, 0]);
+        data.extend(1u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_flatten_event_by_property_name() {
+        let schema = schema();
+        let data = userdata(&schema);
+        let mut header = EVENT_HEADER::default();
+        header.ProviderId = schema.provider_guid;
+        header.EventDescriptor.Id = 7;
+        header.EventDescriptor.Version = 1;
+        header.ProcessId = 4;
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&data).unwrap(),
+        };
+
+        let flat = FlatEvent::from((&schema, &event));
+        assert_eq!(flat.event_id, 7);
+        assert_eq!(flat.process_id, 4);
+        assert_eq!(flat.properties.len(), 7);
+        assert_eq!(flat.properties.get("Count"), Some(&FlatValue::Unsigned(42)));
+        assert_eq!(flat.properties.get("Delta"), Some(&FlatValue::Signed(-3)));
+        assert_eq!(flat.properties.get("Name"), Some(&FlatValue::String("ab".to_string())));
+        assert_eq!(flat.properties.get("Id"), Some(&FlatValue::Guid(schema.provider_guid)));
+        assert_eq!(flat.properties.get("Blob"), Some(&FlatValue::String("0xDEAD".to_string())));
+        assert_eq!(
+            flat.properties.get("Ports"),
+            Some(&FlatValue::Array(vec![FlatValue::Unsigned(1), FlatValue::Unsigned(2)]))
+        );
+        let Some(FlatValue::Struct(inner)) = flat.properties.get("Inner") else {
+            panic!("Expected a struct");
+        };
+        assert_eq!(inner.get("Flag"), Some(&FlatValue::Bool(true)));
+        assert_eq!(flat.properties.get("Missing"), None);
+    }
+
+    #[test]
+    fn test_flat_event_multi_sz_behind_option() {
+        let schema = EventInfo::new(
+            GUID::from_u128(0x6a3e1f90_2b7c_4d15_8e0a_9c4b7f2d1e63),
+            8,
+            0,
+            PropertyStructInfo::new(vec![PropertyInfo::value("Data", InType::Binary, OutType::HexBinary)]),
+        );
+        let data = "a\0bc\0\0".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let event = Event {
+            header: Header::from(&EVENT_HEADER::default()),
+            data: schema.decode_userdata(&data).unwrap(),
+        };
+
+        let flat = FlatEvent::new(&event, &schema);
+        assert_eq!(flat.properties.get("Data"), Some(&FlatValue::String("0x610000006200630000000000".to_string())));
+        let options = FlatOptions {
+            render: RenderOptions {
+                multi_sz: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let flat = FlatEvent::with_options(&event, &schema, &options);
+        assert_eq!(
+            flat.properties.get("Data"),
+            Some(&FlatValue::Array(vec![FlatValue::String("a".to_string()), FlatValue::String("bc".to_string())]))
+        );
+    }
+
+    #[test]
+    fn test_flat_event_opaque_payload_follows_binary_option() {
+        let schema = EventInfo::new(GUID::zeroed(), 9, 0, PropertyStructInfo::new(Vec::new()));
+        let data = [0xde, 0xad, 0xbe, 0xef];
+        let event = Event {
+            header: Header::from(&EVENT_HEADER::default()),
+            data: StringOrStruct::Opaque(&data),
+        };
+
+        assert_eq!(FlatEvent::new(&event, &schema).payload.as_deref(), Some("0xDEADBEEF"));
+        let options = FlatOptions {
+            render: RenderOptions {
+                binary: BinaryRendering::Base64,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(FlatEvent::with_options(&event, &schema, &options).payload.as_deref(), Some("3q2+7w=="));
+        let options = FlatOptions {
+            render: RenderOptions {
+                binary: BinaryRendering::Truncated(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(FlatEvent::with_options(&event, &schema, &options).payload.as_deref(), Some("0xDE... (4 bytes)"));
+    }
+
+    #[test]
+    fn test_flat_event_keywords_behind_option() {
+        let provider = GUID::from_u128(0x2c9d_5e71_8a04_4b3f_b6e2_1f7a_0d93_c458);
+        KeywordResolver::global().insert(
+            provider,
+            KeywordTable::new([("Connection".to_string(), 0x10), ("Errors".to_string(), 0x20)]),
+        );
+        let schema = schema();
+        let data = userdata(&schema);
+        let mut header = EVENT_HEADER::default();
+        header.ProviderId = provider;
+        header.EventDescriptor.Keyword = 0x30;
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&data).unwrap(),
+        };
+
+        assert_eq!(FlatEvent::new(&event, &schema).keywords, None);
+        let options = FlatOptions {
+            resolve_keywords: true,
+            ..Default::default()
+        };
+        let flat = FlatEvent::with_options(&event, &schema, &options);
+        let keywords = flat.keywords.as_ref().unwrap();
+        assert_eq!(format!("Keywords: {}", keywords), "Keywords: Connection|Errors (0x30)");
+        #[cfg(feature = "json")]
+        {
+            let json = serde_json::to_value(&flat).unwrap();
+            assert_eq!(json["keywords"], serde_json::json!(["Connection", "Errors"]));
+            assert!(serde_json::to_value(FlatEvent::new(&event, &schema)).unwrap().get("keywords").is_none());
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_flat_event_serializes_to_json() {
+        let schema = schema();
+        let data = userdata(&schema);
+        let header = EVENT_HEADER::default();
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&data).unwrap(),
+        };
+
+        let json = serde_json::to_value(FlatEvent::new(&event, &schema)).unwrap();
+        assert_eq!(json["provider_id"], "00000000-0000-0000-0000-000000000000");
+        assert!(json.get("message").is_none());
+        assert_eq!(
+            json["properties"],
+            serde_json::json!({
+                "Count": 42,
+                "Delta": -3,
+                "Name": "ab",
+                "Id": "6a3e1f90-2b7c-4d15-8e0a-9c4b7f2d1e63",
+                "Blob": "0xDEAD",
+                "Ports": [1, 2],
+                "Inner": { "Flag": true },
+            })
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_embedded_json_is_nested() {
+        let schema = EventInfo::new(
+            GUID::from_u128(0x6a3e1f90_2b7c_4d15_8e0a_9c4b7f2d1e63),
+            9,
+            0,
+            PropertyStructInfo::new(vec![
+                PropertyInfo::value("Document", InType::UnicodeString, OutType::Json),
+                PropertyInfo::value("Broken", InType::UnicodeString, OutType::Json),
+            ]),
+        );
+        let data = "{\"a\":[1,2]}\0{\0"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        let event = Event {
+            header: Header::from(&EVENT_HEADER::default()),
+            data: schema.decode_userdata(&data).unwrap(),
+        };
+
+        let json = event.to_json(&schema);
+        assert_eq!(json["properties"]["Document"], serde_json::json!({ "a": [1, 2] }));
+        assert_eq!(json["properties"]["Broken"], "{");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_event_to_json_matches_golden_file() {
+        let mut schema = schema();
+        let PropertyNestedInfo::Value(_, count_info) = &mut schema.properties.fields[0].value else {
+            unreachable!();
+        };
+        count_info.map_name = Some("CountMap".to_string());
+        schema.maps.insert(
+            "CountMap".to_string(),
+            StringOrIntegerMap::Integer(HashMap::from([(42, "Answer".to_string())])),
+        );
+        let data = userdata(&schema);
+        let mut header = EVENT_HEADER::default();
+        header.ProviderId = schema.provider_guid;
+        header.EventDescriptor.Id = 7;
+        header.EventDescriptor.Version = 1;
+        header.ProcessId = 1234;
+        header.ThreadId = 5678;
+        header.TimeStamp = 133_000_000_000_000_000;
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&data).unwrap(),
+        };
+
+        let golden: serde_json::Value =
+            serde_json::from_str(include_str!("../../tests/resources/flat_event.json")).unwrap();
+        assert_eq!(event.to_json(&schema), golden);
+
+        let options = FlatOptions {
+            resolve_maps: true,
+            raw_values: true,
+            ..Default::default()
+        };
+        let json = event.to_json_with_options(&schema, &options);
+        assert_eq!(json["properties"]["Count"], "Answer");
+        assert_eq!(json["properties"]["Count_raw"], 42);
+        let json = event.to_json_with_options(&schema, &FlatOptions::default());
+        assert_eq!(json["properties"]["Count"], 42);
+        assert!(json["properties"].get("Count_raw").is_none());
+    }
+}
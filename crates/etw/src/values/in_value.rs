@@ -1,11 +1,17 @@
+use windows::{
+    core::GUID,
+    Win32::Foundation::FILETIME,
+};
+
 use crate::schema::in_type::InType;
 
 use super::{
-    misc::Sid,
+    misc::{Sid, WbemSid},
     primitives::{
         DoubleRef, FileTimeRef, FloatRef, GuidRef, Int16Ref, Int32Ref, Int64Ref, Int8Ref,
-        SystemTimeRef, UInt16Ref, UInt32Ref, UInt64Ref, UInt8Ref, USizeRef,
+        SystemTimeRef, UInt16Ref, UInt32Ref, UInt64Ref, UInt8Ref, PointerRef,
     },
+    semantic::systemtime_key,
     strings::{CountedEtwString, EtwString},
 };
 
@@ -27,7 +33,7 @@ pub enum InValue<'a> {
     Boolean(UInt32Ref<'a>),
     Binary(Vec<&'a [u8]>),
     Guid(GuidRef<'a>),
-    Pointer(USizeRef<'a>),
+    Pointer(PointerRef<'a>),
     FileTime(FileTimeRef<'a>),
     SystemTime(SystemTimeRef<'a>),
     Sid(Vec<Sid<'a>>),
@@ -44,9 +50,9 @@ pub enum InValue<'a> {
     NonNullTerminatedAnsiString(&'a [u8]),
     UnicodeChar(UInt16Ref<'a>),
     AnsiChar(UInt8Ref<'a>),
-    SizeT(USizeRef<'a>),
+    SizeT(PointerRef<'a>),
     HexDump(&'a [u8]),
-    WbemSid(&'a [u8]),
+    WbemSid(Vec<WbemSid<'a>>),
 }
 
 impl<'a> InValue<'a> {
@@ -91,3 +97,132 @@ impl<'a> InValue<'a> {
         }
     }
 }
+
+/// An [`InValue`] with its data copied out of the event record.
+///
+/// Strings are converted lossily, FILETIMEs are stored as their 64 bit value
+/// and SYSTEMTIMEs as their fields in declaration order.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InValueOwned {
+    Null,
+    UnicodeString(Vec<String>),
+    AnsiString(Vec<String>),
+    Int8(Vec<i8>),
+    UInt8(Vec<u8>),
+    Int16(Vec<i16>),
+    UInt16(Vec<u16>),
+    Int32(Vec<i32>),
+    UInt32(Vec<u32>),
+    Int64(Vec<i64>),
+    UInt64(Vec<u64>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    Boolean(Vec<bool>),
+    Binary(Vec<Vec<u8>>),
+    Guid(#[cfg_attr(feature = "serde", serde(with = "crate::serde::guid_vec"))] Vec<GUID>),
+    Pointer(Vec<u64>),
+    FileTime(Vec<u64>),
+    SystemTime(Vec<[u16; 8]>),
+    Sid(Vec<Vec<u8>>),
+    HexInt32(Vec<u32>),
+    HexInt64(Vec<u64>),
+    CountedString(Vec<String>),
+    CountedAnsiString(Vec<String>),
+    ReversedCountedString(Vec<String>),
+    ReversedCountedAnsiString(Vec<String>),
+    NonNullTerminatedString(String),
+    NonNullTerminatedAnsiString(String),
+    UnicodeChar(Vec<u16>),
+    AnsiChar(Vec<u8>),
+    SizeT(Vec<u64>),
+    HexDump(Vec<u8>),
+    /// The `TOKEN_USER` prefix and SID of each value.
+    WbemSid(Vec<Vec<u8>>),
+}
+
+impl InValueOwned {
+    pub fn datatype(&self) -> InType {
+        match self {
+            Self::Null => InType::Null,
+            Self::UnicodeString(_) => InType::UnicodeString,
+            Self::AnsiString(_) => InType::AnsiString,
+            Self::Int8(_) => InType::Int8,
+            Self::UInt8(_) => InType::UInt8,
+            Self::Int16(_) => InType::Int16,
+            Self::UInt16(_) => InType::UInt16,
+            Self::Int32(_) => InType::Int32,
+            Self::UInt32(_) => InType::UInt32,
+            Self::Int64(_) => InType::Int64,
+            Self::UInt64(_) => InType::UInt64,
+            Self::Float(_) => InType::Float,
+            Self::Double(_) => InType::Double,
+            Self::Boolean(_) => InType::Boolean,
+            Self::Binary(_) => InType::Binary,
+            Self::Guid(_) => InType::Guid,
+            Self::Pointer(_) => InType::Pointer,
+            Self::FileTime(_) => InType::FileTime,
+            Self::SystemTime(_) => InType::SystemTime,
+            Self::Sid(_) => InType::Sid,
+            Self::HexInt32(_) => InType::HexInt32,
+            Self::HexInt64(_) => InType::HexInt64,
+            Self::CountedString(_) => InType::CountedString,
+            Self::CountedAnsiString(_) => InType::CountedAnsiString,
+            Self::ReversedCountedString(_) => InType::ReversedCountedString,
+            Self::ReversedCountedAnsiString(_) => InType::ReversedCountedAnsiString,
+            Self::NonNullTerminatedString(_) => InType::NonNullTerminatedString,
+            Self::NonNullTerminatedAnsiString(_) => InType::NonNullTerminatedAnsiString,
+            Self::UnicodeChar(_) => InType::UnicodeChar,
+            Self::AnsiChar(_) => InType::AnsiChar,
+            Self::SizeT(_) => InType::SizeT,
+            Self::HexDump(_) => InType::HexDump,
+            Self::WbemSid(_) => InType::WbemSid,
+        }
+    }
+}
+
+fn file_time(time: FILETIME) -> u64 {
+    (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime)
+}
+
+impl From<&InValue<'_>> for InValueOwned {
+    fn from(value: &InValue<'_>) -> Self {
+        let strings = || -> Vec<String> { value.iter_strings().map(Iterator::collect).unwrap_or_default() };
+        let string = || value.as_string().unwrap_or_default();
+        match value {
+            InValue::Null => Self::Null,
+            InValue::UnicodeString(_) => Self::UnicodeString(strings()),
+            InValue::AnsiString(_) => Self::AnsiString(strings()),
+            InValue::Int8(value) => Self::Int8(value.to_vec()),
+            InValue::UInt8(value) => Self::UInt8(value.to_vec()),
+            InValue::Int16(value) => Self::Int16(value.to_vec()),
+            InValue::UInt16(value) => Self::UInt16(value.to_vec()),
+            InValue::Int32(value) => Self::Int32(value.to_vec()),
+            InValue::UInt32(value) => Self::UInt32(value.to_vec()),
+            InValue::Int64(value) => Self::Int64(value.to_vec()),
+            InValue::UInt64(value) => Self::UInt64(value.to_vec()),
+            InValue::Float(value) => Self::Float(value.to_vec()),
+            InValue::Double(value) => Self::Double(value.to_vec()),
+            InValue::Boolean(value) => Self::Boolean(value.iter().map(|value| value != 0).collect()),
+            InValue::Binary(values) => Self::Binary(values.iter().map(|value| value.to_vec()).collect()),
+            InValue::Guid(value) => Self::Guid(value.to_vec()),
+            InValue::Pointer(value) => Self::Pointer(value.to_vec()),
+            InValue::FileTime(value) => Self::FileTime(value.iter().map(file_time).collect()),
+            InValue::SystemTime(value) => Self::SystemTime(value.iter().map(systemtime_key).collect()),
+            InValue::Sid(sids) => Self::Sid(sids.iter().map(|sid| sid.data().to_vec()).collect()),
+            InValue::HexInt32(value) => Self::HexInt32(value.to_vec()),
+            InValue::HexInt64(value) => Self::HexInt64(value.to_vec()),
+            InValue::CountedString(_) => Self::CountedString(strings()),
+            InValue::CountedAnsiString(_) => Self::CountedAnsiString(strings()),
+            InValue::ReversedCountedString(_) => Self::ReversedCountedString(strings()),
+            InValue::ReversedCountedAnsiString(_) => Self::ReversedCountedAnsiString(strings()),
+            InValue::NonNullTerminatedString(_) => Self::NonNullTerminatedString(string()),
+            InValue::NonNullTerminatedAnsiString(_) => Self::NonNullTerminatedAnsiString(string()),
+            InValue::UnicodeChar(value) => Self::UnicodeChar(value.to_vec()),
+            InValue::AnsiChar(value) => Self::AnsiChar(value.to_vec()),
+            InValue::SizeT(value) => Self::SizeT(value.to_vec()),
+            InValue::HexDump(data) => Self::HexDump(data.to_vec()),
+            InValue::WbemSid(sids) => Self::WbemSid(sids.iter().map(|sid| sid.data().to_vec()).collect()),
+        }
+    }
+}
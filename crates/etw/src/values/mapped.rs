@@ -0,0 +1,267 @@
+//! Integer and string values resolved through a value map.
+//!
+//! Each element of an array is resolved on its own, so an array of status codes renders
+//! as `[Running, Stopped, 0x5 (unknown)]`. Strings without an entry in a string map
+//! render as themselves.
+
+use std::{borrow::Cow, fmt};
+
+use crate::schema::cache::StringOrIntegerMap;
+
+use super::{in_value::InValue, value::Value};
+
+/// A value with the name the map assigns to it, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MappedValue {
+    pub value: u32,
+    pub name: Option<String>,
+}
+
+impl fmt::Display for MappedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{:#x} (unknown)", self.value),
+        }
+    }
+}
+
+/// A string with the name a string map assigns to it, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct MappedString {
+    pub value: String,
+    pub name: Option<String>,
+}
+
+impl fmt::Display for MappedString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name.as_ref().unwrap_or(&self.value))
+    }
+}
+
+/// A scalar or array property resolved through a value map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum MappedValues {
+    Scalar(MappedValue),
+    Array(Vec<MappedValue>),
+    String(MappedString),
+    StringArray(Vec<MappedString>),
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, values: &[T]) -> fmt::Result {
+    f.write_str("[")?;
+    for (idx, value) in values.iter().enumerate() {
+        if idx != 0 {
+            f.write_str(", ")?;
+        }
+        value.fmt(f)?;
+    }
+    f.write_str("]")
+}
+
+impl fmt::Display for MappedValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scalar(value) => value.fmt(f),
+            Self::Array(values) => write_list(f, values),
+            Self::String(value) => value.fmt(f),
+            Self::StringArray(values) => write_list(f, values),
+        }
+    }
+}
+
+fn resolve<T: Into<u32>>(values: impl Iterator<Item = T>, map: &StringOrIntegerMap) -> Vec<MappedValue> {
+    values
+        .map(|value| {
+            let value = value.into();
+            MappedValue {
+                value,
+                name: map.resolve(value).map(Cow::into_owned),
+            }
+        })
+        .collect()
+}
+
+impl Value<'_> {
+    /// The value as resolved through its property's value map, e.g. `Running` or
+    /// `Read | Write`. None if the property has no map or the map doesn't apply.
+    pub fn mapped_name(&self) -> Option<String> {
+        self.mapped.as_ref().map(ToString::to_string)
+    }
+
+    /// Resolve each element of an integer value through an integer `map`, or each
+    /// element of a string value through a string `map`.
+    ///
+    /// Returns None for other values: integer maps only apply to unsigned integers of
+    /// at most 32 bits.
+    pub fn resolve_map(&self, map: &StringOrIntegerMap) -> Option<MappedValues> {
+        if let StringOrIntegerMap::String(names) = map {
+            let mut values = self
+                .string_elements()?
+                .into_iter()
+                .map(|value| MappedString {
+                    name: names.get(&value).cloned(),
+                    value,
+                })
+                .collect::<Vec<_>>();
+            return if !self.is_array && values.len() == 1 {
+                values.pop().map(MappedValues::String)
+            } else {
+                Some(MappedValues::StringArray(values))
+            };
+        }
+        let mut values = match &self.value {
+            InValue::UInt8(values) => resolve(values.iter(), map),
+            InValue::UInt16(values) => resolve(values.iter(), map),
+            InValue::UInt32(values) | InValue::HexInt32(values) => resolve(values.iter(), map),
+            _ => return None,
+        };
+        if self.is_array {
+            Some(MappedValues::Array(values))
+        } else if values.len() == 1 {
+            values.pop().map(MappedValues::Scalar)
+        } else {
+            Some(MappedValues::Array(values))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, collections::HashMap};
+
+    use crate::{
+        schema::{cache::StringOrIntegerMap, in_type::InType},
+        values::{in_value::InValue, value::Value},
+    };
+
+    use super::{MappedString, MappedValue, MappedValues};
+
+    fn service_state_map() -> StringOrIntegerMap {
+        StringOrIntegerMap::Integer(HashMap::from([
+            (1, "Stopped".to_string()),
+            (4, "Running".to_string()),
+        ]))
+    }
+
+    #[test]
+    fn test_iter_mapped_resolves_each_element() {
+        let data = [4u32, 1, 5].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let (value, _) = Value::parse(&data, InType::UInt32, 4, 3, true).unwrap();
+        let map = service_state_map();
+
+        let InValue::UInt32(states) = &value.value else {
+            panic!("Expected UInt32, got {:?}", value.value);
+        };
+        assert_eq!(
+            states.iter_mapped(&map).map(|(state, name)| (state, name.as_deref().map(str::to_string))).collect::<Vec<_>>(),
+            vec![(4, Some("Running".to_string())), (1, Some("Stopped".to_string())), (5, None)]
+        );
+
+        let mapped = value.resolve_map(&map).unwrap();
+        assert_eq!(
+            mapped,
+            MappedValues::Array(vec![
+                MappedValue { value: 4, name: Some("Running".to_string()) },
+                MappedValue { value: 1, name: Some("Stopped".to_string()) },
+                MappedValue { value: 5, name: None },
+            ])
+        );
+        assert_eq!(mapped.to_string(), "[Running, Stopped, 0x5 (unknown)]");
+    }
+
+    #[test]
+    fn test_scalar_resolves_to_single_name() {
+        let data = 4u16.to_le_bytes();
+        let (value, _) = Value::parse(&data, InType::UInt16, 2, 1, false).unwrap();
+        assert_eq!(value.resolve_map(&service_state_map()).unwrap().to_string(), "Running");
+    }
+
+    #[test]
+    fn test_string_map_resolves_strings() {
+        let map = StringOrIntegerMap::String(HashMap::from([("svc".to_string(), "Service".to_string())]));
+        let data = "svc\0usr\0".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let (value, _) = Value::parse(&data, InType::UnicodeString, 0, 2, true).unwrap();
+        let mapped = value.resolve_map(&map).unwrap();
+        assert_eq!(
+            mapped,
+            MappedValues::StringArray(vec![
+                MappedString { value: "svc".to_string(), name: Some("Service".to_string()) },
+                MappedString { value: "usr".to_string(), name: None },
+            ])
+        );
+        assert_eq!(mapped.to_string(), "[Service, usr]");
+        // Integer values aren't looked up in string maps
+        let (value, _) = Value::parse(&4u32.to_le_bytes(), InType::UInt32, 4, 1, false).unwrap();
+        assert_eq!(value.resolve_map(&map), None);
+    }
+
+    #[test]
+    fn test_bitmap_resolves_set_flags() {
+        let map = StringOrIntegerMap::Bitmap(HashMap::from([
+            (0, "None".to_string()),
+            (0x1, "Read".to_string()),
+            (0x2, "Write".to_string()),
+            (0x3, "ReadWrite".to_string()),
+            (0x10, "Delete".to_string()),
+        ]));
+        let data = [0x3u32, 0x11, 0x0, 0x22, 0x40].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let (value, _) = Value::parse(&data, InType::UInt32, 4, 5, true).unwrap();
+        assert_eq!(
+            value.resolve_map(&map).unwrap().to_string(),
+            "[ReadWrite, Read | Delete, None, Write | 0x20, 0x40 (unknown)]"
+        );
+        assert_eq!(map.name(0x11), None);
+        assert_eq!(map.resolve(0x13).as_deref(), Some("ReadWrite | Delete"));
+        let InValue::UInt32(flags) = &value.value else {
+            panic!("Expected UInt32, got {:?}", value.value);
+        };
+        assert_eq!(
+            flags.iter_mapped(&map).map(|(_, name)| name.map(Cow::into_owned)).collect::<Vec<_>>(),
+            [Some("ReadWrite"), Some("Read | Delete"), Some("None"), Some("Write | 0x20"), None].map(|name| name.map(str::to_string))
+        );
+
+        let (mut value, _) = Value::parse(&data[4..8], InType::UInt32, 4, 1, false).unwrap();
+        assert_eq!(value.mapped_name(), None);
+        value.mapped = value.resolve_map(&map);
+        assert_eq!(value.mapped_name().as_deref(), Some("Read | Delete"));
+    }
+
+    #[test]
+    fn test_non_integer_values_are_not_mapped() {
+        let data = 4u64.to_le_bytes();
+        let (value, _) = Value::parse(&data, InType::UInt64, 8, 1, false).unwrap();
+        assert_eq!(value.resolve_map(&service_state_map()), None);
+    }
+
+    #[test]
+    fn test_boolean_array_decodes_per_element() {
+        let data = [1u32, 0, 1].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+        let (value, remainder) = Value::parse(&data, InType::Boolean, 4, 3, true).unwrap();
+        assert!(remainder.is_empty());
+        let InValue::Boolean(values) = &value.value else {
+            panic!("Expected Boolean, got {:?}", value.value);
+        };
+        assert_eq!(values.iter().map(|v| v != 0).collect::<Vec<_>>(), vec![true, false, true]);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_mapped_array_serializes_as_value_name_pairs() {
+        let mapped = MappedValues::Array(vec![
+            MappedValue { value: 4, name: Some("Running".to_string()) },
+            MappedValue { value: 5, name: None },
+        ]);
+        assert_eq!(
+            serde_json::to_string(&mapped).unwrap(),
+            r#"[{"value":4,"name":"Running"},{"value":5,"name":null}]"#
+        );
+    }
+}
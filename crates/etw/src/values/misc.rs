@@ -1,7 +1,13 @@
-use std::mem;
+use std::{fmt, mem};
 
 use windows::Win32::Security::{GetLengthSid, IsValidSid, PSID};
 
+use crate::error::ParseError;
+
+/// Size of a SID without sub-authorities: revision, sub-authority count and the
+/// 6 byte identifier authority.
+const SID_HEADER_SIZE: usize = 8;
+
 #[derive(Debug)]
 pub struct Sid<'a> {
     psid: PSID,
@@ -9,21 +15,26 @@ pub struct Sid<'a> {
 }
 
 impl<'a> Sid<'a> {
+    /// The SID at the start of `data`, or None if `data` doesn't start with a valid SID.
     pub fn new<'b>(data: &'a [u8]) -> Option<Self>
     where
         'b: 'a,
     {
+        // Check the size the header claims before the Win32 APIs read that far
+        let sub_authority_count = usize::from(*data.get(1)?);
+        if data.len() < SID_HEADER_SIZE + sub_authority_count * mem::size_of::<u32>() {
+            return None;
+        }
         unsafe {
             let psid = mem::transmute::<*const u8, PSID>(data.as_ptr());
             if !IsValidSid(psid).as_bool() {
-                None
-            } else {
-                let length = usize::try_from(GetLengthSid(psid)).ok()?;
-                Some(Self {
-                    psid,
-                    data: &data[0..length],
-                })
+                return None;
             }
+            let length = usize::try_from(GetLengthSid(psid)).ok()?;
+            Some(Self {
+                psid,
+                data: data.get(..length)?,
+            })
         }
     }
 
@@ -38,4 +49,105 @@ impl<'a> Sid<'a> {
     pub fn data(&self) -> &'a [u8] {
         self.data
     }
+
+    pub(crate) fn psid(&self) -> PSID {
+        self.psid
+    }
+}
+
+impl TryFrom<&Sid<'_>> for String {
+    type Error = ParseError;
+
+    /// The SID in its string form, e.g. `S-1-5-18`.
+    fn try_from(value: &Sid<'_>) -> Result<Self, Self::Error> {
+        String::try_from(&crate::windows::Sid(value.psid))
+    }
+}
+
+impl fmt::Display for Sid<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&crate::windows::Sid(self.psid), f)
+    }
+}
+
+/// A SID prefixed with a `TOKEN_USER` structure, as logged for `InType::WbemSid`.
+///
+/// The prefix is two pointers long, so its size depends on the pointer size of the
+/// process that logged the event.
+#[derive(Debug)]
+pub struct WbemSid<'a> {
+    data: &'a [u8],
+    sid: Sid<'a>,
+}
+
+impl<'a> WbemSid<'a> {
+    pub fn new(data: &'a [u8], pointer_size: usize) -> Option<Self> {
+        let prefix_size = 2 * pointer_size;
+        let sid = Sid::new(data.get(prefix_size..)?)?;
+        Some(Self {
+            data: &data[..prefix_size + sid.size()],
+            sid,
+        })
+    }
+
+    /// Size of the prefix and the SID.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The prefix and the SID.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The SID after the prefix.
+    pub fn sid(&self) -> &Sid<'a> {
+        &self.sid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sid, WbemSid};
+
+    /// S-1-5-18, the local system account.
+    const LOCAL_SYSTEM: [u8; 12] = [1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+
+    #[test]
+    fn test_sid_from_well_known_blob() {
+        let mut data = LOCAL_SYSTEM.to_vec();
+        data.extend_from_slice(&[0xff, 0xff]);
+        let sid = Sid::new(&data).unwrap();
+        assert!(sid.is_valid());
+        assert_eq!(sid.size(), LOCAL_SYSTEM.len());
+        assert_eq!(sid.data(), &LOCAL_SYSTEM);
+        assert_eq!(String::try_from(&sid).unwrap(), "S-1-5-18");
+        assert_eq!(sid.to_string(), "S-1-5-18");
+    }
+
+    #[test]
+    fn test_sid_rejects_truncated_data() {
+        assert!(Sid::new(&[]).is_none());
+        assert!(Sid::new(&LOCAL_SYSTEM[..4]).is_none());
+        // The header claims a sub-authority that isn't there
+        assert!(Sid::new(&LOCAL_SYSTEM[..8]).is_none());
+        assert!(Sid::new(&LOCAL_SYSTEM[..11]).is_none());
+    }
+
+    #[test]
+    fn test_sid_rejects_invalid_revision() {
+        let mut data = LOCAL_SYSTEM;
+        data[0] = 2;
+        assert!(Sid::new(&data).is_none());
+    }
+
+    #[test]
+    fn test_wbem_sid_skips_token_user_prefix() {
+        let mut data = vec![0u8; 16];
+        data.extend_from_slice(&LOCAL_SYSTEM);
+        let sid = WbemSid::new(&data, 8).unwrap();
+        assert_eq!(sid.size(), data.len());
+        assert_eq!(sid.sid().to_string(), "S-1-5-18");
+        assert!(WbemSid::new(&data[..20], 8).is_none());
+    }
 }
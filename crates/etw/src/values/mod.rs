@@ -1,7 +1,12 @@
 pub mod compound;
+pub mod convert;
+pub mod flat;
 pub mod in_value;
+pub mod mapped;
 pub mod misc;
 pub mod primitives;
+pub mod render;
+pub mod semantic;
 pub mod strings;
 pub mod value;
 pub mod event;
@@ -1,10 +1,12 @@
-use std::mem;
+use std::{borrow::Cow, mem};
 
 use windows::{
     core::GUID,
     Win32::Foundation::{FILETIME, SYSTEMTIME},
 };
 
+use crate::schema::cache::StringOrIntegerMap;
+
 #[cfg(not(feature = "unchecked_cast"))]
 use super::FromLeBytes;
 use super::{ItemSize, TypeName};
@@ -41,8 +43,6 @@ impl_from_le_bytes!(u64);
 impl_from_le_bytes!(f32);
 #[cfg(not(feature = "unchecked_cast"))]
 impl_from_le_bytes!(f64);
-#[cfg(not(feature = "unchecked_cast"))]
-impl_from_le_bytes!(usize);
 
 static_assertions::assert_eq_size!(FILETIME, [u32; 2]);
 
@@ -144,6 +144,33 @@ macro_rules! define_primitive_type_ref {
             pub fn item_size() -> usize {
                 mem::size_of::<$ty>()
             }
+
+            /// The decoded elements.
+            pub fn iter(&self) -> impl Iterator<Item = $ty> {
+                (0..self.len()).filter_map(|idx| self.get(idx))
+            }
+
+            /// Copy the decoded elements.
+            pub fn to_vec(&self) -> Vec<$ty> {
+                self.iter().collect()
+            }
+        }
+    };
+}
+
+// Types that can be keys of integer value maps
+macro_rules! impl_iter_mapped {
+    ($name: ident, $ty: ty) => {
+        impl<'a> $name<'a> {
+            /// The decoded elements, each with its name in `map`, if it has one.
+            ///
+            /// Elements of a bitmap are named by their set flags, see [`StringOrIntegerMap::resolve`].
+            pub fn iter_mapped<'m>(
+                &self,
+                map: &'m StringOrIntegerMap,
+            ) -> impl Iterator<Item = ($ty, Option<Cow<'m, str>>)> {
+                self.iter().map(|value| (value, map.resolve(u32::from(value))))
+            }
         }
     };
 }
@@ -161,4 +188,61 @@ define_primitive_type_ref!(DoubleRef, f64);
 define_primitive_type_ref!(FileTimeRef, FILETIME);
 define_primitive_type_ref!(SystemTimeRef, SYSTEMTIME);
 define_primitive_type_ref!(GuidRef, GUID);
-define_primitive_type_ref!(USizeRef, usize);
+
+/// Pointer-sized integers, which are 4 or 8 bytes depending on the process that logged the event.
+#[derive(Debug)]
+pub struct PointerRef<'a> {
+    pub data: &'a [u8],
+    pub pointer_size: usize,
+}
+
+impl TypeName for PointerRef<'_> {
+    const TYPE_NAME: &'static str = "PointerRef";
+}
+
+impl<'a> PointerRef<'a> {
+    pub fn get(&self, idx: usize) -> Option<u64> {
+        let subslice = self
+            .data
+            .get(idx * self.pointer_size..(idx + 1) * self.pointer_size)?;
+        match self.pointer_size {
+            4 => Some(u64::from(u32::from_le_bytes(subslice.try_into().ok()?))),
+            8 => Some(u64::from_le_bytes(subslice.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len().checked_div(self.pointer_size).unwrap_or(0)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    #[inline]
+    pub fn raw_data(&self) -> &[u8] {
+        self.data
+    }
+
+    #[inline]
+    pub fn item_size(&self) -> usize {
+        self.pointer_size
+    }
+
+    /// The decoded elements.
+    pub fn iter(&self) -> impl Iterator<Item = u64> {
+        (0..self.len()).filter_map(|idx| self.get(idx))
+    }
+
+    /// Copy the decoded elements.
+    pub fn to_vec(&self) -> Vec<u64> {
+        self.iter().collect()
+    }
+}
+
+impl_iter_mapped!(UInt8Ref, u8);
+impl_iter_mapped!(UInt16Ref, u16);
+impl_iter_mapped!(UInt32Ref, u32);
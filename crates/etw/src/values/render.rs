@@ -0,0 +1,250 @@
+//! Rendering of values as text, following the TDH semantics of their out-type.
+
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+};
+
+use crate::{error::ParseError, schema::out_type::OutType, values::value::hex};
+
+/// A value rendered with [`crate::values::value::Value::render`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RenderedValue {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+impl RenderedValue {
+    /// The rendered elements, a single one for scalars.
+    pub fn elements(&self) -> &[String] {
+        match self {
+            Self::Scalar(element) => std::slice::from_ref(element),
+            Self::Array(elements) => elements,
+        }
+    }
+}
+
+/// Arrays are displayed with their elements separated by `, ` and enclosed in brackets.
+impl fmt::Display for RenderedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scalar(element) => f.write_str(element),
+            Self::Array(elements) => write!(f, "[{}]", elements.join(", ")),
+        }
+    }
+}
+
+/// Special cases [`crate::values::value::Value::render_with_options`] applies on top
+/// of the out-type semantics. All of them are off by default, and binary data is hex
+/// encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Render binary values of properties marked [`OutType::HexBinary`] whose data is a
+    /// well-formed `REG_MULTI_SZ` string list as an array of the strings, see
+    /// [`crate::values::value::Value::as_multi_sz`].
+    pub multi_sz: bool,
+    /// Pretty-print the embedded documents of string properties marked [`OutType::Json`].
+    /// Needs the `json` feature; documents that don't parse are rendered as they are,
+    /// like those of [`OutType::Xml`] properties.
+    pub pretty_json: bool,
+    /// How binary values without a special representation and opaque payloads are
+    /// rendered.
+    pub binary: BinaryRendering,
+}
+
+/// How binary data is rendered, see [`RenderOptions::binary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BinaryRendering {
+    /// Upper case hex digits prefixed by `0x`.
+    #[default]
+    Hex,
+    /// Like [`BinaryRendering::Hex`], but only the given number of bytes, followed by
+    /// `...` and the total length if there are more.
+    Truncated(usize),
+    /// Standard base64 with padding.
+    Base64,
+}
+
+impl BinaryRendering {
+    pub fn render(self, data: &[u8]) -> String {
+        match self {
+            Self::Hex => hex(data),
+            Self::Truncated(limit) if data.len() > limit => format!("{}... ({} bytes)", hex(&data[..limit]), data.len()),
+            Self::Truncated(_) => hex(data),
+            Self::Base64 => base64(data),
+        }
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let group = u32::from_be_bytes([0, chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)]);
+        // A chunk of n bytes fills n + 1 characters, the rest is padding
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                output.push(char::from(ALPHABET[(group >> (18 - 6 * idx)) as usize & 0x3f]));
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 23;
+const SOCKADDR_IN_SIZE: usize = 16;
+const SOCKADDR_IN6_SIZE: usize = 28;
+
+/// Parse a `SOCKADDR_IN` or `SOCKADDR_IN6`, as logged for [`OutType::SocketAddress`].
+///
+/// The family is in host byte order, the port and address in network byte order.
+pub(crate) fn parse_socket_address(data: &[u8]) -> Result<SocketAddr, ParseError> {
+    let family = data
+        .get(..2)
+        .map(|family| u16::from_le_bytes([family[0], family[1]]))
+        .ok_or(ParseError::PrematureEndOfData)?;
+    let size = match family {
+        AF_INET => SOCKADDR_IN_SIZE,
+        AF_INET6 => SOCKADDR_IN6_SIZE,
+        _ => return Err(ParseError::UnknownAddressFamily(family)),
+    };
+    let data = data.get(..size).ok_or(ParseError::PrematureEndOfData)?;
+    let port = u16::from_be_bytes([data[2], data[3]]);
+    Ok(match family {
+        AF_INET => {
+            let address = <[u8; 4]>::try_from(&data[4..8]).unwrap();
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(address), port))
+        }
+        _ => {
+            let flow_info = u32::from_be_bytes(data[4..8].try_into().unwrap());
+            let address = <[u8; 16]>::try_from(&data[8..24]).unwrap();
+            let scope_id = u32::from_le_bytes(data[24..28].try_into().unwrap());
+            SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(address), port, flow_info, scope_id))
+        }
+    })
+}
+
+/// The name of a well known Win32 error, NTSTATUS or HRESULT code.
+pub(crate) fn status_name(out_type: OutType, code: u32) -> Option<&'static str> {
+    let names: &[(u32, &str)] = match out_type {
+        OutType::Win32Error => &[
+            (0, "ERROR_SUCCESS"),
+            (1, "ERROR_INVALID_FUNCTION"),
+            (2, "ERROR_FILE_NOT_FOUND"),
+            (3, "ERROR_PATH_NOT_FOUND"),
+            (5, "ERROR_ACCESS_DENIED"),
+            (6, "ERROR_INVALID_HANDLE"),
+            (8, "ERROR_NOT_ENOUGH_MEMORY"),
+            (32, "ERROR_SHARING_VIOLATION"),
+            (50, "ERROR_NOT_SUPPORTED"),
+            (87, "ERROR_INVALID_PARAMETER"),
+            (122, "ERROR_INSUFFICIENT_BUFFER"),
+            (183, "ERROR_ALREADY_EXISTS"),
+            (234, "ERROR_MORE_DATA"),
+            (997, "ERROR_IO_PENDING"),
+            (1168, "ERROR_NOT_FOUND"),
+            (1223, "ERROR_CANCELLED"),
+            (1460, "ERROR_TIMEOUT"),
+        ],
+        OutType::NtStatus => &[
+            (0x0000_0000, "STATUS_SUCCESS"),
+            (0x0000_0103, "STATUS_PENDING"),
+            (0x8000_0005, "STATUS_BUFFER_OVERFLOW"),
+            (0x8000_001A, "STATUS_NO_MORE_ENTRIES"),
+            (0xC000_0001, "STATUS_UNSUCCESSFUL"),
+            (0xC000_0002, "STATUS_NOT_IMPLEMENTED"),
+            (0xC000_0005, "STATUS_ACCESS_VIOLATION"),
+            (0xC000_0008, "STATUS_INVALID_HANDLE"),
+            (0xC000_000D, "STATUS_INVALID_PARAMETER"),
+            (0xC000_000F, "STATUS_NO_SUCH_FILE"),
+            (0xC000_0022, "STATUS_ACCESS_DENIED"),
+            (0xC000_0023, "STATUS_BUFFER_TOO_SMALL"),
+            (0xC000_0034, "STATUS_OBJECT_NAME_NOT_FOUND"),
+            (0xC000_0035, "STATUS_OBJECT_NAME_COLLISION"),
+            (0xC000_003A, "STATUS_OBJECT_PATH_NOT_FOUND"),
+            (0xC000_0043, "STATUS_SHARING_VIOLATION"),
+            (0xC000_009A, "STATUS_INSUFFICIENT_RESOURCES"),
+            (0xC000_00BB, "STATUS_NOT_SUPPORTED"),
+            (0xC000_0120, "STATUS_CANCELLED"),
+            (0xC000_0225, "STATUS_NOT_FOUND"),
+        ],
+        OutType::HResult => &[
+            (0x0000_0000, "S_OK"),
+            (0x0000_0001, "S_FALSE"),
+            (0x8000_4001, "E_NOTIMPL"),
+            (0x8000_4002, "E_NOINTERFACE"),
+            (0x8000_4003, "E_POINTER"),
+            (0x8000_4004, "E_ABORT"),
+            (0x8000_4005, "E_FAIL"),
+            (0x8000_FFFF, "E_UNEXPECTED"),
+            (0x8007_0005, "E_ACCESSDENIED"),
+            (0x8007_0006, "E_HANDLE"),
+            (0x8007_000E, "E_OUTOFMEMORY"),
+            (0x8007_0057, "E_INVALIDARG"),
+        ],
+        _ => return None,
+    };
+    names
+        .iter()
+        .find(|(value, _)| *value == code)
+        .map(|(_, name)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+
+    use crate::{error::ParseError, schema::out_type::OutType};
+
+    use super::{parse_socket_address, status_name, BinaryRendering, RenderedValue};
+
+    #[test]
+    fn test_parse_socket_addresses() {
+        let mut ipv4 = vec![2, 0, 0x01, 0xbb, 192, 168, 0, 1];
+        ipv4.extend([0; 8]);
+        assert_eq!(parse_socket_address(&ipv4).unwrap(), "192.168.0.1:443".parse::<SocketAddr>().unwrap());
+
+        let mut ipv6 = vec![23, 0, 0x00, 0x50, 0, 0, 0, 0, 0xfe, 0x80];
+        ipv6.extend([0; 13]);
+        ipv6.push(1);
+        ipv6.extend(3u32.to_le_bytes());
+        let expected = SocketAddrV6::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1), 80, 0, 3);
+        assert_eq!(parse_socket_address(&ipv6).unwrap(), SocketAddr::V6(expected));
+
+        assert!(matches!(parse_socket_address(&ipv4[..10]), Err(ParseError::PrematureEndOfData)));
+        assert!(matches!(parse_socket_address(&[1, 0, 0, 0]), Err(ParseError::UnknownAddressFamily(1))));
+    }
+
+    #[test]
+    fn test_status_names() {
+        assert_eq!(status_name(OutType::Win32Error, 5), Some("ERROR_ACCESS_DENIED"));
+        assert_eq!(status_name(OutType::NtStatus, 0xC000_0022), Some("STATUS_ACCESS_DENIED"));
+        assert_eq!(status_name(OutType::HResult, 0x8007_0057), Some("E_INVALIDARG"));
+        assert_eq!(status_name(OutType::HResult, 0x8007_1234), None);
+        assert_eq!(status_name(OutType::UnsignedInt, 0), None);
+    }
+
+    #[test]
+    fn test_display_rendered_values() {
+        assert_eq!(RenderedValue::Scalar("1".to_string()).to_string(), "1");
+        assert_eq!(RenderedValue::Array(vec!["1".to_string(), "2".to_string()]).to_string(), "[1, 2]");
+        assert_eq!(RenderedValue::Array(Vec::new()).to_string(), "[]");
+    }
+
+    #[test]
+    fn test_binary_rendering() {
+        assert_eq!(BinaryRendering::Hex.render(&[0xab, 0x01]), "0xAB01");
+        assert_eq!(BinaryRendering::Hex.render(&[]), "0x");
+        assert_eq!(BinaryRendering::Truncated(2).render(&[0xab, 0x01, 0x02]), "0xAB01... (3 bytes)");
+        assert_eq!(BinaryRendering::Truncated(3).render(&[0xab, 0x01, 0x02]), "0xAB0102");
+        assert_eq!(BinaryRendering::Base64.render(b"Man"), "TWFu");
+        assert_eq!(BinaryRendering::Base64.render(b"Ma"), "TWE=");
+        assert_eq!(BinaryRendering::Base64.render(b"M"), "TQ==");
+        assert_eq!(BinaryRendering::Base64.render(b""), "");
+        assert_eq!(BinaryRendering::Base64.render(&[0xfb, 0xff, 0xbf, 0x00]), "+/+/AA==");
+    }
+}
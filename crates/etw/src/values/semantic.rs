@@ -0,0 +1,417 @@
+//! Semantic equality and hashing of decoded values.
+//!
+//! Decoded values borrow the raw event data, which can differ between events that carry
+//! the same logical payload: strings may or may not include their terminating null
+//! character, and the data may be followed by padding. The implementations here compare
+//! the decoded logical values instead:
+//! - Numbers, GUIDs and timestamps compare per element. Floating point numbers compare
+//!   by their bit pattern, so `NaN` equals itself and `0.0` differs from `-0.0`.
+//! - Strings compare their content without the trailing null character.
+//! - SIDs and binary data compare by bytes.
+//! - Values of different in-types never compare equal, even if their content matches.
+//! - Structs compare their members recursively, in order.
+//!
+//! `semantic_hash` is consistent with equality: values that compare equal feed the same
+//! data into the hasher. The hashed data isn't stable across versions of this crate.
+
+use std::{
+    hash::{Hash, Hasher},
+    mem,
+};
+
+use super::{
+    compound::{StringOrStruct, Struct, StructArray, StructOrValue},
+    in_value::InValue,
+    value::Value,
+};
+
+macro_rules! primitive_eq {
+    ($lhs: expr, $rhs: expr, $key: expr) => {
+        $lhs.len() == $rhs.len() && (0..$lhs.len()).all(|idx| $lhs.get(idx).map($key) == $rhs.get(idx).map($key))
+    };
+}
+
+macro_rules! primitive_hash {
+    ($value: expr, $key: expr, $state: expr) => {{
+        $value.len().hash($state);
+        for idx in 0..$value.len() {
+            $value.get(idx).map($key).hash($state);
+        }
+    }};
+}
+
+fn identity<T>(value: T) -> T {
+    value
+}
+
+fn filetime_key(value: windows::Win32::Foundation::FILETIME) -> (u32, u32) {
+    (value.dwLowDateTime, value.dwHighDateTime)
+}
+
+pub(super) fn systemtime_key(value: windows::Win32::Foundation::SYSTEMTIME) -> [u16; 8] {
+    [
+        value.wYear,
+        value.wMonth,
+        value.wDayOfWeek,
+        value.wDay,
+        value.wHour,
+        value.wMinute,
+        value.wSecond,
+        value.wMilliseconds,
+    ]
+}
+
+fn guid_key(value: windows::core::GUID) -> u128 {
+    value.to_u128()
+}
+
+fn slices_eq<'a, T: PartialEq + 'a>(
+    lhs: impl ExactSizeIterator<Item = &'a [T]>,
+    rhs: impl ExactSizeIterator<Item = &'a [T]>,
+) -> bool {
+    lhs.len() == rhs.len() && lhs.zip(rhs).all(|(lhs, rhs)| lhs == rhs)
+}
+
+fn slices_hash<'a, T: Hash + 'a, H: Hasher>(values: impl ExactSizeIterator<Item = &'a [T]>, state: &mut H) {
+    values.len().hash(state);
+    for value in values {
+        value.hash(state);
+    }
+}
+
+impl PartialEq for InValue<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Null, Self::Null) => true,
+            (Self::UnicodeString(lhs), Self::UnicodeString(rhs)) => {
+                slices_eq(lhs.iter().map(|s| s.content()), rhs.iter().map(|s| s.content()))
+            }
+            (Self::AnsiString(lhs), Self::AnsiString(rhs)) => {
+                slices_eq(lhs.iter().map(|s| s.content()), rhs.iter().map(|s| s.content()))
+            }
+            (Self::Int8(lhs), Self::Int8(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::UInt8(lhs), Self::UInt8(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::Int16(lhs), Self::Int16(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::UInt16(lhs), Self::UInt16(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::Int32(lhs), Self::Int32(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::UInt32(lhs), Self::UInt32(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::Int64(lhs), Self::Int64(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::UInt64(lhs), Self::UInt64(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::Float(lhs), Self::Float(rhs)) => primitive_eq!(lhs, rhs, f32::to_bits),
+            (Self::Double(lhs), Self::Double(rhs)) => primitive_eq!(lhs, rhs, f64::to_bits),
+            (Self::Boolean(lhs), Self::Boolean(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::Binary(lhs), Self::Binary(rhs)) => slices_eq(lhs.iter().copied(), rhs.iter().copied()),
+            (Self::Guid(lhs), Self::Guid(rhs)) => primitive_eq!(lhs, rhs, guid_key),
+            (Self::Pointer(lhs), Self::Pointer(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::FileTime(lhs), Self::FileTime(rhs)) => primitive_eq!(lhs, rhs, filetime_key),
+            (Self::SystemTime(lhs), Self::SystemTime(rhs)) => primitive_eq!(lhs, rhs, systemtime_key),
+            (Self::Sid(lhs), Self::Sid(rhs)) => {
+                slices_eq(lhs.iter().map(|sid| sid.data()), rhs.iter().map(|sid| sid.data()))
+            }
+            (Self::HexInt32(lhs), Self::HexInt32(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::HexInt64(lhs), Self::HexInt64(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::CountedString(lhs), Self::CountedString(rhs))
+            | (Self::ReversedCountedString(lhs), Self::ReversedCountedString(rhs)) => {
+                slices_eq(lhs.iter().map(|s| s.data()), rhs.iter().map(|s| s.data()))
+            }
+            (Self::CountedAnsiString(lhs), Self::CountedAnsiString(rhs))
+            | (Self::ReversedCountedAnsiString(lhs), Self::ReversedCountedAnsiString(rhs)) => {
+                slices_eq(lhs.iter().map(|s| s.data()), rhs.iter().map(|s| s.data()))
+            }
+            (Self::NonNullTerminatedString(lhs), Self::NonNullTerminatedString(rhs)) => lhs == rhs,
+            (Self::NonNullTerminatedAnsiString(lhs), Self::NonNullTerminatedAnsiString(rhs)) => lhs == rhs,
+            (Self::UnicodeChar(lhs), Self::UnicodeChar(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::AnsiChar(lhs), Self::AnsiChar(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::SizeT(lhs), Self::SizeT(rhs)) => primitive_eq!(lhs, rhs, identity),
+            (Self::HexDump(lhs), Self::HexDump(rhs)) => lhs == rhs,
+            (Self::WbemSid(lhs), Self::WbemSid(rhs)) => {
+                slices_eq(lhs.iter().map(|sid| sid.data()), rhs.iter().map(|sid| sid.data()))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for InValue<'_> {}
+
+impl InValue<'_> {
+    /// Hash the decoded value, consistent with its [`PartialEq`] implementation.
+    pub fn semantic_hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Self::Null => (),
+            Self::UnicodeString(strings) => slices_hash(strings.iter().map(|s| s.content()), state),
+            Self::AnsiString(strings) => slices_hash(strings.iter().map(|s| s.content()), state),
+            Self::Int8(value) => primitive_hash!(value, identity, state),
+            Self::UInt8(value) | Self::AnsiChar(value) => primitive_hash!(value, identity, state),
+            Self::Int16(value) => primitive_hash!(value, identity, state),
+            Self::UInt16(value) | Self::UnicodeChar(value) => primitive_hash!(value, identity, state),
+            Self::Int32(value) => primitive_hash!(value, identity, state),
+            Self::UInt32(value) | Self::Boolean(value) | Self::HexInt32(value) => {
+                primitive_hash!(value, identity, state)
+            }
+            Self::Int64(value) => primitive_hash!(value, identity, state),
+            Self::UInt64(value) | Self::HexInt64(value) => primitive_hash!(value, identity, state),
+            Self::Float(value) => primitive_hash!(value, f32::to_bits, state),
+            Self::Double(value) => primitive_hash!(value, f64::to_bits, state),
+            Self::Binary(values) => slices_hash(values.iter().copied(), state),
+            Self::Guid(value) => primitive_hash!(value, guid_key, state),
+            Self::Pointer(value) | Self::SizeT(value) => primitive_hash!(value, identity, state),
+            Self::FileTime(value) => primitive_hash!(value, filetime_key, state),
+            Self::SystemTime(value) => primitive_hash!(value, systemtime_key, state),
+            Self::Sid(sids) => slices_hash(sids.iter().map(|sid| sid.data()), state),
+            Self::WbemSid(sids) => slices_hash(sids.iter().map(|sid| sid.data()), state),
+            Self::CountedString(strings) | Self::ReversedCountedString(strings) => {
+                slices_hash(strings.iter().map(|s| s.data()), state)
+            }
+            Self::CountedAnsiString(strings) | Self::ReversedCountedAnsiString(strings) => {
+                slices_hash(strings.iter().map(|s| s.data()), state)
+            }
+            Self::NonNullTerminatedString(data) => data.hash(state),
+            Self::NonNullTerminatedAnsiString(data) | Self::HexDump(data) => data.hash(state),
+        }
+    }
+}
+
+/// Compares the decoded value and whether it is an array, but not the raw data.
+impl PartialEq for Value<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_array == other.is_array && self.value == other.value
+    }
+}
+
+impl Eq for Value<'_> {}
+
+impl Value<'_> {
+    /// Hash the decoded value, consistent with its [`PartialEq`] implementation.
+    pub fn semantic_hash<H: Hasher>(&self, state: &mut H) {
+        self.is_array.hash(state);
+        self.value.semantic_hash(state);
+    }
+}
+
+impl PartialEq for Struct<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl Eq for Struct<'_> {}
+
+impl Struct<'_> {
+    /// Hash the members recursively, consistent with the [`PartialEq`] implementation.
+    pub fn semantic_hash<H: Hasher>(&self, state: &mut H) {
+        self.values.len().hash(state);
+        for value in &self.values {
+            value.semantic_hash(state);
+        }
+    }
+}
+
+impl PartialEq for StructArray<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_array == other.is_array && self.values == other.values
+    }
+}
+
+impl Eq for StructArray<'_> {}
+
+impl StructArray<'_> {
+    /// Hash the elements recursively, consistent with the [`PartialEq`] implementation.
+    pub fn semantic_hash<H: Hasher>(&self, state: &mut H) {
+        self.is_array.hash(state);
+        self.values.len().hash(state);
+        for value in &self.values {
+            value.semantic_hash(state);
+        }
+    }
+}
+
+impl PartialEq for StructOrValue<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Struct(lhs), Self::Struct(rhs)) => lhs == rhs,
+            (Self::Value(lhs), Self::Value(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for StructOrValue<'_> {}
+
+impl StructOrValue<'_> {
+    /// Hash the member, consistent with the [`PartialEq`] implementation.
+    pub fn semantic_hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Self::Struct(value) => value.semantic_hash(state),
+            Self::Value(value) => value.semantic_hash(state),
+        }
+    }
+}
+
+fn trim_null(chars: &[u16]) -> &[u16] {
+    match chars.split_last() {
+        Some((0, content)) => content,
+        _ => chars,
+    }
+}
+
+impl PartialEq for StringOrStruct<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::String(lhs), Self::String(rhs)) => trim_null(&lhs.to_vec()) == trim_null(&rhs.to_vec()),
+            (Self::Struct(lhs), Self::Struct(rhs)) => lhs == rhs,
+            (Self::Opaque(lhs), Self::Opaque(rhs)) => lhs == rhs,
+            (Self::Partial(lhs, lhs_truncation), Self::Partial(rhs, rhs_truncation)) => {
+                lhs_truncation == rhs_truncation && lhs == rhs
+            }
+            (Self::Wpp(lhs), Self::Wpp(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for StringOrStruct<'_> {}
+
+impl StringOrStruct<'_> {
+    /// Hash the payload, consistent with the [`PartialEq`] implementation.
+    pub fn semantic_hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Self::String(value) => trim_null(&value.to_vec()).hash(state),
+            Self::Struct(value) => value.semantic_hash(state),
+            Self::Opaque(value) => value.hash(state),
+            Self::Partial(value, truncation) => {
+                truncation.hash(state);
+                value.semantic_hash(state);
+            }
+            Self::Wpp(value) => value.hash(state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::Hasher,
+    };
+
+    use crate::{
+        schema::in_type::InType,
+        values::{
+            compound::{Struct, StructOrValue},
+            value::Value,
+        },
+    };
+
+    fn hash(value: &Value<'_>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.semantic_hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn parse(data: &[u8], in_type: InType, length: usize, count: usize) -> Value<'_> {
+        Value::parse(data, in_type, length, count, count != 1).unwrap().0
+    }
+
+    #[test]
+    fn test_strings_ignore_trailing_null_and_padding() {
+        for text in ["", "a", "hello", "\u{1F600} wide"] {
+            let without_null = utf16(text);
+            let mut with_null = without_null.clone();
+            with_null.extend([0, 0]);
+            let mut padded = with_null.clone();
+            padded.extend([0xcc; 6]);
+
+            let values = [
+                parse(&without_null, InType::UnicodeString, 0, 1),
+                parse(&with_null, InType::UnicodeString, 0, 1),
+                parse(&padded, InType::UnicodeString, 0, 1),
+            ];
+            for value in &values {
+                assert_eq!(value, &values[0], "{:?}", text);
+                assert_eq!(hash(value), hash(&values[0]), "{:?}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_primitive_arrays_compare_per_element() {
+        for items in [vec![], vec![0u32], vec![1, 2, 3], vec![u32::MAX, 7]] {
+            let data = items.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+            let mut padded = data.clone();
+            padded.extend([0xab, 0xcd]);
+
+            let value = parse(&data, InType::UInt32, 4, items.len());
+            let padded_value = parse(&padded, InType::UInt32, 4, items.len());
+            assert_eq!(value, padded_value);
+            assert_eq!(hash(&value), hash(&padded_value));
+
+            let mut different = items.clone();
+            different.push(42);
+            let different_data = different.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+            assert_ne!(value, parse(&different_data, InType::UInt32, 4, different.len()));
+        }
+    }
+
+    #[test]
+    fn test_different_values_and_types_differ() {
+        let one = 1u32.to_le_bytes();
+        let two = 2u32.to_le_bytes();
+        assert_ne!(parse(&one, InType::UInt32, 4, 1), parse(&two, InType::UInt32, 4, 1));
+        assert_ne!(parse(&one, InType::UInt32, 4, 1), parse(&one, InType::HexInt32, 4, 1));
+        assert_ne!(parse(&one, InType::UInt32, 4, 1), parse(&one, InType::Int32, 4, 1));
+
+        let nan = f64::NAN.to_le_bytes();
+        assert_eq!(parse(&nan, InType::Double, 8, 1), parse(&nan, InType::Double, 8, 1));
+        assert_ne!(
+            parse(&0.0f64.to_le_bytes(), InType::Double, 8, 1),
+            parse(&(-0.0f64).to_le_bytes(), InType::Double, 8, 1)
+        );
+
+        let hello = utf16("hello\0");
+        let world = utf16("world\0");
+        assert_ne!(
+            parse(&hello, InType::UnicodeString, 0, 1),
+            parse(&world, InType::UnicodeString, 0, 1)
+        );
+    }
+
+    fn build_struct<'a>(id: &'a [u8], name: &'a [u8]) -> Struct<'a> {
+        Struct {
+            values: vec![
+                StructOrValue::Value(parse(id, InType::UInt16, 2, 1)),
+                StructOrValue::Value(parse(name, InType::UnicodeString, 0, 1)),
+            ],
+            names: None,
+        }
+    }
+
+    #[test]
+    fn test_structs_compare_recursively() {
+        let id = 5u16.to_le_bytes();
+        let other_id = 6u16.to_le_bytes();
+        let name = utf16("name\0");
+        let short_name = utf16("name");
+
+        let lhs = build_struct(&id, &name);
+        let rhs = build_struct(&id, &short_name);
+        assert_eq!(lhs, rhs);
+        let mut lhs_hasher = DefaultHasher::new();
+        let mut rhs_hasher = DefaultHasher::new();
+        lhs.semantic_hash(&mut lhs_hasher);
+        rhs.semantic_hash(&mut rhs_hasher);
+        assert_eq!(lhs_hasher.finish(), rhs_hasher.finish());
+
+        assert_ne!(lhs, build_struct(&other_id, &name));
+        let mut truncated = build_struct(&id, &name);
+        truncated.values.pop();
+        assert_ne!(lhs, truncated);
+    }
+}
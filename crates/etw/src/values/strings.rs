@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     mem::{
         self,
         size_of,
@@ -34,6 +35,14 @@ impl<'a, T> EtwString<'a, T> {
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
+
+    /// The string data without the trailing null character, if there is one.
+    pub fn content(&self) -> &'a [u8] {
+        match self.data.len().checked_sub(size_of::<T>()) {
+            Some(end) if self.data[end..].iter().all(|c| *c == 0) => &self.data[..end],
+            _ => self.data,
+        }
+    }
 }
 
 impl<'a, T> RawBytes for EtwString<'a, T>
@@ -189,6 +198,164 @@ impl<'a, T> CountedEtwString<'a, T>
     }
 }
 
+/// Case-insensitive comparisons of UTF-16 strings without decoding them.
+///
+/// Only ASCII letters are folded: `ä` doesn't match `Ä`, and neither does any other
+/// non-ASCII character. That's sufficient for paths and file names on watchlists,
+/// which NTFS compares with its own upcase table anyway.
+pub trait Utf16Units {
+    fn unit_count(&self) -> usize;
+
+    fn unit(&self, index: usize) -> u16;
+
+    fn eq_ignore_ascii_case(&self, other: &str) -> bool {
+        let mut units = 0;
+        for (index, c) in other.encode_utf16().enumerate() {
+            if index >= self.unit_count() || !unit_eq_ignore_ascii_case(self.unit(index), c) {
+                return false;
+            }
+            units += 1;
+        }
+        units == self.unit_count()
+    }
+
+    fn ends_with_ignore_case(&self, suffix: &str) -> bool {
+        let Some(start) = self.unit_count().checked_sub(suffix.encode_utf16().count()) else {
+            return false;
+        };
+        suffix
+            .encode_utf16()
+            .enumerate()
+            .all(|(index, c)| unit_eq_ignore_ascii_case(self.unit(start + index), c))
+    }
+
+    fn contains_ignore_case(&self, needle: &str) -> bool {
+        let Some(last_start) = self.unit_count().checked_sub(needle.encode_utf16().count()) else {
+            return false;
+        };
+        (0..=last_start).any(|start| {
+            needle
+                .encode_utf16()
+                .enumerate()
+                .all(|(index, c)| unit_eq_ignore_ascii_case(self.unit(start + index), c))
+        })
+    }
+}
+
+fn fold_ascii(unit: u16) -> u16 {
+    if (u16::from(b'A')..=u16::from(b'Z')).contains(&unit) {
+        unit + u16::from(b'a' - b'A')
+    } else {
+        unit
+    }
+}
+
+fn unit_eq_ignore_ascii_case(a: u16, b: u16) -> bool {
+    fold_ascii(a) == fold_ascii(b)
+}
+
+/// Unicode strings compare without their trailing null character.
+impl<'a> Utf16Units for EtwString<'a, u16> {
+    fn unit_count(&self) -> usize {
+        self.content().len() / size_of::<u16>()
+    }
+
+    fn unit(&self, index: usize) -> u16 {
+        let offset = index * size_of::<u16>();
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+}
+
+impl<'a> Utf16Units for CountedEtwString<'a, u16> {
+    fn unit_count(&self) -> usize {
+        self.data.len()
+    }
+
+    fn unit(&self, index: usize) -> u16 {
+        self.data[index]
+    }
+}
+
+impl Utf16Units for [u16] {
+    fn unit_count(&self) -> usize {
+        self.len()
+    }
+
+    fn unit(&self, index: usize) -> u16 {
+        self[index]
+    }
+}
+
+/// Decode a string, replacing invalid UTF-16 with U+FFFD.
+pub(crate) fn utf16_lossy<S: Utf16Units + ?Sized>(string: &S) -> String {
+    char::decode_utf16((0..string.unit_count()).map(|index| string.unit(index)))
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// [`Utf16Units`]' suffix and substring comparisons for decoded strings, e.g. the
+/// `Cow<str>` results of string accessors. Only ASCII letters are folded.
+pub trait StrIgnoreCase {
+    fn ends_with_ignore_case(&self, suffix: &str) -> bool;
+
+    fn contains_ignore_case(&self, needle: &str) -> bool;
+}
+
+impl StrIgnoreCase for str {
+    fn ends_with_ignore_case(&self, suffix: &str) -> bool {
+        self.len() >= suffix.len() && self.as_bytes()[self.len() - suffix.len()..].eq_ignore_ascii_case(suffix.as_bytes())
+    }
+
+    fn contains_ignore_case(&self, needle: &str) -> bool {
+        needle.is_empty()
+            || self
+                .as_bytes()
+                .windows(needle.len())
+                .any(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+    }
+}
+
+/// A needle encoded and case folded once, to compare against the strings of many events.
+///
+/// Folds ASCII letters only, like [`Utf16Units`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utf16Matcher {
+    needle: Vec<u16>,
+}
+
+impl Utf16Matcher {
+    pub fn new(needle: &str) -> Self {
+        Self {
+            needle: needle.encode_utf16().map(fold_ascii).collect(),
+        }
+    }
+
+    fn matches_at<H: Utf16Units + ?Sized>(&self, haystack: &H, start: usize) -> bool {
+        self.needle
+            .iter()
+            .enumerate()
+            .all(|(index, c)| fold_ascii(haystack.unit(start + index)) == *c)
+    }
+
+    pub fn is_eq<H: Utf16Units + ?Sized>(&self, haystack: &H) -> bool {
+        haystack.unit_count() == self.needle.len() && self.matches_at(haystack, 0)
+    }
+
+    pub fn is_suffix_of<H: Utf16Units + ?Sized>(&self, haystack: &H) -> bool {
+        haystack
+            .unit_count()
+            .checked_sub(self.needle.len())
+            .is_some_and(|start| self.matches_at(haystack, start))
+    }
+
+    pub fn is_contained_in<H: Utf16Units + ?Sized>(&self, haystack: &H) -> bool {
+        haystack
+            .unit_count()
+            .checked_sub(self.needle.len())
+            .is_some_and(|last_start| (0..=last_start).any(|start| self.matches_at(haystack, start)))
+    }
+}
+
 pub fn parse_string_array<'a, T>(
     data: &'a [u8],
     length: usize,
@@ -212,3 +379,140 @@ where
     }
     Ok((strings, raw_size, remainder))
 }
+
+/// Parse a buffer of null terminated UTF-16 strings that ends with an additional
+/// null character, as used by `REG_MULTI_SZ` registry values.
+///
+/// A missing final terminator is tolerated, as is a completely empty buffer.
+/// Returns None if the buffer isn't a well-formed string list.
+pub fn parse_multi_sz(data: &[u8]) -> Option<Vec<Cow<'static, str>>> {
+    if data.len() % size_of::<u16>() != 0 {
+        return None;
+    }
+    let chars = data
+        .chunks_exact(size_of::<u16>())
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect::<Vec<_>>();
+
+    let mut strings = Vec::new();
+    let mut remainder = &chars[..];
+    while !remainder.is_empty() {
+        let end = remainder.iter().position(|c| *c == 0)?;
+        if end == 0 {
+            // Final terminator, nothing may follow it
+            return if remainder.len() == 1 { Some(strings) } else { None };
+        }
+        strings.push(Cow::Owned(String::from_utf16(&remainder[..end]).ok()?));
+        remainder = &remainder[end + 1..];
+    }
+    Some(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_multi_sz, CountedEtwString, EtwString, ParseString, StrIgnoreCase, Utf16Matcher, Utf16Units};
+
+    fn encode(string: &str) -> Vec<u8> {
+        string.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn test_parse_multi_sz_well_formed() {
+        let strings = parse_multi_sz(&encode("first\0second\0\0")).unwrap();
+        assert_eq!(strings, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_parse_multi_sz_missing_final_terminator() {
+        let strings = parse_multi_sz(&encode("first\0second\0")).unwrap();
+        assert_eq!(strings, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_parse_multi_sz_empty() {
+        assert_eq!(parse_multi_sz(&[]).unwrap(), Vec::<String>::new());
+        assert_eq!(parse_multi_sz(&encode("\0")).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse_multi_sz_malformed() {
+        // Odd number of bytes
+        assert!(parse_multi_sz(&[0x41, 0x00, 0x00]).is_none());
+        // Last string isn't terminated
+        assert!(parse_multi_sz(&encode("first\0second")).is_none());
+        // Data after the final terminator
+        assert!(parse_multi_sz(&encode("first\0\0second\0\0")).is_none());
+    }
+
+    /// Small xorshift generator, so failures are reproducible from the seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn string(&mut self, alphabet: &[char], max_len: u64) -> String {
+            let len = self.next() % (max_len + 1);
+            (0..len)
+                .map(|_| alphabet[(self.next() % alphabet.len() as u64) as usize])
+                .collect()
+        }
+    }
+
+    fn naive_fold(string: &str) -> String {
+        string.to_ascii_lowercase()
+    }
+
+    #[test]
+    fn test_utf16_matchers_agree_with_decoded_comparison() {
+        let alphabets: [&[char]; 2] = [
+            &['a', 'b', 'A', 'B', '\\', '.'],
+            &['x', 'X', 'ä', 'Ä', 'ß', '😀', '\\'],
+        ];
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for alphabet in alphabets {
+            for _ in 0..2000 {
+                let haystack = rng.string(alphabet, 12);
+                let needle = rng.string(alphabet, 4);
+
+                let terminated = encode(&format!("{}\0", haystack));
+                let (string, _) = EtwString::<u16>::parse(&terminated).unwrap();
+                let units = haystack.encode_utf16().collect::<Vec<_>>();
+                let counted = CountedEtwString { data: &units[..] };
+                let matcher = Utf16Matcher::new(&needle);
+
+                let (folded_haystack, folded_needle) = (naive_fold(&haystack), naive_fold(&needle));
+                let equal = folded_haystack == folded_needle;
+                let suffix = folded_haystack.ends_with(&folded_needle);
+                let contained = folded_haystack.contains(&folded_needle);
+
+                let context = format!("{:?} / {:?}", haystack, needle);
+                assert_eq!(string.eq_ignore_ascii_case(&needle), equal, "{}", context);
+                assert_eq!(string.ends_with_ignore_case(&needle), suffix, "{}", context);
+                assert_eq!(string.contains_ignore_case(&needle), contained, "{}", context);
+                assert_eq!(counted.eq_ignore_ascii_case(&needle), equal, "{}", context);
+                assert_eq!(counted.ends_with_ignore_case(&needle), suffix, "{}", context);
+                assert_eq!(counted.contains_ignore_case(&needle), contained, "{}", context);
+                assert_eq!(matcher.is_eq(&string), equal, "{}", context);
+                assert_eq!(matcher.is_suffix_of(&counted), suffix, "{}", context);
+                assert_eq!(matcher.is_contained_in(&units[..]), contained, "{}", context);
+                assert_eq!(StrIgnoreCase::ends_with_ignore_case(haystack.as_str(), &needle), suffix, "{}", context);
+                assert_eq!(StrIgnoreCase::contains_ignore_case(haystack.as_str(), &needle), contained, "{}", context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_utf16_matchers_fold_ascii_only() {
+        let units = "C:\\Windows\\Ärger.DLL".encode_utf16().collect::<Vec<_>>();
+        assert!(Utf16Matcher::new("c:\\windows\\Ärger.dll").is_eq(&units[..]));
+        assert!(!Utf16Matcher::new("c:\\windows\\ärger.dll").is_eq(&units[..]));
+        assert!(units[..].ends_with_ignore_case(".dll"));
+        assert!(units[..].contains_ignore_case("\\WINDOWS\\"));
+        assert!(!units[..].contains_ignore_case("system32"));
+    }
+}
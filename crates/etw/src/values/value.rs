@@ -1,17 +1,30 @@
+use std::{
+    borrow::Cow,
+    fmt::Write,
+    mem::size_of,
+    slice,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use time::OffsetDateTime;
+
 use crate::{
     error::ParseError,
-    schema::in_type::InType,
+    schema::{in_type::InType, out_type::OutType},
+    timestamp::{Timestamp, TimestampContext},
     values::{primitives::SystemTimeRef, ItemSize},
 };
 
 use super::{
-    in_value::InValue,
-    misc::Sid,
+    in_value::{InValue, InValueOwned},
+    mapped::MappedValues,
+    render::{parse_socket_address, status_name, BinaryRendering, RenderOptions, RenderedValue},
+    misc::{Sid, WbemSid},
     primitives::{
         DoubleRef, FileTimeRef, FloatRef, GuidRef, Int16Ref, Int32Ref, Int64Ref, Int8Ref,
-        UInt16Ref, UInt32Ref, UInt64Ref, UInt8Ref, USizeRef,
+        UInt16Ref, UInt32Ref, UInt64Ref, UInt8Ref, PointerRef,
     },
-    strings::{parse_string_array, CountedEtwString, EtwString},
+    strings::{parse_multi_sz, parse_string_array, CountedEtwString, EtwString},
 };
 
 #[derive(Debug)]
@@ -19,6 +32,11 @@ pub struct Value<'a> {
     pub(crate) raw: &'a [u8],
     pub value: InValue<'a>,
     pub is_array: bool,
+    /// Out-type of the property the value was decoded for, if decoded through a schema.
+    pub out_type: Option<OutType>,
+    /// The value resolved through the property's value map, if decoded through a
+    /// schema that has the map.
+    pub mapped: Option<MappedValues>,
 }
 
 impl<'a> Value<'a> {
@@ -33,6 +51,275 @@ impl<'a> Value<'a> {
     pub fn is_array(&self) -> bool {
         self.is_array
     }
+
+    /// The in-type of the decoded value and the out-type of its property.
+    ///
+    /// The out-type is None for values parsed without a schema.
+    pub fn type_info(&self) -> (InType, Option<OutType>) {
+        (self.value.datatype(), self.out_type)
+    }
+
+    /// Returns the text of each element of a string value.
+    pub(crate) fn string_elements(&self) -> Option<Vec<String>> {
+        self.value.iter_strings().ok().map(Iterator::collect)
+    }
+
+    /// Returns the text of a single string value.
+    pub(crate) fn string_content(&self) -> Option<String> {
+        self.value.as_string().ok()
+    }
+
+    /// Returns the embedded document of a string property marked as JSON or XML.
+    ///
+    /// The document is returned as-is. Returns None if the out-type is neither
+    /// [`OutType::Json`] nor [`OutType::Xml`], or if the value isn't a single string.
+    pub fn embedded_document(&self, out_type: OutType) -> Option<String> {
+        match out_type {
+            OutType::Json | OutType::Xml => self.string_content(),
+            _ => None,
+        }
+    }
+
+    /// Parse the embedded document of a string property marked as JSON.
+    ///
+    /// Returns None if the property isn't a JSON string property.
+    #[cfg(feature = "json")]
+    pub fn embedded_json(&self, out_type: OutType) -> Option<Result<serde_json::Value, serde_json::Error>> {
+        match out_type {
+            OutType::Json => self.string_content().map(|string| serde_json::from_str(&string)),
+            _ => None,
+        }
+    }
+
+    /// Interpret binary data as a list of null terminated UTF-16 strings (`REG_MULTI_SZ`).
+    ///
+    /// This is a best-effort parse: it returns None for non-binary values and for data
+    /// that isn't a well-formed string list.
+    pub fn as_multi_sz(&self) -> Option<Vec<Cow<'a, str>>> {
+        match &self.value {
+            InValue::Binary(_) | InValue::HexDump(_) => parse_multi_sz(self.raw),
+            _ => None,
+        }
+    }
+
+    /// Parse the `SOCKADDR_IN` or `SOCKADDR_IN6` elements of a binary property marked as
+    /// [`OutType::SocketAddress`].
+    ///
+    /// Returns None if the property isn't a socket address property.
+    pub fn socket_addresses(&self) -> Option<Result<Vec<SocketAddr>, ParseError>> {
+        match (&self.value, self.out_type) {
+            (InValue::Binary(blobs), Some(OutType::SocketAddress)) => {
+                Some(blobs.iter().map(|blob| parse_socket_address(blob)).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse a scalar socket address property, see [`Value::socket_addresses`].
+    pub fn socket_address(&self) -> Option<Result<SocketAddr, ParseError>> {
+        self.socket_addresses().map(|addresses| match addresses?.as_slice() {
+            [address] => Ok(*address),
+            _ => Err(ParseError::UnexpectedCount),
+        })
+    }
+}
+
+/// The elements of a primitive value, formatted with `$format`.
+macro_rules! format_elements {
+    ($value: expr, $format: expr) => {
+        $value.iter().map($format).collect::<Vec<String>>()
+    };
+}
+
+macro_rules! integer_elements {
+    ($value: expr, $ty: ty) => {
+        $value.iter().map(|value| value as $ty as u64).collect::<Vec<u64>>()
+    };
+}
+
+impl<'a> Value<'a> {
+    /// Render the value the way ETW intends for a property with `out_type`, e.g. a
+    /// `UInt32` with [`OutType::IpV4`] as `192.168.0.1`.
+    ///
+    /// Combinations of in- and out-type without a special representation, including
+    /// unknown out-types, are rendered in the in-type's natural representation.
+    /// Elements of arrays are separated by `, ` and enclosed in brackets.
+    pub fn format(&self, out_type: OutType) -> Result<String, ParseError> {
+        self.render(out_type).map(|rendered| rendered.to_string())
+    }
+
+    /// Render the elements of the value as text, like [`Value::format`], but keep them apart.
+    pub fn render(&self, out_type: OutType) -> Result<RenderedValue, ParseError> {
+        self.render_with_options(out_type, &RenderOptions::default())
+    }
+
+    /// Like [`Value::render`], with the special cases `options` enables.
+    pub fn render_with_options(&self, out_type: OutType, options: &RenderOptions) -> Result<RenderedValue, ParseError> {
+        if options.multi_sz
+            && out_type == OutType::HexBinary
+            && !self.is_array
+            && let Some(strings) = self.as_multi_sz()
+        {
+            return Ok(RenderedValue::Array(strings.into_iter().map(Cow::into_owned).collect()));
+        }
+        #[cfg(feature = "json")]
+        if options.pretty_json
+            && let Some(Ok(document)) = self.embedded_json(out_type)
+            && let Ok(pretty) = serde_json::to_string_pretty(&document)
+        {
+            return Ok(RenderedValue::Scalar(pretty));
+        }
+        let elements = match self.format_for_out_type(out_type) {
+            Some(elements) => elements,
+            None => self.format_natural(options.binary)?,
+        };
+        if self.is_array {
+            Ok(RenderedValue::Array(elements))
+        } else {
+            Ok(RenderedValue::Scalar(elements.join(", ")))
+        }
+    }
+
+    /// The elements of an integer value, as unsigned numbers of the same width.
+    fn integer_elements(&self) -> Option<Vec<u64>> {
+        Some(match &self.value {
+            InValue::Int8(value) => integer_elements!(value, u8),
+            InValue::UInt8(value) => integer_elements!(value, u8),
+            InValue::Int16(value) => integer_elements!(value, u16),
+            InValue::UInt16(value) => integer_elements!(value, u16),
+            InValue::Int32(value) => integer_elements!(value, u32),
+            InValue::UInt32(value) | InValue::HexInt32(value) => integer_elements!(value, u32),
+            InValue::Int64(value) => integer_elements!(value, u64),
+            InValue::UInt64(value) | InValue::HexInt64(value) => integer_elements!(value, u64),
+            InValue::Pointer(value) | InValue::SizeT(value) => {
+                value.to_vec()
+            }
+            _ => return None,
+        })
+    }
+
+    fn format_for_out_type(&self, out_type: OutType) -> Option<Vec<String>> {
+        match (&self.value, out_type) {
+            (InValue::UInt32(value) | InValue::HexInt32(value), OutType::IpV4) => {
+                Some(format_elements!(value, |addr: u32| Ipv4Addr::from(addr.to_le_bytes()).to_string()))
+            }
+            (InValue::Binary(blobs), OutType::IpV6) if blobs.iter().all(|blob| blob.len() == 16) => Some(
+                blobs
+                    .iter()
+                    .filter_map(|blob| <[u8; 16]>::try_from(*blob).ok())
+                    .map(|octets| Ipv6Addr::from(octets).to_string())
+                    .collect(),
+            ),
+            // Ports are logged in network byte order.
+            (InValue::UInt16(value), OutType::Port) => {
+                Some(format_elements!(value, |port: u16| u16::from_be(port).to_string()))
+            }
+            (_, OutType::HexInt8 | OutType::HexInt16 | OutType::HexInt32 | OutType::HexInt64) => {
+                let elements = self.integer_elements()?;
+                Some(elements.into_iter().map(|value| format!("0x{:X}", value)).collect())
+            }
+            (InValue::Binary(blobs), OutType::SocketAddress) => blobs
+                .iter()
+                .map(|blob| parse_socket_address(blob).ok().map(|address| address.to_string()))
+                .collect(),
+            // Timestamps in FILETIME units logged as integers
+            (InValue::Int64(value), OutType::DateTime | OutType::EtwTime) => {
+                Some(format_elements!(value, filetime_ticks))
+            }
+            (InValue::UInt64(value), OutType::DateTime | OutType::EtwTime) => {
+                Some(format_elements!(value, |ticks| i64::try_from(ticks).map_or(ticks.to_string(), filetime_ticks)))
+            }
+            (_, OutType::Win32Error | OutType::NtStatus | OutType::HResult) => {
+                let elements = self.integer_elements()?;
+                Some(
+                    elements
+                        .into_iter()
+                        .map(|value| match u32::try_from(value).ok().and_then(|code| status_name(out_type, code)) {
+                            Some(name) => format!("0x{:08X} ({})", value, name),
+                            None => format!("0x{:08X}", value),
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn format_natural(&self, binary: BinaryRendering) -> Result<Vec<String>, ParseError> {
+        Ok(match &self.value {
+            InValue::Null => Vec::new(),
+            InValue::UnicodeString(_)
+            | InValue::AnsiString(_)
+            | InValue::CountedString(_)
+            | InValue::ReversedCountedString(_)
+            | InValue::CountedAnsiString(_)
+            | InValue::ReversedCountedAnsiString(_)
+            | InValue::NonNullTerminatedString(_)
+            | InValue::NonNullTerminatedAnsiString(_) => self.string_elements().unwrap_or_default(),
+            InValue::Int8(value) => format_elements!(value, |value| value.to_string()),
+            InValue::UInt8(value) => format_elements!(value, |value| value.to_string()),
+            InValue::Int16(value) => format_elements!(value, |value| value.to_string()),
+            InValue::UInt16(value) => format_elements!(value, |value| value.to_string()),
+            InValue::Int32(value) => format_elements!(value, |value| value.to_string()),
+            InValue::UInt32(value) => format_elements!(value, |value| value.to_string()),
+            InValue::Int64(value) => format_elements!(value, |value| value.to_string()),
+            InValue::UInt64(value) => format_elements!(value, |value| value.to_string()),
+            InValue::Float(value) => format_elements!(value, |value| value.to_string()),
+            InValue::Double(value) => format_elements!(value, |value| value.to_string()),
+            InValue::Boolean(value) => format_elements!(value, |value| (value != 0).to_string()),
+            InValue::Binary(blobs) => blobs.iter().map(|blob| binary.render(blob)).collect(),
+            InValue::Guid(value) => format_elements!(value, |guid| format!("{{{:?}}}", guid)),
+            InValue::Pointer(value) | InValue::SizeT(value) => format_elements!(value, |value| format!("0x{:X}", value)),
+            InValue::FileTime(value) => format_elements!(value, |time| {
+                filetime_ticks((i64::from(time.dwHighDateTime) << 32) | i64::from(time.dwLowDateTime))
+            }),
+            InValue::SystemTime(value) => format_elements!(value, |time| format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+                time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute, time.wSecond, time.wMilliseconds
+            )),
+            InValue::Sid(sids) => sids
+                .iter()
+                .map(String::try_from)
+                .collect::<Result<_, _>>()?,
+            InValue::WbemSid(sids) => sids
+                .iter()
+                .map(|sid| String::try_from(sid.sid()))
+                .collect::<Result<_, _>>()?,
+            InValue::HexInt32(value) => format_elements!(value, |value| format!("0x{:X}", value)),
+            InValue::HexInt64(value) => format_elements!(value, |value| format!("0x{:X}", value)),
+            InValue::UnicodeChar(value) => format_elements!(value, |c| String::from_utf16_lossy(&[c])),
+            InValue::AnsiChar(value) => format_elements!(value, |c| char::from(c).to_string()),
+            InValue::HexDump(data) => vec![binary.render(data)],
+        })
+    }
+}
+
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().fold(String::from("0x"), |mut output, b| {
+        let _ = write!(output, "{b:02X}");
+        output
+    })
+}
+
+/// A FILETIME as an RFC 3339 timestamp, or its ticks if it's out of range.
+fn filetime_ticks(ticks: i64) -> String {
+    match TimestampContext::new().convert(ticks) {
+        Timestamp::Absolute(time) => iso8601(time),
+        Timestamp::Relative(_) | Timestamp::Missing => ticks.to_string(),
+    }
+}
+
+fn iso8601(time: OffsetDateTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.nanosecond() / 100
+    )
 }
 
 macro_rules! decode_plain_type {
@@ -57,7 +344,31 @@ macro_rules! decode_plain_type {
     };
 }
 
+// The declared length of pointers is that of the process which built the schema,
+// so only the pointer size of the event record decides how many bytes are read.
+macro_rules! decode_pointer_type {
+    ($variant: ident, $data: ident, $length: ident, $count: ident, $pointer_size: ident) => {
+        if ![0, 4, 8].contains(&$length) || ![4, 8].contains(&$pointer_size) {
+            return Err(ParseError::UnexpectedSize);
+        } else {
+            if $data.len() < $pointer_size * $count {
+                return Err(ParseError::PrematureEndOfData);
+            } else {
+                (
+                    InValue::$variant(PointerRef {
+                        data: &$data[..$pointer_size * $count],
+                        pointer_size: $pointer_size,
+                    }),
+                    &$data[..$pointer_size * $count],
+                    &$data[$pointer_size * $count..],
+                )
+            }
+        }
+    };
+}
+
 impl<'a> Value<'a> {
+    /// Parse a value of an event logged by a process with the same pointer size as this one.
     pub fn parse<'b>(
         data: &'b [u8],
         value_type: InType,
@@ -65,6 +376,22 @@ impl<'a> Value<'a> {
         count: usize,
         is_array: bool,
     ) -> Result<(Value<'a>, &'b [u8]), ParseError>
+    where
+        'b: 'a,
+    {
+        Self::parse_with_pointer_size(data, value_type, length, count, is_array, size_of::<usize>())
+    }
+
+    /// Parse a value of an event with pointers of `pointer_size` bytes, see
+    /// [`crate::values::event::EventRecord::pointer_size`].
+    pub fn parse_with_pointer_size<'b>(
+        data: &'b [u8],
+        value_type: InType,
+        length: usize,
+        count: usize,
+        is_array: bool,
+        pointer_size: usize,
+    ) -> Result<(Value<'a>, &'b [u8]), ParseError>
     where
         'b: 'a,
     {
@@ -104,9 +431,12 @@ impl<'a> Value<'a> {
             InType::Double => decode_plain_type!(DoubleRef, Double, data, length, count),
             InType::Boolean => decode_plain_type!(UInt32Ref, Boolean, data, length, count),
             InType::Binary => {
-                if length == 0 {
-                    return Err(ParseError::UnexpectedSize);
-                }
+                // Without a length, a single blob extends to the end of the data
+                let length = if length == 0 && count == 1 { data.len() } else { length };
+                let size = length
+                    .checked_mul(count)
+                    .filter(|size| *size <= data.len())
+                    .ok_or(ParseError::PrematureEndOfData)?;
 
                 let mut values = Vec::with_capacity(count);
 
@@ -114,14 +444,10 @@ impl<'a> Value<'a> {
                     values.push(&data[idx * length..(idx + 1) * length]);
                 }
 
-                (
-                    InValue::Binary(values),
-                    &data[0..length * count],
-                    &data[length * count..],
-                )
+                (InValue::Binary(values), &data[..size], &data[size..])
             }
             InType::Guid => decode_plain_type!(GuidRef, Guid, data, length, count),
-            InType::Pointer => decode_plain_type!(USizeRef, Pointer, data, length, count),
+            InType::Pointer => decode_pointer_type!(Pointer, data, length, count, pointer_size),
             InType::FileTime => decode_plain_type!(FileTimeRef, FileTime, data, length, count),
             InType::SystemTime => {
                 decode_plain_type!(SystemTimeRef, SystemTime, data, length, count)
@@ -203,15 +529,89 @@ impl<'a> Value<'a> {
                     remainder,
                 )
             }
-            InType::NonNullTerminatedString => return Err(ParseError::UnknownInType(value_type)),
+            InType::NonNullTerminatedString => {
+                if count != 1 {
+                    return Err(ParseError::UnexpectedCount);
+                }
+                // Without a length, the string extends to the end of the data
+                let size = if length == 0 {
+                    data.len()
+                } else {
+                    length
+                        .checked_mul(size_of::<u16>())
+                        .filter(|size| *size <= data.len())
+                        .ok_or(ParseError::PrematureEndOfData)?
+                };
+                if size % size_of::<u16>() != 0 {
+                    return Err(ParseError::UnexpectedSize);
+                }
+                let string_data = &data[..size];
+                // Empty data may have a dangling, unaligned pointer
+                let string: &[u16] = if size == 0 {
+                    &[]
+                } else {
+                    #[cfg(not(feature = "unchecked_cast"))]
+                    if string_data.as_ptr().align_offset(std::mem::align_of::<u16>()) != 0 {
+                        return Err(ParseError::UnalignedData("NonNullTerminatedString".to_string()));
+                    }
+                    unsafe { slice::from_raw_parts(string_data.as_ptr() as *const u16, size / size_of::<u16>()) }
+                };
+
+                (InValue::NonNullTerminatedString(string), string_data, &data[size..])
+            }
             InType::NonNullTerminatedAnsiString => {
-                return Err(ParseError::UnknownInType(value_type))
+                if count != 1 {
+                    return Err(ParseError::UnexpectedCount);
+                }
+                let size = if length == 0 { data.len() } else { length };
+                let string_data = data.get(..size).ok_or(ParseError::PrematureEndOfData)?;
+
+                (InValue::NonNullTerminatedAnsiString(string_data), string_data, &data[size..])
             }
             InType::UnicodeChar => decode_plain_type!(UInt16Ref, UnicodeChar, data, length, count),
             InType::AnsiChar => decode_plain_type!(UInt8Ref, AnsiChar, data, length, count),
-            InType::SizeT => decode_plain_type!(USizeRef, SizeT, data, length, count),
-            InType::HexDump => return Err(ParseError::UnknownInType(value_type)),
-            InType::WbemSid => return Err(ParseError::UnknownInType(value_type)),
+            InType::SizeT => decode_pointer_type!(SizeT, data, length, count, pointer_size),
+            InType::HexDump => {
+                // A ULONG size followed by that many bytes
+                if count != 1 {
+                    return Err(ParseError::UnexpectedCount);
+                }
+                let prefix_size = size_of::<u32>();
+                let prefix = data.get(..prefix_size).ok_or(ParseError::PrematureEndOfData)?;
+                let size = usize::try_from(u32::from_le_bytes(prefix.try_into().unwrap()))?;
+                let end = prefix_size
+                    .checked_add(size)
+                    .filter(|end| *end <= data.len())
+                    .ok_or(ParseError::PrematureEndOfData)?;
+
+                (InValue::HexDump(&data[prefix_size..end]), &data[..end], &data[end..])
+            }
+            InType::WbemSid => {
+                if length != 0 {
+                    return Err(ParseError::UnexpectedSize);
+                }
+                let mut sids = Vec::with_capacity(count);
+
+                let mut raw_size = 0;
+                let mut remainder = data;
+
+                for _ in 0..count {
+                    if remainder.len() < 2 * pointer_size {
+                        return Err(ParseError::PrematureEndOfData);
+                    }
+                    match WbemSid::new(remainder, pointer_size) {
+                        Some(sid) => {
+                            let size = sid.size();
+                            raw_size += size;
+                            remainder = &remainder[size..];
+                            sids.push(sid);
+                        }
+                        None => return Err(ParseError::InvalidSid),
+                    }
+                }
+
+                (InValue::WbemSid(sids), &data[0..raw_size], remainder)
+            }
             _ => return Err(ParseError::UnknownInType(value_type)),
         };
 
@@ -220,8 +620,322 @@ impl<'a> Value<'a> {
                 raw,
                 value,
                 is_array,
+                out_type: None,
+                mapped: None,
             },
             remainder,
         ))
     }
 }
+
+/// A [`Value`] with its data copied out of the event record.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValueOwned {
+    pub value: InValueOwned,
+    pub is_array: bool,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub out_type: Option<OutType>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub mapped: Option<MappedValues>,
+}
+
+impl From<&Value<'_>> for ValueOwned {
+    fn from(value: &Value<'_>) -> Self {
+        Self {
+            value: InValueOwned::from(&value.value),
+            is_array: value.is_array,
+            out_type: value.out_type,
+            mapped: value.mapped.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ParseError,
+        schema::{in_type::InType, out_type::OutType},
+        values::{
+            in_value::InValue,
+            render::{BinaryRendering, RenderOptions, RenderedValue},
+            value::Value,
+        },
+    };
+
+    fn format(data: &[u8], in_type: InType, length: usize, count: usize, out_type: OutType) -> String {
+        let is_array = count > 1;
+        let (value, _) = Value::parse(data, in_type, length, count, is_array).unwrap();
+        value.format(out_type).unwrap()
+    }
+
+    #[test]
+    fn test_socket_addresses() {
+        let mut ipv4 = vec![2, 0, 0x01, 0xbb, 10, 0, 0, 1];
+        ipv4.extend([0; 8]);
+        let (mut value, _) = Value::parse(&ipv4, InType::Binary, ipv4.len(), 1, false).unwrap();
+        assert!(value.socket_address().is_none());
+
+        value.out_type = Some(OutType::SocketAddress);
+        assert_eq!(value.socket_address().unwrap().unwrap(), "10.0.0.1:443".parse().unwrap());
+
+        let unknown_family = [1u8, 0, 0, 0];
+        let (mut value, _) = Value::parse(&unknown_family, InType::Binary, 4, 1, false).unwrap();
+        value.out_type = Some(OutType::SocketAddress);
+        assert!(matches!(value.socket_address(), Some(Err(ParseError::UnknownAddressFamily(1)))));
+
+        let (mut value, _) = Value::parse(&ipv4[..6], InType::Binary, 6, 1, false).unwrap();
+        value.out_type = Some(OutType::SocketAddress);
+        assert!(matches!(value.socket_addresses(), Some(Err(ParseError::PrematureEndOfData))));
+    }
+
+    #[test]
+    fn test_format_addresses() {
+        assert_eq!(format(&[192, 168, 0, 1], InType::UInt32, 4, 1, OutType::IpV4), "192.168.0.1");
+        let mut ipv6 = [0u8; 16];
+        ipv6[0] = 0xfe;
+        ipv6[1] = 0x80;
+        ipv6[15] = 1;
+        assert_eq!(format(&ipv6, InType::Binary, 16, 1, OutType::IpV6), "fe80::1");
+        assert_eq!(format(&443u16.to_be_bytes(), InType::UInt16, 2, 1, OutType::Port), "443");
+    }
+
+    #[test]
+    fn test_format_codes() {
+        assert_eq!(format(&255u32.to_le_bytes(), InType::UInt32, 4, 1, OutType::HexInt32), "0xFF");
+        assert_eq!(format(&(-1i64).to_le_bytes(), InType::Int64, 8, 1, OutType::HexInt64), "0xFFFFFFFFFFFFFFFF");
+        assert_eq!(format(&5u32.to_le_bytes(), InType::UInt32, 4, 1, OutType::Win32Error), "0x00000005 (ERROR_ACCESS_DENIED)");
+        assert_eq!(format(&4711u32.to_le_bytes(), InType::UInt32, 4, 1, OutType::Win32Error), "0x00001267");
+        assert_eq!(
+            format(&0xC000_0005u32.to_le_bytes(), InType::UInt32, 4, 1, OutType::NtStatus),
+            "0xC0000005 (STATUS_ACCESS_VIOLATION)"
+        );
+        assert_eq!(
+            format(&0x8007_0005u32.to_le_bytes(), InType::Int32, 4, 1, OutType::HResult),
+            "0x80070005 (E_ACCESSDENIED)"
+        );
+    }
+
+    #[test]
+    fn test_render_socket_addresses_and_times() {
+        let mut socket_address = vec![2, 0, 0x01, 0xbb, 10, 0, 0, 1];
+        socket_address.extend([0; 8]);
+        assert_eq!(format(&socket_address, InType::Binary, 16, 1, OutType::SocketAddress), "10.0.0.1:443");
+        // Unknown families fall back to hex
+        assert_eq!(format(&[9, 0, 0, 0], InType::Binary, 4, 1, OutType::SocketAddress), "0x09000000");
+        let ticks = 133_274_160_000_000_000u64.to_le_bytes();
+        assert_eq!(format(&ticks, InType::UInt64, 8, 1, OutType::EtwTime), "2023-05-01T12:00:00.0000000Z");
+
+        let ports = [80u16, 443].iter().flat_map(|port| port.to_be_bytes()).collect::<Vec<_>>();
+        let (value, _) = Value::parse(&ports, InType::UInt16, 2, 2, true).unwrap();
+        assert_eq!(
+            value.render(OutType::Port).unwrap(),
+            RenderedValue::Array(vec!["80".to_string(), "443".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_render_multi_sz_behind_option() {
+        let multi_sz = "first\0second\0\0".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let (value, _) = Value::parse(&multi_sz, InType::Binary, multi_sz.len(), 1, false).unwrap();
+        let options = RenderOptions {
+            multi_sz: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            value.render_with_options(OutType::HexBinary, &options).unwrap(),
+            RenderedValue::Array(vec!["first".to_string(), "second".to_string()])
+        );
+        assert!(value.format(OutType::HexBinary).unwrap().starts_with("0x6600"));
+        // Only for binary properties
+        assert!(matches!(
+            value.render_with_options(OutType::Null, &options).unwrap(),
+            RenderedValue::Scalar(_)
+        ));
+
+        // Malformed lists stay hex
+        let (value, _) = Value::parse(&multi_sz[..9], InType::Binary, 9, 1, false).unwrap();
+        assert_eq!(
+            value.render_with_options(OutType::HexBinary, &options).unwrap(),
+            RenderedValue::Scalar("0x660069007200730074".to_string())
+        );
+    }
+
+    #[test]
+    fn test_render_binary_behind_option() {
+        let data = [0x01, 0x02, 0x03, 0x04];
+        let (value, _) = Value::parse(&data, InType::Binary, data.len(), 1, false).unwrap();
+        let options = RenderOptions {
+            binary: BinaryRendering::Base64,
+            ..Default::default()
+        };
+        assert_eq!(value.render_with_options(OutType::HexBinary, &options).unwrap().to_string(), "AQIDBA==");
+        let options = RenderOptions {
+            binary: BinaryRendering::Truncated(2),
+            ..Default::default()
+        };
+        assert_eq!(value.render_with_options(OutType::Null, &options).unwrap().to_string(), "0x0102... (4 bytes)");
+        assert_eq!(value.format(OutType::HexBinary).unwrap(), "0x01020304");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_render_pretty_json_behind_option() {
+        let document = r#"{"a":[1,2]}"#.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let (value, _) = Value::parse(&document, InType::UnicodeString, 0, 1, false).unwrap();
+        let options = RenderOptions {
+            pretty_json: true,
+            ..Default::default()
+        };
+        assert_eq!(value.format(OutType::Json).unwrap(), r#"{"a":[1,2]}"#);
+        assert_eq!(
+            value.render_with_options(OutType::Json, &options).unwrap(),
+            RenderedValue::Scalar("{\n  \"a\": [\n    1,\n    2\n  ]\n}".to_string())
+        );
+        // XML and broken JSON are kept as they are
+        assert_eq!(value.render_with_options(OutType::Xml, &options).unwrap().to_string(), r#"{"a":[1,2]}"#);
+        let broken = "{\0".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let (value, _) = Value::parse(&broken, InType::UnicodeString, 0, 1, false).unwrap();
+        assert_eq!(value.render_with_options(OutType::Json, &options).unwrap().to_string(), "{");
+    }
+
+    #[test]
+    fn test_format_falls_back_to_in_type() {
+        assert_eq!(format(&7u32.to_le_bytes(), InType::UInt32, 4, 1, OutType::Unknown(400)), "7");
+        assert_eq!(format(&[1, 2, 3], InType::Binary, 3, 1, OutType::IpV6), "0x010203");
+        let data = [1u16, 2].iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+        assert_eq!(format(&data, InType::UInt16, 2, 2, OutType::Null), "[1, 2]");
+        let string = "abc\0".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        assert_eq!(format(&string, InType::UnicodeString, 0, 1, OutType::String), "abc");
+        let filetime = 133_274_160_000_000_000i64.to_le_bytes();
+        assert_eq!(format(&filetime, InType::FileTime, 8, 1, OutType::DateTime), "2023-05-01T12:00:00.0000000Z");
+    }
+
+    #[test]
+    fn test_parse_wbem_sid_skips_token_user_prefix() {
+        // S-1-5-18
+        let sid = [1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+        for pointer_size in [4, 8] {
+            let mut data = vec![0xcc; 2 * pointer_size];
+            data.extend_from_slice(&sid);
+            data.extend_from_slice(&[0xaa, 0xbb]);
+
+            let (value, remainder) =
+                Value::parse_with_pointer_size(&data, InType::WbemSid, 0, 1, false, pointer_size).unwrap();
+            assert_eq!(remainder, &[0xaa, 0xbb]);
+            assert_eq!(value.raw, &data[..data.len() - 2]);
+            let InValue::WbemSid(sids) = &value.value else {
+                panic!("Expected WbemSid, got {:?}", value.value);
+            };
+            assert_eq!(sids[0].data(), value.raw);
+            assert_eq!(sids[0].sid().data(), &sid);
+            assert_eq!(value.format(OutType::Null).unwrap(), "S-1-5-18");
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_without_length_takes_remaining_data() {
+        let data = [1, 2, 3];
+        let (value, remainder) = Value::parse(&data, InType::Binary, 0, 1, false).unwrap();
+        assert!(remainder.is_empty());
+        assert_eq!(value.raw, &data);
+        let InValue::Binary(blobs) = &value.value else {
+            panic!("Expected Binary, got {:?}", value.value);
+        };
+        assert_eq!(blobs, &[&data[..]]);
+
+        let (value, remainder) = Value::parse(&[], InType::Binary, 0, 1, false).unwrap();
+        assert!(remainder.is_empty());
+        assert!(matches!(&value.value, InValue::Binary(blobs) if blobs == &[&[] as &[u8]]));
+    }
+
+    #[test]
+    fn test_parse_binary_array_without_length_is_empty_blobs() {
+        let data = [1, 2, 3];
+        let (value, remainder) = Value::parse(&data, InType::Binary, 0, 3, true).unwrap();
+        assert_eq!(remainder, &data);
+        assert!(value.raw.is_empty());
+        assert!(matches!(&value.value, InValue::Binary(blobs) if blobs.len() == 3 && blobs.iter().all(|blob| blob.is_empty())));
+    }
+
+    #[test]
+    fn test_parse_sized_binary() {
+        let data = [1, 2, 3, 4, 5];
+        let (value, remainder) = Value::parse(&data, InType::Binary, 2, 2, true).unwrap();
+        assert_eq!(remainder, &[5]);
+        assert_eq!(value.raw, &data[..4]);
+        assert!(matches!(&value.value, InValue::Binary(blobs) if blobs == &[&data[..2], &data[2..4]]));
+
+        let result = Value::parse(&data, InType::Binary, 3, 2, true);
+        assert!(matches!(result, Err(ParseError::PrematureEndOfData)));
+    }
+
+    #[test]
+    fn test_parse_sized_non_null_terminated_strings() {
+        let data = "abcd".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let (value, remainder) = Value::parse(&data, InType::NonNullTerminatedString, 3, 1, false).unwrap();
+        assert_eq!(remainder, &data[6..]);
+        assert_eq!(value.raw, &data[..6]);
+        assert_eq!(value.format(OutType::Null).unwrap(), "abc");
+
+        let (value, remainder) = Value::parse(b"abcd", InType::NonNullTerminatedAnsiString, 3, 1, false).unwrap();
+        assert_eq!(remainder, b"d");
+        assert_eq!(value.format(OutType::Null).unwrap(), "abc");
+
+        let result = Value::parse(&data, InType::NonNullTerminatedString, 5, 1, false);
+        assert!(matches!(result, Err(ParseError::PrematureEndOfData)));
+        let result = Value::parse(b"abcd", InType::NonNullTerminatedAnsiString, 5, 1, false);
+        assert!(matches!(result, Err(ParseError::PrematureEndOfData)));
+    }
+
+    #[test]
+    fn test_parse_non_null_terminated_strings_to_end_of_data() {
+        let data = "abcd".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let (value, remainder) = Value::parse(&data, InType::NonNullTerminatedString, 0, 1, false).unwrap();
+        assert!(remainder.is_empty());
+        assert_eq!(value.format(OutType::Null).unwrap(), "abcd");
+
+        let (value, remainder) = Value::parse(b"abcd", InType::NonNullTerminatedAnsiString, 0, 1, false).unwrap();
+        assert!(remainder.is_empty());
+        assert_eq!(value.format(OutType::Null).unwrap(), "abcd");
+
+        // An empty remainder is an empty string
+        let (value, _) = Value::parse(&[], InType::NonNullTerminatedString, 0, 1, false).unwrap();
+        assert!(matches!(value.value, InValue::NonNullTerminatedString(&[])));
+        let (value, _) = Value::parse(&[], InType::NonNullTerminatedAnsiString, 0, 1, false).unwrap();
+        assert!(matches!(value.value, InValue::NonNullTerminatedAnsiString(&[])));
+
+        let result = Value::parse(&data[..7], InType::NonNullTerminatedString, 0, 1, false);
+        assert!(matches!(result, Err(ParseError::UnexpectedSize)));
+    }
+
+    #[test]
+    fn test_parse_hex_dump_reads_size_prefix() {
+        let data = [3, 0, 0, 0, 0xde, 0xad, 0xbe, 0xef];
+        let (value, remainder) = Value::parse(&data, InType::HexDump, 0, 1, false).unwrap();
+        assert_eq!(remainder, &[0xef]);
+        assert_eq!(value.raw, &data[..7]);
+        let InValue::HexDump(dump) = &value.value else {
+            panic!("Expected HexDump, got {:?}", value.value);
+        };
+        assert_eq!(dump, &[0xde, 0xad, 0xbe]);
+
+        let (value, remainder) = Value::parse(&[0, 0, 0, 0], InType::HexDump, 0, 1, false).unwrap();
+        assert!(remainder.is_empty());
+        assert!(matches!(value.value, InValue::HexDump(&[])));
+    }
+
+    #[test]
+    fn test_parse_hex_dump_shorter_than_size() {
+        let result = Value::parse(&[4, 0, 0, 0, 0xde], InType::HexDump, 0, 1, false);
+        assert!(matches!(result, Err(ParseError::PrematureEndOfData)));
+        let result = Value::parse(&[4, 0], InType::HexDump, 0, 1, false);
+        assert!(matches!(result, Err(ParseError::PrematureEndOfData)));
+    }
+
+    #[test]
+    fn test_parse_wbem_sid_shorter_than_prefix() {
+        let result = Value::parse_with_pointer_size(&[0; 12], InType::WbemSid, 0, 1, false, 8);
+        assert!(matches!(result, Err(ParseError::PrematureEndOfData)));
+    }
+}
@@ -0,0 +1,412 @@
+//! Leases that stop sessions of crashed collectors.
+//!
+//! A session started with [`crate::trace_session::TraceSessionBuilder::auto_stop_after`]
+//! records a lease (session name, session GUID and deadline) in a lease directory and
+//! keeps extending it from a background thread while the [`crate::trace_session::TraceSession`]
+//! is alive. If the process dies without dropping the session, the lease expires, and the
+//! next process using this crate that starts a session stops the orphaned session.
+//!
+//! Reaping is conservative: only sessions whose name starts with [`LEASE_SESSION_PREFIX`]
+//! and whose session GUID still matches the lease are stopped.
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Once,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use windows::core::GUID;
+
+use crate::error::TraceError;
+
+/// Sessions that use leases must have a name starting with this prefix.
+pub const LEASE_SESSION_PREFIX: &str = "etw-rs-";
+
+const LEASE_FILE_MAGIC: &str = "etw-rs-lease v1";
+const LEASE_FILE_EXTENSION: &str = "lease";
+const MINIMUM_RENEW_INTERVAL: Duration = Duration::from_secs(1);
+
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub session_name: String,
+    pub session_guid: GUID,
+    pub deadline: SystemTime,
+}
+
+impl Lease {
+    pub fn new(session_name: &str, session_guid: GUID, now: SystemTime, duration: Duration) -> Self {
+        Self {
+            session_name: session_name.to_string(),
+            session_guid,
+            deadline: now + duration,
+        }
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.deadline < now
+    }
+
+    /// True if the lease may be reaped: it has expired and belongs to a session of this crate.
+    pub fn is_reapable(&self, now: SystemTime) -> bool {
+        self.is_expired(now) && self.session_name.starts_with(LEASE_SESSION_PREFIX)
+    }
+
+    pub fn renew(&mut self, now: SystemTime, duration: Duration) {
+        self.deadline = now + duration;
+    }
+
+    fn to_record(&self) -> String {
+        let deadline = self
+            .deadline
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!(
+            "{}\n{}\n{:?}\n{}\n",
+            LEASE_FILE_MAGIC, self.session_name, self.session_guid, deadline
+        )
+    }
+
+    fn from_record(record: &str) -> Option<Self> {
+        let mut lines = record.lines();
+        if lines.next()? != LEASE_FILE_MAGIC {
+            return None;
+        }
+        let session_name = lines.next()?.to_string();
+        let session_guid = GUID::try_from(lines.next()?).ok()?;
+        let deadline = SystemTime::UNIX_EPOCH + Duration::from_secs(lines.next()?.parse().ok()?);
+        Some(Self {
+            session_name,
+            session_guid,
+            deadline,
+        })
+    }
+}
+
+/// Directory holding one file per lease.
+#[derive(Debug, Clone)]
+pub struct LeaseStore {
+    dir: PathBuf,
+}
+
+impl Default for LeaseStore {
+    fn default() -> Self {
+        Self::new(std::env::temp_dir().join("etw-rs-leases"))
+    }
+}
+
+impl LeaseStore {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, session_name: &str) -> PathBuf {
+        let file_name = session_name
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        self.dir.join(file_name).with_extension(LEASE_FILE_EXTENSION)
+    }
+
+    pub fn write(&self, lease: &Lease) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first so that a reaper never sees a partial lease
+        let path = self.path(&lease.session_name);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, lease.to_record())?;
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn remove(&self, session_name: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(session_name)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// All well-formed leases in the store.
+    pub fn leases(&self) -> io::Result<Vec<Lease>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut leases = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(LEASE_FILE_EXTENSION) {
+                continue;
+            }
+            match fs::read_to_string(&path).ok().as_deref().and_then(Lease::from_record) {
+                Some(lease) => leases.push(lease),
+                None => log::debug!("Ignoring malformed lease file {:?}", path),
+            }
+        }
+        Ok(leases)
+    }
+}
+
+/// What happened to a session when its lease was reaped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReapOutcome {
+    /// The session was stopped.
+    Stopped,
+    /// The session doesn't exist anymore.
+    Gone,
+    /// A session with that name exists, but it isn't the leased one.
+    Replaced,
+}
+
+/// Stop the sessions of all reapable leases in `store`.
+///
+/// `reap` stops a single session and reports what it found. Leases are removed unless
+/// `reap` fails. Returns the reaped leases.
+pub fn reap_expired<C, F>(store: &LeaseStore, clock: &C, mut reap: F) -> io::Result<Vec<(Lease, ReapOutcome)>>
+where
+    C: Clock,
+    F: FnMut(&Lease) -> Result<ReapOutcome, TraceError>,
+{
+    let now = clock.now();
+    let mut reaped = Vec::new();
+    for lease in store.leases()? {
+        if !lease.is_reapable(now) {
+            continue;
+        }
+        match reap(&lease) {
+            Ok(outcome) => {
+                log::info!("Reaped lease of session {}: {:?}", lease.session_name, outcome);
+                store.remove(&lease.session_name)?;
+                reaped.push((lease, outcome));
+            }
+            Err(err) => {
+                log::warn!("Failed to reap session {}: {:?}", lease.session_name, err);
+            }
+        }
+    }
+    Ok(reaped)
+}
+
+/// Stop the sessions of all expired leases in `store`.
+///
+/// [`crate::trace_session::TraceSessionBuilder::start`] does this for the default store
+/// once per process.
+pub fn reap_expired_sessions(store: &LeaseStore) -> io::Result<Vec<(Lease, ReapOutcome)>> {
+    reap_expired(store, &SystemClock, crate::trace_session::stop_leased_session)
+}
+
+pub(crate) fn reap_expired_sessions_once() {
+    static REAPED: Once = Once::new();
+    REAPED.call_once(|| {
+        if let Err(err) = reap_expired_sessions(&LeaseStore::default()) {
+            log::warn!("Failed to reap expired sessions: {:?}", err);
+        }
+    });
+}
+
+/// Keeps renewing a lease from a background thread until dropped.
+#[derive(Debug)]
+pub(crate) struct LeaseKeeper {
+    store: LeaseStore,
+    session_name: String,
+    stop: Option<Sender<bool>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl LeaseKeeper {
+    pub(crate) fn start(store: LeaseStore, mut lease: Lease, duration: Duration) -> io::Result<Self> {
+        store.write(&lease)?;
+        let session_name = lease.session_name.clone();
+        let (stop, stop_receiver) = mpsc::channel();
+        let interval = (duration / 3).max(MINIMUM_RENEW_INTERVAL);
+        let thread_store = store.clone();
+        let thread = thread::spawn(move || loop {
+            match stop_receiver.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {
+                    lease.renew(SystemClock.now(), duration);
+                    if let Err(err) = thread_store.write(&lease) {
+                        log::warn!("Failed to renew lease of session {}: {:?}", lease.session_name, err);
+                    }
+                }
+                Ok(remove) => {
+                    if remove {
+                        if let Err(err) = thread_store.remove(&lease.session_name) {
+                            log::warn!("Failed to remove lease of session {}: {:?}", lease.session_name, err);
+                        }
+                    }
+                    return;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+        Ok(Self {
+            store,
+            session_name,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Stop renewing. If `remove` is false the lease is left to expire.
+    pub(crate) fn release(&mut self, remove: bool) {
+        if let Some(stop) = self.stop.take() {
+            if stop.send(remove).is_err() && remove {
+                let _ = self.store.remove(&self.session_name);
+            }
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for LeaseKeeper {
+    fn drop(&mut self) {
+        self.release(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        time::{Duration, SystemTime},
+    };
+
+    use windows::core::GUID;
+
+    use super::{reap_expired, Clock, Lease, LeaseStore, ReapOutcome};
+
+    const SESSION_GUID: GUID = GUID::from_u128(0x3b9ac49e_1f4c_4d1a_9bb8_63a6c8d1f2e0);
+
+    struct MockClock(Cell<SystemTime>);
+
+    impl MockClock {
+        fn new() -> Self {
+            Self(Cell::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)))
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            self.0.get()
+        }
+    }
+
+    fn temp_store(name: &str) -> LeaseStore {
+        let dir = std::env::temp_dir().join(format!("etw-rs-lease-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        LeaseStore::new(dir)
+    }
+
+    #[test]
+    fn test_lease_expires_unless_renewed() {
+        let clock = MockClock::new();
+        let duration = Duration::from_secs(60);
+        let mut lease = Lease::new("etw-rs-collector", SESSION_GUID, clock.now(), duration);
+
+        clock.advance(Duration::from_secs(30));
+        assert!(!lease.is_expired(clock.now()));
+        lease.renew(clock.now(), duration);
+
+        clock.advance(Duration::from_secs(45));
+        assert!(!lease.is_expired(clock.now()));
+
+        clock.advance(Duration::from_secs(16));
+        assert!(lease.is_expired(clock.now()));
+        assert!(lease.is_reapable(clock.now()));
+    }
+
+    #[test]
+    fn test_foreign_session_is_never_reapable() {
+        let clock = MockClock::new();
+        let lease = Lease::new("SomeoneElsesSession", SESSION_GUID, clock.now(), Duration::from_secs(1));
+        clock.advance(Duration::from_secs(3600));
+        assert!(lease.is_expired(clock.now()));
+        assert!(!lease.is_reapable(clock.now()));
+    }
+
+    #[test]
+    fn test_lease_record_roundtrip() {
+        let lease = Lease::new(
+            "etw-rs-collector",
+            SESSION_GUID,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            Duration::from_secs(60),
+        );
+        assert_eq!(Lease::from_record(&lease.to_record()), Some(lease));
+        assert_eq!(Lease::from_record("not a lease\n"), None);
+    }
+
+    #[test]
+    fn test_reap_only_expired_leases() {
+        let store = temp_store("reap");
+        let clock = MockClock::new();
+        let expired = Lease::new("etw-rs-crashed", SESSION_GUID, clock.now(), Duration::from_secs(10));
+        let alive = Lease::new("etw-rs-alive", SESSION_GUID, clock.now(), Duration::from_secs(120));
+        let foreign = Lease::new("foreign", SESSION_GUID, clock.now(), Duration::from_secs(10));
+        store.write(&expired).unwrap();
+        store.write(&alive).unwrap();
+        store.write(&foreign).unwrap();
+
+        clock.advance(Duration::from_secs(60));
+        let mut stopped = Vec::new();
+        let reaped = reap_expired(&store, &clock, |lease| {
+            stopped.push(lease.session_name.clone());
+            Ok(ReapOutcome::Stopped)
+        })
+        .unwrap();
+
+        assert_eq!(stopped, vec!["etw-rs-crashed".to_string()]);
+        assert_eq!(reaped, vec![(expired, ReapOutcome::Stopped)]);
+        let mut remaining = store
+            .leases()
+            .unwrap()
+            .into_iter()
+            .map(|lease| lease.session_name)
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, vec!["etw-rs-alive".to_string(), "foreign".to_string()]);
+    }
+
+    #[test]
+    fn test_failed_reap_keeps_lease() {
+        let store = temp_store("failed");
+        let clock = MockClock::new();
+        let expired = Lease::new("etw-rs-crashed", SESSION_GUID, clock.now(), Duration::from_secs(10));
+        store.write(&expired).unwrap();
+
+        clock.advance(Duration::from_secs(60));
+        let reaped = reap_expired(&store, &clock, |_| {
+            Err(crate::error::TraceError::Configuration("access denied".to_string()))
+        })
+        .unwrap();
+
+        assert!(reaped.is_empty());
+        assert_eq!(store.leases().unwrap(), vec![expired]);
+    }
+}
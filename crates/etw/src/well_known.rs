@@ -0,0 +1,532 @@
+//! Typed views of frequently used events of inbox providers.
+//!
+//! Properties are looked up by name, so the structs accept all versions of an event
+//! that contain the mandatory properties. Properties that were added or removed in
+//! some versions are optional.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use windows::core::GUID;
+
+use crate::{
+    error::WellKnownEventError,
+    schema::cache::{EventInfo, PropertyStructInfo},
+    values::{
+        compound::{StringOrStruct, Struct, StructOrValue},
+        event::Event,
+        in_value::InValue,
+        value::Value,
+    },
+};
+
+pub const KERNEL_PROCESS_PROVIDER: GUID = GUID::from_u128(0x22fb2cd6_0e7b_422b_a0c7_2fad1fd0e716);
+pub const KERNEL_NETWORK_PROVIDER: GUID = GUID::from_u128(0x7dd42a49_5329_4832_8dfd_43d979153a88);
+pub const DNS_CLIENT_PROVIDER: GUID = GUID::from_u128(0x1c95126e_7eea_49a9_a3fe_a378b03ddb4d);
+
+const PROCESS_START_ID: u16 = 1;
+const PROCESS_STOP_ID: u16 = 2;
+const IMAGE_LOAD_ID: u16 = 5;
+const TCP_CONNECT_V4_ID: u16 = 12;
+const TCP_DISCONNECT_V4_ID: u16 = 13;
+const TCP_CONNECT_V6_ID: u16 = 28;
+const TCP_DISCONNECT_V6_ID: u16 = 29;
+const DNS_QUERY_START_ID: u16 = 3006;
+const DNS_QUERY_COMPLETED_ID: u16 = 3008;
+
+/// Name based access to the top level properties of a decoded event.
+struct Fields<'s, 'a> {
+    schema: &'s PropertyStructInfo,
+    data: &'s Struct<'a>,
+}
+
+impl<'s, 'a> Fields<'s, 'a> {
+    fn new(
+        schema: &'s EventInfo,
+        event: &'s Event<'a>,
+        provider: GUID,
+        ids: &[u16],
+    ) -> Result<Self, WellKnownEventError> {
+        if schema.provider_guid != provider || !ids.contains(&schema.event_id) {
+            return Err(WellKnownEventError::UnexpectedEvent {
+                provider: schema.provider_guid,
+                id: schema.event_id,
+                version: schema.event_version,
+            });
+        }
+        let StringOrStruct::Struct(data) = &event.data else {
+            return Err(WellKnownEventError::NotAStruct);
+        };
+        Ok(Self {
+            schema: &schema.properties,
+            data,
+        })
+    }
+
+    fn value(&self, name: &str) -> Option<&'s Value<'a>> {
+        let idx = self
+            .schema
+            .fields
+            .iter()
+            .position(|field| field.value.name() == name)?;
+        match self.data.values.get(idx)? {
+            StructOrValue::Value(value) => Some(value),
+            StructOrValue::Struct(_) => None,
+        }
+    }
+
+    fn optional<T>(
+        &self,
+        name: &'static str,
+        convert: fn(&Value<'a>) -> Option<T>,
+    ) -> Result<Option<T>, WellKnownEventError> {
+        match self.value(name) {
+            Some(value) => convert(value)
+                .map(Some)
+                .ok_or(WellKnownEventError::UnexpectedType(name)),
+            None => Ok(None),
+        }
+    }
+
+    fn required<T>(
+        &self,
+        name: &'static str,
+        convert: fn(&Value<'a>) -> Option<T>,
+    ) -> Result<T, WellKnownEventError> {
+        self.optional(name, convert)?
+            .ok_or(WellKnownEventError::MissingProperty(name))
+    }
+}
+
+fn as_u32(value: &Value) -> Option<u32> {
+    match &value.value {
+        InValue::UInt8(val) => val.get(0).map(u32::from),
+        InValue::UInt16(val) => val.get(0).map(u32::from),
+        InValue::UInt32(val) | InValue::HexInt32(val) | InValue::Boolean(val) => val.get(0),
+        _ => None,
+    }
+}
+
+fn as_u64(value: &Value) -> Option<u64> {
+    match &value.value {
+        InValue::UInt64(val) | InValue::HexInt64(val) => val.get(0),
+        InValue::Pointer(val) | InValue::SizeT(val) => val.get(0),
+        _ => as_u32(value).map(u64::from),
+    }
+}
+
+fn as_filetime(value: &Value) -> Option<i64> {
+    match &value.value {
+        InValue::FileTime(val) => val
+            .get(0)
+            .map(|time| ((i64::from(time.dwHighDateTime)) << 32) | i64::from(time.dwLowDateTime)),
+        _ => None,
+    }
+}
+
+fn as_string(value: &Value) -> Option<String> {
+    value.string_content()
+}
+
+/// Port numbers are logged in network byte order.
+fn as_port(value: &Value) -> Option<u16> {
+    match &value.value {
+        InValue::UInt16(val) => val.get(0).map(u16::from_be),
+        _ => None,
+    }
+}
+
+fn as_ip_addr(value: &Value) -> Option<IpAddr> {
+    match &value.value {
+        InValue::UInt32(val) | InValue::HexInt32(val) => {
+            val.get(0).map(|addr| IpAddr::V4(Ipv4Addr::from(addr.to_le_bytes())))
+        }
+        InValue::Binary(val) if val.len() == 1 => {
+            let octets: [u8; 16] = val[0].try_into().ok()?;
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Microsoft-Windows-Kernel-Process ProcessStart (event 1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessStart {
+    pub process_id: u32,
+    /// FILETIME of the process creation.
+    pub create_time: i64,
+    pub parent_process_id: u32,
+    pub session_id: u32,
+    pub image_name: String,
+    pub process_sequence_number: Option<u64>,
+    pub parent_process_sequence_number: Option<u64>,
+    pub flags: Option<u32>,
+    pub process_token_elevation_type: Option<u32>,
+    pub process_token_is_elevated: Option<u32>,
+    pub package_full_name: Option<String>,
+    pub package_relative_app_id: Option<String>,
+}
+
+impl TryFrom<(&EventInfo, &Event<'_>)> for ProcessStart {
+    type Error = WellKnownEventError;
+
+    fn try_from((schema, event): (&EventInfo, &Event<'_>)) -> Result<Self, Self::Error> {
+        let fields = Fields::new(schema, event, KERNEL_PROCESS_PROVIDER, &[PROCESS_START_ID])?;
+        Ok(Self {
+            process_id: fields.required("ProcessID", as_u32)?,
+            create_time: fields.required("CreateTime", as_filetime)?,
+            parent_process_id: fields.required("ParentProcessID", as_u32)?,
+            session_id: fields.required("SessionID", as_u32)?,
+            image_name: fields.required("ImageName", as_string)?,
+            process_sequence_number: fields.optional("ProcessSequenceNumber", as_u64)?,
+            parent_process_sequence_number: fields.optional("ParentProcessSequenceNumber", as_u64)?,
+            flags: fields.optional("Flags", as_u32)?,
+            process_token_elevation_type: fields.optional("ProcessTokenElevationType", as_u32)?,
+            process_token_is_elevated: fields.optional("ProcessTokenIsElevated", as_u32)?,
+            package_full_name: fields.optional("PackageFullName", as_string)?,
+            package_relative_app_id: fields.optional("PackageRelativeAppId", as_string)?,
+        })
+    }
+}
+
+/// Microsoft-Windows-Kernel-Process ProcessStop (event 2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessStop {
+    pub process_id: u32,
+    /// FILETIME of the process creation.
+    pub create_time: i64,
+    /// FILETIME of the process exit.
+    pub exit_time: i64,
+    pub exit_code: u32,
+    pub image_name: String,
+    pub process_sequence_number: Option<u64>,
+    pub handle_count: Option<u32>,
+    pub commit_charge: Option<u64>,
+    pub commit_peak: Option<u64>,
+    pub cpu_cycle_count: Option<u64>,
+    pub hard_fault_count: Option<u32>,
+}
+
+impl TryFrom<(&EventInfo, &Event<'_>)> for ProcessStop {
+    type Error = WellKnownEventError;
+
+    fn try_from((schema, event): (&EventInfo, &Event<'_>)) -> Result<Self, Self::Error> {
+        let fields = Fields::new(schema, event, KERNEL_PROCESS_PROVIDER, &[PROCESS_STOP_ID])?;
+        Ok(Self {
+            process_id: fields.required("ProcessID", as_u32)?,
+            create_time: fields.required("CreateTime", as_filetime)?,
+            exit_time: fields.required("ExitTime", as_filetime)?,
+            exit_code: fields.required("ExitCode", as_u32)?,
+            image_name: fields.required("ImageName", as_string)?,
+            process_sequence_number: fields.optional("ProcessSequenceNumber", as_u64)?,
+            handle_count: fields.optional("HandleCount", as_u32)?,
+            commit_charge: fields.optional("CommitCharge", as_u64)?,
+            commit_peak: fields.optional("CommitPeak", as_u64)?,
+            cpu_cycle_count: fields.optional("CPUCycleCount", as_u64)?,
+            hard_fault_count: fields.optional("HardFaultCount", as_u32)?,
+        })
+    }
+}
+
+/// Microsoft-Windows-Kernel-Process ImageLoad (event 5).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImageLoad {
+    pub image_base: u64,
+    pub image_size: u64,
+    pub process_id: u32,
+    pub image_checksum: Option<u32>,
+    pub time_date_stamp: Option<u32>,
+    pub default_base: Option<u64>,
+    pub image_name: String,
+}
+
+impl TryFrom<(&EventInfo, &Event<'_>)> for ImageLoad {
+    type Error = WellKnownEventError;
+
+    fn try_from((schema, event): (&EventInfo, &Event<'_>)) -> Result<Self, Self::Error> {
+        let fields = Fields::new(schema, event, KERNEL_PROCESS_PROVIDER, &[IMAGE_LOAD_ID])?;
+        Ok(Self {
+            image_base: fields.required("ImageBase", as_u64)?,
+            image_size: fields.required("ImageSize", as_u64)?,
+            process_id: fields.required("ProcessID", as_u32)?,
+            image_checksum: fields.optional("ImageCheckSum", as_u32)?,
+            time_date_stamp: fields.optional("TimeDateStamp", as_u32)?,
+            default_base: fields.optional("DefaultBase", as_u64)?,
+            image_name: fields.required("ImageName", as_string)?,
+        })
+    }
+}
+
+/// Microsoft-Windows-Kernel-Network TCP connect and disconnect events (IPv4 12/13, IPv6 28/29).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TcpConnection {
+    pub process_id: u32,
+    pub size: u32,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub sequence_number: Option<u32>,
+    pub connection_id: Option<u64>,
+}
+
+impl TcpConnection {
+    fn parse(schema: &EventInfo, event: &Event<'_>, ids: &[u16]) -> Result<Self, WellKnownEventError> {
+        let fields = Fields::new(schema, event, KERNEL_NETWORK_PROVIDER, ids)?;
+        Ok(Self {
+            process_id: fields.required("PID", as_u32)?,
+            size: fields.required("size", as_u32)?,
+            source: SocketAddr::new(
+                fields.required("saddr", as_ip_addr)?,
+                fields.required("sport", as_port)?,
+            ),
+            destination: SocketAddr::new(
+                fields.required("daddr", as_ip_addr)?,
+                fields.required("dport", as_port)?,
+            ),
+            sequence_number: fields.optional("seqnum", as_u32)?,
+            connection_id: fields.optional("connid", as_u64)?,
+        })
+    }
+}
+
+/// Microsoft-Windows-DNS-Client query start (event 3006).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsQueryStart {
+    pub query_name: String,
+    pub query_type: u32,
+    pub query_options: u64,
+    pub is_network_query: Option<u32>,
+    pub network_query_index: Option<u32>,
+    pub interface_index: Option<u32>,
+    pub is_async_query: Option<u32>,
+}
+
+impl TryFrom<(&EventInfo, &Event<'_>)> for DnsQueryStart {
+    type Error = WellKnownEventError;
+
+    fn try_from((schema, event): (&EventInfo, &Event<'_>)) -> Result<Self, Self::Error> {
+        let fields = Fields::new(schema, event, DNS_CLIENT_PROVIDER, &[DNS_QUERY_START_ID])?;
+        Ok(Self {
+            query_name: fields.required("QueryName", as_string)?,
+            query_type: fields.required("QueryType", as_u32)?,
+            query_options: fields.required("QueryOptions", as_u64)?,
+            is_network_query: fields.optional("IsNetworkQuery", as_u32)?,
+            network_query_index: fields.optional("NetworkQueryIndex", as_u32)?,
+            interface_index: fields.optional("InterfaceIndex", as_u32)?,
+            is_async_query: fields.optional("IsAsyncQuery", as_u32)?,
+        })
+    }
+}
+
+/// Microsoft-Windows-DNS-Client query completion (event 3008).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DnsQueryCompleted {
+    pub query_name: String,
+    pub query_options: u64,
+    pub query_status: u32,
+    pub query_results: String,
+}
+
+impl TryFrom<(&EventInfo, &Event<'_>)> for DnsQueryCompleted {
+    type Error = WellKnownEventError;
+
+    fn try_from((schema, event): (&EventInfo, &Event<'_>)) -> Result<Self, Self::Error> {
+        let fields = Fields::new(schema, event, DNS_CLIENT_PROVIDER, &[DNS_QUERY_COMPLETED_ID])?;
+        Ok(Self {
+            query_name: fields.required("QueryName", as_string)?,
+            query_options: fields.required("QueryOptions", as_u64)?,
+            query_status: fields.required("QueryStatus", as_u32)?,
+            query_results: fields.optional("QueryResults", as_string)?.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WellKnownEvent {
+    ProcessStart(ProcessStart),
+    ProcessStop(ProcessStop),
+    ImageLoad(ImageLoad),
+    TcpConnect(TcpConnection),
+    TcpDisconnect(TcpConnection),
+    DnsQueryStart(DnsQueryStart),
+    DnsQueryCompleted(DnsQueryCompleted),
+}
+
+impl WellKnownEvent {
+    /// Convert an event into its typed representation.
+    ///
+    /// Returns None if the event isn't one of the well known events, and an error if it
+    /// is but doesn't have the expected properties.
+    pub fn classify(schema: &EventInfo, event: &Event<'_>) -> Option<Result<Self, WellKnownEventError>> {
+        let result = match (schema.provider_guid, schema.event_id) {
+            (KERNEL_PROCESS_PROVIDER, PROCESS_START_ID) => {
+                ProcessStart::try_from((schema, event)).map(Self::ProcessStart)
+            }
+            (KERNEL_PROCESS_PROVIDER, PROCESS_STOP_ID) => {
+                ProcessStop::try_from((schema, event)).map(Self::ProcessStop)
+            }
+            (KERNEL_PROCESS_PROVIDER, IMAGE_LOAD_ID) => {
+                ImageLoad::try_from((schema, event)).map(Self::ImageLoad)
+            }
+            (KERNEL_NETWORK_PROVIDER, TCP_CONNECT_V4_ID | TCP_CONNECT_V6_ID) => {
+                TcpConnection::parse(schema, event, &[TCP_CONNECT_V4_ID, TCP_CONNECT_V6_ID])
+                    .map(Self::TcpConnect)
+            }
+            (KERNEL_NETWORK_PROVIDER, TCP_DISCONNECT_V4_ID | TCP_DISCONNECT_V6_ID) => {
+                TcpConnection::parse(schema, event, &[TCP_DISCONNECT_V4_ID, TCP_DISCONNECT_V6_ID])
+                    .map(Self::TcpDisconnect)
+            }
+            (DNS_CLIENT_PROVIDER, DNS_QUERY_START_ID) => {
+                DnsQueryStart::try_from((schema, event)).map(Self::DnsQueryStart)
+            }
+            (DNS_CLIENT_PROVIDER, DNS_QUERY_COMPLETED_ID) => {
+                DnsQueryCompleted::try_from((schema, event)).map(Self::DnsQueryCompleted)
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use windows::{core::GUID, Win32::System::Diagnostics::Etw::EVENT_HEADER};
+
+    use crate::{
+        error::WellKnownEventError,
+        schema::{
+            cache::{EventInfo, PropertyInfo, PropertyStructInfo},
+            in_type::InType,
+            out_type::OutType,
+        },
+        values::event::{Event, Header},
+    };
+
+    use super::{ImageLoad, WellKnownEvent, KERNEL_NETWORK_PROVIDER, KERNEL_PROCESS_PROVIDER};
+
+    fn utf16z(string: &str) -> Vec<u8> {
+        string
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    fn image_load_schema() -> EventInfo {
+        EventInfo::new(
+            KERNEL_PROCESS_PROVIDER,
+            5,
+            0,
+            PropertyStructInfo::new(vec![
+                PropertyInfo::value("ImageBase", InType::Pointer, OutType::HexInt64),
+                PropertyInfo::value("ImageSize", InType::Pointer, OutType::HexInt64),
+                PropertyInfo::value("ProcessID", InType::UInt32, OutType::Int),
+                PropertyInfo::value("ImageName", InType::UnicodeString, OutType::String),
+            ]),
+        )
+    }
+
+    fn image_load_payload() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&0x7ff6_0000_0000usize.to_le_bytes());
+        data.extend_from_slice(&0x1000usize.to_le_bytes());
+        data.extend_from_slice(&1234u32.to_le_bytes());
+        data.extend_from_slice(&utf16z(r"C:\Windows\System32\ntdll.dll"));
+        data
+    }
+
+    #[test]
+    fn test_image_load_without_optional_properties() {
+        let schema = image_load_schema();
+        let payload = image_load_payload();
+        let header = EVENT_HEADER::default();
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&payload).unwrap(),
+        };
+
+        let image_load = ImageLoad::try_from((&schema, &event)).unwrap();
+        assert_eq!(image_load.image_base, 0x7ff6_0000_0000);
+        assert_eq!(image_load.image_size, 0x1000);
+        assert_eq!(image_load.process_id, 1234);
+        assert_eq!(image_load.image_checksum, None);
+        assert_eq!(image_load.image_name, r"C:\Windows\System32\ntdll.dll");
+    }
+
+    #[test]
+    fn test_missing_mandatory_property() {
+        let mut schema = image_load_schema();
+        schema.properties = PropertyStructInfo::new(schema.properties.fields[..3].to_vec());
+        let payload = image_load_payload();
+        let header = EVENT_HEADER::default();
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&payload[..20]).unwrap(),
+        };
+
+        let Err(WellKnownEventError::MissingProperty("ImageName")) = ImageLoad::try_from((&schema, &event)) else {
+            panic!("Expected ImageName to be missing");
+        };
+    }
+
+    #[test]
+    fn test_classify_tcp_connect_v4() {
+        let schema = EventInfo::new(
+            KERNEL_NETWORK_PROVIDER,
+            12,
+            2,
+            PropertyStructInfo::new(vec![
+                PropertyInfo::value("PID", InType::UInt32, OutType::Int),
+                PropertyInfo::value("size", InType::UInt32, OutType::Int),
+                PropertyInfo::value("daddr", InType::UInt32, OutType::IpV4),
+                PropertyInfo::value("saddr", InType::UInt32, OutType::IpV4),
+                PropertyInfo::value("dport", InType::UInt16, OutType::Port),
+                PropertyInfo::value("sport", InType::UInt16, OutType::Port),
+            ]),
+        );
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&4321u32.to_le_bytes());
+        payload.extend_from_slice(&0u32.to_le_bytes());
+        payload.extend_from_slice(&[93, 184, 216, 34]);
+        payload.extend_from_slice(&[192, 168, 0, 2]);
+        payload.extend_from_slice(&443u16.to_be_bytes());
+        payload.extend_from_slice(&50000u16.to_be_bytes());
+        let header = EVENT_HEADER::default();
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&payload).unwrap(),
+        };
+
+        let Some(Ok(WellKnownEvent::TcpConnect(connection))) = WellKnownEvent::classify(&schema, &event) else {
+            panic!("Expected a TCP connect event");
+        };
+        assert_eq!(connection.process_id, 4321);
+        assert_eq!(
+            connection.destination,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)), 443)
+        );
+        assert_eq!(
+            connection.source,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)), 50000)
+        );
+        assert_eq!(connection.connection_id, None);
+    }
+
+    #[test]
+    fn test_classify_unknown_event() {
+        let schema = EventInfo::new(GUID::zeroed(), 1, 0, PropertyStructInfo::new(Vec::new()));
+        let header = EVENT_HEADER::default();
+        let event = Event {
+            header: Header::from(&header),
+            data: schema.decode_userdata(&[]).unwrap(),
+        };
+        assert!(WellKnownEvent::classify(&schema, &event).is_none());
+    }
+}
@@ -0,0 +1,24 @@
+//! Helpers shared by the integration tests.
+
+/// Starting sessions requires administrator rights, so tests that do only run if this is set.
+const ADMIN_ENV: &str = "ETW_TEST_ADMIN";
+
+/// Whether tests that start sessions can run. Prints why they are skipped if not.
+pub fn require_admin() -> bool {
+    if std::env::var_os(ADMIN_ENV).is_none() {
+        eprintln!("{} not set, skipping", ADMIN_ENV);
+        return false;
+    }
+    true
+}
+
+/// The file named by the environment variable `name`, e.g. a recorded ETL file. Prints
+/// why the test is skipped if it isn't set.
+#[allow(dead_code)]
+pub fn require_file(name: &str) -> Option<std::path::PathBuf> {
+    let file = std::env::var_os(name).map(std::path::PathBuf::from);
+    if file.is_none() {
+        eprintln!("{} not set, skipping", name);
+    }
+    file
+}
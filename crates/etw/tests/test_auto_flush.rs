@@ -0,0 +1,66 @@
+use std::{sync::mpsc, time::Duration};
+
+use etw::{
+    provider::{ProviderBuilder, TraceLevel},
+    trace::TraceBuilder,
+    trace_session::{EnableProviderTimeout, TraceSessionBuilder},
+};
+use windows::{
+    core::{w, GUID},
+    Win32::System::Diagnostics::Etw::{EventRegister, EventUnregister, EventWriteString, REGHANDLE},
+};
+
+mod common;
+
+const TEST_PROVIDER: GUID = GUID::from_u128(0x8c0e2b71_4f3d_4a9e_b6d2_71a5e0c93f48);
+const FLUSH_TIMER: Duration = Duration::from_secs(30);
+const AUTO_FLUSH: Duration = Duration::from_millis(500);
+
+#[test]
+fn test_auto_flush_delivers_single_event() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut registration = REGHANDLE::default();
+    assert_eq!(unsafe { EventRegister(&TEST_PROVIDER, None, None, &mut registration) }, 0);
+
+    let mut session = TraceSessionBuilder::new("etw-rs-auto-flush-test")
+        .close_previous()
+        .flush_timer(FLUSH_TIMER)
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&TEST_PROVIDER)
+        .level(TraceLevel::VERBOSE)
+        .build();
+    session
+        .enable_provider(&provider, true, EnableProviderTimeout::Infinite, None)
+        .unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .auto_flush(AUTO_FLUSH)
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            if event_record.EventHeader.ProviderId == TEST_PROVIDER {
+                let _ = sender.send(());
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+
+    assert_eq!(unsafe { EventWriteString(registration, 0, 0, w!("auto flush")) }, 0);
+    receiver
+        .recv_timeout(AUTO_FLUSH * 2)
+        .expect("event was not delivered by the auto flush");
+    assert_eq!(trace.auto_flush_errors(), 0);
+
+    drop(trace);
+    let _ = unsafe { EventUnregister(registration) };
+}
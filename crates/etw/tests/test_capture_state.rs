@@ -0,0 +1,83 @@
+use std::{
+    ffi::c_void,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use etw::{
+    provider::{ProviderBuilder, TraceLevel},
+    trace_session::{EnableProviderTimeout, TraceSessionBuilder},
+};
+use windows::{
+    core::GUID,
+    Win32::System::Diagnostics::Etw::{
+        EventRegister, EventUnregister, ENABLECALLBACK_ENABLED_STATE, EVENT_CONTROL_CODE_CAPTURE_STATE, EVENT_FILTER_DESCRIPTOR, REGHANDLE,
+    },
+};
+
+mod common;
+
+const TEST_PROVIDER: GUID = GUID::from_u128(0x3e7c9a14_62d8_4f0b_a5e1_c84b2d7f9063);
+
+static CAPTURE_STATE_CALLS: AtomicU32 = AtomicU32::new(0);
+static LEVEL: AtomicU32 = AtomicU32::new(0);
+static ANY: AtomicU64 = AtomicU64::new(0);
+static ALL: AtomicU64 = AtomicU64::new(0);
+
+/// Record the arguments of capture state requests.
+unsafe extern "system" fn record_capture_state_callback(
+    _source_id: *const GUID,
+    is_enabled: ENABLECALLBACK_ENABLED_STATE,
+    level: u8,
+    match_any_keyword: u64,
+    match_all_keyword: u64,
+    _filter_data: *const EVENT_FILTER_DESCRIPTOR,
+    _callback_context: *mut c_void,
+) {
+    if is_enabled.0 != EVENT_CONTROL_CODE_CAPTURE_STATE.0 {
+        return;
+    }
+    LEVEL.store(level.into(), Ordering::SeqCst);
+    ANY.store(match_any_keyword, Ordering::SeqCst);
+    ALL.store(match_all_keyword, Ordering::SeqCst);
+    CAPTURE_STATE_CALLS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn test_capture_state_reaches_provider_with_enable_arguments() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut registration = REGHANDLE::default();
+    assert_eq!(
+        unsafe { EventRegister(&TEST_PROVIDER, Some(record_capture_state_callback), None, &mut registration) },
+        0
+    );
+
+    let mut session = TraceSessionBuilder::new("etw-rs-capture-state-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&TEST_PROVIDER)
+        .any(0x10)
+        .all(0x4)
+        .level(TraceLevel::INFORMATION)
+        .build();
+    session
+        .enable_provider(&provider, true, EnableProviderTimeout::Infinite, None)
+        .unwrap();
+    assert_eq!(CAPTURE_STATE_CALLS.load(Ordering::SeqCst), 0);
+
+    session
+        .capture_state(&TEST_PROVIDER, EnableProviderTimeout::Infinite)
+        .unwrap();
+    assert_eq!(CAPTURE_STATE_CALLS.load(Ordering::SeqCst), 1);
+    assert_eq!(LEVEL.load(Ordering::SeqCst), u32::from(u8::from(TraceLevel::INFORMATION)));
+    assert_eq!(ANY.load(Ordering::SeqCst), 0x10);
+    assert_eq!(ALL.load(Ordering::SeqCst), 0x4);
+
+    drop(session);
+    let _ = unsafe { EventUnregister(registration) };
+}
@@ -0,0 +1,72 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use etw::{
+    checkpoint::{event_hash, Checkpoint},
+    trace::TraceBuilder,
+};
+
+mod common;
+
+/// ETL file to run the test against, which needs at least two buffers. The test is
+/// skipped if it isn't set.
+const TEST_ETL_ENV: &str = "ETW_TEST_ETL";
+
+fn process(
+    file: &PathBuf,
+    resume: Option<Checkpoint>,
+    stop_after_buffers: Option<u64>,
+) -> (Vec<u64>, Checkpoint) {
+    let hashes = Arc::new(Mutex::new(Vec::new()));
+    let handler_hashes = Arc::clone(&hashes);
+    let mut builder = TraceBuilder::new()
+        .file(file)
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            handler_hashes.lock().unwrap().push(event_hash(event_record))
+        })
+        .unwrap();
+    if let Some(checkpoint) = resume {
+        builder = builder.resume_from(checkpoint).unwrap();
+    }
+    if let Some(stop_after_buffers) = stop_after_buffers {
+        builder = builder.buffer_predicate(move |position| position.buffers < stop_after_buffers);
+    }
+    let mut trace = builder.open().unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    let result = trace.wait();
+    if stop_after_buffers.is_none() {
+        result.unwrap();
+    }
+
+    let checkpoint = trace.checkpoint();
+    let hashes = hashes.lock().unwrap().clone();
+    (hashes, checkpoint)
+}
+
+#[test]
+fn test_resume_matches_single_pass() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+    let Some(file) = common::require_file(TEST_ETL_ENV) else {
+        return;
+    };
+
+    let (single_pass, end) = process(&file, None, None);
+    assert!(end.buffers >= 2, "test file needs at least two buffers");
+
+    let (first_half, checkpoint) = process(&file, None, Some(end.buffers / 2));
+    assert_eq!(checkpoint.buffers, end.buffers / 2);
+    assert_eq!(checkpoint.last_event_hash, first_half.last().copied());
+
+    let (second_half, _) = process(&file, Some(checkpoint), None);
+
+    let mut resumed = first_half;
+    resumed.extend(second_half);
+    assert_eq!(resumed, single_pass);
+}
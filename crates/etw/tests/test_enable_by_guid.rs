@@ -0,0 +1,67 @@
+use std::{
+    ffi::c_void,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use etw::{provider::TraceLevel, trace_session::TraceSessionBuilder};
+use windows::{
+    core::GUID,
+    Win32::System::Diagnostics::Etw::{EventRegister, EventUnregister, ENABLECALLBACK_ENABLED_STATE, EVENT_FILTER_DESCRIPTOR, REGHANDLE},
+};
+
+mod common;
+
+const TEST_PROVIDER: GUID = GUID::from_u128(0x8f3b2a61_4c1d_4e8a_b7c2_9d5e0f1a2b3c);
+
+static ENABLED: AtomicU32 = AtomicU32::new(u32::MAX);
+static LEVEL: AtomicU32 = AtomicU32::new(0);
+static ANY: AtomicU64 = AtomicU64::new(0);
+static ALL: AtomicU64 = AtomicU64::new(0);
+
+unsafe extern "system" fn record_enable_callback(
+    _source_id: *const GUID,
+    is_enabled: ENABLECALLBACK_ENABLED_STATE,
+    level: u8,
+    match_any_keyword: u64,
+    match_all_keyword: u64,
+    _filter_data: *const EVENT_FILTER_DESCRIPTOR,
+    _callback_context: *mut c_void,
+) {
+    ENABLED.store(is_enabled.0, Ordering::SeqCst);
+    LEVEL.store(level.into(), Ordering::SeqCst);
+    ANY.store(match_any_keyword, Ordering::SeqCst);
+    ALL.store(match_all_keyword, Ordering::SeqCst);
+}
+
+#[test]
+fn test_enable_and_disable_provider_by_guid() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut registration = REGHANDLE::default();
+    assert_eq!(
+        unsafe { EventRegister(&TEST_PROVIDER, Some(record_enable_callback), None, &mut registration) },
+        0
+    );
+
+    let mut session = TraceSessionBuilder::new("etw-rs-enable-by-guid-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    session
+        .enable_provider_by_guid(&TEST_PROVIDER, TraceLevel::WARNING, 0x30, 0x10, None)
+        .unwrap();
+    assert_eq!(ENABLED.load(Ordering::SeqCst), 1);
+    assert_eq!(LEVEL.load(Ordering::SeqCst), u32::from(u8::from(TraceLevel::WARNING)));
+    assert_eq!(ANY.load(Ordering::SeqCst), 0x30);
+    assert_eq!(ALL.load(Ordering::SeqCst), 0x10);
+
+    session.disable_provider(&TEST_PROVIDER).unwrap();
+    assert_eq!(ENABLED.load(Ordering::SeqCst), 0);
+
+    drop(session);
+    let _ = unsafe { EventUnregister(registration) };
+}
@@ -0,0 +1,64 @@
+use std::{ffi::c_void, thread, time::Duration};
+
+use etw::{
+    error::TraceError,
+    provider::{ProviderBuilder, TraceLevel},
+    trace_session::{EnableProviderTimeout, TraceSessionBuilder},
+};
+use windows::{
+    core::GUID,
+    Win32::System::Diagnostics::Etw::{EventRegister, EventUnregister, ENABLECALLBACK_ENABLED_STATE, EVENT_FILTER_DESCRIPTOR, REGHANDLE},
+};
+
+mod common;
+
+const SLOW_PROVIDER: GUID = GUID::from_u128(0x5d1f0c3a_8a44_4b57_9d0e_2f6b1c7e9a13);
+
+/// Enable callback of a provider that takes a long time to process enable requests.
+unsafe extern "system" fn slow_enable_callback(
+    _source_id: *const GUID,
+    _is_enabled: ENABLECALLBACK_ENABLED_STATE,
+    _level: u8,
+    _match_any_keyword: u64,
+    _match_all_keyword: u64,
+    _filter_data: *const EVENT_FILTER_DESCRIPTOR,
+    _callback_context: *mut c_void,
+) {
+    thread::sleep(Duration::from_secs(2));
+}
+
+#[test]
+fn test_enable_times_out_on_slow_provider() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut registration = REGHANDLE::default();
+    assert_eq!(
+        unsafe { EventRegister(&SLOW_PROVIDER, Some(slow_enable_callback), None, &mut registration) },
+        0
+    );
+
+    let mut session = TraceSessionBuilder::new("etw-rs-enable-timeout-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&SLOW_PROVIDER)
+        .level(TraceLevel::VERBOSE)
+        .build();
+    let result = session.enable_provider(
+        &provider,
+        true,
+        EnableProviderTimeout::Timeout(Duration::from_millis(100)),
+        None,
+    );
+    match result {
+        Err(TraceError::EnableTimeout { provider, .. }) => assert_eq!(provider, SLOW_PROVIDER),
+        result => panic!("expected an enable timeout, got {:?}", result),
+    }
+
+    drop(session);
+    let _ = unsafe { EventUnregister(registration) };
+}
@@ -0,0 +1,142 @@
+use std::{
+    net::ToSocketAddrs,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use etw::{
+    provider::{ProviderBuilder, TraceLevel},
+    tdh_wrappers::ProviderEventDescriptors,
+    trace::TraceBuilder,
+    trace_session::{
+        EnableProviderTimeout, EventFilter, EventFilters, PayloadOperator, PayloadPredicate, TraceSessionBuilder,
+    },
+    well_known::{DNS_CLIENT_PROVIDER, KERNEL_PROCESS_PROVIDER},
+};
+
+mod common;
+
+#[test]
+fn test_process_id_filter_only_delivers_own_events() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let own_pid = std::process::id();
+    let mut session = TraceSessionBuilder::new("etw-rs-event-filter-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&DNS_CLIENT_PROVIDER)
+        .level(TraceLevel::VERBOSE)
+        .build();
+    session
+        .enable_provider(
+            &provider,
+            true,
+            EnableProviderTimeout::Infinite,
+            Some(EventFilters::from(vec![EventFilter::process_ids(&[own_pid]).unwrap()])),
+        )
+        .unwrap();
+
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&pids);
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            if event_record.EventHeader.ProviderId == DNS_CLIENT_PROVIDER {
+                seen.lock().unwrap().push(event_record.EventHeader.ProcessId);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+
+    // Queries from this process and from a child process, only ours may arrive
+    let _ = "etw-rs-filter-own.example.com:80".to_socket_addrs();
+    std::process::Command::new("cmd")
+        .args(["/C", "ping -n 1 etw-rs-filter-child.example.com"])
+        .status()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    trace.shutdown(Duration::from_secs(5)).unwrap();
+
+    let pids = pids.lock().unwrap();
+    assert!(!pids.is_empty());
+    assert!(pids.iter().all(|pid| *pid == own_pid), "{:?}", pids);
+}
+
+#[test]
+fn test_payload_filter_only_delivers_matching_events() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    const PROCESS_START_ID: u16 = 1;
+    let own_pid = std::process::id();
+    let descriptors = ProviderEventDescriptors::new(&KERNEL_PROCESS_PROVIDER).unwrap();
+    let process_start = descriptors
+        .iter()
+        .filter(|descriptor| descriptor.id() == PROCESS_START_ID)
+        .max_by_key(|descriptor| descriptor.version())
+        .unwrap();
+    let filter = EventFilter::payload(
+        &KERNEL_PROCESS_PROVIDER,
+        process_start.data(),
+        &[PayloadPredicate::new("ParentProcessID", PayloadOperator::Equal, own_pid.to_string())],
+    )
+    .unwrap();
+
+    let mut session = TraceSessionBuilder::new("etw-rs-payload-filter-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER)
+        .level(TraceLevel::INFORMATION)
+        .any(process_start.keyword())
+        .build();
+    session
+        .enable_provider(
+            &provider,
+            true,
+            EnableProviderTimeout::Infinite,
+            Some(EventFilters::from(vec![filter])),
+        )
+        .unwrap();
+
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&pids);
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            if event_record.EventHeader.ProviderId == KERNEL_PROCESS_PROVIDER
+                && event_record.EventHeader.EventDescriptor.Id == PROCESS_START_ID
+            {
+                // Process start events are logged by the creating process
+                seen.lock().unwrap().push(event_record.EventHeader.ProcessId);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+
+    // Only the start of the direct child may arrive, not that of the grandchild
+    std::process::Command::new("cmd")
+        .args(["/C", "cmd /C exit"])
+        .status()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    trace.shutdown(Duration::from_secs(5)).unwrap();
+
+    let pids = pids.lock().unwrap();
+    assert!(!pids.is_empty());
+    assert!(pids.iter().all(|pid| *pid == own_pid), "{:?}", pids);
+}
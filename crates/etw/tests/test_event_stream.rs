@@ -0,0 +1,108 @@
+//! Consuming a mock trace through an event stream.
+#![cfg(all(feature = "async", feature = "test-util"))]
+
+use std::{future::poll_fn, pin::Pin};
+
+use etw::{
+    event_stream::{Backpressure, EventStream},
+    mock::{EventRecordBuilder, MockEventSource, MockRecord},
+    schema::{
+        cache::{EventInfo, PropertyInfo, PropertyStructInfo},
+        in_type::InType,
+        out_type::OutType,
+    },
+    trace::{Trace, TraceBuilder},
+    values::{
+        compound::{StringOrStructOwned, StructOrValueOwned},
+        event::EventOwned,
+        in_value::InValueOwned,
+    },
+};
+use futures_core::Stream;
+use windows::core::GUID;
+
+const PROVIDER: GUID = GUID::from_u128(0x3c9e5b1a_7d42_4f86_a1b3_5e6f7a8b9c0d);
+const SEQUENCE: u16 = 1;
+const EVENT_COUNT: u32 = 100;
+
+fn sequence_schema() -> EventInfo {
+    EventInfo::new(
+        PROVIDER,
+        SEQUENCE,
+        0,
+        PropertyStructInfo::new(vec![PropertyInfo::value("Number", InType::UInt32, OutType::UnsignedInt)]),
+    )
+}
+
+fn sequence(number: u32) -> MockRecord {
+    EventRecordBuilder::new(PROVIDER, SEQUENCE, 0)
+        .timestamp(133_000_000_000_000_000 + i64::from(number))
+        .userdata(number.to_le_bytes())
+        .build()
+}
+
+fn open(backpressure: Backpressure, capacity: usize) -> (Trace, EventStream) {
+    let source = MockEventSource::new()
+        .schema(sequence_schema())
+        .records((1..=EVENT_COUNT).map(sequence));
+    let (builder, stream) = TraceBuilder::new().into_stream(capacity, backpressure).unwrap();
+    let trace = builder.mock(source).unwrap().open_mock().unwrap();
+    (trace, stream)
+}
+
+fn number(event: &EventOwned) -> u32 {
+    let StringOrStructOwned::Struct(data) = &event.data else {
+        panic!("Expected a struct, got {:?}", event.data);
+    };
+    let Some(StructOrValueOwned::Value(value)) = data.values.first() else {
+        panic!("Expected a value, got {:?}", data.values);
+    };
+    let InValueOwned::UInt32(number) = &value.value else {
+        panic!("Expected UInt32, got {:?}", value.value);
+    };
+    number[0]
+}
+
+async fn collect(mut stream: EventStream) -> Vec<u32> {
+    let mut numbers = Vec::new();
+    while let Some(event) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        numbers.push(number(&event));
+    }
+    numbers
+}
+
+#[tokio::test]
+async fn test_stream_delivers_all_events_with_blocking_backpressure() {
+    let (mut trace, stream) = open(Backpressure::Block, 8);
+    trace.start_processing(None, None, None::<fn()>);
+
+    let numbers = collect(stream).await;
+
+    trace.wait().unwrap();
+    assert_eq!(numbers, (1..=EVENT_COUNT).collect::<Vec<_>>());
+    assert_eq!(trace.dropped_events(), 0);
+}
+
+#[tokio::test]
+async fn test_slow_consumer_drops_newest_events() {
+    let (mut trace, stream) = open(Backpressure::DropNewest, 10);
+    // Nothing is consumed until processing ended
+    trace.process_blocking().unwrap();
+
+    assert_eq!(collect(stream).await, (1..=10).collect::<Vec<_>>());
+    assert_eq!(trace.dropped_events(), u64::from(EVENT_COUNT) - 10);
+}
+
+#[tokio::test]
+async fn test_slow_consumer_drops_oldest_events() {
+    let (mut trace, stream) = open(Backpressure::DropOldest, 10);
+    trace.process_blocking().unwrap();
+
+    assert_eq!(collect(stream).await, (EVENT_COUNT - 9..=EVENT_COUNT).collect::<Vec<_>>());
+    assert_eq!(trace.dropped_events(), u64::from(EVENT_COUNT) - 10);
+}
+
+#[test]
+fn test_stream_needs_capacity() {
+    assert!(TraceBuilder::new().into_stream(0, Backpressure::Block).is_err());
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use etw::{
+    provider::{ProviderBuilder, TraceLevel},
+    trace_session::{EnableProviderTimeout, TraceSessionBuilder},
+    well_known::KERNEL_PROCESS_PROVIDER,
+};
+
+mod common;
+
+#[test]
+fn test_flush_buffered_session_to_file() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut session = TraceSessionBuilder::new("etw-rs-flight-recorder-test")
+        .close_previous()
+        .in_memory(4)
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER)
+        .level(TraceLevel::INFORMATION)
+        .build();
+    session
+        .enable_provider(&provider, true, EnableProviderTimeout::Infinite, None)
+        .unwrap();
+    std::process::Command::new("cmd").args(["/C", "exit"]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+
+    let path = std::env::temp_dir().join(format!("etw-rs-flight-recorder-{}.etl", std::process::id()));
+    session.flush_buffered_to(&path).unwrap();
+    let size = std::fs::metadata(&path).unwrap().len();
+    std::fs::remove_file(&path).unwrap();
+    assert!(size > 0);
+}
+
+#[test]
+fn test_flush_of_realtime_session_is_rejected() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut session = TraceSessionBuilder::new("etw-rs-flight-recorder-realtime-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    let path = std::env::temp_dir().join("etw-rs-flight-recorder-realtime.etl");
+    assert!(session.flush_buffered_to(&path).is_err());
+}
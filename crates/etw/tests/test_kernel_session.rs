@@ -0,0 +1,99 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use etw::{
+    provider::PROCESS_GUID,
+    trace::TraceBuilder,
+    trace_session::{EnableFlags, StackWalkEvent, TraceSessionBuilder},
+    values::{
+        compound::StringOrStruct,
+        event::{EventRecord, ExtendedDataItem},
+    },
+};
+
+mod common;
+
+#[test]
+fn test_kernel_session_decodes_process_events() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let session = TraceSessionBuilder::kernel(EnableFlags::PROCESS)
+        .close_previous()
+        .start()
+        .unwrap();
+    let events = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&events);
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_handler(move |event, schema, _| {
+            let has_image_file_name = schema
+                .properties
+                .fields
+                .iter()
+                .any(|field| field.value.name() == "ImageFileName");
+            if *event.header.provider_id() == PROCESS_GUID
+                && has_image_file_name
+                && matches!(event.data, StringOrStruct::Struct(_))
+            {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    std::process::Command::new("cmd").args(["/C", "exit"]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    // Stops the session as well
+    trace.shutdown(Duration::from_secs(5)).unwrap();
+    assert!(events.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn test_kernel_session_walks_stacks_of_process_start() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    const PROCESS_START_OPCODE: u8 = 1;
+    let mut session = TraceSessionBuilder::kernel(EnableFlags::PROCESS)
+        .close_previous()
+        .start()
+        .unwrap();
+    session
+        .enable_stack_walk(&[StackWalkEvent::new(PROCESS_GUID, PROCESS_START_OPCODE)])
+        .unwrap();
+    let stacks = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&stacks);
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            let has_stack = EventRecord(event_record).extended_data().any(|item| {
+                matches!(item, ExtendedDataItem::StackTrace64 { .. } | ExtendedDataItem::StackTrace32 { .. })
+            });
+            if event_record.EventHeader.ProviderId == PROCESS_GUID && has_stack {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    std::process::Command::new("cmd").args(["/C", "exit"]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    trace.shutdown(Duration::from_secs(5)).unwrap();
+    assert!(stacks.load(Ordering::Relaxed) > 0);
+}
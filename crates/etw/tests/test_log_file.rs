@@ -0,0 +1,172 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use etw::{
+    error::TraceError,
+    provider::{ProviderBuilder, TraceLevel},
+    trace::{Trace, TraceBuilder},
+    trace_session::{EnableProviderTimeout, TraceSessionBuilder},
+    well_known::KERNEL_PROCESS_PROVIDER,
+};
+
+mod common;
+
+#[test]
+fn test_file_session_writes_readable_etl() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let path = std::env::temp_dir().join(format!("etw-rs-log-file-{}.etl", std::process::id()));
+    let mut session = TraceSessionBuilder::new("etw-rs-log-file-test")
+        .close_previous()
+        .log_file(&path)
+        .unwrap()
+        .start()
+        .unwrap();
+    assert_eq!(session.log_file(), Some(path.as_path()));
+    let provider = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER)
+        .level(TraceLevel::INFORMATION)
+        .build();
+    session
+        .enable_provider(&provider, true, EnableProviderTimeout::Infinite, None)
+        .unwrap();
+    std::process::Command::new("cmd").args(["/C", "exit"]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    // Stops the session, which closes the file
+    drop(session);
+
+    assert!(std::fs::metadata(&path).unwrap().len() > 0);
+    let events = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&events);
+    let mut trace = TraceBuilder::new()
+        .file(&path)
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            if event_record.EventHeader.ProviderId == KERNEL_PROCESS_PROVIDER {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.wait().unwrap();
+    let statistics = trace.statistics().unwrap();
+    assert!(statistics.buffers_read > 0);
+    assert!(statistics.buffers_written >= statistics.buffers_read);
+    drop(trace);
+
+    // Offline decoding of the same file, once alone and once twice in a group
+    let open_decoding = || {
+        TraceBuilder::new()
+            .file(&path)
+            .unwrap()
+            .set_handler(|_, _, _| ())
+            .unwrap()
+            .open()
+            .unwrap()
+    };
+    let mut trace = open_decoding();
+    let summary = trace.process_blocking().unwrap();
+    assert!(summary.events_processed > 0);
+    drop(trace);
+    let (first, second) = (open_decoding(), open_decoding());
+    let summaries = Trace::process_group_blocking(&[&first, &second]).unwrap();
+    assert_eq!(summaries, vec![summary, summary]);
+    drop((first, second));
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(events.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn test_missing_log_file_fails_to_open() {
+    let path = std::env::temp_dir().join("etw-rs-missing-log-file.etl");
+    let result = TraceBuilder::new()
+        .file(&path)
+        .unwrap()
+        .set_handler(|_, _, _| ())
+        .unwrap()
+        .open();
+    match result {
+        Err(TraceError::OpenTrace { target, .. }) => assert_eq!(target, path.display().to_string()),
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Opened missing log file {:?}", path),
+    }
+}
+
+/// Record the process events of starting a process into `path`.
+fn record_process_start(session_name: &str, path: &Path) {
+    let mut session = TraceSessionBuilder::new(session_name)
+        .close_previous()
+        .log_file(path)
+        .unwrap()
+        .start()
+        .unwrap();
+    let provider = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER)
+        .level(TraceLevel::INFORMATION)
+        .build();
+    session
+        .enable_provider(&provider, true, EnableProviderTimeout::Infinite, None)
+        .unwrap();
+    std::process::Command::new("cmd").args(["/C", "exit"]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+}
+
+#[test]
+fn test_files_are_merged_in_timestamp_order() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let path = |idx: usize| -> PathBuf {
+        std::env::temp_dir().join(format!("etw-rs-multi-file-{}-{}.etl", std::process::id(), idx))
+    };
+    let files = [path(0), path(1)];
+    record_process_start("etw-rs-multi-file-test-0", &files[0]);
+    let between = SystemTime::now();
+    record_process_start("etw-rs-multi-file-test-1", &files[1]);
+
+    let timestamps = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&timestamps);
+    // Pass the later file first, the events are merged regardless
+    let mut trace = TraceBuilder::new()
+        .files(files.iter().rev().cloned())
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            if event_record.EventHeader.ProviderId == KERNEL_PROCESS_PROVIDER {
+                seen.lock().unwrap().push(event_record.EventHeader.TimeStamp);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    assert_eq!(trace.log_file_names().len(), 2);
+    let timestamp_context = trace.timestamp_context();
+    trace.process_blocking().unwrap();
+    drop(trace);
+    for file in &files {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    let timestamps = timestamps.lock().unwrap();
+    assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", timestamps);
+    let times = timestamps
+        .iter()
+        .map(|timestamp| timestamp_context.to_system_time(*timestamp).unwrap())
+        .collect::<Vec<_>>();
+    // Events of both files arrived
+    assert!(times.iter().any(|time| *time < between));
+    assert!(times.iter().any(|time| *time > between));
+}
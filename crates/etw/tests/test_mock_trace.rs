@@ -0,0 +1,355 @@
+//! Example of testing an application handler end-to-end without an ETW session.
+#![cfg(feature = "test-util")]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use etw::{
+    clock_check::ClockSanityChecker,
+    mock::{EventRecordBuilder, MockEventSource},
+    schema::{
+        cache::{EventInfo, PropertyInfo, PropertyStructInfo, SchemaCache},
+        in_type::InType,
+        out_type::OutType,
+    },
+    trace::TraceBuilder,
+    values::{
+        compound::{StringOrStruct, StructOrValue},
+        in_value::InValue,
+    },
+};
+use windows::core::GUID;
+
+const PROVIDER: GUID = GUID::from_u128(0x6f2b6c4e_6a1d_4c59_9d5e_0c9a3f1b7e21);
+const PROCESS_START: u16 = 1;
+
+fn process_start_schema() -> EventInfo {
+    EventInfo::new(
+        PROVIDER,
+        PROCESS_START,
+        0,
+        PropertyStructInfo::new(vec![PropertyInfo::value("ProcessId", InType::UInt32, OutType::Pid)]),
+    )
+}
+
+fn process_start(pid: u32) -> etw::mock::MockRecord {
+    EventRecordBuilder::new(PROVIDER, PROCESS_START, 0)
+        .timestamp(133_000_000_000_000_000 + i64::from(pid))
+        .userdata(pid.to_le_bytes())
+        .build()
+}
+
+/// The application code under test: collects the pids of started processes.
+fn pid_collector(pids: Arc<Mutex<Vec<u32>>>) -> impl FnMut(etw::values::event::Event, Arc<EventInfo>, &windows::Win32::System::Diagnostics::Etw::EVENT_RECORD) + Send + 'static {
+    move |event, _schema, _record| {
+        let StringOrStruct::Struct(data) = event.data else {
+            return;
+        };
+        if let Some(StructOrValue::Value(value)) = data.values.first()
+            && let InValue::UInt32(pid) = &value.value
+        {
+            pids.lock().unwrap().extend(pid.get(0));
+        }
+    }
+}
+
+#[test]
+fn test_handler_sees_mock_events() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=40).map(process_start));
+    let mut trace = TraceBuilder::new()
+        .set_handler(pid_collector(Arc::clone(&pids)))
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.wait().unwrap();
+
+    assert_eq!(*pids.lock().unwrap(), (1..=40).collect::<Vec<_>>());
+    assert!(trace.checkpoint().buffers >= 2);
+}
+
+#[test]
+fn test_process_blocking_counts_events() {
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    // The last record is too short for its schema
+    let truncated = EventRecordBuilder::new(PROVIDER, PROCESS_START, 0).userdata([1, 0]).build();
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=5).map(process_start))
+        .records([truncated]);
+    let mut trace = TraceBuilder::new()
+        .set_handler(pid_collector(Arc::clone(&pids)))
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    let summary = trace.process_blocking().unwrap();
+
+    assert_eq!(*pids.lock().unwrap(), (1..=5).collect::<Vec<_>>());
+    assert_eq!(summary.events_processed, 6);
+    assert_eq!(summary.events_failed, 1);
+    assert_eq!(trace.summary(), summary);
+}
+
+#[test]
+fn test_buffer_predicate_stops_mock_trace() {
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=10).map(process_start))
+        .events_per_buffer(4);
+    let mut trace = TraceBuilder::new()
+        .set_handler(pid_collector(Arc::clone(&pids)))
+        .unwrap()
+        .buffer_predicate(|checkpoint| checkpoint.buffers < 1)
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.wait().unwrap();
+
+    assert_eq!(*pids.lock().unwrap(), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn test_shutdown_stops_paced_mock_trace() {
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=1000).map(process_start))
+        .pacing(Duration::from_millis(10));
+    let mut trace = TraceBuilder::new()
+        .set_raw_handler(|_| ())
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.shutdown(Duration::from_secs(1)).unwrap();
+}
+
+#[test]
+fn test_mock_trace_needs_open_mock() {
+    let result = TraceBuilder::new()
+        .set_raw_handler(|_| ())
+        .unwrap()
+        .mock(MockEventSource::new())
+        .unwrap()
+        .open();
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_panicking_handler_keeps_receiving_events() {
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=10).map(process_start));
+    let mut collect = pid_collector(Arc::clone(&pids));
+    let mut trace = TraceBuilder::new()
+        .set_handler(move |event, schema, record| {
+            if record.EventHeader.TimeStamp == 133_000_000_000_000_003 {
+                panic!("handler failure for pid 3");
+            }
+            collect(event, schema, record)
+        })
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.wait().unwrap();
+
+    assert_eq!(*pids.lock().unwrap(), vec![1, 2, 4, 5, 6, 7, 8, 9, 10]);
+}
+
+#[test]
+fn test_provider_handlers_route_events() {
+    const OTHER_PROVIDER: GUID = GUID::from_u128(0x0b1f3c2d_9a7e_4e51_8c6d_2f4a5b6c7d8e);
+
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let other = Arc::new(Mutex::new(0));
+    let other_events = Arc::clone(&other);
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .schema(EventInfo::new(OTHER_PROVIDER, 7, 0, PropertyStructInfo::new(vec![])))
+        .records((1..=4).map(process_start))
+        .records([
+            EventRecordBuilder::new(OTHER_PROVIDER, 7, 0).build(),
+            EventRecordBuilder::new(OTHER_PROVIDER, 8, 0).build(),
+        ]);
+    let mut trace = TraceBuilder::new()
+        .add_provider_handler(PROVIDER, Some(vec![PROCESS_START]), pid_collector(Arc::clone(&pids)))
+        .add_provider_handler(OTHER_PROVIDER, Some(vec![7]), move |_event, _schema, record| {
+            assert_eq!(record.EventHeader.EventDescriptor.Id, 7);
+            *other_events.lock().unwrap() += 1;
+        })
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.wait().unwrap();
+
+    assert_eq!(*pids.lock().unwrap(), vec![1, 2, 3, 4]);
+    assert_eq!(*other.lock().unwrap(), 1);
+    assert_eq!(trace.unmatched_events(), 1);
+}
+
+#[test]
+fn test_catch_all_handler_gets_unmatched_events() {
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let unmatched = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&unmatched);
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=3).map(process_start));
+    let mut trace = TraceBuilder::new()
+        .add_provider_handler(PROVIDER, Some(vec![PROCESS_START + 1]), pid_collector(Arc::clone(&pids)))
+        .set_raw_handler(move |record| seen.lock().unwrap().push(record.EventHeader.EventDescriptor.Id))
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.wait().unwrap();
+
+    assert!(pids.lock().unwrap().is_empty());
+    assert_eq!(*unmatched.lock().unwrap(), vec![PROCESS_START; 3]);
+    assert_eq!(trace.unmatched_events(), 0);
+}
+
+#[test]
+fn test_stop_and_wait_ends_paced_mock_trace() {
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=1000).map(process_start))
+        .events_per_buffer(1)
+        .pacing(Duration::from_millis(10));
+    let mut trace = TraceBuilder::new()
+        .set_handler(pid_collector(Arc::clone(&pids)))
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    std::thread::sleep(Duration::from_millis(50));
+    trace.stop_and_wait(Duration::from_secs(1)).unwrap();
+    assert!(trace.is_finished());
+
+    let delivered = pids.lock().unwrap().len();
+    assert!(delivered < 1000, "{} events delivered", delivered);
+    // Stopping again and dropping the stopped trace are no-ops
+    trace.stop().unwrap();
+    drop(trace);
+}
+
+#[test]
+fn test_request_stop_ends_processing() {
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=1000).map(process_start))
+        .events_per_buffer(1)
+        .pacing(Duration::from_millis(10));
+    let mut trace = TraceBuilder::new()
+        .set_raw_handler(|_| ())
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.request_stop();
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(1);
+    while !trace.is_finished() {
+        assert!(std::time::Instant::now() < deadline, "processing didn't stop");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    trace.wait().unwrap();
+}
+
+#[test]
+fn test_trace_decodes_with_own_schema_cache() {
+    // Not used by the other tests, so the global cache never has a schema for it
+    const PROCESS_START_V1: u8 = 1;
+    let mut schema = process_start_schema();
+    schema.event_version = PROCESS_START_V1;
+    let cache = Arc::new(SchemaCache::new());
+    cache.insert(PROVIDER, PROCESS_START, schema);
+
+    let pids = Arc::new(Mutex::new(Vec::new()));
+    let source = MockEventSource::new().records((1..=3).map(|pid| {
+        EventRecordBuilder::new(PROVIDER, PROCESS_START, PROCESS_START_V1)
+            .userdata(u32::to_le_bytes(pid))
+            .build()
+    }));
+    let mut trace = TraceBuilder::new()
+        .set_handler(pid_collector(Arc::clone(&pids)))
+        .unwrap()
+        .schema_cache(Arc::clone(&cache))
+        .unwrap()
+        .mock(source)
+        .unwrap()
+        .open_mock()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.wait().unwrap();
+
+    assert_eq!(*pids.lock().unwrap(), vec![1, 2, 3]);
+    assert!(SchemaCache::global().get(PROVIDER, PROCESS_START, PROCESS_START_V1).unwrap().is_none());
+}
+
+#[test]
+fn test_mock_schemas_stay_in_their_trace() {
+    let source = MockEventSource::new()
+        .schema(process_start_schema())
+        .records((1..=2).map(process_start));
+    let mut trace = TraceBuilder::new().mock(source).unwrap().open_mock().unwrap();
+    trace.process_blocking().unwrap();
+
+    assert!(SchemaCache::global().get(PROVIDER, PROCESS_START, 0).unwrap().is_none());
+}
+
+#[test]
+fn test_clock_check_records_each_trace() {
+    let checker = Arc::new(Mutex::new(ClockSanityChecker::new(Duration::from_millis(5))));
+    for (session, pids) in [("first", 1..=5), ("second", 11..=13)] {
+        let source = MockEventSource::new()
+            .schema(process_start_schema())
+            .records(pids.map(process_start));
+        let mut trace = TraceBuilder::new()
+            .set_raw_handler(|_| ())
+            .unwrap()
+            .clock_check(Arc::clone(&checker), session)
+            .mock(source)
+            .unwrap()
+            .open_mock()
+            .unwrap();
+        trace.process_blocking().unwrap();
+    }
+
+    let diagnosis = checker.lock().unwrap().diagnose();
+    let samples = diagnosis
+        .sessions
+        .iter()
+        .map(|report| (report.session.as_str(), report.samples))
+        .collect::<Vec<_>>();
+    assert_eq!(samples, vec![("first", 5), ("second", 3)]);
+    assert!(diagnosis.is_healthy());
+}
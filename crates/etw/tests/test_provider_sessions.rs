@@ -0,0 +1,44 @@
+use etw::{
+    provider::{query_provider_sessions, TraceLevel},
+    trace_session::TraceSessionBuilder,
+};
+use windows::{
+    core::GUID,
+    Win32::System::Diagnostics::Etw::{EventRegister, EventUnregister, REGHANDLE},
+};
+
+mod common;
+
+const TEST_PROVIDER: GUID = GUID::from_u128(0x2d7c4e91_5b3a_4f60_8e1d_6a9b0c2f3e4d);
+
+#[test]
+fn test_enabled_provider_is_reported_for_session() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut registration = REGHANDLE::default();
+    assert_eq!(unsafe { EventRegister(&TEST_PROVIDER, None, None, &mut registration) }, 0);
+
+    let mut session = TraceSessionBuilder::new("etw-rs-provider-sessions-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    assert!(!session.is_provider_enabled(&TEST_PROVIDER).unwrap());
+
+    session
+        .enable_provider_by_guid(&TEST_PROVIDER, TraceLevel::WARNING, 0x30, 0x10, None)
+        .unwrap();
+    let info = session.provider_session_info(&TEST_PROVIDER).unwrap().unwrap();
+    assert_eq!(info.level, TraceLevel::WARNING);
+    assert_eq!((info.any, info.all), (0x30, 0x10));
+    assert!(query_provider_sessions(&TEST_PROVIDER).unwrap().contains(&info));
+
+    session.disable_provider(&TEST_PROVIDER).unwrap();
+    assert!(!session.is_provider_enabled(&TEST_PROVIDER).unwrap());
+
+    drop(session);
+    let _ = unsafe { EventUnregister(registration) };
+}
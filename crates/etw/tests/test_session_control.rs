@@ -0,0 +1,89 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use etw::{
+    provider::{ProviderBuilder, TraceLevel},
+    trace::TraceBuilder,
+    trace_session::{EventTracePropertiesBuilder, TraceSession, TraceSessionBuilder},
+    well_known::{DNS_CLIENT_PROVIDER, KERNEL_PROCESS_PROVIDER},
+};
+
+mod common;
+
+const SESSION_NAME: &str = "etw-rs-session-control-test";
+
+#[test]
+fn test_query_flush_and_update_running_session() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut session = TraceSessionBuilder::new(SESSION_NAME)
+        .close_previous()
+        .maximum_buffers(16)
+        .start()
+        .unwrap();
+    let statistics = session.query().unwrap();
+    assert!(statistics.number_of_buffers > 0);
+    assert_eq!(statistics.events_lost, 0);
+    session.flush().unwrap();
+
+    session
+        .update(
+            &EventTracePropertiesBuilder::new()
+                .maximum_buffers(32)
+                .try_flush_timer(Duration::from_secs(2))
+                .unwrap(),
+        )
+        .unwrap();
+    assert_eq!(session.maximum_buffers(), Some(32));
+
+    // Sessions opened by name only know their name
+    let mut existing = TraceSession::open_existing(SESSION_NAME);
+    assert!(existing.query().unwrap().number_of_buffers > 0);
+    existing.flush().unwrap();
+}
+
+#[test]
+fn test_trace_builder_enables_added_providers() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let session = TraceSessionBuilder::new("etw-rs-add-provider-test")
+        .close_previous()
+        .start()
+        .unwrap();
+    let events = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&events);
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .add_provider(ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER).level(TraceLevel::INFORMATION).build(), None)
+        .unwrap()
+        .add_provider(ProviderBuilder::from_guid(&DNS_CLIENT_PROVIDER).level(TraceLevel::INFORMATION).build(), None)
+        .unwrap()
+        .set_raw_handler(move |event_record| {
+            if event_record.EventHeader.ProviderId == KERNEL_PROCESS_PROVIDER {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    std::process::Command::new("cmd").args(["/C", "exit"]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(trace.statistics().unwrap().events_lost, 0);
+    trace.shutdown(Duration::from_secs(5)).unwrap();
+    assert!(events.load(Ordering::Relaxed) > 0);
+}
@@ -0,0 +1,49 @@
+use std::{process::Command, thread, time::Duration};
+
+use etw::{
+    trace_session::TraceSessionBuilder,
+    watchdog::{reap_expired_sessions, LeaseStore, ReapOutcome},
+};
+
+const SESSION_NAME: &str = "etw-rs-watchdog-test";
+/// Set in the child process that starts the session and crashes.
+const CHILD_ENV: &str = "ETW_WATCHDOG_TEST_CHILD";
+mod common;
+
+#[test]
+fn test_crashed_collector_session_is_reaped() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if std::env::var_os(CHILD_ENV).is_some() {
+        let session = TraceSessionBuilder::new(SESSION_NAME)
+            .close_previous()
+            .auto_stop_after(Duration::from_secs(1))
+            .start()
+            .unwrap();
+        std::mem::forget(session);
+        std::process::abort();
+    }
+    if !common::require_admin() {
+        return;
+    }
+
+    let status = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "test_crashed_collector_session_is_reaped", "--nocapture"])
+        .env(CHILD_ENV, "1")
+        .status()
+        .unwrap();
+    assert!(!status.success(), "child process should have crashed");
+
+    let store = LeaseStore::default();
+    assert!(
+        store.leases().unwrap().iter().any(|lease| lease.session_name == SESSION_NAME),
+        "crashed child should have left its lease behind"
+    );
+
+    thread::sleep(Duration::from_secs(3));
+    let reaped = reap_expired_sessions(&store).unwrap();
+    assert!(reaped
+        .iter()
+        .any(|(lease, outcome)| lease.session_name == SESSION_NAME && *outcome == ReapOutcome::Stopped));
+    assert!(store.leases().unwrap().iter().all(|lease| lease.session_name != SESSION_NAME));
+}
@@ -0,0 +1,63 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use etw::{
+    error::TraceError,
+    provider::{ProviderBuilder, PROCESS_GUID, SYSTEM_PROCESS_KW_GENERAL, SYSTEM_PROCESS_PROVIDER_GUID},
+    trace::TraceBuilder,
+    trace_session::{EnableProviderTimeout, TraceSessionBuilder},
+    well_known::KERNEL_PROCESS_PROVIDER,
+};
+
+mod common;
+
+#[test]
+fn test_system_process_provider_delivers_process_events() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let mut session = TraceSessionBuilder::new("etw-rs-system-provider-test")
+        .close_previous()
+        .system_logger()
+        .start()
+        .unwrap();
+    let regular = ProviderBuilder::from_guid(&KERNEL_PROCESS_PROVIDER).build();
+    assert!(matches!(
+        session.enable_provider(&regular, true, EnableProviderTimeout::Infinite, None),
+        Err(TraceError::Configuration(_))
+    ));
+    let provider = ProviderBuilder::system_provider(&SYSTEM_PROCESS_PROVIDER_GUID, SYSTEM_PROCESS_KW_GENERAL)
+        .unwrap()
+        .build();
+    session
+        .enable_provider(&provider, true, EnableProviderTimeout::Infinite, None)
+        .unwrap();
+
+    let events = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&events);
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_handler(move |event, _schema, _| {
+            // The system process provider logs the classic process events
+            if *event.header.provider_id() == PROCESS_GUID {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    std::process::Command::new("cmd").args(["/C", "exit"]).status().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    trace.shutdown(Duration::from_secs(5)).unwrap();
+    assert!(events.load(Ordering::Relaxed) > 0);
+}
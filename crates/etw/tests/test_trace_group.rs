@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use etw::trace::{Trace, TraceBuilder};
+
+mod common;
+
+/// ETL file recorded by a user mode session. The test is skipped if it isn't set.
+const TEST_ETL_ENV: &str = "ETW_TEST_ETL";
+/// ETL file recorded by the NT kernel logger. The test is skipped if it isn't set.
+const TEST_KERNEL_ETL_ENV: &str = "ETW_TEST_KERNEL_ETL";
+
+fn open(file: &PathBuf) -> Trace {
+    TraceBuilder::new()
+        .file(file)
+        .unwrap()
+        .set_raw_handler(|_| ())
+        .unwrap()
+        .open()
+        .unwrap()
+}
+
+#[test]
+fn test_file_trace_headers() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+    let (Some(user_file), Some(kernel_file)) =
+        (common::require_file(TEST_ETL_ENV), common::require_file(TEST_KERNEL_ETL_ENV))
+    else {
+        return;
+    };
+
+    let user_trace = open(&user_file);
+    let kernel_trace = open(&kernel_file);
+    assert!(!user_trace.is_kernel_trace());
+    assert!(kernel_trace.is_kernel_trace());
+    assert_eq!(user_trace.log_file_name(), Some(user_file));
+
+    let result = Trace::check_group_compatible(&[&user_trace, &kernel_trace]);
+    if user_trace.is_private_trace() {
+        assert!(result.is_err());
+    } else {
+        result.unwrap();
+    }
+}
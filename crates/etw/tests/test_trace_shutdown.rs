@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant};
+
+use etw::{trace::TraceBuilder, trace_session::TraceSessionBuilder};
+
+mod common;
+
+const FLUSH_TIMER: Duration = Duration::from_secs(2);
+
+#[test]
+fn test_drop_of_idle_realtime_trace_does_not_hang() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let session = TraceSessionBuilder::new("etw-rs-shutdown-test")
+        .close_previous()
+        .flush_timer(FLUSH_TIMER)
+        .start()
+        .unwrap();
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_raw_handler(|_| ())
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+
+    let start = Instant::now();
+    drop(trace);
+    let elapsed = start.elapsed();
+    assert!(elapsed < FLUSH_TIMER * 2, "drop took {:?}", elapsed);
+}
+
+#[test]
+fn test_shutdown_reports_success() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    if !common::require_admin() {
+        return;
+    }
+
+    let session = TraceSessionBuilder::new("etw-rs-shutdown-explicit-test")
+        .close_previous()
+        .flush_timer(FLUSH_TIMER)
+        .start()
+        .unwrap();
+    let mut trace = TraceBuilder::new()
+        .session(session)
+        .unwrap()
+        .set_raw_handler(|_| ())
+        .unwrap()
+        .open()
+        .unwrap();
+    trace.start_processing(None, None, None::<fn()>);
+    trace.shutdown(FLUSH_TIMER * 2).unwrap();
+}
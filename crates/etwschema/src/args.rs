@@ -98,4 +98,7 @@ pub struct SchemaArgs {
     ///Some event specification in the form of <Provider GUID>[:<Event ID>,...]
     #[clap(value_parser = ProviderEventsSpecification::from_str)]
     pub event_specifications: Vec<ProviderEventsSpecification>,
+    ///Print each event as a map of property names with their min_version, the format used before merged events
+    #[clap(long)]
+    pub legacy_format: bool,
 }
\ No newline at end of file
@@ -1,26 +1,33 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use clap::Parser;
 
 use args::{Args, VersionSpecification};
-use etw::{schema::cache::{EventInfo, PropertyInfo}, tdh_wrappers::{Providers, TraceEventInfo}};
+use etw::{schema::{cache::{EventInfo, PropertyInfo}, merged::MergedEvent}, tdh_wrappers::{Providers, TraceEventInfo}};
 use uuid::Uuid;
 
 mod args;
 
-#[derive(serde::Serialize, serde::Deserialize)]
-pub struct NullablePropertyInfo {
+/// A property as printed with `--legacy-format`, keyed by its name.
+#[derive(serde::Serialize)]
+pub struct LegacyPropertyInfo<'a> {
     min_version: u8,
     #[serde(flatten)]
-    property_info: PropertyInfo,
+    property_info: &'a PropertyInfo,
 }
 
-
+fn legacy_format(merged: &MergedEvent) -> HashMap<&str, LegacyPropertyInfo<'_>> {
+    merged
+        .properties
+        .iter()
+        .map(|prop| (prop.name(), LegacyPropertyInfo { min_version: prop.min_version, property_info: &prop.property_info }))
+        .collect()
+}
 
 fn main() {
     match Args::parse() {
         Args::Schema(args) => {
-            let mut processed_schemas = HashMap::<Uuid, HashMap<u16, HashMap<String, NullablePropertyInfo>>>::new();
+            let mut processed_schemas = HashMap::<Uuid, HashMap<u16, MergedEvent>>::new();
 
             for provider in Providers::new().unwrap().iter() {
                 let provider_guid = provider.guid();
@@ -71,37 +78,27 @@ fn main() {
                     }
         
                     for (event_id, schemas) in events.iter() {
-                        let mut properties = HashMap::<String, NullablePropertyInfo>::new();
-                        let mut versions = schemas.keys().copied().collect::<Vec<_>>();
-                        versions.sort_unstable();
-                        for version in versions {
-                            let schema = schemas.get(&version).unwrap();
-                            if version >= 1 {
-                                let prev_properties = properties.keys().cloned().collect::<HashSet<_>>();
-                                let cur_properties = schema.properties.fields.iter().map(|f| f.value.name().to_string()).collect::<HashSet<_>>();
-                                if !prev_properties.is_subset(&cur_properties) {
-                                    eprintln!("Schemas of event {provider_guid:?}:{event_id} has events removed at version {version}");
-                                }
-                            }
-                            for prop in & schema.properties.fields {
-                                let name = prop.value.name();
-                                if let Some(prev_prop) = properties.get(name) {
-                                    if &prev_prop.property_info != prop {
-                                        eprintln!("Schemas of event {provider_guid:?}:{event_id} don't agree on property {name} at version {version}")
-                                    }
-                                }
-                                else {
-                                    properties.insert(name.to_string(), NullablePropertyInfo {min_version: version, property_info: prop.clone()});
-                                }
-                            }
+                        let merged = MergedEvent::merge(schemas.values());
+                        for (name, version) in &merged.conflicts {
+                            eprintln!("Schemas of event {provider_guid:?}:{event_id} don't agree on property {name} at version {version}")
                         }
-        
-                        processed_events.insert(*event_id, properties);
+                        processed_events.insert(*event_id, merged);
                     }
                 }
             }
         
-            println!("{}", serde_json::to_string_pretty(&processed_schemas).unwrap());
+            if args.legacy_format {
+                let legacy = processed_schemas
+                    .iter()
+                    .map(|(provider, events)| {
+                        (provider, events.iter().map(|(event_id, merged)| (event_id, legacy_format(merged))).collect::<HashMap<_, _>>())
+                    })
+                    .collect::<HashMap<_, _>>();
+                println!("{}", serde_json::to_string_pretty(&legacy).unwrap());
+            }
+            else {
+                println!("{}", serde_json::to_string_pretty(&processed_schemas).unwrap());
+            }
         }
     }
 }